@@ -0,0 +1,28 @@
+use async_imap::Client;
+use async_std::io::Cursor;
+use async_std::task;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn literal_response(size: usize) -> Vec<u8> {
+    let mut data = format!("* 1 FETCH (BODY[] {{{}}}\r\n", size).into_bytes();
+    data.extend(std::iter::repeat(b'a').take(size));
+    data.extend_from_slice(b")\r\nA0001 OK FETCH completed\r\n");
+    data
+}
+
+fn large_literal(c: &mut Criterion) {
+    let data = literal_response(16 * 1024 * 1024);
+
+    c.bench_function("read 16 MiB literal", |b| {
+        b.iter(|| {
+            task::block_on(async {
+                let mut client = Client::new(Cursor::new(data.clone()));
+                let response = client.read_response().await.unwrap().unwrap();
+                black_box(response);
+            })
+        })
+    });
+}
+
+criterion_group!(benches, large_literal);
+criterion_main!(benches);
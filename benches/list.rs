@@ -0,0 +1,77 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_std::task;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures::io::{self, AsyncRead, AsyncWrite, Cursor};
+use futures::StreamExt;
+
+/// Replays canned responses and discards whatever the client writes.
+struct MockServer(Cursor<Vec<u8>>);
+
+impl AsyncRead for MockServer {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MockServer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn list_response(count: usize) -> Vec<u8> {
+    let mut data = b"A0001 OK Logged in\r\n".to_vec();
+    for i in 0..count {
+        data.extend(
+            format!(
+                "* LIST (\\HasNoChildren) \"/\" \"Archive/{}/Folder {}\"\r\n",
+                i / 100,
+                i
+            )
+            .into_bytes(),
+        );
+    }
+    data.extend_from_slice(b"A0002 OK LIST completed\r\n");
+    data
+}
+
+fn list(c: &mut Criterion) {
+    let data = list_response(50_000);
+
+    c.bench_function("list 50k mailboxes", |b| {
+        b.iter(|| {
+            task::block_on(async {
+                let client = async_imap::Client::new(MockServer(Cursor::new(data.clone())));
+                let mut session = client.login("user", "pass").await.map_err(|e| e.0).unwrap();
+                let mut names = session.list(None, Some("*")).await.unwrap();
+                let mut count = 0;
+                while let Some(name) = names.next().await {
+                    black_box(name.unwrap());
+                    count += 1;
+                }
+                assert_eq!(count, 50_000);
+            })
+        })
+    });
+}
+
+criterion_group!(benches, list);
+criterion_main!(benches);
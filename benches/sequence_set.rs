@@ -0,0 +1,32 @@
+use async_imap::types::to_sequence_set;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// 100k UIDs in no particular order, mostly in short runs, as left behind by a search in a
+/// mailbox where some messages were deleted.
+fn scattered_uids() -> Vec<u32> {
+    let mut state = 1u32;
+    let mut uids = Vec::with_capacity(100_000);
+    let mut uid = 0;
+    while uids.len() < 100_000 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        uid += 1 + (state >> 16) % 3;
+        uids.push(uid);
+    }
+    // the server does not have to list them in order
+    uids.reverse();
+    uids
+}
+
+fn sequence_set(c: &mut Criterion) {
+    let uids = scattered_uids();
+
+    c.bench_function("to_sequence_set 100k scattered uids", |b| {
+        b.iter(|| black_box(to_sequence_set(uids.iter().cloned())))
+    });
+    c.bench_function("to_sequence_set 100k contiguous uids", |b| {
+        b.iter(|| black_box(to_sequence_set(1..=100_000)))
+    });
+}
+
+criterion_group!(benches, sequence_set);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+//! A skeleton of a push-notification daemon.
+//!
+//! It keeps a mailbox selected and uses `IDLE` to wait for changes, reporting them to a callback
+//! as they happen. The `IDLE` command is re-issued every 29 minutes so that the server does not
+//! consider the connection inactive, and the connection is re-established with an exponential
+//! backoff whenever it fails.
+//!
+//! Usage: `cargo run --example idle_daemon -- imap-server login password [mailbox]`
+
+use std::env;
+use std::time::Duration;
+
+use async_imap::error::{Error, Result};
+use async_imap::extensions::idle::IdleResponse;
+use async_imap::types::UnsolicitedResponse;
+use async_std::task;
+
+/// Re-issue `IDLE` before the 30 minute inactivity timeout of RFC 2177 kicks in.
+const IDLE_KEEPALIVE: Duration = Duration::from_secs(29 * 60);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+struct Config {
+    server: String,
+    login: String,
+    password: String,
+    mailbox: String,
+}
+
+/// What the daemon reports to its callback.
+#[derive(Debug)]
+enum Event {
+    /// The mailbox was (re-)selected and contains `exists` messages.
+    Connected { exists: u32 },
+    /// The mailbox now contains `exists` messages, some of which are new.
+    Exists { exists: u32 },
+    /// The message with the given sequence number was expunged.
+    Expunged { seq: u32 },
+    /// Something else changed, e.g. the flags of a message.
+    Changed(UnsolicitedResponse),
+    /// The connection failed; the daemon will reconnect after `retry_in`.
+    Disconnected { error: String, retry_in: Duration },
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 && args.len() != 5 {
+        eprintln!("need three or four arguments: imap-server login password [mailbox]");
+        return Err(Error::Bad("need three or four arguments".into()));
+    }
+    let config = Config {
+        server: args[1].clone(),
+        login: args[2].clone(),
+        password: args[3].clone(),
+        mailbox: args.get(4).cloned().unwrap_or_else(|| "INBOX".into()),
+    };
+
+    task::block_on(run(&config, |event| println!("-- {:?}", event)))
+}
+
+/// Watches the mailbox forever, reconnecting whenever the connection fails.
+async fn run<F: FnMut(Event)>(config: &Config, mut on_event: F) -> Result<()> {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let err = match watch(config, &mut on_event).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        // wrong credentials or a missing mailbox will not go away by retrying
+        if let Error::No(_) | Error::Auth(_) = err {
+            return Err(err);
+        }
+
+        on_event(Event::Disconnected {
+            error: err.to_string(),
+            retry_in: backoff,
+        });
+        task::sleep(backoff).await;
+        backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
+
+/// Connects, selects the mailbox and idles until the connection fails.
+async fn watch<F: FnMut(Event)>(config: &Config, on_event: &mut F) -> Result<()> {
+    let tls = async_native_tls::TlsConnector::new();
+    let client = async_imap::connect((config.server.as_str(), 993), &config.server, tls).await?;
+    let mut session = client
+        .login(&config.login, &config.password)
+        .await
+        .map_err(|e| e.0)?;
+
+    let capabilities = session.capabilities().await?;
+    if !capabilities.has_str("IDLE") {
+        return Err(Error::Bad("server does not support IDLE".into()));
+    }
+    if capabilities.has_str("ENABLE") && capabilities.has_str("CONDSTORE") {
+        // make the server include MODSEQ in flag updates
+        session.run_command_and_check_ok("ENABLE CONDSTORE").await?;
+    }
+
+    let mailbox = session.select(&config.mailbox).await?;
+    on_event(Event::Connected {
+        exists: mailbox.exists,
+    });
+
+    loop {
+        let mut idle = session.idle();
+        idle.init().await?;
+        let response = {
+            // dropping the interrupt handle ends the wait, so keep it until the wait is over;
+            // a daemon that needs to shut down cleanly would hand it to its signal handler
+            let (wait, _interrupt) = idle.wait_with_timeout(IDLE_KEEPALIVE);
+            wait.await?
+        };
+        session = idle.done().await?;
+
+        match response {
+            IdleResponse::Timeout => {}
+            IdleResponse::ManualInterrupt => {
+                session.logout().await?;
+                return Ok(());
+            }
+            response => {
+                if let Some(response) = response.into_unsolicited() {
+                    report(response, on_event);
+                }
+            }
+        }
+
+        // anything else that arrived while leaving IDLE
+        for response in session.drain_unsolicited() {
+            report(response, on_event);
+        }
+    }
+}
+
+fn report<F: FnMut(Event)>(response: UnsolicitedResponse, on_event: &mut F) {
+    let event = match response {
+        UnsolicitedResponse::Exists(exists) => Event::Exists { exists },
+        UnsolicitedResponse::Expunge(seq) => Event::Expunged { seq },
+        // `RECENT` always accompanies `EXISTS` for new messages
+        UnsolicitedResponse::Recent(_) => return,
+        other => Event::Changed(other),
+    };
+    on_event(event);
+}
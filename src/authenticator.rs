@@ -1,5 +1,40 @@
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::error::Result;
+
 /// This trait allows for pluggable authentication schemes. It is used by `Client::authenticate` to
 /// [authenticate using SASL](https://tools.ietf.org/html/rfc3501#section-6.2.2).
+///
+/// `process` is called once for every continuation request of the server, for as many rounds as
+/// the mechanism needs, until the server completes the command. Since it takes `&self`,
+/// mechanisms with several steps keep their state in a `Cell` or `Mutex`, like the `SCRAM`
+/// mechanisms in [`sasl`](crate::sasl) do:
+///
+/// ```
+/// use std::cell::Cell;
+///
+/// /// A made-up mechanism that sends the user name first, and then answers the challenge.
+/// struct ChallengeResponse {
+///     user: String,
+///     step: Cell<u32>,
+/// }
+///
+/// impl async_imap::Authenticator for ChallengeResponse {
+///     type Response = Vec<u8>;
+///
+///     fn process(&self, challenge: &[u8]) -> Self::Response {
+///         let step = self.step.get();
+///         self.step.set(step + 1);
+///         match step {
+///             0 => self.user.clone().into_bytes(),
+///             _ => challenge.iter().rev().cloned().collect(),
+///         }
+///     }
+/// }
+/// ```
+///
+/// Authentication is only possible before logging in, so there is no equivalent on `Session`.
 pub trait Authenticator {
     /// The type of the response to the challenge. This will usually be a `Vec<u8>` or `String`.
     type Response: AsRef<[u8]>;
@@ -7,4 +42,103 @@ pub trait Authenticator {
     /// Each base64-decoded server challenge is passed to `process`.
     /// The returned byte-string is base64-encoded and then sent back to the server.
     fn process(&self, challenge: &[u8]) -> Self::Response;
+
+    /// Checked after each call to `process`. Returning `true` cancels the exchange instead of
+    /// sending the response, e.g. because the server failed to prove its identity. The command
+    /// then fails with a `BAD` response.
+    fn cancelled(&self) -> bool {
+        false
+    }
+
+    /// Checked when the server accepts the client. Returning `false` fails the authentication
+    /// with [`Error::Auth`](crate::error::Error::Auth) anyway, e.g. because the server skipped
+    /// the step in which it proves its identity. Mechanisms that do not authenticate the server
+    /// keep the default, `true`.
+    fn completed(&self) -> bool {
+        true
+    }
+
+    /// Whether the mechanism starts with a response of the client, which `process` returns when
+    /// it is passed an empty challenge. If so, and the server advertises
+    /// [`SASL-IR`](https://tools.ietf.org/html/rfc4959), this initial response is sent along with
+    /// the `AUTHENTICATE` command, which saves a round trip. Mechanisms in which the server
+    /// speaks first, such as `CRAM-MD5`, must return `false`, which is the default.
+    fn client_first(&self) -> bool {
+        false
+    }
+}
+
+/// The kinds of TLS channel binding that SASL `-PLUS` mechanisms such as `SCRAM-SHA-256-PLUS`
+/// can use to tie the authentication exchange to the underlying TLS connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelBindingType {
+    /// [`tls-server-end-point`](https://tools.ietf.org/html/rfc5929#section-4): a hash of the
+    /// server's certificate.
+    TlsServerEndPoint,
+    /// [`tls-exporter`](https://tools.ietf.org/html/rfc9266): keying material exported from the
+    /// TLS session. This is the only binding defined for TLS 1.3.
+    TlsExporter,
+    /// [`tls-unique`](https://tools.ietf.org/html/rfc5929#section-3): the first `Finished`
+    /// message of the TLS handshake. It is only defined for TLS 1.2 and older, and neither
+    /// native-tls nor rustls expose it, so it is only available from custom transports.
+    TlsUnique,
+}
+
+impl ChannelBindingType {
+    /// The name of the binding type, as used in the GS2 header of a SCRAM exchange.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelBindingType::TlsServerEndPoint => "tls-server-end-point",
+            ChannelBindingType::TlsExporter => "tls-exporter",
+            ChannelBindingType::TlsUnique => "tls-unique",
+        }
+    }
+}
+
+/// Transports that can provide [channel binding](https://tools.ietf.org/html/rfc5056) data for
+/// the TLS connection they wrap.
+///
+/// Use [`Client::channel_binding`](crate::Client) to get the data for the current connection and
+/// hand it to an [`Authenticator`] implementing a `-PLUS` mechanism.
+pub trait ChannelBinding {
+    /// Returns the channel binding data of type `kind`, or `None` if the transport does not
+    /// support that type.
+    fn channel_binding(&self, kind: ChannelBindingType) -> Result<Option<Vec<u8>>>;
+}
+
+#[cfg(feature = "tls-native")]
+impl<S: AsyncRead + AsyncWrite + Unpin> ChannelBinding for async_native_tls::TlsStream<S> {
+    fn channel_binding(&self, kind: ChannelBindingType) -> Result<Option<Vec<u8>>> {
+        match kind {
+            ChannelBindingType::TlsServerEndPoint => Ok(self.tls_server_end_point()?),
+            // native-tls does not expose keying material exporters or the `Finished` messages
+            ChannelBindingType::TlsExporter | ChannelBindingType::TlsUnique => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+impl<S: AsyncRead + AsyncWrite + Unpin> ChannelBinding for async_rustls::client::TlsStream<S> {
+    fn channel_binding(&self, kind: ChannelBindingType) -> Result<Option<Vec<u8>>> {
+        use async_rustls::rustls::{ProtocolVersion, Session};
+
+        let (_, session) = self.get_ref();
+        match kind {
+            ChannelBindingType::TlsExporter => {
+                // the exporter binding is only defined for TLS 1.3
+                if session.get_protocol_version() != Some(ProtocolVersion::TLSv1_3) {
+                    return Ok(None);
+                }
+                let mut data = vec![0; 32];
+                session
+                    .export_keying_material(&mut data, b"EXPORTER-Channel-Binding", Some(&[]))
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                Ok(Some(data))
+            }
+            // computing it requires parsing the certificate's signature algorithm
+            ChannelBindingType::TlsServerEndPoint => Ok(None),
+            // rustls does not expose the `Finished` messages
+            ChannelBindingType::TlsUnique => Ok(None),
+        }
+    }
 }
@@ -0,0 +1,411 @@
+//! Looking up the IMAP settings for an email address in [Thunderbird-style autoconfig
+//! files](https://wiki.mozilla.org/Thunderbird:Autoconfiguration:ConfigFileFormat).
+//!
+//! The configuration is fetched from the following locations, in order, and the first one that
+//! exists is used:
+//!
+//!  1. `https://autoconfig.<domain>/mail/config-v1.1.xml`, which is published by the provider,
+//!  2. `https://<domain>/.well-known/autoconfig/mail/config-v1.1.xml`, the same on the main
+//!     web server of the domain,
+//!  3. the [ISPDB](https://autoconfig.thunderbird.net/v1.1/) maintained by Mozilla, which has
+//!     entries for many large providers.
+//!
+//! ```no_run
+//! # fn main() -> async_imap::error::Result<()> {
+//! # async_std::task::block_on(async {
+//! let (client, config) = async_imap::autoconfig::connect("user@example.org").await?;
+//! let session = client
+//!     .login(config.username.as_deref().unwrap_or("user@example.org"), "password")
+//!     .await
+//!     .map_err(|e| e.0)?;
+//! # Ok(())
+//! # }) }
+//! ```
+//!
+//! Like the SRV lookup in the `discovery` module, this trusts the answer to name the right server;
+//! only HTTPS locations are queried, but applications should still show the user which server
+//! was picked before sending a password to it.
+//!
+//! This module requires the `autoconfig` feature.
+
+use std::io;
+
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::builder::{ClientBuilder, MaybeTlsStream};
+use crate::client::Client;
+use crate::endpoint::{Endpoint, TlsMode};
+use crate::error::{Error, Result};
+
+/// An authentication method listed for a server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AuthMethod {
+    /// The password is sent as is, i.e. `LOGIN` or `AUTHENTICATE PLAIN`.
+    PasswordCleartext,
+    /// A challenge-response mechanism that does not reveal the password, e.g. `CRAM-MD5`.
+    PasswordEncrypted,
+    /// `AUTHENTICATE XOAUTH2` or `OAUTHBEARER` with a token from the provider.
+    OAuth2,
+    /// Kerberos through `AUTHENTICATE GSSAPI`.
+    Gssapi,
+    /// Any other value of the `<authentication>` element.
+    Other(String),
+}
+
+impl<'a> From<&'a str> for AuthMethod {
+    fn from(s: &'a str) -> Self {
+        match s {
+            "password-cleartext" | "plain" => AuthMethod::PasswordCleartext,
+            "password-encrypted" | "secure" => AuthMethod::PasswordEncrypted,
+            "OAuth2" => AuthMethod::OAuth2,
+            "GSSAPI" => AuthMethod::Gssapi,
+            _ => AuthMethod::Other(s.into()),
+        }
+    }
+}
+
+/// The settings for one IMAP server, as listed in an autoconfig file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Where to connect to.
+    pub endpoint: Endpoint,
+    /// The user name to log in with, with placeholders such as `%EMAILLOCALPART%` already
+    /// replaced. `None` if the file did not specify one.
+    pub username: Option<String>,
+    /// The supported authentication methods, most preferred first.
+    pub auth_methods: Vec<AuthMethod>,
+}
+
+/// Fetches the autoconfig file for `email` and returns the IMAP servers it lists, in the
+/// provider's order of preference. Returns an empty list if no autoconfig file was found.
+pub async fn lookup(email: &str) -> Result<Vec<ServerConfig>> {
+    for url in urls(email)?.iter() {
+        if let Some(xml) = fetch(url).await {
+            return parse(&xml, email);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// The locations of the autoconfig file for `email`, in the order they are tried.
+fn urls(email: &str) -> Result<Vec<String>> {
+    let invalid = || Error::InvalidEndpoint(format!("not an email address: {}", email));
+    let domain = match email.rfind('@') {
+        Some(i) => &email[i + 1..],
+        None => return Err(invalid()),
+    };
+    // the domain ends up in host names and paths, so it must not be able to change the URL
+    if !is_domain(domain) {
+        return Err(invalid());
+    }
+
+    Ok(vec![
+        format!(
+            "https://autoconfig.{}/mail/config-v1.1.xml?emailaddress={}",
+            domain,
+            percent_encode(email)
+        ),
+        format!(
+            "https://{}/.well-known/autoconfig/mail/config-v1.1.xml",
+            domain
+        ),
+        format!("https://autoconfig.thunderbird.net/v1.1/{}", domain),
+    ])
+}
+
+/// Whether `s` is a domain name made of letters, digits and hyphens, separated by dots
+/// ([RFC 1123](https://tools.ietf.org/html/rfc1123#section-2.1)).
+fn is_domain(s: &str) -> bool {
+    s.len() <= 253
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Percent-encodes everything but the unreserved characters of
+/// [RFC 3986](https://tools.ietf.org/html/rfc3986#section-2.3), for use in a query.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Connects to the first server for `email` that can be reached, see [`lookup`]. The returned
+/// [`ServerConfig`] tells how to log in.
+///
+/// Servers that are listed without TLS are skipped, since the password would be sent over them
+/// in cleartext. See [`connect_allow_plaintext`] to fall back to them.
+pub async fn connect(email: &str) -> Result<(Client<MaybeTlsStream>, ServerConfig)> {
+    connect_to(candidates(lookup(email).await?, false)).await
+}
+
+/// Like [`connect`], but falls back to servers that are listed without TLS if none of the
+/// others can be reached. Anyone on the network path can read the password sent to those, so
+/// only use this for servers on trusted networks.
+pub async fn connect_allow_plaintext(
+    email: &str,
+) -> Result<(Client<MaybeTlsStream>, ServerConfig)> {
+    connect_to(candidates(lookup(email).await?, true)).await
+}
+
+/// The servers to try connecting to, in order: the ones with TLS first, in the provider's order,
+/// then the ones without it if `allow_plaintext` is set.
+fn candidates(configs: Vec<ServerConfig>, allow_plaintext: bool) -> Vec<ServerConfig> {
+    let (plaintext, mut secure): (Vec<_>, Vec<_>) = configs
+        .into_iter()
+        .partition(|config| config.endpoint.tls_mode() == TlsMode::Plaintext);
+    if allow_plaintext {
+        secure.extend(plaintext);
+    }
+    secure
+}
+
+async fn connect_to(configs: Vec<ServerConfig>) -> Result<(Client<MaybeTlsStream>, ServerConfig)> {
+    let mut last_err = None;
+    for config in configs {
+        match ClientBuilder::from_endpoint(config.endpoint.clone())
+            .connect()
+            .await
+        {
+            Ok(client) => return Ok((client, config)),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no IMAP server found in autoconfig",
+        ))
+    }))
+}
+
+/// Fetches `url`, returning `None` if it does not exist or can not be reached, so that the
+/// next location is tried.
+async fn fetch(url: &str) -> Option<String> {
+    let mut response = match surf::get(url).await {
+        Ok(response) => response,
+        Err(err) => {
+            log::debug!("autoconfig: {}: {}", url, err);
+            return None;
+        }
+    };
+    if !response.status().is_success() {
+        log::debug!("autoconfig: {}: {}", url, response.status());
+        return None;
+    }
+    response.body_string().await.ok()
+}
+
+/// Extracts the `<incomingServer type="imap">` entries from an autoconfig file.
+fn parse(xml: &str, email: &str) -> Result<Vec<ServerConfig>> {
+    let invalid = |e: quick_xml::Error| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid autoconfig file: {}", e),
+        ))
+    };
+
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut servers = Vec::new();
+    let mut buf = Vec::new();
+    // the element whose text is being read, and the server that is being read, if it is an IMAP
+    // server
+    let mut element = Vec::new();
+    let mut server: Option<(Option<String>, Option<u16>, Option<TlsMode>, ServerConfig)> = None;
+
+    loop {
+        match reader.read_event(&mut buf).map_err(invalid)? {
+            Event::Start(ref e) if e.name() == b"incomingServer" => {
+                let is_imap = e.attributes().filter_map(|a| a.ok()).any(|a| {
+                    a.key == b"type" && a.unescaped_value().map_or(false, |v| &*v == b"imap")
+                });
+                if is_imap {
+                    server = Some((
+                        None,
+                        None,
+                        None,
+                        ServerConfig {
+                            endpoint: Endpoint::new("", 0, TlsMode::Implicit),
+                            username: None,
+                            auth_methods: Vec::new(),
+                        },
+                    ));
+                }
+            }
+            Event::Start(ref e) => element = e.name().to_vec(),
+            Event::Text(ref e) => {
+                if let Some((host, port, tls_mode, config)) = server.as_mut() {
+                    let text = e.unescape_and_decode(&reader).map_err(invalid)?;
+                    match &element[..] {
+                        b"hostname" => *host = Some(expand(&text, email)),
+                        b"port" => *port = text.parse().ok(),
+                        b"socketType" => {
+                            *tls_mode = match &text[..] {
+                                "SSL" => Some(TlsMode::Implicit),
+                                "STARTTLS" => Some(TlsMode::StartTls),
+                                "plain" => Some(TlsMode::Plaintext),
+                                _ => None,
+                            }
+                        }
+                        b"username" => config.username = Some(expand(&text, email)),
+                        b"authentication" => config.auth_methods.push(text[..].into()),
+                        _ => {}
+                    }
+                }
+            }
+            Event::End(ref e) if e.name() == b"incomingServer" => {
+                if let Some((Some(host), Some(port), Some(tls_mode), mut config)) = server.take() {
+                    config.endpoint = Endpoint::new(host, port, tls_mode);
+                    servers.push(config);
+                }
+            }
+            Event::End(_) => element.clear(),
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(servers)
+}
+
+/// Replaces the placeholders that autoconfig files use in host and user names.
+fn expand(s: &str, email: &str) -> String {
+    let (local, domain) = match email.rfind('@') {
+        Some(i) => (&email[..i], &email[i + 1..]),
+        None => (email, ""),
+    };
+    s.replace("%EMAILADDRESS%", email)
+        .replace("%EMAILLOCALPART%", local)
+        .replace("%EMAILDOMAIN%", domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let xml = r#"<?xml version="1.0"?>
+<clientConfig version="1.1">
+  <emailProvider id="example.org">
+    <domain>example.org</domain>
+    <incomingServer type="pop3">
+      <hostname>pop.example.org</hostname>
+      <port>995</port>
+      <socketType>SSL</socketType>
+    </incomingServer>
+    <incomingServer type="imap">
+      <hostname>imap.%EMAILDOMAIN%</hostname>
+      <port>993</port>
+      <socketType>SSL</socketType>
+      <username>%EMAILLOCALPART%</username>
+      <authentication>OAuth2</authentication>
+      <authentication>password-cleartext</authentication>
+    </incomingServer>
+    <incomingServer type="imap">
+      <hostname>imap.example.org</hostname>
+      <port>143</port>
+      <socketType>STARTTLS</socketType>
+      <authentication>password-encrypted</authentication>
+    </incomingServer>
+  </emailProvider>
+</clientConfig>"#;
+
+        let servers = parse(xml, "jane@example.org").unwrap();
+        assert_eq!(
+            servers,
+            vec![
+                ServerConfig {
+                    endpoint: Endpoint::new("imap.example.org", 993, TlsMode::Implicit),
+                    username: Some("jane".into()),
+                    auth_methods: vec![AuthMethod::OAuth2, AuthMethod::PasswordCleartext],
+                },
+                ServerConfig {
+                    endpoint: Endpoint::new("imap.example.org", 143, TlsMode::StartTls),
+                    username: None,
+                    auth_methods: vec![AuthMethod::PasswordEncrypted],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_urls() {
+        assert_eq!(
+            urls("jane+x&y=1#z@mail.example.org").unwrap(),
+            vec![
+                "https://autoconfig.mail.example.org/mail/config-v1.1.xml\
+                 ?emailaddress=jane%2Bx%26y%3D1%23z%40mail.example.org"
+                    .to_string(),
+                "https://mail.example.org/.well-known/autoconfig/mail/config-v1.1.xml".to_string(),
+                "https://autoconfig.thunderbird.net/v1.1/mail.example.org".to_string(),
+            ]
+        );
+
+        for email in &[
+            "jane",
+            "jane@",
+            "jane@example.org/evil",
+            "jane@evil.org?x=example.org",
+            "jane@exa mple.org",
+            "jane@example..org",
+            "jane@example.org.",
+            "jane@-example.org",
+            "jane@example-.org",
+            "jane@b\u{fc}cher.example",
+        ] {
+            match urls(email) {
+                Err(Error::InvalidEndpoint(_)) => {}
+                res => panic!("unexpected result for {}: {:?}", email, res),
+            }
+        }
+    }
+
+    #[test]
+    fn plaintext_candidates() {
+        let config = |host: &str, tls_mode| ServerConfig {
+            endpoint: Endpoint::new(host, 143, tls_mode),
+            username: None,
+            auth_methods: Vec::new(),
+        };
+        let configs = vec![
+            config("plain.example.org", TlsMode::Plaintext),
+            config("imap.example.org", TlsMode::StartTls),
+            config("imaps.example.org", TlsMode::Implicit),
+        ];
+        let hosts = |configs: Vec<ServerConfig>| -> Vec<String> {
+            configs
+                .iter()
+                .map(|c| c.endpoint.host().to_string())
+                .collect()
+        };
+
+        // a cleartext server listed first is not used without opting in, and only last with it
+        assert_eq!(
+            hosts(candidates(configs.clone(), false)),
+            vec!["imap.example.org", "imaps.example.org"]
+        );
+        assert_eq!(
+            hosts(candidates(configs, true)),
+            vec!["imap.example.org", "imaps.example.org", "plain.example.org"]
+        );
+    }
+}
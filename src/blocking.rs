@@ -0,0 +1,683 @@
+//! A synchronous interface for programs that do not use `async` otherwise, such as command line
+//! tools, scripts and plugins of synchronous applications.
+//!
+//! [`Client`] and [`Session`] wrap their asynchronous counterparts, and each call blocks until
+//! the command has completed. Commands whose responses are streamed by the asynchronous API,
+//! such as `FETCH`, return all responses at once instead. Commands that are not wrapped here can
+//! still be used through [`Session::run`].
+//!
+//! A [`Session`] drives the asynchronous session on a thread of its own, and hands the commands
+//! to it, so it can be moved between threads and used from threads that belong to some other
+//! runtime, without running an executor inside of theirs. The thread ends when the session is
+//! dropped, or returned by [`Session::into_inner`].
+//!
+//! ```no_run
+//! use async_imap::blocking;
+//! use async_imap::ClientBuilder;
+//!
+//! # fn main() -> async_imap::error::Result<()> {
+//! let client = blocking::Client::connect(&ClientBuilder::new("imap.example.org", 993))?;
+//! let mut session = client.login("user", "password").map_err(|e| e.0)?;
+//! session.select("INBOX")?;
+//! for message in session.fetch("1:*", "ENVELOPE")? {
+//!     println!("{:?}", message.envelope());
+//! }
+//! session.logout()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Unsolicited responses are queued by the session as usual, and taken out with
+//! [`Session::drain_unsolicited`]. While a command runs, they are moved out of the queue as they
+//! arrive, since the caller can not take them out before the command has completed, so they
+//! should be drained now and then to keep them from piling up.
+//!
+//! This module requires the `blocking` feature.
+
+use std::collections::HashSet;
+use std::panic;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use async_std::task::block_on;
+use chrono::Offset;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, LocalBoxFuture};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::TryStreamExt;
+
+use crate::authenticator::Authenticator;
+use crate::builder::{ClientBuilder, MaybeTlsStream};
+use crate::error::{Error, Result};
+use crate::replay::{unsolicited_channel, ReplayBuffer, UnsolicitedReceiver};
+use crate::types::{
+    AppendUid, Capabilities, CommandResult, CopyUid, Fetch, Mailbox, Moved, Name, Namespaces,
+    Quota, QuotaRoot, Seq, Uid, UnsolicitedResponse,
+};
+
+/// A command for the thread of a [`Session`].
+type Job<T> = Box<dyn FnOnce(&mut crate::Session<T>) + Send>;
+
+/// A connection that has not been authenticated yet, see [`crate::Client`].
+#[derive(Debug)]
+pub struct Client<T: AsyncRead + AsyncWrite + Unpin> {
+    inner: crate::Client<T>,
+}
+
+/// An authenticated connection, see [`crate::Session`].
+#[derive(Debug)]
+pub struct Session<T: AsyncRead + AsyncWrite + Unpin> {
+    jobs: mpsc::Sender<Job<T>>,
+    /// The thread that owns the asynchronous session, and returns it once `jobs` is closed.
+    worker: Option<JoinHandle<crate::Session<T>>>,
+    /// The unsolicited responses of the asynchronous session, which are taken out here while it
+    /// runs a command on its thread.
+    unsolicited: UnsolicitedReceiver,
+}
+
+impl Client<MaybeTlsStream> {
+    /// Connects as configured by `builder`, see [`ClientBuilder::connect`].
+    pub fn connect(builder: &ClientBuilder) -> Result<Self> {
+        block_on(builder.connect()).map(Client::from)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> From<crate::Client<T>> for Client<T> {
+    fn from(inner: crate::Client<T>) -> Self {
+        Client { inner }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
+    /// Creates a client over `stream` and reads the server greeting, see
+    /// [`crate::Client::from_stream`].
+    pub fn from_stream(stream: T) -> Result<Self> {
+        block_on(crate::Client::from_stream(stream)).map(Client::from)
+    }
+
+    /// See [`crate::Client::capabilities`].
+    pub fn capabilities(&mut self) -> Result<CommandResult<Capabilities>> {
+        block_on(self.inner.capabilities())
+    }
+
+    /// See [`crate::Client::login`].
+    pub fn login<U: AsRef<str>, P: AsRef<str>>(
+        self,
+        username: U,
+        password: P,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        block_on(self.inner.login(username, password))
+            .map(Session::from)
+            .map_err(|(err, inner)| (err, Client { inner }))
+    }
+
+    /// See [`crate::Client::authenticate`].
+    pub fn authenticate<A: Authenticator + ?Sized, S: AsRef<str>>(
+        self,
+        auth_type: S,
+        authenticator: &A,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        block_on(self.inner.authenticate(auth_type, authenticator))
+            .map(Session::from)
+            .map_err(|(err, inner)| (err, Client { inner }))
+    }
+
+    /// The asynchronous client.
+    pub fn into_inner(self) -> crate::Client<T> {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> From<crate::Session<T>> for Session<T> {
+    /// Moves `inner` to a new thread, which runs the commands of the returned session.
+    fn from(mut inner: crate::Session<T>) -> Self {
+        // the session gets its receiver back in `into_inner`
+        let (_, closed) = unsolicited_channel(0, Arc::new(Mutex::new(ReplayBuffer::new(0))));
+        let unsolicited = std::mem::replace(&mut inner.unsolicited_responses, closed);
+        let (jobs, rx) = mpsc::channel::<Job<T>>();
+        let worker = thread::spawn(move || {
+            let mut session = inner;
+            for job in rx {
+                job(&mut session);
+            }
+            session
+        });
+        Session {
+            jobs,
+            worker: Some(worker),
+            unsolicited,
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Session<T> {
+    /// Runs a command of the asynchronous [`crate::Session`] to completion on the thread of the
+    /// session, for commands that are not wrapped by this type. `f` returns the boxed future of
+    /// the command, since it borrows the session.
+    ///
+    /// If `f` or its future panics, the session is lost, and the panic is passed on to the
+    /// caller. Later calls panic as well, while the wrapped commands fail with
+    /// [`Error::ConnectionLost`].
+    ///
+    /// ```no_run
+    /// use futures::FutureExt;
+    ///
+    /// # fn run(session: &mut async_imap::blocking::Session<async_imap::MaybeTlsStream>)
+    /// #     -> async_imap::error::Result<()> {
+    /// let sizes = session.run(|s| s.estimate_mailbox_size("INBOX").boxed_local())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn run<F, R>(&mut self, f: F) -> R
+    where
+        F: for<'s> FnOnce(&'s mut crate::Session<T>) -> LocalBoxFuture<'s, R> + Send + 'static,
+        R: Send + 'static,
+    {
+        match self.dispatch(f) {
+            Some(result) => result,
+            None => panic!("the thread of the session has ended"),
+        }
+    }
+
+    /// Like [`Session::run`], but fails with [`Error::ConnectionLost`] instead of panicking if
+    /// the thread of the session has ended before.
+    pub(crate) fn run_fallible<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: for<'s> FnOnce(&'s mut crate::Session<T>) -> LocalBoxFuture<'s, Result<R>>
+            + Send
+            + 'static,
+        R: Send + 'static,
+    {
+        self.dispatch(f).unwrap_or(Err(Error::ConnectionLost))
+    }
+
+    /// Runs `f` on the thread of the session. Returns `None` if the thread has ended before,
+    /// and passes on the panic if it ends now.
+    fn dispatch<F, R>(&mut self, f: F) -> Option<R>
+    where
+        F: for<'s> FnOnce(&'s mut crate::Session<T>) -> LocalBoxFuture<'s, R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job<T> = Box::new(move |session| {
+            let _ = reply.send(block_on(f(session)));
+        });
+        // if the thread is gone, the job and its reply sender are dropped right away
+        let _ = self.jobs.send(job);
+        // the session waits for room in the queue of unsolicited responses once it is full, so
+        // they are taken out while the command runs
+        match block_on(self.unsolicited.collect_while(result)) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                if let Some(Err(payload)) = self.worker.take().map(JoinHandle::join) {
+                    panic::resume_unwind(payload);
+                }
+                None
+            }
+        }
+    }
+
+    /// See [`crate::Session::select`].
+    pub fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<CommandResult<Mailbox>> {
+        let mailbox_name = mailbox_name.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.select(mailbox_name).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::examine`].
+    pub fn examine<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<CommandResult<Mailbox>> {
+        let mailbox_name = mailbox_name.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.examine(mailbox_name).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::fetch`].
+    pub fn fetch<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (sequence_set, query) = (
+            sequence_set.as_ref().to_string(),
+            query.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.fetch(sequence_set, query).await?.try_collect().await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::uid_fetch`].
+    pub fn uid_fetch<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (uid_set, query) = (uid_set.as_ref().to_string(), query.as_ref().to_string());
+        self.run_fallible(move |s| {
+            async move { s.uid_fetch(uid_set, query).await?.try_collect().await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::store`].
+    pub fn store<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (sequence_set, query) = (
+            sequence_set.as_ref().to_string(),
+            query.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.store(sequence_set, query).await?.try_collect().await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::uid_store`].
+    pub fn uid_store<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (uid_set, query) = (uid_set.as_ref().to_string(), query.as_ref().to_string());
+        self.run_fallible(move |s| {
+            async move { s.uid_store(uid_set, query).await?.try_collect().await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::copy`].
+    pub fn copy<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        mailbox_name: S2,
+    ) -> Result<CommandResult<Option<CopyUid>>> {
+        let (sequence_set, mailbox_name) = (
+            sequence_set.as_ref().to_string(),
+            mailbox_name.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.copy(sequence_set, mailbox_name).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::uid_copy`].
+    pub fn uid_copy<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        mailbox_name: S2,
+    ) -> Result<CommandResult<Option<CopyUid>>> {
+        let (uid_set, mailbox_name) = (
+            uid_set.as_ref().to_string(),
+            mailbox_name.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.uid_copy(uid_set, mailbox_name).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::mv`].
+    pub fn mv<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        mailbox_name: S2,
+    ) -> Result<CommandResult<Moved>> {
+        let (sequence_set, mailbox_name) = (
+            sequence_set.as_ref().to_string(),
+            mailbox_name.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.mv(sequence_set, mailbox_name).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::uid_mv`].
+    pub fn uid_mv<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        mailbox_name: S2,
+    ) -> Result<CommandResult<Moved>> {
+        let (uid_set, mailbox_name) = (
+            uid_set.as_ref().to_string(),
+            mailbox_name.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.uid_mv(uid_set, mailbox_name).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::expunge`].
+    pub fn expunge(&mut self) -> Result<Vec<Seq>> {
+        self.run_fallible(|s| async move { s.expunge().await?.try_collect().await }.boxed_local())
+    }
+
+    /// See [`crate::Session::uid_expunge`].
+    pub fn uid_expunge<S: AsRef<str>>(&mut self, uid_set: S) -> Result<Vec<Uid>> {
+        let uid_set = uid_set.as_ref().to_string();
+        self.run_fallible(move |s| {
+            async move { s.uid_expunge(uid_set).await?.try_collect().await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::search`].
+    pub fn search<S: AsRef<str>>(&mut self, query: S) -> Result<CommandResult<HashSet<Seq>>> {
+        let query = query.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.search(query).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::uid_search`].
+    pub fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<CommandResult<HashSet<Uid>>> {
+        let query = query.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.uid_search(query).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::list`].
+    pub fn list(
+        &mut self,
+        reference_name: Option<&str>,
+        mailbox_pattern: Option<&str>,
+    ) -> Result<Vec<Name>> {
+        let reference_name = reference_name.map(str::to_string);
+        let mailbox_pattern = mailbox_pattern.map(str::to_string);
+        self.run_fallible(move |s| {
+            async move {
+                s.list(reference_name.as_deref(), mailbox_pattern.as_deref())
+                    .await?
+                    .try_collect()
+                    .await
+            }
+            .boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::lsub`].
+    pub fn lsub(
+        &mut self,
+        reference_name: Option<&str>,
+        mailbox_pattern: Option<&str>,
+    ) -> Result<Vec<Name>> {
+        let reference_name = reference_name.map(str::to_string);
+        let mailbox_pattern = mailbox_pattern.map(str::to_string);
+        self.run_fallible(move |s| {
+            async move {
+                s.lsub(reference_name.as_deref(), mailbox_pattern.as_deref())
+                    .await?
+                    .try_collect()
+                    .await
+            }
+            .boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::status`].
+    pub fn status<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        mailbox_name: S1,
+        data_items: S2,
+    ) -> Result<CommandResult<Mailbox>> {
+        let (mailbox_name, data_items) = (
+            mailbox_name.as_ref().to_string(),
+            data_items.as_ref().to_string(),
+        );
+        self.run_fallible(move |s| {
+            async move { s.status(mailbox_name, data_items).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::create`].
+    pub fn create<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<CommandResult<()>> {
+        let mailbox_name = mailbox_name.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.create(mailbox_name).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::delete`].
+    pub fn delete<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<CommandResult<()>> {
+        let mailbox_name = mailbox_name.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.delete(mailbox_name).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::rename`].
+    pub fn rename<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        from: S1,
+        to: S2,
+    ) -> Result<CommandResult<()>> {
+        let (from, to) = (from.as_ref().to_string(), to.as_ref().to_string());
+        self.run_fallible(move |s| async move { s.rename(from, to).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::subscribe`].
+    pub fn subscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<CommandResult<()>> {
+        let mailbox = mailbox.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.subscribe(mailbox).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::unsubscribe`].
+    pub fn unsubscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<CommandResult<()>> {
+        let mailbox = mailbox.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.unsubscribe(mailbox).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::append`].
+    pub fn append<S: AsRef<str>, B: AsRef<[u8]>>(
+        &mut self,
+        mailbox: S,
+        content: B,
+    ) -> Result<CommandResult<Option<AppendUid>>> {
+        let (mailbox, content) = (mailbox.as_ref().to_string(), content.as_ref().to_vec());
+        self.run_fallible(move |s| async move { s.append(mailbox, content).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::append_with_date`].
+    pub fn append_with_date<S: AsRef<str>, B: AsRef<[u8]>, Tz: chrono::TimeZone>(
+        &mut self,
+        mailbox: S,
+        internal_date: &chrono::DateTime<Tz>,
+        content: B,
+    ) -> Result<CommandResult<Option<AppendUid>>> {
+        let (mailbox, content) = (mailbox.as_ref().to_string(), content.as_ref().to_vec());
+        // the same time in the same zone, but of a type that can be sent to the thread
+        let internal_date = internal_date.with_timezone(&internal_date.offset().fix());
+        self.run_fallible(move |s| {
+            async move { s.append_with_date(mailbox, &internal_date, content).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::namespace`].
+    pub fn namespace(&mut self) -> Result<CommandResult<Namespaces>> {
+        self.run_fallible(|s| async move { s.namespace().await }.boxed_local())
+    }
+
+    /// See [`crate::Session::get_quota`].
+    pub fn get_quota<S: AsRef<str>>(&mut self, root: S) -> Result<CommandResult<Quota>> {
+        let root = root.as_ref().to_string();
+        self.run_fallible(move |s| async move { s.get_quota(root).await }.boxed_local())
+    }
+
+    /// See [`crate::Session::get_quota_root`].
+    pub fn get_quota_root<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<QuotaRoot>> {
+        let mailbox_name = mailbox_name.as_ref().to_string();
+        self.run_fallible(move |s| {
+            async move { s.get_quota_root(mailbox_name).await }.boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::set_quota`].
+    pub fn set_quota<S: AsRef<str>>(
+        &mut self,
+        root: S,
+        limits: &[(&str, u64)],
+    ) -> Result<CommandResult<Quota>> {
+        let root = root.as_ref().to_string();
+        let limits: Vec<(String, u64)> = limits
+            .iter()
+            .map(|(name, limit)| (name.to_string(), *limit))
+            .collect();
+        self.run_fallible(move |s| {
+            async move {
+                let limits: Vec<(&str, u64)> = limits
+                    .iter()
+                    .map(|(name, limit)| (name.as_str(), *limit))
+                    .collect();
+                s.set_quota(root, &limits).await
+            }
+            .boxed_local()
+        })
+    }
+
+    /// See [`crate::Session::capabilities`].
+    pub fn capabilities(&mut self) -> Result<CommandResult<Capabilities>> {
+        self.run_fallible(|s| async move { s.capabilities().await }.boxed_local())
+    }
+
+    /// See [`crate::Session::noop`].
+    pub fn noop(&mut self) -> Result<CommandResult<()>> {
+        self.run_fallible(|s| async move { s.noop().await }.boxed_local())
+    }
+
+    /// See [`crate::Session::close`].
+    pub fn close(&mut self) -> Result<CommandResult<()>> {
+        self.run_fallible(|s| async move { s.close().await }.boxed_local())
+    }
+
+    /// See [`crate::Session::logout`].
+    pub fn logout(&mut self) -> Result<()> {
+        self.run_fallible(|s| async move { s.logout().await }.boxed_local())
+    }
+
+    /// See [`crate::Session::drain_unsolicited`].
+    pub fn drain_unsolicited(&mut self) -> Vec<UnsolicitedResponse> {
+        let mut responses = Vec::new();
+        while let Ok(Some(response)) = self.unsolicited.try_next() {
+            responses.push(response);
+        }
+        responses
+    }
+
+    /// Ends the thread of the session, and returns the asynchronous session.
+    pub fn into_inner(self) -> crate::Session<T> {
+        let Session {
+            jobs,
+            worker,
+            unsolicited,
+        } = self;
+        // the thread returns the session once there are no more jobs
+        drop(jobs);
+        match worker.map(JoinHandle::join) {
+            Some(Ok(mut session)) => {
+                session.unsolicited_responses = unsolicited;
+                session
+            }
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => panic!("the thread of the session has ended"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_stream::MockStream;
+
+    fn login(response: &[u8]) -> Session<MockStream> {
+        let mut data = b"A0001 OK Logged in\r\n".to_vec();
+        data.extend_from_slice(response);
+        let client = Client::from(crate::Client::new(MockStream::new(data)));
+        client.login("user", "pass").map_err(|e| e.0).unwrap()
+    }
+
+    #[test]
+    fn login_and_fetch() {
+        let response = b"A0001 OK Logged in\r\n\
+            * 1 FETCH (UID 7)\r\n\
+            * 2 FETCH (UID 9)\r\n\
+            A0002 OK FETCH completed\r\n"
+            .to_vec();
+        let client = Client::from(crate::Client::new(MockStream::new(response)));
+        let mut session = client.login("user", "pass").map_err(|e| e.0).unwrap();
+
+        let uids: Vec<_> = session
+            .fetch("1:2", "UID")
+            .unwrap()
+            .iter()
+            .map(|f| f.uid)
+            .collect();
+        assert_eq!(uids, vec![Some(7), Some(9)]);
+        assert_eq!(
+            session.into_inner().stream.inner.written_buf,
+            b"A0001 LOGIN \"user\" \"pass\"\r\nA0002 FETCH 1:2 UID\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn worker_thread() {
+        let mut session = login(
+            b"* 3 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n\
+            * QUOTA \"\" (STORAGE 10 512)\r\n\
+            A0003 OK Getquota completed\r\n",
+        );
+        let caller = thread::current().id();
+
+        // the commands run on the thread of the session, which may also be used from elsewhere
+        let worker = session.run(|_| async { thread::current().id() }.boxed_local());
+        assert_ne!(worker, caller);
+        let mut session = thread::spawn(move || {
+            session.noop().unwrap();
+            session
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(
+            session.drain_unsolicited(),
+            vec![UnsolicitedResponse::Exists(3)]
+        );
+        let quota = session.get_quota("").unwrap();
+        assert_eq!(quota.resource("STORAGE").unwrap().usage, 10);
+        assert_eq!(
+            session.into_inner().stream.inner.written_buf,
+            b"A0001 LOGIN \"user\" \"pass\"\r\nA0002 NOOP\r\nA0003 GETQUOTA \"\"\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn unsolicited_overflow() {
+        let mut response = b"A0001 OK Logged in\r\n".to_vec();
+        for exists in 1..=20 {
+            response.extend_from_slice(format!("* {} EXISTS\r\n", exists).as_bytes());
+        }
+        response.extend_from_slice(b"A0002 OK NOOP completed\r\n");
+        let mut client = crate::Client::new(MockStream::new(response));
+        client.unsolicited_capacity = 2;
+        let mut session = Client::from(client)
+            .login("user", "pass")
+            .map_err(|e| e.0)
+            .unwrap();
+
+        // more responses than the queue has room for do not hold up the command
+        session.noop().unwrap();
+        let responses = session.drain_unsolicited();
+        assert_eq!(responses.len(), 20);
+        assert_eq!(responses[19], UnsolicitedResponse::Exists(20));
+
+        assert!(session.into_inner().unsolicited_responses.is_empty());
+    }
+
+    #[test]
+    fn worker_panic() {
+        let mut session = login(b"");
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            session.run(|_| async { panic!("boom") }.boxed_local())
+        }));
+        let payload = res.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+
+        // the session is lost
+        match session.noop() {
+            Err(Error::ConnectionLost) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -1,22 +1,46 @@
-use std::collections::HashSet;
-use std::fmt;
+use std::collections::{HashMap, HashSet};
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::str;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "runtime")]
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "tls-native")]
 use async_native_tls::{TlsConnector, TlsStream};
-use async_std::io::{self, Read, Write};
-use async_std::net::{TcpStream, ToSocketAddrs};
-use async_std::prelude::*;
-use async_std::sync;
-use imap_proto::{RequestId, Response};
-
-use super::authenticator::Authenticator;
-use super::error::{Error, ParseError, Result, ValidateError};
+#[cfg(feature = "runtime")]
+use async_std::net::TcpStream;
+#[cfg(any(feature = "tls-native", feature = "tls-rustls"))]
+use async_std::net::ToSocketAddrs;
+use futures::channel::mpsc;
+use futures::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures::prelude::*;
+use futures::stream::FusedStream;
+use imap_proto::{MailboxDatum, RequestId, Response};
+
+use super::authenticator::{Authenticator, ChannelBinding, ChannelBindingType};
+use super::error::{AuthError, Error, ParseError, Result, ValidateError};
 use super::parse::*;
 use super::types::*;
 use crate::extensions;
 use crate::imap_stream::ImapStream;
+use crate::passthrough::Passthrough;
+use crate::protocol::LiteralLimit;
+#[cfg(feature = "tls-native")]
+use crate::proxy::{http_connect, socks5_connect, HttpProxyAuth, Socks5Auth};
+use crate::quirks::Quirks;
+use crate::replay::{
+    unsolicited_channel, ReplayBuffer, UnsolicitedReceiver, UnsolicitedSender,
+    DEFAULT_REPLAY_CAPACITY, DEFAULT_UNSOLICITED_CAPACITY,
+};
+use crate::retry::{self, RetryPolicy};
+use crate::secret::Secret;
+use crate::sync_state::SyncStateStore;
+use crate::uid_map::UidMap;
+
+/// How long [`Session::shutdown`] waits for the server in total.
+#[cfg(feature = "runtime")]
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 macro_rules! quote {
     ($x:expr) => {
@@ -24,6 +48,21 @@ macro_rules! quote {
     };
 }
 
+/// Evaluates `$run`, an attempt at running `$command` on `$session`, until it succeeds or the
+/// retry policy of the session gives up, see `Session::should_retry`.
+macro_rules! retrying {
+    ($session:expr, $command:expr, $run:expr) => {{
+        let mut attempt = 1;
+        loop {
+            let res = $run;
+            match res {
+                Err(ref err) if $session.should_retry($command, err, attempt).await => attempt += 1,
+                res => break res,
+            }
+        }
+    }};
+}
+
 /// An authenticated IMAP session providing the usual IMAP commands. This type is what you get from
 /// a succesful login attempt.
 ///
@@ -31,50 +70,194 @@ macro_rules! quote {
 /// a selected mailbox whose status has changed. See the note on [unilateral server responses
 /// in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7). Any such messages are parsed out
 /// and sent on `Session::unsolicited_responses`.
+///
+/// The [connection states](https://tools.ietf.org/html/rfc3501#section-3) are reflected in the
+/// types: a [`Client`] is not authenticated yet, and only offers what is allowed before logging
+/// in, such as `STARTTLS`. Logging in consumes it and returns a `Session`, so the connection can
+/// not be upgraded after credentials were sent over it:
+///
+/// ```compile_fail
+/// # async fn f(session: async_imap::Session<async_std::net::TcpStream>) {
+/// let tls = async_native_tls::TlsConnector::new();
+/// let client = session.starttls("imap.example.org", tls).await;
+/// # }
+/// ```
+///
+/// Likewise, [`Session::idle`] consumes the session until [`Handle::done`] returns it, so no
+/// command can interrupt an `IDLE`. Commands that are only valid before logging in, such as
+/// `STARTTLS`, `LOGIN` and `AUTHENTICATE`, are also refused with [`Error::WrongState`] when they
+/// are sent with [`Session::run_command`], [`Session::run_command_and_check_ok`] or
+/// [`Session::enqueue`].
+///
+/// The selected state is not reflected in the types: a `Session` may or may not have a mailbox
+/// selected, so commands that need one fail with a `BAD` from the server if none is.
+///
+/// [`Handle::done`]: crate::extensions::idle::Handle::done
 // Both `Client` and `Session` deref to [`Connection`](struct.Connection.html), the underlying
 // primitives type.
 #[derive(Debug)]
-pub struct Session<T: Read + Write + Unpin + fmt::Debug> {
+pub struct Session<T: AsyncRead + AsyncWrite + Unpin> {
     pub(crate) conn: Connection<T>,
-    pub(crate) unsolicited_responses_tx: sync::Sender<UnsolicitedResponse>,
+    pub(crate) unsolicited_responses_tx: UnsolicitedSender,
+    /// The most recent unsolicited responses, see `Session::replay_unsolicited`.
+    replay: Arc<Mutex<ReplayBuffer>>,
+    /// Commands queued with `Session::enqueue`, in submission order.
+    queued: Vec<Request>,
+    /// The completion that ended the stream returned by `Session::responses`.
+    done: Option<ResponseData>,
+    quirks: Quirks,
+    /// Whether the server supports `SEARCHRES`, once `Session::fetch_matching` has asked.
+    searchres: Option<bool>,
+    /// Whether the server supports `UIDPLUS`, once `Session::delete_messages` has asked.
+    uidplus: Option<bool>,
+    /// Whether the server supports `ESEARCH`, once `Session::search_return` has asked.
+    esearch: Option<bool>,
+    /// Whether the server supports `ESORT`, once `Session::sort_return` has asked.
+    esort: Option<bool>,
+    /// Whether `Session::archive` found that the server does not support `MOVE`.
+    move_rejected: bool,
+    /// The name of the `\Archive` mailbox, once `Session::archive` has looked for it.
+    archive_mailbox: Option<String>,
+    retry_policy: Option<RetryPolicy>,
 
     /// Server responses that are not related to the current command. See also the note on
     /// [unilateral server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
-    pub unsolicited_responses: sync::Receiver<UnsolicitedResponse>,
+    ///
+    /// The channel is bounded, and the session waits for room in it, so it has to be drained
+    /// while commands are running, see [`UnsolicitedReceiver`].
+    pub unsolicited_responses: UnsolicitedReceiver,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Unpin for Session<T> {}
+impl<T: AsyncRead + AsyncWrite + Unpin> Unpin for Client<T> {}
+impl<T: AsyncRead + AsyncWrite + Unpin> Unpin for Connection<T> {}
+
+/// A [`Session`] that is kept around for later use, and that is logged out and closed
+/// automatically once it has been unused for [`Connection::idle_logout`]. See
+/// [`Session::park`].
+#[cfg(feature = "runtime")]
+#[derive(Debug)]
+pub struct ParkedSession<T: AsyncRead + AsyncWrite + Unpin> {
+    unpark: futures::channel::oneshot::Sender<()>,
+    task: async_std::task::JoinHandle<Option<Session<T>>>,
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Session<T> {}
-impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Client<T> {}
-impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Connection<T> {}
+#[cfg(feature = "runtime")]
+impl<T: AsyncRead + AsyncWrite + Unpin> ParkedSession<T> {
+    /// Takes the session back for use. `None` if it has already been logged out, in which case
+    /// a new one has to be established.
+    pub async fn unpark(self) -> Option<Session<T>> {
+        // fails if the session is being logged out, which the task then finishes
+        let _ = self.unpark.send(());
+        self.task.await
+    }
+}
 
 /// An (unauthenticated) handle to talk to an IMAP server. This is what you get when first
 /// connecting. A succesfull call to [`Client::login`] or [`Client::authenticate`] will return a
-/// [`Session`] instance that provides the usual IMAP methods.
+/// [`Session`] instance that provides the usual IMAP methods, which can not be used before:
+///
+/// ```compile_fail
+/// # async fn f(mut client: async_imap::Client<async_std::net::TcpStream>) {
+/// client.select("INBOX").await;
+/// # }
+/// ```
 // Both `Client` and `Session` deref to [`Connection`](struct.Connection.html), the underlying
 // primitives type.
 #[derive(Debug)]
-pub struct Client<T: Read + Write + Unpin + fmt::Debug> {
-    conn: Connection<T>,
+pub struct Client<T: AsyncRead + AsyncWrite + Unpin> {
+    pub(crate) conn: Connection<T>,
+    /// Whether the last `CAPABILITY` response included `SASL-IR`.
+    sasl_ir: bool,
+    /// The capabilities announced in the greeting, if any. They are only used if
+    /// [`Connection::capability_trust`] allows it.
+    greeting_capabilities: Option<Capabilities>,
 }
 
 /// The underlying primitives type. Both `Client`(unauthenticated) and `Session`(after succesful
 /// login) use a `Connection` internally for the TCP stream primitives.
 #[derive(Debug)]
 #[doc(hidden)]
-pub struct Connection<T: Read + Write + Unpin + fmt::Debug> {
+pub struct Connection<T: AsyncRead + AsyncWrite + Unpin> {
     pub(crate) stream: ImapStream<T>,
 
     /// Enable debug mode for this connection so that all client-server interactions are printed to
     /// `STDERR`.
     pub debug: bool,
 
-    /// Manages the request ids.
-    pub(crate) request_ids: IdGenerator,
+    /// The maximum length of a command line, in bytes. Commands that would exceed it fail with
+    /// [`Error::CommandTooLong`] instead of being sent, since servers typically reject or
+    /// truncate overly long lines ([RFC 7162](https://tools.ietf.org/html/rfc7162#section-4)
+    /// recommends limiting them to 8192 octets). Sequence sets are sent in minimal form to stay
+    /// below the limit where possible. `None` disables the check.
+    pub max_command_len: Option<usize>,
+
+    /// The server's answer to the last `ID` command.
+    pub(crate) server_id: Option<ServerId>,
+
+    /// How long a session may go without sending a command before [`Session::park`] logs it
+    /// out automatically, see also [`Session::logout_if_idle`]. Some providers limit the number of concurrent
+    /// connections per account (Gmail allows 15), so sessions that are kept around for later
+    /// use should not hold on to a connection forever. `None` disables the timeout.
+    #[cfg(feature = "runtime")]
+    pub idle_logout: Option<Duration>,
+
+    /// How [`Handle::wait_keepalive`](extensions::idle::Handle::wait_keepalive) keeps the
+    /// connection alive while idling.
+    #[cfg(feature = "runtime")]
+    pub idle_keepalive: extensions::idle::Keepalive,
+
+    /// How many responses [`Session::unsolicited_responses`] has room for, 100 by default. The
+    /// channel is created when logging in, so changing this on a [`Session`] has no effect.
+    pub unsolicited_capacity: usize,
+
+    /// Whether [`Client::capabilities`] may answer with the capabilities announced in the
+    /// greeting, which then also decide whether `SASL-IR` is used when authenticating. By
+    /// default they are not trusted, see [`CapabilityTrust`]. Has no effect after logging in.
+    pub capability_trust: CapabilityTrust,
+}
+
+/// The settings of a [`Connection`] that are carried over when its stream is replaced, e.g. by
+/// the TLS stream after `STARTTLS`.
+#[derive(Debug, Clone)]
+struct Settings {
+    debug: bool,
+    max_command_len: Option<usize>,
+    #[cfg(feature = "runtime")]
+    idle_logout: Option<Duration>,
+    #[cfg(feature = "runtime")]
+    idle_keepalive: extensions::idle::Keepalive,
+    unsolicited_capacity: usize,
+    capability_trust: CapabilityTrust,
+    buffer_sizes: (usize, usize),
+    max_consecutive: Option<usize>,
+}
+
+/// Whether the capabilities that a server announces in its greeting are used before logging in,
+/// see [`Connection::capability_trust`].
+///
+/// Some servers announce different capabilities in the greeting than in response to
+/// `CAPABILITY`, e.g. authentication mechanisms that are only offered after `STARTTLS`, or
+/// `SASL-IR` without supporting it. Trusting the greeting saves a round trip, but picking a
+/// mechanism from a stale list can make authentication fail, or fall back to a weaker one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityTrust {
+    /// Ignore the capabilities in the greeting, and send `CAPABILITY` whenever they are needed.
+    /// This is the default.
+    Refetch,
+    /// Use the capabilities in the greeting, if there were any, instead of sending `CAPABILITY`.
+    TrustGreeting,
+}
+
+impl Default for CapabilityTrust {
+    fn default() -> Self {
+        CapabilityTrust::Refetch
+    }
 }
 
 // `Deref` instances are so we can make use of the same underlying primitives in `Client` and
 // `Session`
-impl<T: Read + Write + Unpin + fmt::Debug> Deref for Client<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> Deref for Client<T> {
     type Target = Connection<T>;
 
     fn deref(&self) -> &Connection<T> {
@@ -82,13 +265,13 @@ impl<T: Read + Write + Unpin + fmt::Debug> Deref for Client<T> {
     }
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> DerefMut for Client<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> DerefMut for Client<T> {
     fn deref_mut(&mut self) -> &mut Connection<T> {
         &mut self.conn
     }
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> Deref for Session<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> Deref for Session<T> {
     type Target = Connection<T>;
 
     fn deref(&self) -> &Connection<T> {
@@ -96,7 +279,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> Deref for Session<T> {
     }
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> DerefMut for Session<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> DerefMut for Session<T> {
     fn deref_mut(&mut self) -> &mut Connection<T> {
         &mut self.conn
     }
@@ -122,6 +305,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> DerefMut for Session<T> {
 /// # Ok(())
 /// # }) }
 /// ```
+#[cfg(feature = "tls-native")]
 pub async fn connect<A: ToSocketAddrs, S: AsRef<str>>(
     addr: A,
     domain: S,
@@ -130,35 +314,160 @@ pub async fn connect<A: ToSocketAddrs, S: AsRef<str>>(
     let stream = TcpStream::connect(addr).await?;
     let ssl_stream = ssl_connector.connect(domain.as_ref(), stream).await?;
 
-    let mut client = Client::new(ssl_stream);
-    let _greeting = match client.read_response().await {
-        Some(greeting) => greeting,
-        None => {
-            return Err(Error::Bad(
-                "could not read server Greeting after connect".into(),
-            ));
-        }
-    };
+    Client::new(ssl_stream).read_greeting().await
+}
+
+/// Connect to a server using a TLS-encrypted connection provided by
+/// [rustls](https://github.com/ctz/rustls), without depending on OpenSSL.
+///
+/// This is the equivalent of [`connect`] for the `tls-rustls` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// use std::sync::Arc;
+///
+/// let config = async_rustls::rustls::ClientConfig::new();
+/// // add the trusted root certificates to `config.root_store` here
+/// let tls = async_rustls::TlsConnector::from(Arc::new(config));
+/// let client =
+///     async_imap::connect_rustls(("imap.example.org", 993), "imap.example.org", tls).await?;
+///
+/// # Ok(())
+/// # }) }
+/// ```
+#[cfg(feature = "tls-rustls")]
+pub async fn connect_rustls<A: ToSocketAddrs, S: AsRef<str>>(
+    addr: A,
+    domain: S,
+    tls_connector: async_rustls::TlsConnector,
+) -> Result<Client<async_rustls::client::TlsStream<TcpStream>>> {
+    let stream = TcpStream::connect(addr).await?;
+    let tls_stream = tls_connector
+        .connect(dns_name(domain.as_ref())?, stream)
+        .await?;
+
+    Client::new(tls_stream).read_greeting().await
+}
+
+/// Connect to a server using a TLS-encrypted connection that is tunneled through a [SOCKS5
+/// proxy](https://tools.ietf.org/html/rfc1928), such as the one provided by Tor.
+///
+/// The host name in `imap_addr` is resolved by the proxy rather than locally, and is also the
+/// domain the server certificate is validated against. See the [`proxy`](crate::proxy) module to
+/// tunnel cleartext connections that are upgraded with [`Client::starttls`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+///
+/// let tls = async_native_tls::TlsConnector::new();
+/// let client = async_imap::connect_via_socks5(
+///     ("127.0.0.1", 9050),
+///     None,
+///     ("imap.example.org", 993),
+///     tls,
+/// )
+/// .await?;
+///
+/// # Ok(())
+/// # }) }
+/// ```
+#[cfg(feature = "tls-native")]
+pub async fn connect_via_socks5<A: ToSocketAddrs>(
+    proxy_addr: A,
+    auth: Option<&Socks5Auth>,
+    imap_addr: (&str, u16),
+    ssl_connector: TlsConnector,
+) -> Result<Client<TlsStream<TcpStream>>> {
+    let (host, port) = imap_addr;
+    let stream = socks5_connect(proxy_addr, auth, host, port).await?;
+    let ssl_stream = ssl_connector.connect(host, stream).await?;
+
+    Client::new(ssl_stream).read_greeting().await
+}
+
+/// Connect to a server using a TLS-encrypted connection that is tunneled through an HTTP proxy
+/// using the `CONNECT` method.
+///
+/// The host name in `imap_addr` is resolved by the proxy, and is also the domain the server
+/// certificate is validated against.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// use async_imap::proxy::HttpProxyAuth;
+///
+/// let auth = HttpProxyAuth {
+///     username: "me".into(),
+///     password: "secret".into(),
+/// };
+/// let tls = async_native_tls::TlsConnector::new();
+/// let client = async_imap::connect_via_http_proxy(
+///     ("proxy.example.org", 3128),
+///     Some(&auth),
+///     ("imap.example.org", 993),
+///     tls,
+/// )
+/// .await?;
+///
+/// # Ok(())
+/// # }) }
+/// ```
+#[cfg(feature = "tls-native")]
+pub async fn connect_via_http_proxy<A: ToSocketAddrs>(
+    proxy_addr: A,
+    auth: Option<&HttpProxyAuth>,
+    imap_addr: (&str, u16),
+    ssl_connector: TlsConnector,
+) -> Result<Client<TlsStream<TcpStream>>> {
+    let (host, port) = imap_addr;
+    let stream = http_connect(proxy_addr, auth, host, port).await?;
+    let ssl_stream = ssl_connector.connect(host, stream).await?;
+
+    Client::new(ssl_stream).read_greeting().await
+}
 
-    Ok(client)
+/// Converts `domain` into the name that the server certificate is validated against.
+#[cfg(feature = "tls-rustls")]
+pub(crate) fn dns_name(domain: &str) -> Result<webpki::DNSNameRef<'_>> {
+    webpki::DNSNameRef::try_from_ascii_str(domain).map_err(|_| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid DNS name: {:?}", domain),
+        ))
+    })
 }
 
+#[cfg(feature = "runtime")]
 impl Client<TcpStream> {
     /// This will upgrade an IMAP client from using a regular TCP connection to use TLS.
     ///
     /// The domain parameter is required to perform hostname verification.
+    #[cfg(feature = "tls-native")]
     pub async fn secure<S: AsRef<str>>(
-        mut self,
+        self,
         domain: S,
         ssl_connector: TlsConnector,
     ) -> Result<Client<TlsStream<TcpStream>>> {
-        self.run_command_and_check_ok("STARTTLS", None).await?;
-        let ssl_stream = ssl_connector
-            .connect(domain.as_ref(), self.conn.stream.into_inner())
-            .await?;
+        self.starttls(domain, ssl_connector).await
+    }
 
-        let client = Client::new(ssl_stream);
-        Ok(client)
+    /// Equivalent to [`Client::secure`], but upgrades the connection using
+    /// [rustls](https://github.com/ctz/rustls).
+    #[cfg(feature = "tls-rustls")]
+    pub async fn secure_rustls<S: AsRef<str>>(
+        self,
+        domain: S,
+        tls_connector: async_rustls::TlsConnector,
+    ) -> Result<Client<async_rustls::client::TlsStream<TcpStream>>> {
+        self.starttls_rustls(domain, tls_connector).await
     }
 }
 
@@ -178,14 +487,137 @@ macro_rules! ok_or_unauth_client_err {
     };
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> Client<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> Client<T> {
+    /// Reads the server greeting, which is the first thing sent after connecting.
+    pub(crate) async fn read_greeting(mut self) -> Result<Self> {
+        let greeting = match self.read_response().await {
+            Some(greeting) => greeting,
+            None => {
+                return Err(Error::Bad(
+                    "could not read server Greeting after connect".into(),
+                ))
+            }
+        };
+        if let Ok(greeting) = greeting {
+            if let Response::Data {
+                code: None,
+                information,
+                ..
+            } = greeting.parsed()
+            {
+                self.greeting_capabilities = Capabilities::from_response_code(*information);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Upgrades the connection to TLS using the [`STARTTLS`
+    /// command](https://tools.ietf.org/html/rfc3501#section-6.2.1), e.g. after connecting to
+    /// port 143 in cleartext.
+    ///
+    /// The domain parameter is required to perform hostname verification. The upgrade is refused
+    /// if the server sent anything after its response to `STARTTLS`, since such data would have
+    /// been injected before the connection was secured.
+    ///
+    /// Note that the capabilities of the server must be requested again after the upgrade.
+    #[cfg(feature = "tls-native")]
+    pub async fn starttls<S: AsRef<str>>(
+        self,
+        domain: S,
+        ssl_connector: TlsConnector,
+    ) -> Result<Client<TlsStream<T>>> {
+        let settings = self.settings();
+        let stream = self.prepare_starttls().await?;
+        let ssl_stream = ssl_connector.connect(domain.as_ref(), stream).await?;
+        Ok(Client::with_settings(ssl_stream, settings))
+    }
+
+    /// Equivalent to [`Client::starttls`], but upgrades the connection using
+    /// [rustls](https://github.com/ctz/rustls).
+    #[cfg(feature = "tls-rustls")]
+    pub async fn starttls_rustls<S: AsRef<str>>(
+        self,
+        domain: S,
+        tls_connector: async_rustls::TlsConnector,
+    ) -> Result<Client<async_rustls::client::TlsStream<T>>> {
+        let settings = self.settings();
+        let stream = self.prepare_starttls().await?;
+        let tls_stream = tls_connector
+            .connect(dns_name(domain.as_ref())?, stream)
+            .await?;
+        Ok(Client::with_settings(tls_stream, settings))
+    }
+
+    /// Sends `STARTTLS` and returns the underlying stream once the server is ready for the TLS
+    /// handshake.
+    pub(crate) async fn prepare_starttls(mut self) -> Result<T> {
+        self.run_command_and_check_ok("STARTTLS", None).await?;
+        if self.conn.stream.protocol.has_buffered_data() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected data after STARTTLS response",
+            )));
+        }
+
+        Ok(self.conn.stream.into_inner())
+    }
+
+    /// Turns the connection into a [`Passthrough`], which forwards raw commands and responses,
+    /// e.g. to proxy a client that handles authentication itself.
+    pub fn into_passthrough(self) -> Passthrough<T> {
+        Passthrough::from_stream(self.conn.stream)
+    }
+
+    /// Logs out without having logged in, and closes the connection, see [`Session::shutdown`].
+    #[cfg(feature = "runtime")]
+    pub async fn shutdown(self) -> ConnectionStats {
+        self.conn.shutdown().await
+    }
+
+    /// The [`CAPABILITY` command](https://tools.ietf.org/html/rfc3501#section-6.1.1) requests a
+    /// listing of capabilities that the server supports, e.g. to find out which authentication
+    /// mechanisms can be used before logging in. See [`Session::capabilities`].
+    ///
+    /// If the server announced its capabilities in the greeting, as many do, and
+    /// [`Connection::capability_trust`] is set to [`CapabilityTrust::TrustGreeting`], those are
+    /// returned without a round trip. The result then has no completion.
+    pub async fn capabilities(&mut self) -> Result<CommandResult<Capabilities>> {
+        if let Some(capabilities) = self.trusted_greeting_capabilities() {
+            let capabilities = capabilities.clone();
+            self.sasl_ir = capabilities.has_str("SASL-IR");
+            return Ok(CommandResult::new(capabilities, None));
+        }
+        // there is no session to deliver unsolicited responses to yet, so they are dropped
+        let (tx, _) = mpsc::channel(1);
+        let id = self.run_command("CAPABILITY").await?;
+        let capabilities = parse_capabilities(&mut self.conn.stream, tx.into(), id).await?;
+        self.sasl_ir = capabilities.has_str("SASL-IR");
+        Ok(capabilities)
+    }
+
+    /// The capabilities announced in the greeting, if there were any and they may be used.
+    fn trusted_greeting_capabilities(&self) -> Option<&Capabilities> {
+        match self.capability_trust {
+            CapabilityTrust::Refetch => None,
+            CapabilityTrust::TrustGreeting => self.greeting_capabilities.as_ref(),
+        }
+    }
+
+    /// Sends an [`ID` command](https://tools.ietf.org/html/rfc2971) before logging in, which
+    /// some providers (e.g. NetEase) require. See [`Session::id`]. The answer remains available
+    /// as [`Session::server_id`] after logging in.
+    pub async fn id(&mut self, fields: &[(&str, &str)]) -> Result<CommandResult<ServerId>> {
+        self.conn.id(fields, None).await
+    }
+
     /// Creates a new client over the given stream.
     ///
     /// For an example of how to use this method to provide a pure-Rust TLS integration, see the
     /// rustls.rs in the examples/ directory.
     ///
     /// This method primarily exists for writing tests that mock the underlying transport, but can
-    /// also be used to support IMAP over custom tunnels.
+    /// also be used to support IMAP over custom tunnels. Note that it does not read the server
+    /// greeting; use [`Client::from_stream`] for connections that have just been opened.
     pub fn new(stream: T) -> Client<T> {
         let stream = ImapStream::new(stream);
 
@@ -193,9 +625,48 @@ impl<T: Read + Write + Unpin + fmt::Debug> Client<T> {
             conn: Connection {
                 stream,
                 debug: false,
-                request_ids: IdGenerator::new(),
+                max_command_len: None,
+                server_id: None,
+                #[cfg(feature = "runtime")]
+                idle_logout: None,
+                #[cfg(feature = "runtime")]
+                idle_keepalive: Default::default(),
+                unsolicited_capacity: DEFAULT_UNSOLICITED_CAPACITY,
+                capability_trust: CapabilityTrust::default(),
             },
+            sasl_ir: false,
+            greeting_capabilities: None,
+        }
+    }
+
+    /// Creates a new client over `stream` with the settings of a previous connection.
+    fn with_settings(stream: T, settings: Settings) -> Client<T> {
+        let mut client = Client::new(stream);
+        let (initial_capacity, max_capacity) = settings.buffer_sizes;
+        client
+            .stream
+            .protocol
+            .set_buffer_sizes(initial_capacity, max_capacity);
+        client.set_max_consecutive_responses(settings.max_consecutive);
+        client.debug = settings.debug;
+        client.max_command_len = settings.max_command_len;
+        client.unsolicited_capacity = settings.unsolicited_capacity;
+        #[cfg(feature = "runtime")]
+        {
+            client.idle_logout = settings.idle_logout;
+            client.idle_keepalive = settings.idle_keepalive;
         }
+        client.capability_trust = settings.capability_trust;
+        client
+    }
+
+    /// Creates a new client over an already connected transport, such as a Unix domain socket,
+    /// an SSH tunnel or an in-memory pipe, and reads the server greeting from it.
+    ///
+    /// The stream is used as is: if it needs TLS, it must already be wrapped in it. Any
+    /// `AsyncRead + AsyncWrite` type works, it does not have to implement `Debug`.
+    pub async fn from_stream(stream: T) -> Result<Client<T>> {
+        Client::new(stream).read_greeting().await
     }
 
     /// Log in to the IMAP server. Upon success a [`Session`](struct.Session.html) instance is
@@ -235,10 +706,14 @@ impl<T: Read + Write + Unpin + fmt::Debug> Client<T> {
         password: P,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
         let u = ok_or_unauth_client_err!(validate_str(username.as_ref()), self);
-        let p = ok_or_unauth_client_err!(validate_str(password.as_ref()), self);
+        let p = Secret::from(ok_or_unauth_client_err!(
+            validate_str(password.as_ref()),
+            self
+        ));
+        let command = Secret::from(format!("LOGIN {} {}", u, p.expose()));
+        let id = ok_or_unauth_client_err!(self.run_command(command.expose()).await, self);
         ok_or_unauth_client_err!(
-            self.run_command_and_check_ok(&format!("LOGIN {} {}", u, p), None)
-                .await,
+            self.check_done(id, None).await.and_then(complete_auth),
             self
         );
 
@@ -246,7 +721,14 @@ impl<T: Read + Write + Unpin + fmt::Debug> Client<T> {
     }
 
     /// Authenticate with the server using the given custom `authenticator` to handle the server's
-    /// challenge.
+    /// challenges. Common mechanisms such as [`XOAuth2`](crate::sasl::XOAuth2) are provided in
+    /// the [`sasl`](crate::sasl) module.
+    ///
+    /// If [`Client::capabilities`], or the greeting if it is
+    /// [trusted](Connection::capability_trust), showed that the server supports
+    /// [`SASL-IR`](https://tools.ietf.org/html/rfc4959), the initial response of mechanisms in
+    /// which the client speaks first is sent along with the command, see
+    /// [`Authenticator::client_first`].
     ///
     /// ```no_run
     /// struct OAuth2 {
@@ -288,68 +770,154 @@ impl<T: Read + Write + Unpin + fmt::Debug> Client<T> {
     /// # Ok(())
     /// # }) }
     /// ```
-    pub async fn authenticate<A: Authenticator, S: AsRef<str>>(
+    pub async fn authenticate<A: Authenticator + ?Sized, S: AsRef<str>>(
         mut self,
         auth_type: S,
         authenticator: &A,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
-        ok_or_unauth_client_err!(
-            self.run_command(&format!("AUTHENTICATE {}", auth_type.as_ref()))
-                .await,
-            self
-        );
-        let session = self.do_auth_handshake(authenticator).await?;
+        let sasl_ir = self.sasl_ir
+            || self
+                .trusted_greeting_capabilities()
+                .map_or(false, |capabilities| capabilities.has_str("SASL-IR"));
+        let command = if sasl_ir && authenticator.client_first() {
+            let response = authenticator.process(b"");
+            let response = response.as_ref();
+            // an empty initial response is sent as `=`, since nothing would mean there is none
+            let response = if response.is_empty() {
+                "=".to_string()
+            } else {
+                base64::encode(response)
+            };
+            format!("AUTHENTICATE {} {}", auth_type.as_ref(), response)
+        } else {
+            format!("AUTHENTICATE {}", auth_type.as_ref())
+        };
+        let id = ok_or_unauth_client_err!(self.run_command(&command).await, self);
+        let session = self.do_auth_handshake(id, authenticator).await?;
 
         Ok(session)
     }
 
-    /// This func does the handshake process once the authenticate command is made.
-    async fn do_auth_handshake<A: Authenticator>(
+    /// Authenticate with an OAuth mechanism such as [`XOAuth2`](crate::sasl::XOAuth2), asking
+    /// its [`TokenProvider`](crate::sasl::TokenProvider), if it has one, for the access token.
+    /// If the server rejects the token because it has expired, the provider is asked for a new
+    /// one and the login is tried once more.
+    ///
+    /// ```no_run
+    /// # use async_imap::sasl::{TokenFuture, TokenProvider};
+    /// # struct Refreshing;
+    /// # impl TokenProvider for Refreshing {
+    /// #     fn token(&self) -> TokenFuture<'_> { unimplemented!() }
+    /// # }
+    /// # fn main() -> async_imap::error::Result<()> {
+    /// # async_std::task::block_on(async {
+    /// # let client: async_imap::Client<async_std::net::TcpStream> = unimplemented!();
+    /// use async_imap::sasl::OAuthBearer;
+    ///
+    /// let auth = OAuthBearer::with_provider("me@example.com", Refreshing);
+    /// let session = client.authenticate_oauth(&auth).await.map_err(|(err, _)| err)?;
+    /// # Ok(())
+    /// # }) }
+    /// ```
+    pub async fn authenticate_oauth<A: crate::sasl::OAuth + ?Sized>(
+        self,
+        auth: &A,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        ok_or_unauth_client_err!(auth.refresh().await, self);
+        match self.authenticate(auth.mechanism(), auth).await {
+            Err((Error::Auth(_), client)) if auth.expired() => {
+                log::debug!("access token expired, retrying with a new one");
+                ok_or_unauth_client_err!(auth.refresh().await, client);
+                client.authenticate(auth.mechanism(), auth).await
+            }
+            res => res,
+        }
+    }
+
+    /// Authenticate with `AUTHENTICATE PLAIN`, see [`Plain`](crate::sasl::Plain). `authcid` and
+    /// `password` are the credentials to log in with, and `authzid` is the user to act as, if
+    /// that differs from `authcid`, e.g. when an administrator logs in on behalf of a user.
+    ///
+    /// Use this instead of [`Client::login`] on servers that disable `LOGIN`.
+    pub async fn authenticate_plain<C: AsRef<str>, P: AsRef<str>>(
+        self,
+        authzid: Option<&str>,
+        authcid: C,
+        password: P,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        let plain = crate::sasl::Plain::new(authzid, authcid.as_ref(), password.as_ref());
+        self.authenticate(crate::sasl::Plain::MECHANISM, &plain)
+            .await
+    }
+
+    /// Authenticate with `AUTHENTICATE EXTERNAL`, see [`External`](crate::sasl::External), using
+    /// the identity the server derives from e.g. the client certificate of the TLS connection.
+    /// `authzid` is the user to act as, if that differs from this identity.
+    pub async fn authenticate_external(
+        self,
+        authzid: Option<&str>,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        let external = crate::sasl::External::new(authzid);
+        self.authenticate(crate::sasl::External::MECHANISM, &external)
+            .await
+    }
+
+    /// This func does the handshake process once the authenticate command is made: every
+    /// challenge is answered by the `authenticator` until the command tagged `id` completes.
+    async fn do_auth_handshake<A: Authenticator + ?Sized>(
         mut self,
+        id: RequestId,
         authenticator: &A,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
         // explicit match blocks neccessary to convert error to tuple and not bind self too
         // early (see also comment on `login`)
-        if let Some(res) = self.read_response().await {
+        loop {
+            let res = match self.read_response().await {
+                Some(res) => ok_or_unauth_client_err!(res.map_err(Into::into), self),
+                None => return Err((Error::ConnectionLost, self)),
+            };
             // FIXME: Some servers will only send `+\r\n` need to handle that in imap_proto.
             // https://github.com/djc/tokio-imap/issues/67
-            let res = ok_or_unauth_client_err!(res.map_err(Into::into), self);
-            match res.parsed() {
-                Response::Continue { information, .. } => {
-                    let challenge = if let Some(text) = information {
-                        ok_or_unauth_client_err!(
-                            base64::decode(text).map_err(|e| Error::Parse(
-                                ParseError::Authentication(text.to_string(), Some(e))
-                            )),
-                            self
-                        )
-                    } else {
-                        Vec::new()
-                    };
-                    let raw_response = &authenticator.process(&challenge);
-                    let auth_response = base64::encode(raw_response);
-
-                    ok_or_unauth_client_err!(
-                        self.conn.run_command_untagged(&auth_response).await,
+            let challenge = match res.parsed() {
+                Response::Continue { information, .. } => match information {
+                    Some(text) => ok_or_unauth_client_err!(
+                        base64::decode(text).map_err(|e| Error::Parse(ParseError::Authentication(
+                            text.to_string(),
+                            Some(e)
+                        ))),
                         self
-                    );
-                    Ok(Session::new(self.conn))
-                }
-                _ => {
-                    if self.read_response().await.is_some() {
-                        Ok(Session::new(self.conn))
-                    } else {
-                        Err((Error::ConnectionLost, self))
+                    ),
+                    None => Vec::new(),
+                },
+                Response::Done { tag, .. } if *tag == id => {
+                    ok_or_unauth_client_err!(complete_auth(res), self);
+                    if !authenticator.completed() {
+                        return Err((
+                            Error::Auth(AuthError::Other(
+                                "the server accepted the client before completing the exchange"
+                                    .into(),
+                            )),
+                            self,
+                        ));
                     }
+                    return Ok(Session::new(self.conn));
                 }
-            }
-        } else {
-            Err((Error::ConnectionLost, self))
+                // e.g. untagged `CAPABILITY` responses
+                _ => continue,
+            };
+
+            let raw_response = &authenticator.process(&challenge);
+            let auth_response = if authenticator.cancelled() {
+                "*".to_string()
+            } else {
+                base64::encode(raw_response)
+            };
+            ok_or_unauth_client_err!(self.conn.run_command_untagged(&auth_response).await, self);
         }
     }
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> Session<T> {
     unsafe_pinned!(conn: Connection<T>);
 
     pub(crate) fn get_stream(self: Pin<&mut Self>) -> Pin<&mut ImapStream<T>> {
@@ -357,12 +925,30 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     }
 
     // not public, just to avoid duplicating the channel creation code
-    fn new(conn: Connection<T>) -> Self {
-        let (tx, rx) = sync::channel(100);
+    pub(crate) fn new(mut conn: Connection<T>) -> Self {
+        let replay = Arc::new(Mutex::new(ReplayBuffer::new(DEFAULT_REPLAY_CAPACITY)));
+        let (tx, rx) = unsolicited_channel(conn.unsolicited_capacity, replay.clone());
+        conn.stream.protocol.unsolicited = Some(tx.clone());
+        let quirks = conn
+            .server_id
+            .as_ref()
+            .map(Quirks::detect)
+            .unwrap_or_default();
         Session {
             conn,
             unsolicited_responses: rx,
             unsolicited_responses_tx: tx,
+            replay,
+            queued: Vec::new(),
+            done: None,
+            quirks,
+            searchres: None,
+            uidplus: None,
+            esearch: None,
+            esort: None,
+            move_rejected: false,
+            archive_mailbox: None,
+            retry_policy: None,
         }
     }
 
@@ -384,44 +970,327 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// [`Connection::run_command_and_read_response`], you *may* see additional untagged `RECENT`,
     /// `EXISTS`, `FETCH`, and `EXPUNGE` responses. You can get them from the
     /// `unsolicited_responses` channel of the [`Session`](struct.Session.html).
-    pub async fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
-        // TODO: also note READ/WRITE vs READ-only mode!
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run(
+    /// #     "* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+    /// #     * 3 EXISTS\r\n\
+    /// #     * 0 RECENT\r\n\
+    /// #     * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
+    /// #     A0001 OK [READ-WRITE] SELECT completed\r\n", |mut session| async move {
+    /// let inbox = session.select("INBOX").await?;
+    /// assert_eq!(inbox.exists, 3);
+    /// assert_eq!(inbox.uid_validity, Some(1257842737));
+    /// # assert_eq!(async_imap::doctest_utils::commands(&session), "A0001 SELECT \"INBOX\"\r\n");
+    /// # Ok(()) });
+    /// ```
+    pub async fn select<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<Mailbox>> {
+        self.run_select(&format!("SELECT {}", validate_str(mailbox_name.as_ref())?))
+            .await
+    }
+
+    /// Selects `mailbox_name` like [`Session::select`], and enables
+    /// [`CONDSTORE`](https://tools.ietf.org/html/rfc7162) for the session.
+    ///
+    /// The returned [`Mailbox`] includes the mailbox's
+    /// [`highest_modseq`](Mailbox::highest_modseq), unless the server does not keep
+    /// mod-sequences for it. From then on, `FETCH` responses include the
+    /// [`modseq`](Fetch::modseq) of the message. The server must advertise the `CONDSTORE`
+    /// capability.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     * OK [HIGHESTMODSEQ 715194045007] Highest\r\n\
+    /// #     A0001 OK [READ-WRITE] SELECT completed, CONDSTORE is now enabled\r\n",
+    /// #     |mut session| async move {
+    /// let inbox = session.select_condstore("INBOX").await?;
+    /// assert_eq!(inbox.highest_modseq, Some(715194045007));
+    /// # Ok(()) });
+    /// ```
+    pub async fn select_condstore<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<Mailbox>> {
+        self.run_select(&format!(
+            "SELECT {} (CONDSTORE)",
+            validate_str(mailbox_name.as_ref())?
+        ))
+        .await
+    }
+
+    /// Issues a `SELECT` or `EXAMINE` command and collects the mailbox it returns.
+    async fn run_select(&mut self, command: &str) -> Result<CommandResult<Mailbox>> {
+        retrying!(
+            self,
+            command,
+            async {
+                let id = self.run_command(command).await?;
+                // responses about the previously selected mailbox are of no use anymore
+                self.replay.lock().unwrap().clear();
+                parse_mailbox(
+                    &mut self.conn.stream,
+                    self.unsolicited_responses_tx.clone(),
+                    id,
+                )
+                .await
+            }
+            .await
+        )
+    }
+
+    /// Checks that `mailbox_name` can be selected before issuing [`Session::select`].
+    ///
+    /// Servers with hierarchical mail stores (e.g. Courier) often contain intermediate names that
+    /// only exist to hold child mailboxes. Selecting such a name fails with a server-specific `NO`
+    /// response that is hard to present to users. This method issues a `LIST` for the exact name
+    /// and returns [`Error::NotSelectable`] with a clear reason if the name does not exist or is
+    /// marked [`NameAttribute::NoSelect`] or [`NameAttribute::NonExistent`].
+    ///
+    /// ```
+    /// use async_imap::error::Error;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* LIST (\\NoSelect \\HasChildren) \".\" \"Archive\"\r\n\
+    /// #     A0001 OK LIST completed\r\n", |mut session| async move {
+    /// match session.check_selectable("Archive").await {
+    ///     Err(Error::NotSelectable(reason)) => println!("{}", reason),
+    ///     res => panic!("unexpected result: {:?}", res),
+    /// }
+    /// # Ok(()) });
+    /// ```
+    pub async fn check_selectable<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+        let mailbox_name = mailbox_name.as_ref();
         let id = self
-            .run_command(&format!("SELECT {}", validate_str(mailbox_name.as_ref())?))
+            .run_command(&format!("LIST \"\" {}", validate_str(mailbox_name)?))
             .await?;
-        let mbox = parse_mailbox(
+        let names: Vec<Name> = parse_names(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
         )
+        .try_collect()
         .await?;
 
-        Ok(mbox)
+        // `INBOX` is case-insensitive, all other names are matched exactly
+        let is_inbox = mailbox_name.eq_ignore_ascii_case("INBOX");
+        let name = names.iter().find(|name| {
+            name.name() == mailbox_name || (is_inbox && name.name().eq_ignore_ascii_case("INBOX"))
+        });
+
+        let name = match name {
+            Some(name) => name,
+            None => {
+                return Err(Error::NotSelectable(format!(
+                    "mailbox {:?} does not exist",
+                    mailbox_name
+                )))
+            }
+        };
+        for attr in name.attributes() {
+            match attr {
+                NameAttribute::NoSelect => {
+                    return Err(Error::NotSelectable(format!(
+                        "mailbox {:?} is a \\Noselect container",
+                        mailbox_name
+                    )))
+                }
+                NameAttribute::NonExistent => {
+                    return Err(Error::NotSelectable(format!(
+                        "mailbox {:?} does not exist (\\NonExistent)",
+                        mailbox_name
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Equivalent to [`Session::select`], but first verifies the mailbox using
+    /// [`Session::check_selectable`] so that unselectable names produce a descriptive
+    /// [`Error::NotSelectable`] instead of the server's `NO` response.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n\
+    /// #     A0001 OK LIST completed\r\n\
+    /// #     * 3 EXISTS\r\n\
+    /// #     A0002 OK [READ-WRITE] SELECT completed\r\n", |mut session| async move {
+    /// let inbox = session.select_checked("INBOX").await?;
+    /// assert_eq!(inbox.exists, 3);
+    /// # Ok(()) });
+    /// ```
+    pub async fn select_checked<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<Mailbox>> {
+        self.check_selectable(mailbox_name.as_ref()).await?;
+        self.select(mailbox_name).await
+    }
+
+    /// Selects `mailbox_name` like [`Session::select`], and reconciles the state kept for it in
+    /// `store`.
+    ///
+    /// If the mailbox's `UIDVALIDITY` differs from the stored one, the UIDs known to `store` no
+    /// longer refer to the same messages, so the stored state is reset before the new
+    /// `UIDVALIDITY` is recorded.
+    ///
+    /// ```
+    /// use async_imap::sync_state::MemoryStore;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     * OK [UIDVALIDITY 3857529045] UIDs valid\r\n\
+    /// #     A0001 OK [READ-WRITE] SELECT completed\r\n", |mut session| async move {
+    /// let mut store = MemoryStore::new();
+    /// session.select_synced("INBOX", &mut store).await?;
+    /// assert_eq!(store.get("INBOX").unwrap().uid_validity, Some(3857529045));
+    /// # Ok(()) });
+    /// ```
+    pub async fn select_synced<S, St>(
+        &mut self,
+        mailbox_name: S,
+        store: &mut St,
+    ) -> Result<CommandResult<Mailbox>>
+    where
+        S: AsRef<str>,
+        St: SyncStateStore,
+    {
+        let mailbox_name = mailbox_name.as_ref();
+        let mailbox = self.select(mailbox_name).await?;
+        if let Some(uid_validity) = mailbox.uid_validity {
+            if store.uid_validity(mailbox_name)? != Some(uid_validity) {
+                store.reset(mailbox_name)?;
+                store.set_uid_validity(mailbox_name, uid_validity)?;
+            }
+        }
+
+        Ok(mailbox)
     }
 
     /// The `EXAMINE` command is identical to [`Session::select`] and returns the same output;
     /// however, the selected mailbox is identified as read-only. No changes to the permanent state
     /// of the mailbox, including per-user state, will happen in a mailbox opened with `examine`;
     /// in particular, messagess cannot lose [`Flag::Recent`] in an examined mailbox.
-    pub async fn examine<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
-        let id = self
-            .run_command(&format!("EXAMINE {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
-        let mbox = parse_mailbox(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     A0001 OK [READ-ONLY] EXAMINE completed\r\n", |mut session| async move {
+    /// let inbox = session.examine("INBOX").await?;
+    /// println!("{} messages", inbox.exists);
+    /// # Ok(()) });
+    /// ```
+    pub async fn examine<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<Mailbox>> {
+        self.run_select(&format!("EXAMINE {}", validate_str(mailbox_name.as_ref())?))
+            .await
+    }
 
-        Ok(mbox)
+    /// Examines `mailbox_name` like [`Session::examine`], and enables
+    /// [`CONDSTORE`](https://tools.ietf.org/html/rfc7162) for the session like
+    /// [`Session::select_condstore`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     * OK [HIGHESTMODSEQ 715194045007] Highest\r\n\
+    /// #     A0001 OK [READ-ONLY] EXAMINE completed\r\n", |mut session| async move {
+    /// let inbox = session.examine_condstore("INBOX").await?;
+    /// assert_eq!(inbox.highest_modseq, Some(715194045007));
+    /// # Ok(()) });
+    /// ```
+    pub async fn examine_condstore<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<Mailbox>> {
+        self.run_select(&format!(
+            "EXAMINE {} (CONDSTORE)",
+            validate_str(mailbox_name.as_ref())?
+        ))
+        .await
     }
 
-    /// Fetch retreives data associated with a set of messages in the mailbox.
+    /// Selects `mailbox_name` like [`Session::select`], and has the server report what changed
+    /// since the client last synchronized it, using the [`QRESYNC`
+    /// extension](https://tools.ietf.org/html/rfc7162#section-3.2.5).
     ///
-    /// Note that the server *is* allowed to unilaterally include `FETCH` responses for other
-    /// messages in the selected mailbox whose status has changed. See the note on [unilateral
-    /// server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
+    /// `uid_validity` and `modseq` are the [`uid_validity`](Mailbox::uid_validity) and
+    /// [`highest_modseq`](Mailbox::highest_modseq) known from the last time. `known_uids`
+    /// optionally limits the report to these UIDs, as a sequence set like `1:100,205`. The
+    /// messages that have been expunged since then are returned as a [`Vanished`], so that the
+    /// client does not have to search for all UIDs to find them. Messages whose flags changed
+    /// are reported as `FETCH` responses on the `unsolicited_responses` channel and in
+    /// [`Session::flag_events`].
+    ///
+    /// If `uid_validity` does not match anymore, nothing is reported, and the client must
+    /// synchronize the mailbox from scratch. `QRESYNC` must have been enabled with
+    /// [`Session::enable`] before.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* ENABLED QRESYNC\r\n\
+    /// #     A0001 OK ENABLE completed\r\n\
+    /// #     * 3 EXISTS\r\n\
+    /// #     * OK [UIDVALIDITY 67890007] UIDs valid\r\n\
+    /// #     * OK [HIGHESTMODSEQ 90060115205545] Highest\r\n\
+    /// #     * VANISHED (EARLIER) 41,43:116\r\n\
+    /// #     A0002 OK [READ-WRITE] SELECT completed\r\n", |mut session| async move {
+    /// session.enable(&["QRESYNC"]).await?;
+    /// let (inbox, vanished) = session
+    ///     .select_qresync("INBOX", 67890007, 90060115194045, None)
+    ///     .await?
+    ///     .into_inner();
+    /// assert_eq!(inbox.highest_modseq, Some(90060115205545));
+    /// assert!(vanished.contains(43));
+    /// assert!(!vanished.contains(42));
+    /// # Ok(()) });
+    /// ```
+    pub async fn select_qresync<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+        uid_validity: u32,
+        modseq: u64,
+        known_uids: Option<&str>,
+    ) -> Result<CommandResult<(Mailbox, Vanished)>> {
+        let mut params = format!("{} {}", uid_validity, modseq);
+        if let Some(uids) = known_uids {
+            params.push(' ');
+            params.push_str(&compress_set(uids));
+        }
+        let mailbox = self
+            .run_select(&format!(
+                "SELECT {} (QRESYNC ({}))",
+                validate_str(mailbox_name.as_ref())?,
+                params
+            ))
+            .await?;
+
+        // `VANISHED` responses are collected by the stream, since imap-proto can not parse them
+        let reported = std::mem::replace(&mut self.conn.stream.protocol.vanished, Vec::new());
+        let mut vanished = Vanished {
+            earlier: true,
+            uids: Vec::new(),
+        };
+        for v in reported {
+            vanished.uids.extend(v.uids);
+        }
+        Ok(mailbox.map(|mailbox| (mailbox, vanished)))
+    }
+
+    /// Fetch retreives data associated with a set of messages in the mailbox.
+    ///
+    /// Note that the server *is* allowed to unilaterally include `FETCH` responses for other
+    /// messages in the selected mailbox whose status has changed. See the note on [unilateral
+    /// server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
     ///
     /// `query` is a list of "data items" (space-separated in parentheses if `>1`). There are three
     /// "macro items" which specify commonly-used sets of data items, and can be used instead of
@@ -475,11 +1344,28 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///  - `RFC822.HEADER`: Functionally equivalent to `BODY.PEEK[HEADER]`.
     ///  - `RFC822.SIZE`: The [RFC-2822](https://tools.ietf.org/html/rfc2822) size of the message.
     ///  - `UID`: The unique identifier for the message.
+    ///
+    /// ```
+    /// use async_imap::types::Flag;
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 2 FETCH (UID 18 FLAGS (\\Seen) RFC822.SIZE 1240)\r\n\
+    /// #     A0001 OK FETCH completed\r\n", |mut session| async move {
+    /// let messages: Vec<_> = session
+    ///     .fetch("2", "(UID FLAGS RFC822.SIZE)")
+    ///     .await?
+    ///     .try_collect()
+    ///     .await?;
+    /// assert_eq!(messages[0].size, Some(1240));
+    /// assert!(messages[0].flags().contains(&Flag::Seen));
+    /// # Ok(()) });
+    /// ```
     pub async fn fetch<S1, S2>(
         &mut self,
         sequence_set: S1,
         query: S2,
-    ) -> Result<impl Stream<Item = Result<Fetch>> + '_>
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
@@ -487,7 +1373,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         let id = self
             .run_command(&format!(
                 "FETCH {} {}",
-                sequence_set.as_ref(),
+                compress_set(sequence_set.as_ref()),
                 query.as_ref()
             ))
             .await?;
@@ -502,11 +1388,26 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
 
     /// Equivalent to [`Session::fetch`], except that all identifiers in `uid_set` are
     /// [`Uid`]s. See also the [`UID` command](https://tools.ietf.org/html/rfc3501#section-6.4.8).
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run(
+    /// #     "* 2 FETCH (UID 18 BODY[HEADER] {20}\r\nSubject: Greetings\r\n)\r\n\
+    /// #     A0001 OK FETCH completed\r\n", |mut session| async move {
+    /// let mut messages = session.uid_fetch("18", "BODY.PEEK[HEADER]").await?;
+    /// while let Some(message) = messages.try_next().await? {
+    ///     assert_eq!(message.uid, Some(18));
+    ///     assert_eq!(message.header(), Some(&b"Subject: Greetings\r\n"[..]));
+    /// }
+    /// # Ok(()) });
+    /// ```
     pub async fn uid_fetch<S1, S2>(
         &mut self,
         uid_set: S1,
         query: S2,
-    ) -> Result<impl Stream<Item = Result<Fetch>> + '_>
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
@@ -514,7 +1415,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         let id = self
             .run_command(&format!(
                 "UID FETCH {} {}",
-                uid_set.as_ref(),
+                compress_set(uid_set.as_ref()),
                 query.as_ref()
             ))
             .await?;
@@ -526,19 +1427,510 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         Ok(res)
     }
 
+    /// Equivalent to [`Session::fetch`], but limits the size of the literals in the responses,
+    /// such as message bodies, to `max_size` bytes. What happens to larger literals is decided by
+    /// `policy`: they can be skipped or passed to a [`LiteralSink`] without being buffered, or the
+    /// fetch can be aborted before they are received. This protects clients with little memory
+    /// from unexpectedly large messages.
+    ///
+    /// The limit applies to all responses received until the fetch has completed.
+    ///
+    /// ```
+    /// use async_imap::types::LiteralPolicy;
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 1 FETCH (UID 3 BODY[] {12}\r\nhello\r\nworld)\r\n\
+    /// #     A0001 OK FETCH completed\r\n", |mut session| async move {
+    /// let messages: Vec<_> = session
+    ///     .fetch_limited("1", "BODY[]", 5, LiteralPolicy::Skip)
+    ///     .await?
+    ///     .try_collect()
+    ///     .await?;
+    /// // the body was too large, and has been skipped
+    /// assert_eq!(messages[0].uid, Some(3));
+    /// assert_eq!(messages[0].body(), None);
+    /// # Ok(()) });
+    /// ```
+    pub async fn fetch_limited<S1, S2>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+        max_size: usize,
+        policy: LiteralPolicy,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.run_fetch_limited(
+            "FETCH",
+            sequence_set.as_ref(),
+            query.as_ref(),
+            max_size,
+            policy,
+        )
+        .await
+    }
+
+    /// Runs `FETCH` or `UID FETCH`, as given by `command`, with a limit on the size of literals.
+    async fn run_fetch_limited(
+        &mut self,
+        command: &str,
+        set: &str,
+        query: &str,
+        max_size: usize,
+        policy: LiteralPolicy,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_> {
+        let id = self
+            .run_command(&format!("{} {} {}", command, compress_set(set), query))
+            .await?;
+        self.conn.stream.protocol.literal_limit = Some(LiteralLimit {
+            tag: id.clone(),
+            max_size,
+            policy,
+        });
+        let res = parse_fetches(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        );
+        Ok(res)
+    }
+
+    /// Fetches `query` for the messages that match the search `criteria`, see
+    /// [`Session::search`] and [`Session::fetch`] for the syntax of both.
+    ///
+    /// If the server supports [`SEARCHRES`](https://tools.ietf.org/html/rfc5182), the search
+    /// result is saved on the server and the `UID SEARCH` and `UID FETCH` commands are sent
+    /// together, so that the messages are fetched in a single round trip. Otherwise, the matching
+    /// [`Uid`]s are searched first and then fetched. Either way, the messages are identified by
+    /// their `UID`, so messages expunged in between can not cause the wrong ones to be fetched.
+    ///
+    /// The capabilities of the server are asked for on the first call.
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     * SEARCH 17 18\r\n\
+    /// #     A0002 OK SEARCH completed\r\n\
+    /// #     * 1 FETCH (UID 17 FLAGS ())\r\n\
+    /// #     * 2 FETCH (UID 18 FLAGS ())\r\n\
+    /// #     A0003 OK FETCH completed\r\n", |mut session| async move {
+    /// let unseen: Vec<_> = session
+    ///     .fetch_matching("UNSEEN", "FLAGS")
+    ///     .await?
+    ///     .try_collect()
+    ///     .await?;
+    /// assert_eq!(unseen.len(), 2);
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 CAPABILITY\r\nA0002 UID SEARCH UNSEEN\r\nA0003 UID FETCH 17:18 FLAGS\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn fetch_matching<S1, S2>(
+        &mut self,
+        criteria: S1,
+        query: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        if self.searchres.is_none() {
+            self.searchres = Some(self.capabilities().await?.has_str("SEARCHRES"));
+        }
+
+        let id = if self.searchres == Some(true) {
+            let search = self
+                .run_command(&format!(
+                    "UID SEARCH RETURN (SAVE) {}",
+                    compress_query(criteria.as_ref())
+                ))
+                .await?;
+            let id = self
+                .run_command(&format!("UID FETCH $ {}", query.as_ref()))
+                .await?;
+            if let Err(err) = self.check_done(&search).await {
+                // a failed search empties the saved result, so the fetch completes without data
+                let _ = self.check_done(&id).await;
+                return Err(err);
+            }
+            id
+        } else {
+            let uids = self.uid_search(criteria).await?;
+            if uids.is_empty() {
+                return Ok(future::Either::Left(stream::empty()));
+            }
+            self.run_command(&format!(
+                "UID FETCH {} {}",
+                to_sequence_set(uids.iter().cloned()),
+                query.as_ref()
+            ))
+            .await?
+        };
+
+        let res = parse_fetches(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        );
+        Ok(future::Either::Right(res))
+    }
+
+    /// Equivalent to [`Session::fetch_limited`], except that all identifiers in `uid_set` are
+    /// [`Uid`]s.
+    ///
+    /// ```
+    /// use async_imap::types::LiteralPolicy;
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 1 FETCH (UID 3 BODY[] {5}\r\nhello)\r\n\
+    /// #     A0001 OK FETCH completed\r\n", |mut session| async move {
+    /// let messages: Vec<_> = session
+    ///     .uid_fetch_limited("3", "BODY[]", 1024 * 1024, LiteralPolicy::Abort)
+    ///     .await?
+    ///     .try_collect()
+    ///     .await?;
+    /// assert_eq!(messages[0].body(), Some(&b"hello"[..]));
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_fetch_limited<S1, S2>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+        max_size: usize,
+        policy: LiteralPolicy,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.run_fetch_limited(
+            "UID FETCH",
+            uid_set.as_ref(),
+            query.as_ref(),
+            max_size,
+            policy,
+        )
+        .await
+    }
+
+    /// When the session will have been unused for [`Connection::idle_logout`], i.e. when
+    /// [`Session::park`] logs it out. `None` if no timeout is set.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// # #[cfg(all(feature = "doctest-utils", feature = "runtime"))]
+    /// # async_imap::doctest_utils::run("", |mut session| async move {
+    /// session.idle_logout = Some(Duration::from_secs(300));
+    /// assert!(session.idle_deadline().is_some());
+    /// # Ok(()) });
+    /// ```
+    #[cfg(feature = "runtime")]
+    pub fn idle_deadline(&self) -> Option<Instant> {
+        self.idle_logout
+            .map(|timeout| self.stream.protocol.last_used + timeout)
+    }
+
+    /// Logs out and closes the connection if no command has been sent for
+    /// [`Connection::idle_logout`]. Returns whether the session was closed, in which case it
+    /// can not be used anymore.
+    ///
+    /// This is meant to be called periodically for sessions that are kept around, e.g. in a
+    /// connection pool, see [`Session::idle_deadline`]. A session that is in `IDLE` is in use,
+    /// and is not affected.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// # #[cfg(all(feature = "doctest-utils", feature = "runtime"))]
+    /// # async_imap::doctest_utils::run("* BYE IMAP4rev1 Server logging out\r\n\
+    /// #     A0001 OK LOGOUT completed\r\n", |mut session| async move {
+    /// session.idle_logout = Some(Duration::from_secs(0));
+    /// if session.logout_if_idle().await? {
+    ///     println!("the session was closed");
+    /// }
+    /// # Ok(()) });
+    /// ```
+    #[cfg(feature = "runtime")]
+    pub async fn logout_if_idle(&mut self) -> Result<bool> {
+        match self.idle_deadline() {
+            Some(deadline) if Instant::now() >= deadline => {}
+            _ => return Ok(false),
+        }
+
+        log::debug!("logging out unused session");
+        self.logout_and_close().await.map(|_| true)
+    }
+
+    /// Puts the session aside until it is needed again, logging it out and closing the
+    /// connection once it has been unused for [`Connection::idle_logout`]. Dropping the
+    /// returned handle logs the session out right away.
+    ///
+    /// This runs on a task of its own, so the session does not have to be polled meanwhile.
+    /// See [`Session::logout_if_idle`] for checking the timeout by hand instead.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// # #[cfg(all(feature = "doctest-utils", feature = "runtime"))]
+    /// # async_imap::doctest_utils::run("A0001 OK NOOP completed\r\n", |mut session| async move {
+    /// session.idle_logout = Some(Duration::from_secs(300));
+    /// let parked = session.park();
+    /// // ...
+    /// if let Some(mut session) = parked.unpark().await {
+    ///     session.noop().await?;
+    /// }
+    /// # Ok(()) });
+    /// ```
+    #[cfg(feature = "runtime")]
+    pub fn park(self) -> ParkedSession<T>
+    where
+        T: Send + 'static,
+    {
+        let (unpark, unparked) = futures::channel::oneshot::channel();
+        let task = async_std::task::spawn(async move {
+            let mut session = self;
+            let unparked = match session.idle_deadline() {
+                Some(deadline) => {
+                    let wait = deadline.saturating_duration_since(Instant::now());
+                    matches!(async_std::future::timeout(wait, unparked).await, Ok(Ok(())))
+                }
+                None => unparked.await.is_ok(),
+            };
+            if unparked {
+                return Some(session);
+            }
+
+            log::debug!("logging out parked session");
+            if let Err(err) = session.logout_and_close().await {
+                log::debug!("failed to log out parked session: {}", err);
+            }
+            None
+        });
+        ParkedSession { unpark, task }
+    }
+
+    #[cfg(feature = "runtime")]
+    async fn logout_and_close(&mut self) -> Result<()> {
+        let res = self.logout().await;
+        // the server closes the connection after LOGOUT, and may already have done so
+        let _ = self.conn.stream.inner.close().await;
+        res
+    }
+
+    /// Logs out and closes the connection, sending the TLS `close_notify` alert if the
+    /// connection is secured, and resolves once the server has closed its side too. The server
+    /// is given a few seconds for this, after which the connection is dropped regardless, so this
+    /// always completes. Returns how much the connection was used, e.g. for logging.
+    ///
+    /// See [`Handle::shutdown`](extensions::idle::Handle::shutdown) to shut down a session that
+    /// is in `IDLE`.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "doctest-utils", feature = "runtime"))]
+    /// # async_imap::doctest_utils::run("* BYE IMAP4rev1 Server logging out\r\n\
+    /// #     A0001 OK LOGOUT completed\r\n", |session| async move {
+    /// let stats = session.shutdown().await;
+    /// println!("{:?}", stats);
+    /// # Ok(()) });
+    /// ```
+    #[cfg(feature = "runtime")]
+    pub async fn shutdown(self) -> ConnectionStats {
+        self.conn.shutdown().await
+    }
+
     /// Noop always succeeds, and it does nothing.
-    pub async fn noop(&mut self) -> Result<()> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 4 EXISTS\r\n\
+    /// #     A0001 OK NOOP completed\r\n", |mut session| async move {
+    /// session.noop().await?;
+    /// for response in session.drain_unsolicited() {
+    ///     println!("{:?}", response);
+    /// }
+    /// # Ok(()) });
+    /// ```
+    pub async fn noop(&mut self) -> Result<CommandResult<()>> {
         let id = self.run_command("NOOP").await?;
         parse_noop(
             &mut self.conn.stream,
             self.unsolicited_responses_tx.clone(),
             id,
         )
-        .await?;
-        Ok(())
+        .await
+    }
+
+    /// Exchanges implementation details with the server using the [`ID`
+    /// command](https://tools.ietf.org/html/rfc2971), e.g. `&[("name", "my-client")]`. Passing
+    /// no fields sends `ID NIL`.
+    ///
+    /// The server's answer is returned, and remembered as [`Session::server_id`]. It is also used
+    /// to detect the [`Quirks`] of the server.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* ID (\"name\" \"Dovecot\" \"version\" NIL)\r\n\
+    /// #     A0001 OK ID completed\r\n", |mut session| async move {
+    /// let id = session.id(&[("name", "my-client")]).await?;
+    /// assert_eq!(id.name(), Some("Dovecot"));
+    /// assert!(session.server_id().is_some());
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 ID (\"name\" \"my-client\")\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn id(&mut self, fields: &[(&str, &str)]) -> Result<CommandResult<ServerId>> {
+        let res = self
+            .conn
+            .id(fields, Some(self.unsolicited_responses_tx.clone()))
+            .await?;
+        self.quirks = Quirks::detect(&res);
+        Ok(res)
+    }
+
+    /// The server's answer to the last `ID` command, if any. This includes an `ID` command sent
+    /// with [`Client::id`] before logging in.
+    pub fn server_id(&self) -> Option<&ServerId> {
+        self.conn.server_id.as_ref()
+    }
+
+    /// The known bugs of the server, as detected by [`Session::id`]. Until then, no quirks are
+    /// assumed.
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    /// Overrides the detected quirks of the server.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Runs a battery of commands that do not change anything on the server (`CAPABILITY`,
+    /// `NOOP`, `LIST "" ""` and `STATUS INBOX`), and checks the responses for known deviations
+    /// from the standard, such as responses that can not be parsed or attributes that are
+    /// missing. See the [`self_test`](crate::self_test) module.
+    ///
+    /// Commands that the server rejects are part of the report, rather than an error. Only a
+    /// lost connection or a failure to parse an expected response ends the self-test early.
+    /// Call [`Session::id`] first to include the server's name and version in the report.
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "doctest-utils", feature = "self-test"))]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1 IDLE\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     A0002 OK NOOP completed\r\n\
+    /// #     * LIST (\\Noselect) \"/\" INBOX\r\n\
+    /// #     A0003 OK LIST completed\r\n\
+    /// #     * STATUS INBOX (MESSAGES 2 UIDNEXT 3 UIDVALIDITY 1 UNSEEN 0)\r\n\
+    /// #     A0004 OK STATUS completed\r\n", |mut session| async move {
+    /// let report = session.self_test().await?;
+    /// if !report.passed() {
+    ///     eprintln!("{}", report);
+    /// }
+    /// # assert_eq!(report.checks[2].deviations, ["the root name is \"INBOX\" instead of empty"]);
+    /// # Ok(()) });
+    /// ```
+    #[cfg(feature = "self-test")]
+    pub async fn self_test(&mut self) -> Result<crate::self_test::ConformanceReport> {
+        crate::self_test::run(self).await
+    }
+
+    /// Sets how commands that fail for a transient reason are retried, for the whole session.
+    /// `None`, the default, disables retrying.
+    ///
+    /// Only commands that are safe to repeat are retried, such as `SELECT`, `EXAMINE`, `STATUS`,
+    /// `SEARCH`, or `NOOP` and `CHECK`, and only when the server rejected them: once the
+    /// connection is lost, it can not be tried again on this session, see
+    /// [`SessionTemplate::retry`](crate::SessionTemplate::retry) for that. Commands whose
+    /// results are streamed, like `FETCH` or `LIST`, are not retried either, since part of the
+    /// results may already have been handed out.
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.retry_policy = policy;
+    }
+
+    /// The retry policy of the session, see [`Session::set_retry_policy`].
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    /// Runs `op`, and runs it again as long as `policy` says that the error it failed with is
+    /// worth another attempt. This applies a retry policy to individual commands, or to a
+    /// sequence of them, rather than to the whole session. `op` must be safe to run more than
+    /// once.
+    ///
+    /// Errors that mean the connection is lost are never retried, since the session can not be
+    /// used anymore.
+    ///
+    /// ```
+    /// # use futures::prelude::*;
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     A0001 OK [READ-ONLY] EXAMINE completed\r\n", |mut session| async move {
+    /// use async_imap::retry::RetryPolicy;
+    ///
+    /// let policy = RetryPolicy::new(5);
+    /// let inbox = session
+    ///     .retry(&policy, |session| session.examine("INBOX").boxed())
+    ///     .await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn retry<R, F>(&mut self, policy: &RetryPolicy, mut op: F) -> Result<R>
+    where
+        F: for<'s> FnMut(&'s mut Session<T>) -> future::BoxFuture<'s, Result<R>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let res = op(&mut *self).await;
+            match res {
+                Err(ref err)
+                    if !retry::is_connection_error(err) && policy.should_retry(err, attempt) =>
+                {
+                    log::debug!("attempt {} failed, retrying: {}", attempt, err);
+                    policy.wait(attempt).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Whether `command`, whose `attempt`th attempt failed with `err`, is sent again according
+    /// to the retry policy of the session. Waits for the backoff before returning true.
+    async fn should_retry(&self, command: &str, err: &Error, attempt: u32) -> bool {
+        let policy = match self.retry_policy {
+            Some(ref policy) => policy,
+            None => return false,
+        };
+        if retry::is_connection_error(err)
+            || !retry::is_idempotent(command)
+            || !policy.should_retry(err, attempt)
+        {
+            return false;
+        }
+        log::debug!("attempt {} failed, retrying: {}", attempt, err);
+        policy.wait(attempt).await;
+        true
     }
 
     /// Logout informs the server that the client is done with the connection.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* BYE IMAP4rev1 Server logging out\r\n\
+    /// #     A0001 OK LOGOUT completed\r\n", |mut session| async move {
+    /// session.logout().await?;
+    /// # Ok(()) });
+    /// ```
     pub async fn logout(&mut self) -> Result<()> {
         self.run_command_and_check_ok("LOGOUT").await?;
         Ok(())
@@ -566,11 +1958,20 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// the mailbox UNLESS the new incarnation has a different unique identifier validity value.
     /// See the description of the [`UID`
     /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8) for more detail.
-    pub async fn create<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK CREATE completed\r\n", |mut session| async move {
+    /// session.create("Archive/2020").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 CREATE \"Archive/2020\"\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn create<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<CommandResult<()>> {
         self.run_command_and_check_ok(&format!("CREATE {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
-
-        Ok(())
+            .await
     }
 
     /// The [`DELETE` command](https://tools.ietf.org/html/rfc3501#section-6.3.4) permanently
@@ -592,11 +1993,16 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// incarnation, UNLESS the new incarnation has a different unique identifier validity value.
     /// See the description of the [`UID`
     /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8) for more detail.
-    pub async fn delete<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK DELETE completed\r\n", |mut session| async move {
+    /// session.delete("Archive/2020").await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn delete<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<CommandResult<()>> {
         self.run_command_and_check_ok(&format!("DELETE {}", validate_str(mailbox_name.as_ref())?))
-            .await?;
-
-        Ok(())
+            .await
     }
 
     /// The [`RENAME` command](https://tools.ietf.org/html/rfc3501#section-6.3.5) changes the name
@@ -624,15 +2030,28 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// to a new mailbox with the given name, leaving `INBOX` empty.  If the server implementation
     /// supports inferior hierarchical names of `INBOX`, these are unaffected by a rename of
     /// `INBOX`.
-    pub async fn rename<S1: AsRef<str>, S2: AsRef<str>>(&mut self, from: S1, to: S2) -> Result<()> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK RENAME completed\r\n", |mut session| async move {
+    /// session.rename("Archive/2020", "Archive/Old").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 RENAME \"Archive/2020\" \"Archive/Old\"\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn rename<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        from: S1,
+        to: S2,
+    ) -> Result<CommandResult<()>> {
         self.run_command_and_check_ok(&format!(
             "RENAME {} {}",
             quote!(from.as_ref()),
             quote!(to.as_ref())
         ))
-        .await?;
-
-        Ok(())
+        .await
     }
 
     /// The [`SUBSCRIBE` command](https://tools.ietf.org/html/rfc3501#section-6.3.6) adds the
@@ -643,26 +2062,50 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// The server may validate the mailbox argument to `SUBSCRIBE` to verify that it exists.
     /// However, it will not unilaterally remove an existing mailbox name from the subscription
     /// list even if a mailbox by that name no longer exists.
-    pub async fn subscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK SUBSCRIBE completed\r\n",
+    /// #     |mut session| async move {
+    /// session.subscribe("Lists/rust").await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn subscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<CommandResult<()>> {
         self.run_command_and_check_ok(&format!("SUBSCRIBE {}", quote!(mailbox.as_ref())))
-            .await?;
-        Ok(())
+            .await
     }
 
     /// The [`UNSUBSCRIBE` command](https://tools.ietf.org/html/rfc3501#section-6.3.7) removes the
     /// specified mailbox name from the server's set of "active" or "subscribed" mailboxes as
     /// returned by [`Session::lsub`].  This command returns `Ok` only if the unsubscription is
     /// successful.
-    pub async fn unsubscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK UNSUBSCRIBE completed\r\n",
+    /// #     |mut session| async move {
+    /// session.unsubscribe("Lists/rust").await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn unsubscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<CommandResult<()>> {
         self.run_command_and_check_ok(&format!("UNSUBSCRIBE {}", quote!(mailbox.as_ref())))
-            .await?;
-        Ok(())
+            .await
     }
 
     /// The [`CAPABILITY` command](https://tools.ietf.org/html/rfc3501#section-6.1.1) requests a
     /// listing of capabilities that the server supports.  The server will include "IMAP4rev1" as
     /// one of the listed capabilities. See [`Capabilities`] for further details.
-    pub async fn capabilities(&mut self) -> Result<Capabilities> {
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1 IDLE MOVE\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n", |mut session| async move {
+    /// let capabilities = session.capabilities().await?;
+    /// assert!(capabilities.has_str("IDLE"));
+    /// assert!(!capabilities.has_str("QUOTA"));
+    /// # Ok(()) });
+    /// ```
+    pub async fn capabilities(&mut self) -> Result<CommandResult<Capabilities>> {
         let id = self.run_command("CAPABILITY").await?;
         let c = parse_capabilities(
             &mut self.conn.stream,
@@ -673,10 +2116,72 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         Ok(c)
     }
 
+    /// The [`ENABLE` command](https://tools.ietf.org/html/rfc5161) turns on extensions that
+    /// change how the server responds, such as `CONDSTORE` or `QRESYNC` (see
+    /// [`Session::select_qresync`]). The server only enables extensions that it advertises, and
+    /// ignores the others. The ones that were turned on are reported as
+    /// [`UnsolicitedResponse::Enabled`] on the `unsolicited_responses` channel.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* ENABLED CONDSTORE\r\n\
+    /// #     A0001 OK ENABLE completed\r\n", |mut session| async move {
+    /// session.enable(&["CONDSTORE", "QRESYNC"]).await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 ENABLE CONDSTORE QRESYNC\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn enable<S: AsRef<str>>(&mut self, extensions: &[S]) -> Result<CommandResult<()>> {
+        let extensions: Vec<&str> = extensions.iter().map(AsRef::as_ref).collect();
+        self.run_command_and_check_ok(&format!("ENABLE {}", extensions.join(" ")))
+            .await
+    }
+
+    /// The [`COMPRESS` command](https://tools.ietf.org/html/rfc4978) turns on `DEFLATE`
+    /// compression for the rest of the session, in both directions. This typically shrinks the
+    /// traffic to a fraction, which is worth it on mobile and metered connections.
+    ///
+    /// The server must advertise the `COMPRESS=DEFLATE` capability. Compression can not be turned
+    /// off again, and a second call fails. If TLS compression is already in use, the server
+    /// refuses the command. If the [quirks](Session::quirks) of the server say that its
+    /// compression is broken, the command is not sent, and [`Error::BrokenExtension`] is
+    /// returned instead.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK DEFLATE active\r\n", |mut session| async move {
+    /// session.compress().await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn compress(&mut self) -> Result<CommandResult<()>> {
+        if self.quirks.broken_compress {
+            return Err(Error::BrokenExtension("COMPRESS=DEFLATE".into()));
+        }
+        let res = self.run_command_and_check_ok("COMPRESS DEFLATE").await?;
+        // the server compresses everything after its response
+        self.conn.stream.start_compression();
+        Ok(res)
+    }
+
     /// The [`EXPUNGE` command](https://tools.ietf.org/html/rfc3501#section-6.4.3) permanently
     /// removes all messages that have [`Flag::Deleted`] set from the currently selected mailbox.
     /// The message sequence number of each message that is removed is returned.
-    pub async fn expunge(&mut self) -> Result<impl Stream<Item = Result<Seq>> + '_> {
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXPUNGE\r\n\
+    /// #     * 3 EXPUNGE\r\n\
+    /// #     A0001 OK EXPUNGE completed\r\n", |mut session| async move {
+    /// let expunged: Vec<_> = session.expunge().await?.try_collect().await?;
+    /// // every `EXPUNGE` renumbers the messages after it
+    /// assert_eq!(expunged, vec![3, 3]);
+    /// # Ok(()) });
+    /// ```
+    pub async fn expunge(&mut self) -> Result<impl Stream<Item = Result<Seq>> + FusedStream + '_> {
         let id = self.run_command("EXPUNGE").await?;
         let res = parse_expunge(
             &mut self.conn.stream,
@@ -708,12 +2213,23 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///
     /// Alternatively, the client may fall back to using just [`Session::expunge`], risking the
     /// unintended removal of some messages.
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXPUNGE\r\n\
+    /// #     A0001 OK UID EXPUNGE completed\r\n", |mut session| async move {
+    /// let expunged: Vec<_> = session.uid_expunge("3000:3002").await?.try_collect().await?;
+    /// assert_eq!(expunged.len(), 1);
+    /// # Ok(()) });
+    /// ```
     pub async fn uid_expunge<S: AsRef<str>>(
         &mut self,
         uid_set: S,
-    ) -> Result<impl Stream<Item = Result<Uid>> + '_> {
+    ) -> Result<impl Stream<Item = Result<Uid>> + FusedStream + '_> {
         let id = self
-            .run_command(&format!("UID EXPUNGE {}", uid_set.as_ref()))
+            .run_command(&format!("UID EXPUNGE {}", compress_set(uid_set.as_ref())))
             .await?;
         let res = parse_expunge(
             &mut self.conn.stream,
@@ -733,9 +2249,15 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///
     /// There is no guarantee that an `EXISTS` untagged response will happen as a result of
     /// `CHECK`.  [`Session::noop`] SHOULD be used for new message polling.
-    pub async fn check(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("CHECK").await?;
-        Ok(())
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK CHECK completed\r\n", |mut session| async move {
+    /// session.check().await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn check(&mut self) -> Result<CommandResult<()>> {
+        self.run_command_and_check_ok("CHECK").await
     }
 
     /// The [`CLOSE` command](https://tools.ietf.org/html/rfc3501#section-6.4.2) permanently
@@ -753,9 +2275,15 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// deleted, a `CLOSE-LOGOUT` or `CLOSE-SELECT` sequence is considerably faster than an
     /// `EXPUNGE-LOGOUT` or `EXPUNGE-SELECT` because no `EXPUNGE` responses (which the client would
     /// probably ignore) are sent.
-    pub async fn close(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("CLOSE").await?;
-        Ok(())
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK CLOSE completed\r\n", |mut session| async move {
+    /// session.close().await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn close(&mut self) -> Result<CommandResult<()>> {
+        self.run_command_and_check_ok("CLOSE").await
     }
 
     /// The [`STORE` command](https://tools.ietf.org/html/rfc3501#section-6.4.6) alters data
@@ -792,23 +2320,26 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///
     /// Delete a message:
     ///
-    /// ```no_run
-    /// use async_imap::{types::Seq, Session, error::Result};
+    /// ```
+    /// use async_imap::error::Result;
     /// use async_std::prelude::*;
-    /// use async_std::net::TcpStream;
     ///
-    /// async fn delete(seq: Seq, s: &mut Session<TcpStream>) -> Result<()> {
-    ///     let updates_stream = s.store(format!("{}", seq), "+FLAGS (\\Deleted)").await?;
-    ///     let _updates: Vec<_> = updates_stream.collect::<Result<_>>().await?;
-    ///     s.expunge().await?;
-    ///     Ok(())
-    /// }
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 2 FETCH (FLAGS (\\Deleted \\Seen))\r\n\
+    /// #     A0001 OK STORE completed\r\n\
+    /// #     * 2 EXPUNGE\r\n\
+    /// #     A0002 OK EXPUNGE completed\r\n", |mut session| async move {
+    /// let seq = 2;
+    /// let updates_stream = session.store(format!("{}", seq), "+FLAGS (\\Deleted)").await?;
+    /// let _updates: Vec<_> = updates_stream.collect::<Result<_>>().await?;
+    /// session.expunge().await?;
+    /// # Ok(()) });
     /// ```
     pub async fn store<S1, S2>(
         &mut self,
         sequence_set: S1,
         query: S2,
-    ) -> Result<impl Stream<Item = Result<Fetch>> + '_>
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
@@ -816,7 +2347,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         let id = self
             .run_command(&format!(
                 "STORE {} {}",
-                sequence_set.as_ref(),
+                compress_set(sequence_set.as_ref()),
                 query.as_ref()
             ))
             .await?;
@@ -830,11 +2361,28 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
 
     /// Equivalent to [`Session::store`], except that all identifiers in `sequence_set` are
     /// [`Uid`]s. See also the [`UID` command](https://tools.ietf.org/html/rfc3501#section-6.4.8).
+    ///
+    /// ```
+    /// use async_imap::types::Flag;
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 2 FETCH (UID 17 FLAGS (\\Flagged \\Seen))\r\n\
+    /// #     A0001 OK STORE completed\r\n", |mut session| async move {
+    /// let updates: Vec<_> = session
+    ///     .uid_store("17", "+FLAGS (\\Flagged)")
+    ///     .await?
+    ///     .try_collect()
+    ///     .await?;
+    /// assert_eq!(updates[0].uid, Some(17));
+    /// assert!(updates[0].flags().contains(&Flag::Flagged));
+    /// # Ok(()) });
+    /// ```
     pub async fn uid_store<S1, S2>(
         &mut self,
         uid_set: S1,
         query: S2,
-    ) -> Result<impl Stream<Item = Result<Fetch>> + '_>
+    ) -> Result<impl Stream<Item = Result<Fetch>> + FusedStream + '_>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
@@ -842,7 +2390,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         let id = self
             .run_command(&format!(
                 "UID STORE {} {}",
-                uid_set.as_ref(),
+                compress_set(uid_set.as_ref()),
                 query.as_ref()
             ))
             .await?;
@@ -854,6 +2402,265 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
         Ok(res)
     }
 
+    /// Sets [`Flag::FORWARDED`] on the messages in `uid_set`, e.g. after the user forwarded them.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n", |mut session| async move {
+    /// session.mark_forwarded("17").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 UID STORE 17 +FLAGS.SILENT ($Forwarded)\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn mark_forwarded<S: AsRef<str>>(&mut self, uid_set: S) -> Result<CommandResult<()>> {
+        self.uid_store_keyword(uid_set.as_ref(), '+', &Flag::FORWARDED)
+            .await
+    }
+
+    /// Sets [`Flag::MDN_SENT`] on the messages in `uid_set`, once a disposition notification has
+    /// been sent for them, so that other clients do not send one again.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n", |mut session| async move {
+    /// session.mark_mdn_sent("17").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 UID STORE 17 +FLAGS.SILENT ($MDNSent)\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn mark_mdn_sent<S: AsRef<str>>(&mut self, uid_set: S) -> Result<CommandResult<()>> {
+        self.uid_store_keyword(uid_set.as_ref(), '+', &Flag::MDN_SENT)
+            .await
+    }
+
+    /// Classifies the messages in `uid_set` as spam if `junk` is true, or as legitimate
+    /// otherwise, by setting [`Flag::JUNK`] or [`Flag::NOT_JUNK`] and removing the other one.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n\
+    /// #     A0002 OK STORE completed\r\n", |mut session| async move {
+    /// session.mark_junk("17:19", true).await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 UID STORE 17:19 -FLAGS.SILENT ($NotJunk)\r\n\
+    /// #      A0002 UID STORE 17:19 +FLAGS.SILENT ($Junk)\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn mark_junk<S: AsRef<str>>(
+        &mut self,
+        uid_set: S,
+        junk: bool,
+    ) -> Result<CommandResult<()>> {
+        let (set, clear) = if junk {
+            (Flag::JUNK, Flag::NOT_JUNK)
+        } else {
+            (Flag::NOT_JUNK, Flag::JUNK)
+        };
+        self.uid_store_keyword(uid_set.as_ref(), '-', &clear)
+            .await?;
+        self.uid_store_keyword(uid_set.as_ref(), '+', &set).await
+    }
+
+    /// Marks the messages in `uid_set` as read, by setting [`Flag::Seen`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n", |mut session| async move {
+    /// session.mark_seen("17,19").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 UID STORE 17,19 +FLAGS.SILENT (\\Seen)\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn mark_seen<S: AsRef<str>>(&mut self, uid_set: S) -> Result<CommandResult<()>> {
+        self.uid_store_keyword(uid_set.as_ref(), '+', &Flag::Seen)
+            .await
+    }
+
+    /// Marks the messages in `uid_set` as unread, by removing [`Flag::Seen`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n", |mut session| async move {
+    /// session.mark_unseen("17").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 UID STORE 17 -FLAGS.SILENT (\\Seen)\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn mark_unseen<S: AsRef<str>>(&mut self, uid_set: S) -> Result<CommandResult<()>> {
+        self.uid_store_keyword(uid_set.as_ref(), '-', &Flag::Seen)
+            .await
+    }
+
+    /// Sets [`Flag::Deleted`] on the messages in `uid_set`, and removes them for good right
+    /// away if `expunge` is true.
+    ///
+    /// If the server supports `UIDPLUS`, only these messages are expunged, with
+    /// [`Session::uid_expunge`]. Otherwise this falls back to [`Session::expunge`], which also
+    /// removes any other message in the mailbox that has [`Flag::Deleted`] set. The capabilities
+    /// of the server are asked for the first time messages are expunged.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n\
+    /// #     * CAPABILITY IMAP4rev1 UIDPLUS\r\n\
+    /// #     A0002 OK CAPABILITY completed\r\n\
+    /// #     * 2 EXPUNGE\r\n\
+    /// #     A0003 OK UID EXPUNGE completed\r\n", |mut session| async move {
+    /// session.delete_messages("17", true).await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 UID STORE 17 +FLAGS.SILENT (\\Deleted)\r\n\
+    /// #      A0002 CAPABILITY\r\n\
+    /// #      A0003 UID EXPUNGE 17\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn delete_messages<S: AsRef<str>>(
+        &mut self,
+        uid_set: S,
+        expunge: bool,
+    ) -> Result<()> {
+        self.uid_store_keyword(uid_set.as_ref(), '+', &Flag::Deleted)
+            .await?;
+        if !expunge {
+            return Ok(());
+        }
+
+        if self.supports_uidplus().await? {
+            self.uid_expunge(uid_set)
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?;
+        } else {
+            self.expunge().await?.try_collect::<Vec<_>>().await?;
+        }
+        Ok(())
+    }
+
+    /// Whether the server supports `UIDPLUS`, asking for its capabilities the first time.
+    async fn supports_uidplus(&mut self) -> Result<bool> {
+        if self.uidplus.is_none() {
+            self.uidplus = Some(self.capabilities().await?.has_str("UIDPLUS"));
+        }
+        Ok(self.uidplus == Some(true))
+    }
+
+    /// Moves the messages in `uid_set` to the mailbox that the server marks as
+    /// [`\Archive`](https://tools.ietf.org/html/rfc6154#section-2), which is looked up with
+    /// `LIST` on the first call. Fails with [`Error::NoSpecialUse`] if there is none.
+    ///
+    /// Gmail has no `\Archive` mailbox, but archives messages by removing the `\Inbox` label,
+    /// which moving them to the `\All` mailbox does. It is used instead on servers that
+    /// advertise Gmail's `X-GM-EXT-1` capability.
+    ///
+    /// If the server does not support `MOVE`, the messages are copied and then
+    /// [deleted](Session::delete_messages) instead. Once `MOVE` has been rejected, later calls
+    /// fall back right away. The copied messages are only expunged if the server also supports
+    /// `UIDPLUS`, since expunging the whole mailbox would remove other messages that have
+    /// [`Flag::Deleted`] set as well. Otherwise they are left in the mailbox with
+    /// [`Flag::Deleted`] set, and are removed by the next [`Session::expunge`] or
+    /// [`Session::close`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+    /// #     * LIST (\\HasNoChildren \\Archive) \"/\" \"Old Mail\"\r\n\
+    /// #     A0001 OK LIST completed\r\n\
+    /// #     * 2 EXPUNGE\r\n\
+    /// #     A0002 OK MOVE completed\r\n", |mut session| async move {
+    /// session.archive("17").await?;
+    /// # assert_eq!(
+    /// #     async_imap::doctest_utils::commands(&session),
+    /// #     "A0001 LIST \"\" \"*\"\r\nA0002 UID MOVE 17 \"Old Mail\"\r\n"
+    /// # );
+    /// # Ok(()) });
+    /// ```
+    pub async fn archive<S: AsRef<str>>(&mut self, uid_set: S) -> Result<CommandResult<()>> {
+        let mailbox = match self.archive_mailbox {
+            Some(ref mailbox) => mailbox.clone(),
+            None => {
+                let mailbox = match self.special_use_mailbox("\\Archive").await {
+                    Err(Error::NoSpecialUse(attribute)) => {
+                        let gmail = self
+                            .capabilities()
+                            .await
+                            .map_or(false, |caps| caps.has_str("X-GM-EXT-1"));
+                        if !gmail {
+                            return Err(Error::NoSpecialUse(attribute));
+                        }
+                        self.special_use_mailbox("\\All").await?
+                    }
+                    res => res?,
+                };
+                self.archive_mailbox = Some(mailbox.clone());
+                mailbox
+            }
+        };
+
+        if !self.move_rejected {
+            match self.uid_mv(uid_set.as_ref(), &mailbox).await {
+                Err(Error::MissingCapability { .. }) => self.move_rejected = true,
+                res => return res.map(|res| res.map(|_| ())),
+            }
+        }
+
+        let res = self
+            .uid_copy(uid_set.as_ref(), validate_str(&mailbox)?)
+            .await?;
+        let expunge = self.supports_uidplus().await?;
+        self.delete_messages(uid_set, expunge).await?;
+        Ok(res.map(|_| ()))
+    }
+
+    /// Finds the mailbox that has the special-use `attribute`, e.g. `\Archive`.
+    async fn special_use_mailbox(&mut self, attribute: &str) -> Result<String> {
+        let id = self.run_command("LIST \"\" \"*\"").await?;
+        let names: Vec<Name> = parse_names(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .try_collect()
+        .await?;
+
+        names
+            .iter()
+            .find(|name| {
+                name.attributes().iter().any(|attr| match attr {
+                    NameAttribute::Custom(a) => a.eq_ignore_ascii_case(attribute),
+                    _ => false,
+                })
+            })
+            .map(|name| name.name().to_string())
+            .ok_or_else(|| Error::NoSpecialUse(attribute.to_string()))
+    }
+
+    /// Adds (`op` is `+`) or removes (`op` is `-`) `flag` on the messages in `uid_set`.
+    async fn uid_store_keyword(
+        &mut self,
+        uid_set: &str,
+        op: char,
+        flag: &Flag<'_>,
+    ) -> Result<CommandResult<()>> {
+        self.run_command_and_check_ok(&format!(
+            "UID STORE {} {}FLAGS.SILENT ({})",
+            compress_set(uid_set),
+            op,
+            flag
+        ))
+        .await
+    }
+
     /// The [`COPY` command](https://tools.ietf.org/html/rfc3501#section-6.4.7) copies the
     /// specified message(s) to the end of the specified destination mailbox.  The flags and
     /// internal date of the message(s) will generally be preserved, and [`Flag::Recent`] will
@@ -861,36 +2668,65 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///
     /// If the `COPY` command is unsuccessful for any reason, the server restores the destination
     /// mailbox to its state before the `COPY` attempt.
+    ///
+    /// If the server supports [`UIDPLUS`](https://tools.ietf.org/html/rfc4315), the [`Uid`]s that
+    /// the copies were given in the destination mailbox are returned as a [`CopyUid`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run(
+    /// #     "A0001 OK [COPYUID 38505 304,319:320 3956:3958] COPY completed\r\n",
+    /// #     |mut session| async move {
+    /// let copied = session.copy("2:4", "Archive").await?;
+    /// if let Some(ref copy_uid) = *copied {
+    ///     for (uid, new_uid) in copy_uid.pairs() {
+    ///         println!("{} is now {}", uid, new_uid);
+    ///     }
+    /// }
+    /// # Ok(()) });
+    /// ```
     pub async fn copy<S1: AsRef<str>, S2: AsRef<str>>(
         &mut self,
         sequence_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
-            "COPY {} {}",
-            sequence_set.as_ref(),
-            mailbox_name.as_ref()
-        ))
-        .await?;
-
-        Ok(())
+    ) -> Result<CommandResult<Option<CopyUid>>> {
+        let res = self
+            .run_command_and_check_ok(&format!(
+                "COPY {} {}",
+                compress_set(sequence_set.as_ref()),
+                mailbox_name.as_ref()
+            ))
+            .await?;
+        let copy_uid = CopyUid::parse(res.code(), res.information());
+        Ok(res.map(|()| copy_uid))
     }
 
     /// Equivalent to [`Session::copy`], except that all identifiers in `sequence_set` are
     /// [`Uid`]s. See also the [`UID` command](https://tools.ietf.org/html/rfc3501#section-6.4.8).
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run(
+    /// #     "A0001 OK [COPYUID 38505 304,319:320 3956:3958] COPY completed\r\n",
+    /// #     |mut session| async move {
+    /// let copied = session.uid_copy("304,319:320", "Archive").await?;
+    /// assert_eq!(copied.into_inner().unwrap().destination, vec![3956..=3958]);
+    /// # Ok(()) });
+    /// ```
     pub async fn uid_copy<S1: AsRef<str>, S2: AsRef<str>>(
         &mut self,
         uid_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
-            "UID COPY {} {}",
-            uid_set.as_ref(),
-            mailbox_name.as_ref()
-        ))
-        .await?;
-
-        Ok(())
+    ) -> Result<CommandResult<Option<CopyUid>>> {
+        let res = self
+            .run_command_and_check_ok(&format!(
+                "UID COPY {} {}",
+                compress_set(uid_set.as_ref()),
+                mailbox_name.as_ref()
+            ))
+            .await?;
+        let copy_uid = CopyUid::parse(res.code(), res.information());
+        Ok(res.map(|()| copy_uid))
     }
 
     /// The [`MOVE` command](https://tools.ietf.org/html/rfc6851#section-3.1) takes two
@@ -923,38 +2759,82 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// orphaned).  The server will generally not leave any message in both mailboxes (it would be
     /// bad for a partial failure to result in a bunch of duplicate messages).  This is true even
     /// if the server returns with [`Error::No`].
+    ///
+    /// The returned [`Moved`] holds the `EXPUNGE` responses for the moved messages, which are
+    /// also delivered on the `unsolicited_responses` channel, and the new [`Uid`]s of the messages
+    /// if the server supports `UIDPLUS`.
+    ///
+    /// If the server rejects the command and does not advertise `MOVE`, this fails with
+    /// [`Error::MissingCapability`] instead, so that callers can fall back to `COPY` and
+    /// `EXPUNGE`.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n\
+    /// #     * 2 EXPUNGE\r\n\
+    /// #     * 1 EXPUNGE\r\n\
+    /// #     A0001 OK MOVE completed\r\n", |mut session| async move {
+    /// let moved = session.mv("1:2", "Archive").await?;
+    /// assert_eq!(moved.expunged, vec![2, 1]);
+    /// assert!(moved.copy_uid.is_some());
+    /// # Ok(()) });
+    /// ```
     pub async fn mv<S1: AsRef<str>, S2: AsRef<str>>(
         &mut self,
         sequence_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
-            "MOVE {} {}",
-            sequence_set.as_ref(),
-            validate_str(mailbox_name.as_ref())?
-        ))
-        .await?;
-
-        Ok(())
+    ) -> Result<CommandResult<Moved>> {
+        let id = self
+            .run_command(&format!(
+                "MOVE {} {}",
+                compress_set(sequence_set.as_ref()),
+                validate_str(mailbox_name.as_ref())?
+            ))
+            .await?;
+        let res = parse_move(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await;
+        self.explain_rejection(res, "MOVE").await
     }
 
     /// Equivalent to [`Session::copy`], except that all identifiers in `sequence_set` are
     /// [`Uid`]s. See also the [`UID` command](https://tools.ietf.org/html/rfc3501#section-6.4.8)
     /// and the [semantics of `MOVE` and `UID
     /// MOVE`](https://tools.ietf.org/html/rfc6851#section-3.3).
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* OK [COPYUID 1511554416 142,399 41:42] Moved UIDs.\r\n\
+    /// #     * 2 EXPUNGE\r\n\
+    /// #     * 1 EXPUNGE\r\n\
+    /// #     A0001 OK MOVE completed\r\n", |mut session| async move {
+    /// let moved = session.uid_mv("142,399", "Archive").await?;
+    /// let copy_uid = moved.copy_uid.as_ref().unwrap();
+    /// assert_eq!(copy_uid.pairs().collect::<Vec<_>>(), vec![(142, 41), (399, 42)]);
+    /// # Ok(()) });
+    /// ```
     pub async fn uid_mv<S1: AsRef<str>, S2: AsRef<str>>(
         &mut self,
         uid_set: S1,
         mailbox_name: S2,
-    ) -> Result<()> {
-        self.run_command_and_check_ok(&format!(
-            "UID MOVE {} {}",
-            uid_set.as_ref(),
-            validate_str(mailbox_name.as_ref())?
-        ))
-        .await?;
-
-        Ok(())
+    ) -> Result<CommandResult<Moved>> {
+        let id = self
+            .run_command(&format!(
+                "UID MOVE {} {}",
+                compress_set(uid_set.as_ref()),
+                validate_str(mailbox_name.as_ref())?
+            ))
+            .await?;
+        let res = parse_move(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id,
+        )
+        .await;
+        self.explain_rejection(res, "MOVE").await
     }
 
     /// The [`LIST` command](https://tools.ietf.org/html/rfc3501#section-6.3.8) returns a subset of
@@ -988,11 +2868,27 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// arguments with wildcards.  The criteria for omitting `INBOX` is whether `SELECT INBOX` will
     /// return failure; it is not relevant whether the user's real `INBOX` resides on this or some
     /// other server.
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+    /// #     * LIST (\\HasChildren) \"/\" \"Archive\"\r\n\
+    /// #     * LIST (\\HasNoChildren) \"/\" \"Archive/2020\"\r\n\
+    /// #     A0001 OK LIST completed\r\n", |mut session| async move {
+    /// let names: Vec<_> = session.list(Some(""), Some("*")).await?.try_collect().await?;
+    /// for name in &names {
+    ///     println!("{} ({:?})", name.name(), name.attributes());
+    /// }
+    /// assert_eq!(names[2].delimiter(), Some("/"));
+    /// # Ok(()) });
+    /// ```
     pub async fn list(
         &mut self,
         reference_name: Option<&str>,
         mailbox_pattern: Option<&str>,
-    ) -> Result<impl Stream<Item = Result<Name>> + '_> {
+    ) -> Result<impl Stream<Item = Result<Name>> + FusedStream + '_> {
         let id = self
             .run_command(&format!(
                 "LIST {} {}",
@@ -1023,11 +2919,23 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///
     /// The server will not unilaterally remove an existing mailbox name from the subscription list
     /// even if a mailbox by that name no longer exists.
+    ///
+    /// ```
+    /// use futures::prelude::*;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* LSUB () \"/\" INBOX\r\n\
+    /// #     * LSUB () \"/\" \"Lists/rust\"\r\n\
+    /// #     A0001 OK LSUB completed\r\n", |mut session| async move {
+    /// let subscribed: Vec<_> = session.lsub(Some(""), Some("*")).await?.try_collect().await?;
+    /// assert_eq!(subscribed[1].name(), "Lists/rust");
+    /// # Ok(()) });
+    /// ```
     pub async fn lsub(
         &mut self,
         reference_name: Option<&str>,
         mailbox_pattern: Option<&str>,
-    ) -> Result<impl Stream<Item = Result<Name>> + '_> {
+    ) -> Result<impl Stream<Item = Result<Name>> + FusedStream + '_> {
         let id = self
             .run_command(&format!(
                 "LSUB {} {}",
@@ -1078,25 +2986,240 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///  - `UNSEEN`: The number of messages which do not have [`Flag::Seen`] set.
     ///
     /// `data_items` is a space-separated list enclosed in parentheses.
+    ///
+    /// ```
+    /// use async_imap::types::UnsolicitedResponse;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* STATUS INBOX (MESSAGES 17 UNSEEN 3)\r\n\
+    /// #     A0001 OK STATUS completed\r\n", |mut session| async move {
+    /// session.status("INBOX", "(MESSAGES UNSEEN)").await?;
+    /// // the counts are delivered like any other `STATUS` response
+    /// for response in session.drain_unsolicited() {
+    ///     if let UnsolicitedResponse::Status { mailbox, attributes } = response {
+    ///         println!("{}: {:?}", mailbox, attributes);
+    ///     }
+    /// }
+    /// # Ok(()) });
+    /// ```
     pub async fn status<S1: AsRef<str>, S2: AsRef<str>>(
         &mut self,
         mailbox_name: S1,
         data_items: S2,
-    ) -> Result<Mailbox> {
-        let id = self
-            .run_command(&format!(
-                "STATUS {} {}",
-                validate_str(mailbox_name.as_ref())?,
-                data_items.as_ref()
-            ))
-            .await?;
-        let mbox = parse_mailbox(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
+    ) -> Result<CommandResult<Mailbox>> {
+        let command = format!(
+            "STATUS {} {}",
+            validate_str(mailbox_name.as_ref())?,
+            data_items.as_ref()
+        );
+        retrying!(
+            self,
+            &command,
+            async {
+                let id = self.run_command(&command).await?;
+                parse_mailbox(
+                    &mut self.conn.stream,
+                    self.unsolicited_responses_tx.clone(),
+                    id,
+                )
+                .await
+            }
+            .await
         )
-        .await?;
-        Ok(mbox)
+    }
+
+    /// Estimates the total size of the messages in `mailbox_name`, e.g. for planning a migration
+    /// or displaying quota usage.
+    ///
+    /// If the server advertises the [`STATUS=SIZE`](https://tools.ietf.org/html/rfc8438)
+    /// capability, the exact size is requested using [`Session::status`]. Otherwise the mailbox
+    /// is opened with [`Session::examine`], which deselects the currently selected mailbox, and
+    /// the `RFC822.SIZE` of up to 200 messages spread evenly over the mailbox is fetched and
+    /// extrapolated. See [`MailboxSize`] for how to interpret the result.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1 STATUS=SIZE\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     * STATUS INBOX (MESSAGES 3 SIZE 12345)\r\n\
+    /// #     A0002 OK STATUS completed\r\n", |mut session| async move {
+    /// let size = session.estimate_mailbox_size("INBOX").await?;
+    /// assert!(size.is_exact());
+    /// println!("{} bytes in {} messages", size.bytes, size.messages);
+    /// # Ok(()) });
+    /// ```
+    pub async fn estimate_mailbox_size<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<MailboxSize> {
+        const SAMPLE_SIZE: u32 = 200;
+
+        let mailbox_name = mailbox_name.as_ref();
+        if self.capabilities().await?.has_str("STATUS=SIZE") {
+            if let Some((messages, bytes)) = self.status_size(mailbox_name).await? {
+                return Ok(MailboxSize::exact(messages, bytes));
+            }
+        }
+
+        let messages = self.examine(mailbox_name).await?.exists;
+        if messages == 0 {
+            return Ok(MailboxSize::exact(0, 0));
+        }
+        let sequence_set = if messages <= SAMPLE_SIZE {
+            "1:*".to_string()
+        } else {
+            (0..SAMPLE_SIZE)
+                .map(|i| {
+                    (1 + u64::from(i) * u64::from(messages) / u64::from(SAMPLE_SIZE)).to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let sizes = self
+            .fetch(sequence_set, "RFC822.SIZE")
+            .await?
+            .try_filter_map(|fetch| future::ready(Ok(fetch.size)))
+            .try_collect::<Vec<u32>>()
+            .await?;
+
+        Ok(MailboxSize::from_sample(messages, &sizes))
+    }
+
+    /// Counts the messages and unseen messages in each of `mailbox_names` without selecting
+    /// them, e.g. to show badges with the number of unread messages.
+    ///
+    /// If the server supports [`LIST-STATUS`](https://tools.ietf.org/html/rfc5819), all counts
+    /// are requested with a single `LIST` command. Otherwise one [`STATUS`
+    /// command](https://tools.ietf.org/html/rfc3501#section-6.3.10) is sent per mailbox, and
+    /// the commands are pipelined. Mailboxes that do not exist or can not be accessed are
+    /// missing from the result.
+    ///
+    /// The result is keyed by the mailbox names as returned by the server.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     * STATUS INBOX (MESSAGES 3 UNSEEN 1)\r\n\
+    /// #     A0002 OK STATUS completed\r\n\
+    /// #     * STATUS Sent (MESSAGES 7 UNSEEN 0)\r\n\
+    /// #     A0003 OK STATUS completed\r\n", |mut session| async move {
+    /// let counts = session.unread_counts(&["INBOX", "Sent"]).await?;
+    /// for (mailbox, count) in &counts {
+    ///     println!("{}: {} of {} unread", mailbox, count.unseen, count.messages);
+    /// }
+    /// assert_eq!(counts["INBOX"].unseen, 1);
+    /// # Ok(()) });
+    /// ```
+    pub async fn unread_counts<I, S>(
+        &mut self,
+        mailbox_names: I,
+    ) -> Result<HashMap<String, UnreadCount>>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let names = mailbox_names
+            .into_iter()
+            .map(|name| validate_str(name.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        if names.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let commands = if self.capabilities().await?.has_str("LIST-STATUS") {
+            vec![format!(
+                "LIST \"\" ({}) RETURN (STATUS (MESSAGES UNSEEN))",
+                names.join(" ")
+            )]
+        } else {
+            names
+                .iter()
+                .map(|name| format!("STATUS {} (MESSAGES UNSEEN)", name))
+                .collect()
+        };
+
+        let mut pending = Vec::with_capacity(commands.len());
+        for command in commands {
+            let id = self.conn.stream.protocol.next_tag();
+            let command = command.into_bytes();
+            self.conn.check_command_len(&id, &command)?;
+            self.conn
+                .stream
+                .encode(Request(Some(id.clone()), command))
+                .await?;
+            pending.push(id);
+        }
+        self.conn.stream.flush().await?;
+
+        let mut counts = HashMap::new();
+        let mut result = Ok(());
+        while !pending.is_empty() {
+            let res = match self.conn.stream.next().await {
+                Some(res) => res?,
+                None => return Err(Error::ConnectionLost),
+            };
+            match res.parsed() {
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } if pending.contains(tag) => {
+                    pending.retain(|id| id != tag);
+                    match status_to_result(status, code, information) {
+                        // the mailbox does not exist, or is not accessible
+                        Err(Error::No(_)) => {}
+                        res if result.is_ok() => result = res,
+                        _ => {}
+                    }
+                }
+                Response::MailboxData(MailboxDatum::Status { mailbox, status }) => {
+                    let mut count = UnreadCount::default();
+                    for attribute in status {
+                        match attribute {
+                            StatusAttribute::Messages(n) => count.messages = *n,
+                            StatusAttribute::Unseen(n) => count.unseen = *n,
+                            _ => {}
+                        }
+                    }
+                    counts.insert(res.unquoted(mailbox).into_owned(), count);
+                }
+                Response::MailboxData(MailboxDatum::List { .. }) => {}
+                _ => handle_unilateral(res, self.unsolicited_responses_tx.clone()).await,
+            }
+        }
+        result?;
+
+        Ok(counts)
+    }
+
+    /// Requests `STATUS <mailbox> (MESSAGES SIZE)`.
+    ///
+    /// imap-proto does not know the `SIZE` attribute, so the response is captured in raw form
+    /// while the command runs and parsed by hand.
+    async fn status_size(&mut self, mailbox_name: &str) -> Result<Option<(u32, u64)>> {
+        let (tx, mut rx) = mpsc::unbounded();
+        let previous = std::mem::replace(&mut self.conn.stream.protocol.unrecognized, Some(tx));
+        let res = self.status(mailbox_name, "(MESSAGES SIZE)").await;
+        self.conn.stream.protocol.unrecognized = previous;
+        res?;
+
+        let mut size = None;
+        while let Ok(Some(raw)) = rx.try_next() {
+            match parse_status_size(raw.as_bytes()) {
+                Some(s) if size.is_none() => size = Some(s),
+                _ => {
+                    // not ours, pass it on to whoever was listening before
+                    if let Some(ref tx) = self.conn.stream.protocol.unrecognized {
+                        let _ = tx.unbounded_send(raw);
+                    }
+                }
+            }
+        }
+
+        Ok(size)
     }
 
     /// This method returns a handle that lets you use the [`IDLE`
@@ -1116,7 +3239,29 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// the client MUST continue to be able to accept unsolicited untagged responses to ANY
     /// command, as specified in the base IMAP specification.
     ///
+    /// If the server rejects `IDLE` and does not advertise it, [`Handle::init`] fails with
+    /// [`Error::MissingCapability`].
+    ///
     /// See [`extensions::idle::Handle`] for details.
+    ///
+    /// ```
+    /// use async_imap::extensions::idle::IdleResponse;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("+ idling\r\n\
+    /// #     * 4 EXISTS\r\n\
+    /// #     A0001 OK IDLE terminated\r\n", |session| async move {
+    /// let mut idle = session.idle();
+    /// idle.init().await?;
+    /// let (wait, _stop) = idle.wait();
+    /// if let IdleResponse::NewData(data) = wait.await? {
+    ///     println!("the mailbox changed: {:?}", data.parsed());
+    /// }
+    /// let session = idle.done().await?;
+    /// # Ok(()) });
+    /// ```
+    ///
+    /// [`Handle::init`]: extensions::idle::Handle::init
     pub fn idle(self) -> extensions::idle::Handle<T> {
         extensions::idle::Handle::new(self)
     }
@@ -1140,27 +3285,86 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     /// Specifically, the server will generally notify the client immediately via an untagged
     /// `EXISTS` response.  If the server does not do so, the client MAY issue a `NOOP` command (or
     /// failing that, a `CHECK` command) after one or more `APPEND` commands.
+    ///
+    /// If the server supports [`UIDPLUS`](https://tools.ietf.org/html/rfc4315), the [`Uid`] of
+    /// the new message is returned as an [`AppendUid`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("+ Ready for literal data\r\n\
+    /// #     A0001 OK [APPENDUID 38505 3955] APPEND completed\r\n", |mut session| async move {
+    /// let message = "From: alice@example.org\r\nSubject: Hello\r\n\r\nHi Bob\r\n";
+    /// let appended = session.append("Drafts", message).await?;
+    /// assert_eq!(appended.into_inner().map(|appended| appended.uid), Some(3955));
+    /// # Ok(()) });
+    /// ```
     pub async fn append<S: AsRef<str>, B: AsRef<[u8]>>(
         &mut self,
         mailbox: S,
         content: B,
-    ) -> Result<()> {
-        let content = content.as_ref();
-        self.run_command(&format!(
-            "APPEND \"{}\" {{{}}}",
-            mailbox.as_ref(),
-            content.len()
-        ))
-        .await?;
+    ) -> Result<CommandResult<Option<AppendUid>>> {
+        self.append_message(mailbox.as_ref(), None, content.as_ref())
+            .await
+    }
+
+    /// Appends `content` to `mailbox` like [`Session::append`], and sets its internal date to
+    /// `internal_date` instead of the time of the command, e.g. to keep the date of a message
+    /// that is moved from another account.
+    ///
+    /// ```
+    /// use chrono::{FixedOffset, TimeZone};
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("+ Ready for literal data\r\n\
+    /// #     A0001 OK APPEND completed\r\n", |mut session| async move {
+    /// let date = FixedOffset::east(3600).ymd(2020, 2, 1).and_hms(10, 0, 0);
+    /// session
+    ///     .append_with_date("Archive", &date, "Subject: Hello\r\n\r\nHi\r\n")
+    ///     .await?;
+    /// # assert!(async_imap::doctest_utils::commands(&session)
+    /// #     .starts_with("A0001 APPEND \"Archive\" \"01-Feb-2020 10:00:00 +0100\" {22}\r\n"));
+    /// # Ok(()) });
+    /// ```
+    pub async fn append_with_date<S: AsRef<str>, B: AsRef<[u8]>, Tz: chrono::TimeZone>(
+        &mut self,
+        mailbox: S,
+        internal_date: &chrono::DateTime<Tz>,
+        content: B,
+    ) -> Result<CommandResult<Option<AppendUid>>> {
+        let date = format_date_time(internal_date);
+        self.append_message(mailbox.as_ref(), Some(&date), content.as_ref())
+            .await
+    }
+
+    async fn append_message(
+        &mut self,
+        mailbox: &str,
+        internal_date: Option<&str>,
+        content: &[u8],
+    ) -> Result<CommandResult<Option<AppendUid>>> {
+        let date = internal_date.map(|d| format!("{} ", d)).unwrap_or_default();
+        let id = self
+            .run_command(&format!(
+                "APPEND \"{}\" {}{{{}}}",
+                mailbox,
+                date,
+                content.len()
+            ))
+            .await?;
 
         match self.read_response().await {
             Some(Ok(res)) => {
                 if let Response::Continue { .. } = res.parsed() {
-                    self.stream.as_mut().write_all(content).await?;
-                    self.stream.as_mut().write_all(b"\r\n").await?;
+                    self.stream.write_all(content).await?;
+                    self.stream.write_all(b"\r\n").await?;
                     self.stream.flush().await?;
-                    self.read_response().await.transpose()?;
-                    Ok(())
+                    self.stream.protocol.count_written(content.len() + 2);
+                    let res = self
+                        .conn
+                        .check_ok(id, Some(self.unsolicited_responses_tx.clone()))
+                        .await?;
+                    let append_uid = AppendUid::parse(res.code(), res.information());
+                    Ok(res.map(|()| append_uid))
                 } else {
                     Err(Error::Append)
                 }
@@ -1214,603 +3418,3863 @@ impl<T: Read + Write + Unpin + fmt::Debug> Session<T> {
     ///
     ///  - `BEFORE <date>`: Messages whose internal date (disregarding time and timezone) is earlier than the specified date.
     ///  - `SINCE <date>`: Messages whose internal date (disregarding time and timezone) is within or later than the specified date.
-    pub async fn search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Seq>> {
-        let id = self
-            .run_command(&format!("SEARCH {}", query.as_ref()))
-            .await?;
-        let seqs = parse_ids(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
-
-        Ok(seqs)
+    ///  - `OLDER <seconds>`, `YOUNGER <seconds>`: Messages whose internal date is at least, or less than, the given number of seconds ago, see [`older`](crate::types::older) and [`younger`](crate::types::younger). These need the [`WITHIN`](https://tools.ietf.org/html/rfc5032) capability, and the search fails with [`Error::MissingCapability`] if the server rejects them without advertising it.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* SEARCH 2 3 5\r\n\
+    /// #     A0001 OK SEARCH completed\r\n", |mut session| async move {
+    /// let found = session.search("UNSEEN FROM \"alice\"").await?;
+    /// assert!(found.contains(&3));
+    /// # Ok(()) });
+    /// ```
+    pub async fn search<S: AsRef<str>>(&mut self, query: S) -> Result<CommandResult<HashSet<Seq>>> {
+        let command = format!("SEARCH {}", compress_query(query.as_ref()));
+        let res = retrying!(
+            self,
+            &command,
+            async {
+                let id = self.run_command(&command).await?;
+                parse_ids(
+                    &mut self.conn.stream,
+                    self.unsolicited_responses_tx.clone(),
+                    id,
+                )
+                .await
+            }
+            .await
+        );
+        self.explain_search_rejection(res, query.as_ref()).await
     }
 
     /// Equivalent to [`Session::search`], except that the returned identifiers
     /// are [`Uid`] instead of [`Seq`]. See also the [`UID`
     /// command](https://tools.ietf.org/html/rfc3501#section-6.4.8).
-    pub async fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Uid>> {
-        let id = self
-            .run_command(&format!("UID SEARCH {}", query.as_ref()))
-            .await?;
-        let uids = parse_ids(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
-
-        Ok(uids)
-    }
-
-    // these are only here because they are public interface, the rest is in `Connection`
-    /// Runs a command and checks if it returns OK.
-    pub async fn run_command_and_check_ok<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
-        self.conn
-            .run_command_and_check_ok(
-                command.as_ref(),
-                Some(self.unsolicited_responses_tx.clone()),
-            )
-            .await?;
-
-        Ok(())
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* SEARCH 17 18\r\n\
+    /// #     A0001 OK SEARCH completed\r\n", |mut session| async move {
+    /// let uids = session.uid_search("SINCE 1-Feb-2020").await?;
+    /// assert_eq!(uids.len(), 2);
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_search<S: AsRef<str>>(
+        &mut self,
+        query: S,
+    ) -> Result<CommandResult<HashSet<Uid>>> {
+        let command = format!("UID SEARCH {}", compress_query(query.as_ref()));
+        let res = retrying!(
+            self,
+            &command,
+            async {
+                let id = self.run_command(&command).await?;
+                parse_ids(
+                    &mut self.conn.stream,
+                    self.unsolicited_responses_tx.clone(),
+                    id,
+                )
+                .await
+            }
+            .await
+        );
+        self.explain_search_rejection(res, query.as_ref()).await
     }
 
-    /// Runs any command passed to it.
-    pub async fn run_command<S: AsRef<str>>(&mut self, command: S) -> Result<RequestId> {
-        let id = self.conn.run_command(command.as_ref()).await?;
-
-        Ok(id)
+    /// Equivalent to [`Session::search`], but returns the matching sequence numbers in
+    /// ascending order, along with the number of response lines they were merged from.
+    ///
+    /// Besides `SEARCH` responses, this also understands the [`ESEARCH`
+    /// responses](https://tools.ietf.org/html/rfc4731) that servers send when `query` starts with
+    /// return options, e.g. `RETURN (ALL) UNSEEN` or `RETURN (PARTIAL 1:100) ALL`.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* SEARCH 5 2 3\r\n\
+    /// #     A0001 OK SEARCH completed\r\n", |mut session| async move {
+    /// let found = session.search_detailed("UNSEEN").await?;
+    /// assert_eq!(found.ids().collect::<Vec<_>>(), vec![2, 3, 5]);
+    /// # Ok(()) });
+    /// ```
+    pub async fn search_detailed<S: AsRef<str>>(
+        &mut self,
+        query: S,
+    ) -> Result<CommandResult<SearchResult>> {
+        let command = format!("SEARCH {}", compress_query(query.as_ref()));
+        let res = retrying!(
+            self,
+            &command,
+            async {
+                let id = self.run_command(&command).await?;
+                self.read_search_result(id).await
+            }
+            .await
+        );
+        self.explain_search_rejection(res, query.as_ref()).await
     }
 
-    /// Runs an arbitrary command, without adding a tag to it.
-    pub async fn run_command_untagged<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
-        self.conn.run_command_untagged(command.as_ref()).await?;
-
-        Ok(())
+    /// Equivalent to [`Session::search_detailed`], except that the returned identifiers are
+    /// [`Uid`]s.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* ESEARCH (TAG \"A0001\") UID ALL 4:6,10\r\n\
+    /// #     A0001 OK SEARCH completed\r\n", |mut session| async move {
+    /// let found = session.uid_search_detailed("RETURN (ALL) UNSEEN").await?;
+    /// assert_eq!(found.len(), 4);
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_search_detailed<S: AsRef<str>>(
+        &mut self,
+        query: S,
+    ) -> Result<CommandResult<SearchResult>> {
+        let command = format!("UID SEARCH {}", compress_query(query.as_ref()));
+        let res = retrying!(
+            self,
+            &command,
+            async {
+                let id = self.run_command(&command).await?;
+                self.read_search_result(id).await
+            }
+            .await
+        );
+        self.explain_search_rejection(res, query.as_ref()).await
     }
 
-    /// Read the next response on the connection.
-    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
-        self.conn.read_response().await
+    /// Tells whether a rejected search used the criteria of an extension that the server does
+    /// not support.
+    async fn explain_search_rejection<R>(&mut self, res: Result<R>, query: &str) -> Result<R> {
+        if uses_within(query) {
+            self.explain_rejection(res, "WITHIN").await
+        } else {
+            res
+        }
     }
-}
 
-impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
-    unsafe_pinned!(stream: ImapStream<T>);
-
-    /// Read the next response on the connection.
-    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
-        self.stream.next().await
+    async fn read_search_result(&mut self, id: RequestId) -> Result<CommandResult<SearchResult>> {
+        let res = parse_search(
+            &mut self.conn.stream,
+            self.unsolicited_responses_tx.clone(),
+            id.clone(),
+        )
+        .await;
+        // `ESEARCH` responses are collected by the stream, since imap-proto can not parse them
+        let esearch = std::mem::replace(&mut self.conn.stream.protocol.esearch, Vec::new());
+
+        let mut res = res?;
+        let mut listed = 0;
+        for (tag, esearch) in esearch {
+            if tag.map_or(true, |tag| tag == id.0) {
+                listed += esearch.listed();
+                if listed > MAX_SEARCH_RESULTS as u64 {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "too many search results",
+                    )));
+                }
+                res.merge(esearch.all.into_iter().chain(esearch.partial).flatten());
+            }
+        }
+        Ok(res)
     }
 
-    pub(crate) async fn run_command_untagged(&mut self, command: &str) -> Result<()> {
-        self.stream
-            .encode(Request(None, command.as_bytes().into()))
-            .await?;
-        self.stream.flush().await?;
-        Ok(())
+    /// Searches for the messages that match `query` like [`Session::search`], but only returns
+    /// what `options` ask for, e.g. just the number of matches, or the lowest and highest one.
+    /// An empty `options` returns all matches.
+    ///
+    /// If the server supports [`ESEARCH`](https://tools.ietf.org/html/rfc4731), the options are
+    /// sent along with the query, so that the server only sends the requested data, and lists
+    /// the matches as ranges rather than one by one. This keeps searches cheap that match most
+    /// of a large mailbox. Otherwise, a plain `SEARCH` is sent and the result is computed from
+    /// its response, except for [`SearchReturn::Update`], which is then ignored.
+    /// [`SearchReturn::Save`] is always sent to the server, and fails with
+    /// [`Error::MissingCapability`] if it does not support `SEARCHRES`.
+    ///
+    /// ```
+    /// use async_imap::types::SearchReturn;
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     A0001 OK [READ-WRITE] SELECT completed\r\n\
+    /// #     * CAPABILITY IMAP4rev1 ESEARCH\r\n\
+    /// #     A0002 OK CAPABILITY completed\r\n\
+    /// #     * ESEARCH (TAG \"A0003\") MIN 2 COUNT 3\r\n\
+    /// #     A0003 OK SEARCH completed\r\n", |mut session| async move {
+    /// session.select("INBOX").await?;
+    /// let unseen = session
+    ///     .search_return(&[SearchReturn::Min, SearchReturn::Count], "UNSEEN")
+    ///     .await?;
+    /// println!("{:?} unseen, the first is {:?}", unseen.count, unseen.min);
+    /// # Ok(()) });
+    /// ```
+    pub async fn search_return<S: AsRef<str>>(
+        &mut self,
+        options: &[SearchReturn],
+        query: S,
+    ) -> Result<CommandResult<EsearchResult>> {
+        self.run_search_return(false, options, query.as_ref()).await
     }
 
-    pub(crate) async fn run_command(&mut self, command: &str) -> Result<RequestId> {
-        let request_id = self.request_ids.next().unwrap(); // safe: never returns Err
-        self.stream
-            .encode(Request(Some(request_id.clone()), command.as_bytes().into()))
-            .await?;
-        self.stream.flush().await?;
-        Ok(request_id)
+    /// Equivalent to [`Session::search_return`], except that the returned identifiers are
+    /// [`Uid`]s.
+    ///
+    /// ```
+    /// use async_imap::types::SearchReturn;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     * SEARCH 17 18 20\r\n\
+    /// #     A0002 OK SEARCH completed\r\n", |mut session| async move {
+    /// // without `ESEARCH`, the result is computed from a plain search
+    /// let newest = session.uid_search_return(&[SearchReturn::Max], "UNSEEN").await?;
+    /// assert_eq!(newest.max, Some(20));
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_search_return<S: AsRef<str>>(
+        &mut self,
+        options: &[SearchReturn],
+        query: S,
+    ) -> Result<CommandResult<EsearchResult>> {
+        self.run_search_return(true, options, query.as_ref()).await
     }
 
-    /// Execute a command and check that the next response is a matching done.
-    pub(crate) async fn run_command_and_check_ok(
+    async fn run_search_return(
         &mut self,
-        command: &str,
-        unsolicited: Option<sync::Sender<UnsolicitedResponse>>,
-    ) -> Result<()> {
-        let id = self.run_command(command).await?;
-        self.check_ok(id, unsolicited).await?;
+        uid: bool,
+        options: &[SearchReturn],
+        query: &str,
+    ) -> Result<CommandResult<EsearchResult>> {
+        if self.esearch.is_none() {
+            self.esearch = Some(self.capabilities().await?.has_str("ESEARCH"));
+        }
+        // saving the result can not be emulated, so the server has to reject it
+        if self.esearch != Some(true) && !options.contains(&SearchReturn::Save) {
+            let res = if uid {
+                self.uid_search_detailed(query).await?
+            } else {
+                self.search_detailed(query).await?
+            };
+            return Ok(res
+                .map(|ids| EsearchResult::from_ids(&ids.ids().collect::<Vec<_>>(), options, uid)));
+        }
 
-        Ok(())
+        let command = format!(
+            "{}SEARCH RETURN ({}) {}",
+            if uid { "UID " } else { "" },
+            return_options(options),
+            compress_query(query)
+        );
+        self.run_esearch(&command, uid, options, "CONTEXT=SEARCH")
+            .await
     }
 
-    pub(crate) async fn check_ok(
+    /// Runs a `SEARCH` or `SORT` command with return options, and merges the `ESEARCH`
+    /// responses to it. If an option that needs `context` or `SEARCHRES` was rejected, tells
+    /// whether the server lacks it.
+    async fn run_esearch(
         &mut self,
-        id: RequestId,
-        unsolicited: Option<sync::Sender<UnsolicitedResponse>>,
-    ) -> Result<()> {
-        while let Some(res) = self.stream.next().await {
-            let res = res?;
-            if let Response::Done {
-                status,
-                code,
-                information,
-                tag,
-            } = res.parsed()
-            {
-                use imap_proto::Status;
-                match status {
-                    Status::Ok => {
-                        if tag != &id {
-                            if let Some(unsolicited) = unsolicited.clone() {
-                                handle_unilateral(res, unsolicited).await;
-                            }
-                            continue;
-                        }
-
-                        return Ok(());
-                    }
-                    Status::Bad => {
-                        return Err(Error::Bad(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    Status::No => {
-                        return Err(Error::No(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    _ => {
-                        return Err(Error::Io(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "status: {:?}, code: {:?}, information: {:?}",
-                                status, code, information
-                            ),
-                        )));
+        command: &str,
+        uid: bool,
+        options: &[SearchReturn],
+        context: &str,
+    ) -> Result<CommandResult<EsearchResult>> {
+        let res = retrying!(
+            self,
+            command,
+            async {
+                let id = self.run_command(command).await?;
+                let res = parse_search(
+                    &mut self.conn.stream,
+                    self.unsolicited_responses_tx.clone(),
+                    id.clone(),
+                )
+                .await;
+                // `ESEARCH` responses are collected by the stream, since imap-proto can not
+                // parse them
+                let esearch = std::mem::replace(&mut self.conn.stream.protocol.esearch, Vec::new());
+
+                let mut result = EsearchResult {
+                    uid,
+                    ..EsearchResult::default()
+                };
+                for (tag, esearch) in esearch {
+                    if tag.map_or(true, |tag| tag == id.0) {
+                        result.merge(esearch);
                     }
                 }
+                Ok(res?.map(|_| result))
             }
+            .await
+        );
+        if options.iter().any(|option| option.needs_context()) {
+            self.explain_rejection(res, context).await
+        } else if options.contains(&SearchReturn::Save) {
+            self.explain_rejection(res, "SEARCHRES").await
+        } else {
+            res
         }
-
-        Err(Error::ConnectionLost)
     }
-}
 
-fn validate_str(value: &str) -> Result<String> {
-    let quoted = quote!(value);
-    if quoted.find('\n').is_some() {
-        return Err(Error::Validate(ValidateError('\n')));
-    }
-    if quoted.find('\r').is_some() {
-        return Err(Error::Validate(ValidateError('\r')));
+    /// Stops the updates of the results of the commands with the given tags, which were started
+    /// with [`SearchReturn::Update`], using the [`CANCELUPDATE`
+    /// command](https://tools.ietf.org/html/rfc5267#section-4.3). The tags are available from
+    /// [`CommandResult::tag`].
+    ///
+    /// ```
+    /// use async_imap::types::SearchReturn;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1 ESEARCH CONTEXT=SEARCH\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     * ESEARCH (TAG \"A0002\") COUNT 3\r\n\
+    /// #     A0002 OK SEARCH completed\r\n\
+    /// #     A0003 OK CANCELUPDATE completed\r\n", |mut session| async move {
+    /// let options = [SearchReturn::Count, SearchReturn::Update];
+    /// let unseen = session.search_return(&options, "UNSEEN").await?;
+    /// // ... and once the count is no longer shown
+    /// if let Some(tag) = unseen.tag().cloned() {
+    ///     session.cancel_update(&[tag]).await?;
+    /// }
+    /// # assert!(async_imap::doctest_utils::commands(&session)
+    /// #     .ends_with("A0003 CANCELUPDATE \"A0002\"\r\n"));
+    /// # Ok(()) });
+    /// ```
+    pub async fn cancel_update(&mut self, tags: &[RequestId]) -> Result<CommandResult<()>> {
+        let tags: Vec<String> = tags.iter().map(|tag| quote!(tag.0)).collect();
+        self.run_command_and_check_ok(&format!("CANCELUPDATE {}", tags.join(" ")))
+            .await
     }
-    Ok(quoted)
-}
-
-#[cfg(test)]
-mod tests {
-    use pretty_assertions::assert_eq;
-
-    use super::super::error::Result;
-    use super::super::mock_stream::MockStream;
-    use super::*;
 
-    use async_std::sync::{Arc, Mutex};
-    use imap_proto::Status;
+    /// Returns the messages that match `query` in the order given by `criteria`, using the
+    /// [`SORT` command](https://tools.ietf.org/html/rfc5256), which the server must advertise.
+    ///
+    /// `criteria` are one or more sort keys, e.g. `REVERSE DATE` or `FROM SUBJECT`, and
+    /// `charset` is the charset of the strings in `query`, e.g. `UTF-8`. `query` takes the same
+    /// criteria as [`Session::search`]. See [`Session::sort_return`] to only get part of the
+    /// sorted messages, e.g. a page of a message list.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* SORT 5 3 4\r\n\
+    /// #     A0001 OK SORT completed\r\n", |mut session| async move {
+    /// let newest_first = session.sort("REVERSE DATE", "UTF-8", "ALL").await?;
+    /// assert_eq!(*newest_first, vec![5, 3, 4]);
+    /// # Ok(()) });
+    /// ```
+    pub async fn sort<S1, S2, S3>(
+        &mut self,
+        criteria: S1,
+        charset: S2,
+        query: S3,
+    ) -> Result<CommandResult<Vec<Seq>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: AsRef<str>,
+    {
+        let command = format!(
+            "SORT ({}) {} {}",
+            criteria.as_ref(),
+            charset.as_ref(),
+            compress_query(query.as_ref())
+        );
+        self.run_sort(&command).await
+    }
 
-    macro_rules! mock_client {
-        ($s:expr) => {
-            Client::new($s)
-        };
+    /// Equivalent to [`Session::sort`], except that the returned identifiers are [`Uid`]s.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* SORT 17 12\r\n\
+    /// #     A0001 OK SORT completed\r\n", |mut session| async move {
+    /// let by_subject = session.uid_sort("SUBJECT", "UTF-8", "SINCE 1-Feb-2020").await?;
+    /// assert_eq!(*by_subject, vec![17, 12]);
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_sort<S1, S2, S3>(
+        &mut self,
+        criteria: S1,
+        charset: S2,
+        query: S3,
+    ) -> Result<CommandResult<Vec<Uid>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: AsRef<str>,
+    {
+        let command = format!(
+            "UID SORT ({}) {} {}",
+            criteria.as_ref(),
+            charset.as_ref(),
+            compress_query(query.as_ref())
+        );
+        self.run_sort(&command).await
     }
 
-    macro_rules! mock_session {
-        ($s:expr) => {
-            Session::new(mock_client!($s).conn)
-        };
+    async fn run_sort(&mut self, command: &str) -> Result<CommandResult<Vec<u32>>> {
+        let res = self.run_capturing(command, b"* SORT ", parse_sort).await;
+        let res = self.explain_rejection(res, "SORT").await?;
+        Ok(res.map(|ids| ids.into_iter().flatten().collect()))
     }
 
-    macro_rules! assert_eq_bytes {
-        ($a:expr, $b:expr, $c:expr) => {
-            assert_eq!(
-                std::str::from_utf8($a).unwrap(),
-                std::str::from_utf8($b).unwrap(),
-                $c
-            )
-        };
+    /// Sorts the messages that match `query` like [`Session::sort`], but only returns what
+    /// `options` ask for, using the [`ESORT`](https://tools.ietf.org/html/rfc5267) extension.
+    /// An empty `options` returns all matches.
+    ///
+    /// This is how message lists that do not fit on the screen are built: a
+    /// [`SearchReturn::Partial`] fetches just the visible part of the sorted list, and
+    /// [`SearchReturn::Update`] keeps it up to date with [`ContextUpdate`]s delivered on the
+    /// `unsolicited_responses` channel. Both need the `CONTEXT=SORT` capability.
+    ///
+    /// If the server does not support `ESORT`, a plain `SORT` is sent and the result is
+    /// computed from its response, except for [`SearchReturn::Update`], which is then ignored.
+    ///
+    /// ```
+    /// use async_imap::types::SearchReturn;
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     A0001 OK [READ-WRITE] SELECT completed\r\n\
+    /// #     * CAPABILITY IMAP4rev1 SORT ESORT CONTEXT=SORT\r\n\
+    /// #     A0002 OK CAPABILITY completed\r\n\
+    /// #     * ESEARCH (TAG \"A0003\") UID COUNT 300 PARTIAL (1:50 90,88,85)\r\n\
+    /// #     A0003 OK SORT completed\r\n\
+    /// #     A0004 OK CANCELUPDATE completed\r\n", |mut session| async move {
+    /// session.select("INBOX").await?;
+    /// let options = [SearchReturn::Count, SearchReturn::Partial(1, 50), SearchReturn::Update];
+    /// let page = session
+    ///     .uid_sort_return(&options, "REVERSE ARRIVAL", "UTF-8", "ALL")
+    ///     .await?;
+    /// println!("showing {:?} of {:?} messages", page.partial, page.count);
+    /// // ... and once the list is closed
+    /// if let Some(tag) = page.tag().cloned() {
+    ///     session.cancel_update(&[tag]).await?;
+    /// }
+    /// # Ok(()) });
+    /// ```
+    pub async fn sort_return<S1, S2, S3>(
+        &mut self,
+        options: &[SearchReturn],
+        criteria: S1,
+        charset: S2,
+        query: S3,
+    ) -> Result<CommandResult<EsearchResult>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: AsRef<str>,
+    {
+        self.run_sort_return(
+            false,
+            options,
+            criteria.as_ref(),
+            charset.as_ref(),
+            query.as_ref(),
+        )
+        .await
     }
 
-    #[async_attributes::test]
-    async fn fetch_body() {
-        let response = "a0 OK Logged in.\r\n\
-                        * 2 FETCH (BODY[TEXT] {3}\r\nfoo)\r\n\
-                        a0 OK FETCH completed\r\n";
-        let mut session = mock_session!(MockStream::new(response.as_bytes().to_vec()));
-        session.read_response().await.unwrap().unwrap();
-        session.read_response().await.unwrap().unwrap();
+    /// Equivalent to [`Session::sort_return`], except that the returned identifiers are
+    /// [`Uid`]s.
+    ///
+    /// ```
+    /// use async_imap::types::SearchReturn;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* CAPABILITY IMAP4rev1 SORT\r\n\
+    /// #     A0001 OK CAPABILITY completed\r\n\
+    /// #     * SORT 17 12 5\r\n\
+    /// #     A0002 OK SORT completed\r\n", |mut session| async move {
+    /// let sorted = session
+    ///     .uid_sort_return(&[SearchReturn::Count], "ARRIVAL", "UTF-8", "UNSEEN")
+    ///     .await?;
+    /// assert_eq!(sorted.count, Some(3));
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_sort_return<S1, S2, S3>(
+        &mut self,
+        options: &[SearchReturn],
+        criteria: S1,
+        charset: S2,
+        query: S3,
+    ) -> Result<CommandResult<EsearchResult>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: AsRef<str>,
+    {
+        self.run_sort_return(
+            true,
+            options,
+            criteria.as_ref(),
+            charset.as_ref(),
+            query.as_ref(),
+        )
+        .await
     }
 
-    #[async_attributes::test]
-    async fn readline_delay_read() {
-        let greeting = "* OK Dovecot ready.\r\n";
-        let mock_stream = MockStream::default()
-            .with_buf(greeting.as_bytes().to_vec())
-            .with_delay();
+    async fn run_sort_return(
+        &mut self,
+        uid: bool,
+        options: &[SearchReturn],
+        criteria: &str,
+        charset: &str,
+        query: &str,
+    ) -> Result<CommandResult<EsearchResult>> {
+        if self.esort.is_none() {
+            self.esort = Some(self.capabilities().await?.has_str("ESORT"));
+        }
+        if self.esort != Some(true) && !options.contains(&SearchReturn::Save) {
+            let res = if uid {
+                self.uid_sort(criteria, charset, query).await?
+            } else {
+                self.sort(criteria, charset, query).await?
+            };
+            return Ok(res.map(|ids| EsearchResult::from_ids(&ids, options, uid)));
+        }
 
-        let mut client = mock_client!(mock_stream);
+        let command = format!(
+            "{}SORT RETURN ({}) ({}) {} {}",
+            if uid { "UID " } else { "" },
+            return_options(options),
+            criteria,
+            charset,
+            compress_query(query)
+        );
+        self.run_esearch(&command, uid, options, "CONTEXT=SORT")
+            .await
+    }
+
+    /// Groups the messages that match `query` into conversations with the [`THREAD`
+    /// command](https://tools.ietf.org/html/rfc5256), so that a conversation view can be shown
+    /// without fetching the headers of every message in the mailbox.
+    ///
+    /// `algorithm` is the threading algorithm, e.g. `REFERENCES` or `ORDEREDSUBJECT`, which the
+    /// server must advertise as a `THREAD=<algorithm>` capability, and `charset` is the charset
+    /// of the strings in `query`, e.g. `UTF-8`. `query` takes the same criteria as
+    /// [`Session::search`]. The threads refer to the messages by sequence number.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 EXISTS\r\n\
+    /// #     A0001 OK [READ-WRITE] SELECT completed\r\n\
+    /// #     * THREAD (2)(3 6 (4 23)(44 7 96))\r\n\
+    /// #     A0002 OK THREAD completed\r\n", |mut session| async move {
+    /// session.select("INBOX").await?;
+    /// for thread in session.thread("REFERENCES", "UTF-8", "ALL").await?.iter() {
+    ///     println!("conversation of {} messages: {:?}", thread.len(), thread.ids());
+    /// }
+    /// # Ok(()) });
+    /// ```
+    pub async fn thread<S1, S2, S3>(
+        &mut self,
+        algorithm: S1,
+        charset: S2,
+        query: S3,
+    ) -> Result<CommandResult<Vec<Thread>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: AsRef<str>,
+    {
+        let command = format!(
+            "THREAD {} {} {}",
+            algorithm.as_ref(),
+            charset.as_ref(),
+            compress_query(query.as_ref())
+        );
+        self.run_thread(&command, algorithm.as_ref()).await
+    }
+
+    /// Equivalent to [`Session::thread`], except that the threads refer to the messages by
+    /// [`Uid`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* THREAD (17 (18)(19))\r\n\
+    /// #     A0001 OK THREAD completed\r\n", |mut session| async move {
+    /// let threads = session.uid_thread("REFERENCES", "UTF-8", "ALL").await?;
+    /// assert_eq!(threads[0].ids(), vec![17, 18, 19]);
+    /// # Ok(()) });
+    /// ```
+    pub async fn uid_thread<S1, S2, S3>(
+        &mut self,
+        algorithm: S1,
+        charset: S2,
+        query: S3,
+    ) -> Result<CommandResult<Vec<Thread>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+        S3: AsRef<str>,
+    {
+        let command = format!(
+            "UID THREAD {} {} {}",
+            algorithm.as_ref(),
+            charset.as_ref(),
+            compress_query(query.as_ref())
+        );
+        self.run_thread(&command, algorithm.as_ref()).await
+    }
+
+    /// Runs a `THREAD` or `UID THREAD` command.
+    async fn run_thread(
+        &mut self,
+        command: &str,
+        algorithm: &str,
+    ) -> Result<CommandResult<Vec<Thread>>> {
+        let res = self
+            .run_capturing(command, b"* THREAD ", parse_thread)
+            .await;
+        let capability = format!("THREAD={}", algorithm.to_ascii_uppercase());
+        let res = self.explain_rejection(res, &capability).await?;
+        Ok(res.map(|threads| threads.into_iter().flatten().collect()))
+    }
+
+    /// Asks for the [namespaces](https://tools.ietf.org/html/rfc2342) of the server with the
+    /// `NAMESPACE` command, which the server must advertise. The prefix of the personal
+    /// namespace tells how the names of new mailboxes have to start, e.g. with `INBOX.` on
+    /// servers such as Courier, where all mailboxes of the user are below the `INBOX`.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* NAMESPACE ((\"INBOX.\" \".\")) NIL NIL\r\n\
+    /// #     A0001 OK NAMESPACE completed\r\n\
+    /// #     A0002 OK CREATE completed\r\n", |mut session| async move {
+    /// let namespaces = session.namespace().await?;
+    /// let archive = match namespaces.personal() {
+    ///     Some(personal) => personal.mailbox("Archive"),
+    ///     None => "Archive".to_string(),
+    /// };
+    /// session.create(&archive).await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn namespace(&mut self) -> Result<CommandResult<Namespaces>> {
+        let res = self
+            .run_capturing("NAMESPACE", b"* NAMESPACE ", parse_namespace)
+            .await;
+        let res = self.explain_rejection(res, "NAMESPACE").await?;
+        Ok(res.map(|mut namespaces| namespaces.pop().unwrap_or_default()))
+    }
+
+    /// Asks for the usage and limits of the resources under the quota root `root` with the
+    /// [`GETQUOTA` command](https://tools.ietf.org/html/rfc9208#section-4.2), which requires the
+    /// `QUOTA` capability. The names of the roots that apply to a mailbox can be found with
+    /// [`Session::get_quota_root`].
+    ///
+    /// Resources are e.g. `STORAGE`, in units of 1024 octets, and `MESSAGE`, the number of
+    /// messages, see [`QuotaResource`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* QUOTA \"\" (STORAGE 10 512)\r\n\
+    /// #     A0001 OK GETQUOTA completed\r\n", |mut session| async move {
+    /// let quota = session.get_quota("").await?;
+    /// let storage = quota.resource("STORAGE").unwrap();
+    /// println!("{} of {} KiB used", storage.usage, storage.limit);
+    /// # Ok(()) });
+    /// ```
+    pub async fn get_quota<S: AsRef<str>>(&mut self, root: S) -> Result<CommandResult<Quota>> {
+        let root = root.as_ref();
+        let command = format!("GETQUOTA {}", validate_str(root)?);
+        let res = self.run_quota(&command).await?;
+        Ok(res.map(|responses| {
+            let mut quotas = quotas(responses);
+            let position = quotas.iter().rposition(|quota| quota.root == root);
+            match position {
+                Some(i) => quotas.swap_remove(i),
+                None => Quota {
+                    root: root.to_string(),
+                    resources: Vec::new(),
+                },
+            }
+        }))
+    }
+
+    /// Asks for the quota roots of the mailbox `mailbox_name`, and for their usage and limits,
+    /// with the [`GETQUOTAROOT` command](https://tools.ietf.org/html/rfc9208#section-4.3), which
+    /// requires the `QUOTA` capability.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* QUOTAROOT INBOX \"\"\r\n\
+    /// #     * QUOTA \"\" (STORAGE 470 512)\r\n\
+    /// #     A0001 OK GETQUOTAROOT completed\r\n", |mut session| async move {
+    /// let root = session.get_quota_root("INBOX").await?;
+    /// for quota in &root.quotas {
+    ///     if let Some(storage) = quota.resource("STORAGE") {
+    ///         if storage.fraction_used() > 0.9 {
+    ///             println!("{} of {} KiB used", storage.usage, storage.limit);
+    ///         }
+    ///     }
+    /// }
+    /// # Ok(()) });
+    /// ```
+    pub async fn get_quota_root<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+    ) -> Result<CommandResult<QuotaRoot>> {
+        let mailbox_name = mailbox_name.as_ref();
+        let command = format!("GETQUOTAROOT {}", validate_str(mailbox_name)?);
+        let res = self.run_quota(&command).await?;
+        Ok(res.map(|responses| {
+            let mut quota_root = QuotaRoot {
+                mailbox: mailbox_name.to_string(),
+                ..QuotaRoot::default()
+            };
+            for response in &responses {
+                if let UnsolicitedResponse::QuotaRoot { mailbox, roots } = response {
+                    quota_root.mailbox = mailbox.clone();
+                    quota_root.roots = roots.clone();
+                }
+            }
+            quota_root.quotas = quotas(responses);
+            quota_root
+        }))
+    }
+
+    /// Changes the limits of the resources under the quota root `root` with the
+    /// [`SETQUOTA` command](https://tools.ietf.org/html/rfc9208#section-4.1), which requires the
+    /// `QUOTA` capability and is usually reserved for administrators. `limits` are the names of
+    /// the resources and their new limits, and resources that are left out are no longer
+    /// limited. Returns the quota as the server reported it afterwards, which is empty if it did
+    /// not.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* QUOTA \"\" (STORAGE 10 1024)\r\n\
+    /// #     A0001 OK SETQUOTA completed\r\n", |mut session| async move {
+    /// let quota = session.set_quota("", &[("STORAGE", 1024)]).await?;
+    /// assert_eq!(quota.resource("STORAGE").map(|storage| storage.limit), Some(1024));
+    /// # Ok(()) });
+    /// ```
+    pub async fn set_quota<S: AsRef<str>>(
+        &mut self,
+        root: S,
+        limits: &[(&str, u64)],
+    ) -> Result<CommandResult<Quota>> {
+        let root = root.as_ref();
+        let limits = limits
+            .iter()
+            .map(|(name, limit)| format!("{} {}", name, limit))
+            .collect::<Vec<_>>();
+        let command = format!("SETQUOTA {} ({})", validate_str(root)?, limits.join(" "));
+        let res = self.run_quota(&command).await?;
+        Ok(res.map(|responses| {
+            quotas(responses).pop().unwrap_or_else(|| Quota {
+                root: root.to_string(),
+                resources: Vec::new(),
+            })
+        }))
+    }
+
+    /// Runs one of the quota commands, and returns the `QUOTA` and `QUOTAROOT` responses to it.
+    /// The other responses that imap-proto can not parse either, such as `ACL`, are passed on to
+    /// the unsolicited responses as usual.
+    async fn run_quota(
+        &mut self,
+        command: &str,
+    ) -> Result<CommandResult<Vec<UnsolicitedResponse>>> {
+        let previous = self.conn.stream.protocol.extension_data.replace(Vec::new());
+        let res = self.run_command_and_check_ok(command).await;
+        let captured = std::mem::replace(&mut self.conn.stream.protocol.extension_data, previous);
+
+        let mut responses = Vec::new();
+        for response in captured.unwrap_or_default() {
+            match response {
+                UnsolicitedResponse::Quota { .. } | UnsolicitedResponse::QuotaRoot { .. } => {
+                    responses.push(response)
+                }
+                response => self.unsolicited_responses_tx.send(response).await,
+            }
+        }
+        let res = self.explain_rejection(res, "QUOTA").await?;
+        Ok(res.map(|()| responses))
+    }
+
+    /// Runs `command`, and parses the responses to it that imap-proto does not know, such as
+    /// `THREAD`, with `parse`. They are captured in raw form while the command runs. Responses
+    /// that start with `name` but can not be parsed fail the command, and all others are passed
+    /// on to the channel of [`Session::unrecognized_responses`], if any.
+    async fn run_capturing<R>(
+        &mut self,
+        command: &str,
+        name: &[u8],
+        parse: fn(&[u8]) -> Option<R>,
+    ) -> Result<CommandResult<Vec<R>>> {
+        let (tx, mut rx) = mpsc::unbounded();
+        let previous = std::mem::replace(&mut self.conn.stream.protocol.unrecognized, Some(tx));
+        let res = self.run_command_and_check_ok(command).await;
+        self.conn.stream.protocol.unrecognized = previous;
+
+        let mut parsed = Vec::new();
+        let mut malformed = None;
+        while let Ok(Some(raw)) = rx.try_next() {
+            match parse(raw.as_bytes()) {
+                Some(value) => parsed.push(value),
+                None if raw.as_bytes().starts_with(name) => malformed = Some(raw),
+                None => {
+                    // not ours, pass it on to whoever was listening before
+                    if let Some(ref tx) = self.conn.stream.protocol.unrecognized {
+                        let _ = tx.unbounded_send(raw);
+                    }
+                }
+            }
+        }
+        let res = res?;
+        if let Some(raw) = malformed {
+            return Err(Error::Parse(ParseError::Invalid(raw.0)));
+        }
+        Ok(res.map(|()| parsed))
+    }
+
+    /// Opts into receiving untagged responses that can not be parsed, instead of failing the
+    /// command during which they were received.
+    ///
+    /// Servers may send vendor-specific untagged responses (e.g. `* XSTATE ...`) that this crate
+    /// does not know about. By default these abort the current command with a parse error. Once
+    /// this method has been called, they are skipped and delivered in raw form on the returned
+    /// channel instead, while all other responses are still handled as usual. Calling this
+    /// method again replaces the previous channel; dropping the receiver restores the default
+    /// behavior.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* XSTATE idle\r\n\
+    /// #     A0001 OK NOOP completed\r\n", |mut session| async move {
+    /// let mut unrecognized = session.unrecognized_responses();
+    /// session.noop().await?;
+    /// let raw = unrecognized.try_next().unwrap().unwrap();
+    /// assert_eq!(raw.as_bytes(), b"* XSTATE idle\r\n");
+    /// # Ok(()) });
+    /// ```
+    pub fn unrecognized_responses(&mut self) -> mpsc::UnboundedReceiver<RawResponse> {
+        let (tx, rx) = mpsc::unbounded();
+        self.conn.stream.protocol.unrecognized = Some(tx);
+        rx
+    }
+
+    /// Returns a stream of the flags of messages in the selected mailbox, as reported by the
+    /// server in `FETCH` responses, e.g. to keep flag badges in a message list up to date.
+    ///
+    /// Events are derived from every response that includes `FLAGS`: the results of
+    /// [`Session::fetch`] and [`Session::store`], as well as unsolicited updates, e.g. when
+    /// another client marks a message as seen. Those responses are still returned or delivered
+    /// on [`Session::unsolicited_responses`] as usual.
+    ///
+    /// The stream is scoped to the mailbox that is currently selected: it ends when another
+    /// mailbox is selected or the mailbox is closed, so call this again after selecting.
+    /// Calling this method again replaces the previous stream.
+    ///
+    /// ```
+    /// use async_imap::types::Flag;
+    ///
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 3 FETCH (FLAGS (\\Seen))\r\n\
+    /// #     A0001 OK NOOP completed\r\n", |mut session| async move {
+    /// let mut events = session.flag_events();
+    /// // another client marked a message as read
+    /// session.noop().await?;
+    /// let event = events.try_next().unwrap().unwrap();
+    /// assert_eq!(event.message, 3);
+    /// assert!(event.flags.contains(&Flag::Seen));
+    /// # Ok(()) });
+    /// ```
+    pub fn flag_events(&mut self) -> mpsc::UnboundedReceiver<FlagEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.conn.stream.protocol.flag_events = Some(tx);
+        rx
+    }
+
+    /// Starts keeping track of the [`Uid`] of every message in the selected mailbox, so that
+    /// sequence numbers and UIDs can be translated with [`Session::uid_for_seq`] and
+    /// [`Session::seq_for_uid`] without asking the server.
+    ///
+    /// This runs `UID SEARCH ALL` to learn the UIDs of all messages. From then on, the map is
+    /// updated from `EXISTS`, `EXPUNGE` and `VANISHED` responses, and learns the UIDs of new
+    /// messages from every `FETCH` response that includes them, e.g. those of
+    /// [`Session::uid_fetch`]. Selecting another mailbox empties the map, so call this again
+    /// after selecting to fill it.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* SEARCH 3 5 8\r\n\
+    /// #     A0001 OK SEARCH completed\r\n", |mut session| async move {
+    /// session.track_uids().await?;
+    /// assert_eq!(session.uid_for_seq(2), Some(5));
+    /// assert_eq!(session.seq_for_uid(8), Some(3));
+    /// # Ok(()) });
+    /// ```
+    pub async fn track_uids(&mut self) -> Result<()> {
+        let protocol = &mut self.conn.stream.protocol;
+        if protocol.uid_map.is_none() {
+            protocol.uid_map = Some(UidMap::default());
+        }
+        let uids = self.uid_search_detailed("ALL").await?;
+        self.conn.stream.protocol.uid_map = Some(UidMap::from_uids(uids.ids()));
+        Ok(())
+    }
+
+    /// The [`Uid`] of the message with sequence number `seq` in the selected mailbox, if it is
+    /// known. Always `None` unless [`Session::track_uids`] has been called.
+    pub fn uid_for_seq(&self, seq: Seq) -> Option<Uid> {
+        self.conn.stream.protocol.uid_map.as_ref()?.uid(seq)
+    }
+
+    /// The sequence number of the message with `uid` in the selected mailbox, if it is known.
+    /// Always `None` unless [`Session::track_uids`] has been called.
+    pub fn seq_for_uid(&self, uid: Uid) -> Option<Seq> {
+        self.conn.stream.protocol.uid_map.as_ref()?.seq(uid)
+    }
+
+    /// Returns all [`UnsolicitedResponse`]s that are currently queued on
+    /// [`Session::unsolicited_responses`], without waiting for new ones to arrive.
+    ///
+    /// This is convenient for clients that poll (e.g. by calling [`Session::noop`] periodically)
+    /// rather than consuming the channel from a separate task.
+    pub fn drain_unsolicited(&mut self) -> Vec<UnsolicitedResponse> {
+        let mut responses = Vec::new();
+        while let Ok(Some(response)) = self.unsolicited_responses.try_next() {
+            responses.push(response);
+        }
+
+        responses
+    }
+
+    /// Returns the most recent unsolicited responses about the selected mailbox, oldest first,
+    /// whether or not they have already been taken from [`Session::unsolicited_responses`].
+    ///
+    /// This lets a consumer that starts listening some time after [`Session::select`], e.g. a
+    /// UI attaching to a connection that runs in the background, catch up on what it missed.
+    /// The responses are forgotten when another mailbox is selected or examined, and only the
+    /// last 32 are kept unless configured otherwise with [`Session::set_replay_capacity`].
+    /// [`UnsolicitedResponse::Other`] responses are never kept.
+    pub fn replay_unsolicited(&self) -> Vec<UnsolicitedResponse> {
+        self.replay.lock().unwrap().responses()
+    }
+
+    /// Sets how many unsolicited responses [`Session::replay_unsolicited`] keeps. `0` disables
+    /// keeping them.
+    pub fn set_replay_capacity(&mut self, capacity: usize) {
+        self.replay.lock().unwrap().set_capacity(capacity);
+    }
+
+    /// Queues `command` to be sent by the next call to [`Session::flush`], and returns the tag
+    /// that will be used for it.
+    ///
+    /// Queued commands are sent in exactly the order in which they were queued. A `STORE` queued
+    /// before an `EXPUNGE` is therefore guaranteed to be executed first, even if the two are
+    /// queued by different tasks sharing the session (e.g. through a mutex) and regardless of
+    /// which task ends up calling `flush`. Nothing is sent until `flush` is called.
+    ///
+    /// Commands that are only valid before logging in are refused with [`Error::WrongState`],
+    /// like with [`Session::run_command`].
+    pub fn enqueue<S: AsRef<str>>(&mut self, command: S) -> Result<RequestId> {
+        check_authenticated_state(command.as_ref())?;
+        let id = self.conn.stream.protocol.next_tag();
+        self.queued.push(Request(
+            Some(id.clone()),
+            command.as_ref().as_bytes().into(),
+        ));
+        Ok(id)
+    }
+
+    /// The number of commands queued with [`Session::enqueue`] that have not been sent yet.
+    pub fn queued_commands(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// The tags of the commands that have been sent, but not completed by the server yet, oldest
+    /// first.
+    ///
+    /// Together with [`Session::queued_commands`], this tells how far the server is behind, e.g.
+    /// to show that synchronization is paused while it is busy, or to hold back new commands.
+    /// [`Connection::stats`] reports the most commands that were ever in flight at once.
+    pub fn pending_commands(&self) -> &[RequestId] {
+        self.conn.stream.protocol.in_flight()
+    }
+
+    /// Sends all commands queued with [`Session::enqueue`], in order, and waits until the server
+    /// has completed all of them.
+    ///
+    /// The commands are pipelined, i.e. all of them are written before waiting for the first
+    /// completion. Untagged responses received in the meantime are sent on
+    /// [`Session::unsolicited_responses`]. If a command fails, the completions of the remaining
+    /// commands are still consumed, and the first error is returned.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK STORE completed\r\n\
+    /// #     * 1 EXPUNGE\r\n\
+    /// #     A0002 OK EXPUNGE completed\r\n", |mut session| async move {
+    /// session.enqueue("UID STORE 17 +FLAGS.SILENT (\\Deleted)")?;
+    /// session.enqueue("EXPUNGE")?;
+    /// assert_eq!(session.queued_commands(), 2);
+    /// session.flush().await?;
+    /// assert_eq!(session.queued_commands(), 0);
+    /// # Ok(()) });
+    /// ```
+    pub async fn flush(&mut self) -> Result<()> {
+        for request in &self.queued {
+            if let Some(ref id) = request.0 {
+                self.conn.check_command_len(id, &request.1)?;
+            }
+        }
+        let queued = std::mem::replace(&mut self.queued, Vec::new());
+        let mut pending = Vec::with_capacity(queued.len());
+        for request in queued {
+            if let Some(ref id) = request.0 {
+                pending.push(id.clone());
+            }
+            self.conn.stream.encode(request).await?;
+        }
+        self.conn.stream.flush().await?;
+
+        let mut result = Ok(());
+        while !pending.is_empty() {
+            let res = match self.conn.stream.next().await {
+                Some(res) => res?,
+                None => return Err(Error::ConnectionLost),
+            };
+            match res.parsed() {
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } if pending.contains(tag) => {
+                    pending.retain(|id| id != tag);
+                    if result.is_ok() {
+                        result = status_to_result(status, code, information);
+                    }
+                }
+                _ => handle_unilateral(res, self.unsolicited_responses_tx.clone()).await,
+            }
+        }
+
+        result
+    }
+
+    // these are only here because they are public interface, the rest is in `Connection`
+
+    /// Streams the untagged responses the server sends until it completes the command tagged
+    /// `tag`, which was sent with [`Session::run_command`]. The completion itself ends the
+    /// stream, and is returned by a subsequent call to [`Session::check_done`].
+    ///
+    /// Together, these allow using commands and extensions that the typed API does not support
+    /// yet:
+    ///
+    /// ```
+    /// # use futures::prelude::*;
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* QUOTAROOT INBOX \"\"\r\n\
+    /// #     * QUOTA \"\" (STORAGE 470 512)\r\n\
+    /// #     A0001 OK GETQUOTAROOT completed\r\n", |mut session| async move {
+    /// let tag = session.run_command("GETQUOTAROOT INBOX").await?;
+    /// let responses: Vec<_> = session.responses(&tag).try_collect().await?;
+    /// let completion = session.check_done(&tag).await?;
+    /// # Ok(()) });
+    /// ```
+    ///
+    /// Responses that arrive in the meantime which belong to other commands are part of the
+    /// stream as well, so callers should only look at the kinds of responses they expect.
+    pub fn responses<'a>(
+        &'a mut self,
+        tag: &RequestId,
+    ) -> impl Stream<Item = Result<ResponseData>> + FusedStream + 'a {
+        let tag = tag.clone();
+        let responses = stream::unfold(Some(self), move |session| {
+            let tag = tag.clone();
+            async move {
+                let session = session?;
+                match session.conn.stream.next().await {
+                    Some(Ok(res)) if res.request_id() == Some(&tag) => {
+                        session.done = Some(res);
+                        None
+                    }
+                    Some(Ok(res)) => Some((Ok(res), Some(session))),
+                    Some(Err(err)) => Some((Err(err.into()), None)),
+                    None => Some((Err(Error::ConnectionLost), None)),
+                }
+            }
+        });
+        responses.fuse()
+    }
+
+    /// Waits until the server completes the command tagged `tag`, which was sent with
+    /// [`Session::run_command`], and checks that it succeeded. Untagged responses that are
+    /// still outstanding are sent on [`Session::unsolicited_responses`].
+    ///
+    /// If the completion was already received by the stream returned by
+    /// [`Session::responses`], it is checked directly.
+    pub async fn check_done(&mut self, tag: &RequestId) -> Result<CommandResult<()>> {
+        if let Some(done) = self.done.take() {
+            if done.request_id() == Some(tag) {
+                return complete((), done);
+            }
+            self.done = Some(done);
+        }
+
+        while let Some(res) = self.conn.stream.next().await {
+            let res = res?;
+            if res.request_id() == Some(tag) {
+                return complete((), res);
+            }
+            handle_unilateral(res, self.unsolicited_responses_tx.clone()).await;
+        }
+
+        Err(Error::ConnectionLost)
+    }
+
+    /// Returns [`Error::MissingCapability`] if the server does not advertise `capability`, or
+    /// `None` if it does or its capabilities could not be fetched.
+    pub(crate) async fn missing_capability(&mut self, capability: &str) -> Option<Error> {
+        self.capabilities().await.ok()?.require(capability).err()
+    }
+
+    /// Turns the rejection of an extension command into [`Error::MissingCapability`] if the server
+    /// does not advertise `capability`, so callers learn why it failed rather than getting a
+    /// generic `BAD` or `NO`. Costs a `CAPABILITY` round trip, but only when the command failed.
+    async fn explain_rejection<R>(&mut self, res: Result<R>, capability: &str) -> Result<R> {
+        match res {
+            Err(Error::Bad(_)) | Err(Error::No(_)) => {
+                match self.missing_capability(capability).await {
+                    Some(err) => Err(err),
+                    None => res,
+                }
+            }
+            res => res,
+        }
+    }
+
+    /// Runs a command and checks if it returns OK. If the command is safe to repeat, it is
+    /// retried according to [`Session::set_retry_policy`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("A0001 OK UNSELECT completed\r\n",
+    /// #     |mut session| async move {
+    /// session.run_command_and_check_ok("UNSELECT").await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn run_command_and_check_ok<S: AsRef<str>>(
+        &mut self,
+        command: S,
+    ) -> Result<CommandResult<()>> {
+        let command = command.as_ref();
+        check_authenticated_state(command)?;
+        retrying!(
+            self,
+            command,
+            self.conn
+                .run_command_and_check_ok(command, Some(self.unsolicited_responses_tx.clone()))
+                .await
+        )
+    }
+
+    /// Sends any command, and returns the tag it was sent with. Use [`Session::responses`] and
+    /// [`Session::check_done`] to process the server's answer.
+    ///
+    /// Commands that are only valid before logging in are refused with [`Error::WrongState`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* 4 EXISTS\r\n\
+    /// #     A0001 OK NOOP completed\r\n", |mut session| async move {
+    /// let tag = session.run_command("NOOP").await?;
+    /// session.check_done(&tag).await?;
+    /// # Ok(()) });
+    /// ```
+    pub async fn run_command<S: AsRef<str>>(&mut self, command: S) -> Result<RequestId> {
+        check_authenticated_state(command.as_ref())?;
+        let id = self.conn.run_command(command.as_ref()).await?;
+
+        Ok(id)
+    }
+
+    /// Runs an arbitrary command, without adding a tag to it.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("", |mut session| async move {
+    /// // e.g. the continuation of a command that was sent with `run_command`
+    /// session.run_command_untagged("DONE").await?;
+    /// # assert_eq!(async_imap::doctest_utils::commands(&session), "DONE\r\n");
+    /// # Ok(()) });
+    /// ```
+    pub async fn run_command_untagged<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
+        self.conn.run_command_untagged(command.as_ref()).await?;
+
+        Ok(())
+    }
+
+    /// Read the next response on the connection.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* OK [ALERT] System shutdown in 10 minutes\r\n",
+    /// #     |mut session| async move {
+    /// if let Some(response) = session.read_response().await {
+    ///     println!("{:?}", response?.parsed());
+    /// }
+    /// # Ok(()) });
+    /// ```
+    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
+        self.conn.read_response().await
+    }
+
+    /// Turns the session into a [`Passthrough`], which forwards raw commands and responses,
+    /// e.g. to proxy a client once the session has logged in on its behalf. Commands queued with
+    /// [`Session::enqueue`] are dropped.
+    ///
+    /// ```
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("a1 OK NOOP completed\r\n", |session| async move {
+    /// let mut passthrough = session.into_passthrough();
+    /// passthrough.send(b"a1 NOOP\r\n").await?;
+    /// let response = passthrough.read_response().await.unwrap()?;
+    /// assert_eq!(response.as_bytes(), b"a1 OK NOOP completed\r\n");
+    /// # Ok(()) });
+    /// ```
+    pub fn into_passthrough(self) -> Passthrough<T> {
+        Passthrough::from_stream(self.conn.stream)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + ChannelBinding> Connection<T> {
+    /// Returns the [channel binding](https://tools.ietf.org/html/rfc5056) data of type `kind`
+    /// for the underlying TLS connection, or `None` if the transport does not support it.
+    ///
+    /// This is needed by SASL `-PLUS` mechanisms such as `SCRAM-SHA-256-PLUS`. Obtain the data
+    /// from the [`Client`] before calling [`Client::authenticate`], and pass it to the
+    /// [`Authenticator`].
+    pub fn channel_binding(&self, kind: ChannelBindingType) -> Result<Option<Vec<u8>>> {
+        self.stream.inner.channel_binding(kind)
+    }
+}
+
+#[cfg(feature = "scram")]
+impl<T: AsyncRead + AsyncWrite + Unpin + ChannelBinding> Client<T> {
+    /// Authenticates with the strongest [`Scram`](crate::sasl::Scram) mechanism that the server
+    /// offers, preferring `SCRAM-SHA-256` over `SCRAM-SHA-1`, and the `-PLUS` variants whenever
+    /// the transport provides channel binding data.
+    ///
+    /// Fails with [`Error::MissingCapability`] if the server does not offer SCRAM at all.
+    ///
+    /// Requires the `scram` feature.
+    pub async fn authenticate_scram<U: AsRef<str>, P: AsRef<str>>(
+        mut self,
+        user: U,
+        password: P,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        use crate::sasl::{Scram, ScramHash};
+
+        let capabilities = ok_or_unauth_client_err!(self.capabilities().await, self);
+        let mut binding = None;
+        for kind in &[
+            ChannelBindingType::TlsExporter,
+            ChannelBindingType::TlsUnique,
+            ChannelBindingType::TlsServerEndPoint,
+        ] {
+            if let Some(data) = ok_or_unauth_client_err!(self.channel_binding(*kind), self) {
+                binding = Some((*kind, data));
+                break;
+            }
+        }
+
+        let offered = |mechanism: &str| capabilities.has_str(format!("AUTH={}", mechanism));
+        let hash = match [ScramHash::Sha256, ScramHash::Sha1].iter().find(|hash| {
+            offered(hash.mechanism()) || offered(&format!("{}-PLUS", hash.mechanism()))
+        }) {
+            Some(hash) => *hash,
+            None => {
+                let err = capabilities.require("AUTH=SCRAM-SHA-256").unwrap_err();
+                return Err((err, self));
+            }
+        };
+
+        let mut scram = Scram::new(hash, user.as_ref(), password.as_ref());
+        if let Some((kind, data)) = binding {
+            scram = if offered(&format!("{}-PLUS", hash.mechanism())) {
+                scram.channel_binding(kind, data)
+            } else {
+                scram.channel_binding_not_offered()
+            };
+        }
+        self.authenticate(scram.mechanism(), &scram).await
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
+    unsafe_pinned!(stream: ImapStream<T>);
+
+    /// Read the next response on the connection.
+    pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
+        self.stream.next().await
+    }
+
+    /// How much the connection has been used so far.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stream.protocol.stats()
+    }
+
+    /// Sends `LOGOUT`, closes the stream and waits for the server to close the connection, for
+    /// no longer than [`SHUTDOWN_TIMEOUT`] in total.
+    #[cfg(feature = "runtime")]
+    pub(crate) async fn shutdown(mut self) -> ConnectionStats {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        let remaining = || deadline.saturating_duration_since(Instant::now());
+
+        // unsolicited responses are dropped, nobody may be left to receive them
+        let logout = self.run_command_and_check_ok("LOGOUT", None);
+        match async_std::future::timeout(remaining(), logout).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => log::debug!("LOGOUT failed during shutdown: {}", err),
+            Err(_) => log::debug!("LOGOUT timed out during shutdown"),
+        }
+        // for TLS streams, this sends `close_notify` before closing the socket
+        let _ = async_std::future::timeout(remaining(), self.stream.inner.close()).await;
+        let stream = &mut self.stream;
+        let eof = async { while let Some(Ok(_)) = stream.next().await {} };
+        let _ = async_std::future::timeout(remaining(), eof).await;
+        self.stream.protocol.stats()
+    }
+
+    /// Limits how many responses that have already been received are handed out in a row before
+    /// the connection yields to other tasks. Without this, consuming the stream of a large
+    /// `FETCH` never returns control to the executor while the server keeps up, so a task that
+    /// reads [`Session::unsolicited_responses`] on the same executor could starve. `None`
+    /// disables yielding. Defaults to 32.
+    pub fn set_max_consecutive_responses(&mut self, max: Option<usize>) {
+        self.stream.protocol.set_max_consecutive(max);
+    }
+
+    /// The settings of the connection, to be applied to the one that replaces it.
+    fn settings(&self) -> Settings {
+        Settings {
+            debug: self.debug,
+            max_command_len: self.max_command_len,
+            #[cfg(feature = "runtime")]
+            idle_logout: self.idle_logout,
+            #[cfg(feature = "runtime")]
+            idle_keepalive: self.idle_keepalive.clone(),
+            unsolicited_capacity: self.unsolicited_capacity,
+            capability_trust: self.capability_trust,
+            buffer_sizes: self.stream.protocol.buffer_sizes(),
+            max_consecutive: self.stream.protocol.max_consecutive(),
+        }
+    }
+
+    pub(crate) async fn run_command_untagged(&mut self, command: &str) -> Result<()> {
+        self.stream
+            .encode(Request(None, command.as_bytes().into()))
+            .await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub(crate) async fn run_command(&mut self, command: &str) -> Result<RequestId> {
+        let request_id = self.stream.protocol.next_tag();
+        self.check_command_len(&request_id, command.as_bytes())?;
+        self.stream
+            .encode(Request(Some(request_id.clone()), command.as_bytes().into()))
+            .await?;
+        self.stream.flush().await?;
+        Ok(request_id)
+    }
+
+    /// Fails with [`Error::CommandTooLong`] if `command` exceeds `max_command_len` once tagged.
+    pub(crate) fn check_command_len(&self, tag: &RequestId, command: &[u8]) -> Result<()> {
+        // the tag, a space, and the trailing CRLF
+        let len = tag.0.len() + 1 + command.len() + 2;
+        match self.max_command_len {
+            Some(max) if len > max => Err(Error::CommandTooLong(len)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Execute a command and check that the next response is a matching done.
+    pub(crate) async fn run_command_and_check_ok(
+        &mut self,
+        command: &str,
+        unsolicited: Option<UnsolicitedSender>,
+    ) -> Result<CommandResult<()>> {
+        let id = self.run_command(command).await?;
+        self.check_ok(id, unsolicited).await
+    }
+
+    /// Sends an `ID` command with the given fields, and remembers the server's answer.
+    pub(crate) async fn id(
+        &mut self,
+        fields: &[(&str, &str)],
+        unsolicited: Option<UnsolicitedSender>,
+    ) -> Result<CommandResult<ServerId>> {
+        let command = if fields.is_empty() {
+            "ID NIL".to_string()
+        } else {
+            let mut values = Vec::with_capacity(fields.len() * 2);
+            for (key, value) in fields {
+                values.push(validate_str(key)?);
+                values.push(validate_str(value)?);
+            }
+            format!("ID ({})", values.join(" "))
+        };
+
+        self.stream.protocol.server_id = None;
+        let res = self.run_command_and_check_ok(&command, unsolicited).await?;
+        let server_id = self.stream.protocol.server_id.take().unwrap_or_default();
+        self.server_id = Some(server_id.clone());
+        Ok(res.map(|()| server_id))
+    }
+
+    pub(crate) async fn check_ok(
+        &mut self,
+        id: RequestId,
+        unsolicited: Option<UnsolicitedSender>,
+    ) -> Result<CommandResult<()>> {
+        complete((), self.check_done(id, unsolicited).await?)
+    }
+
+    /// Reads the responses to the command tagged `id` up to its completion, which is returned
+    /// regardless of its status.
+    pub(crate) async fn check_done(
+        &mut self,
+        id: RequestId,
+        unsolicited: Option<UnsolicitedSender>,
+    ) -> Result<ResponseData> {
+        while let Some(res) = self.stream.next().await {
+            let res = res?;
+            if let Response::Done {
+                status,
+                code,
+                information,
+                tag,
+            } = res.parsed()
+            {
+                if tag == &id {
+                    return Ok(res);
+                }
+
+                // the completion of another command
+                status_to_result(status, code, information)?;
+                if let Some(unsolicited) = unsolicited.clone() {
+                    handle_unilateral(res, unsolicited).await;
+                }
+            }
+        }
+
+        Err(Error::ConnectionLost)
+    }
+}
+
+fn validate_str(value: &str) -> Result<String> {
+    let quoted = quote!(value);
+    if quoted.find('\n').is_some() {
+        return Err(Error::Validate(ValidateError('\n')));
+    }
+    if quoted.find('\r').is_some() {
+        return Err(Error::Validate(ValidateError('\r')));
+    }
+    Ok(quoted)
+}
+
+/// Formats return options for `SEARCH` or `SORT`.
+fn return_options(options: &[SearchReturn]) -> String {
+    let options: Vec<String> = options.iter().map(ToString::to_string).collect();
+    options.join(" ")
+}
+
+/// Collects the `QUOTA` responses among the responses to a quota command.
+fn quotas(responses: Vec<UnsolicitedResponse>) -> Vec<Quota> {
+    responses
+        .into_iter()
+        .filter_map(|response| match response {
+            UnsolicitedResponse::Quota { root, resources } => Some(Quota { root, resources }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Refuses the commands that are only valid in the [not authenticated
+/// state](https://tools.ietf.org/html/rfc3501#section-6.2). Upgrading the connection with a
+/// `STARTTLS` sent this way would leave the session reading TLS records as IMAP responses.
+fn check_authenticated_state(command: &str) -> Result<()> {
+    let verb = command.split_whitespace().next().unwrap_or("");
+    if ["AUTHENTICATE", "LOGIN", "STARTTLS"]
+        .iter()
+        .any(|forbidden| forbidden.eq_ignore_ascii_case(verb))
+    {
+        return Err(Error::WrongState(verb.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::super::error::{AuthError, Result};
+    use super::super::mock_stream::{MockStream, Profile, Script};
+    use super::*;
+
+    use async_std::sync::{Arc, Mutex};
+    use imap_proto::Status;
+
+    macro_rules! mock_client {
+        ($s:expr) => {
+            Client::new($s)
+        };
+    }
+
+    macro_rules! mock_session {
+        ($s:expr) => {
+            Session::new(mock_client!($s).conn)
+        };
+    }
+
+    macro_rules! assert_eq_bytes {
+        ($a:expr, $b:expr, $c:expr) => {
+            assert_eq!(
+                std::str::from_utf8($a).unwrap(),
+                std::str::from_utf8($b).unwrap(),
+                $c
+            )
+        };
+    }
+
+    #[async_attributes::test]
+    async fn wrong_state() {
+        let mock_stream = MockStream::new(Vec::new());
+        let mut session = mock_session!(mock_stream);
+        for command in &["STARTTLS", "login user pass", "AUTHENTICATE PLAIN"] {
+            match session.run_command(command).await {
+                Err(Error::WrongState(_)) => {}
+                res => panic!("{} was not refused: {:?}", command, res),
+            }
+        }
+        match session.run_command_and_check_ok("starttls").await {
+            Err(Error::WrongState(verb)) => assert_eq!(verb, "starttls"),
+            res => panic!("STARTTLS was not refused: {:?}", res),
+        }
+        // queued commands are checked as well
+        match session.enqueue("STARTTLS") {
+            Err(Error::WrongState(_)) => {}
+            res => panic!("STARTTLS was not refused: {:?}", res),
+        }
+        assert_eq!(session.queued_commands(), 0);
+        session.flush().await.unwrap();
+        assert!(session.stream.inner.written_buf.is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn fetch_body() {
+        let response = "a0 OK Logged in.\r\n\
+                        * 2 FETCH (BODY[TEXT] {3}\r\nfoo)\r\n\
+                        a0 OK FETCH completed\r\n";
+        let mut session = mock_session!(MockStream::new(response.as_bytes().to_vec()));
+        session.read_response().await.unwrap().unwrap();
+        session.read_response().await.unwrap().unwrap();
+    }
+
+    #[async_attributes::test]
+    async fn readline_delay_read() {
+        let greeting = "* OK Dovecot ready.\r\n";
+        let mock_stream = MockStream::default()
+            .with_buf(greeting.as_bytes().to_vec())
+            .with_delay();
+
+        let mut client = mock_client!(mock_stream);
         let actual_response = client.read_response().await.unwrap().unwrap();
         assert_eq!(
-            actual_response.parsed(),
-            &Response::Data {
-                status: Status::Ok,
-                code: None,
-                information: Some("Dovecot ready."),
+            actual_response.parsed(),
+            &Response::Data {
+                status: Status::Ok,
+                code: None,
+                information: Some("Dovecot ready."),
+            }
+        );
+    }
+
+    #[async_attributes::test]
+    async fn readline_eof() {
+        let mock_stream = MockStream::default().with_eof();
+        let mut client = mock_client!(mock_stream);
+        let res = client.read_response().await;
+        println!("{:?}", res);
+        assert!(res.is_none());
+    }
+
+    #[async_attributes::test]
+    #[should_panic]
+    async fn readline_err() {
+        // TODO Check the error test
+        let mock_stream = MockStream::default().with_err();
+        let mut client = mock_client!(mock_stream);
+        client.read_response().await.unwrap().unwrap();
+    }
+
+    #[async_attributes::test]
+    async fn authenticate() {
+        let response = b"+ YmFy\r\n\
+                         A0001 OK Logged in\r\n"
+            .to_vec();
+        let command = "A0001 AUTHENTICATE PLAIN\r\n\
+                       Zm9v\r\n";
+        let mock_stream = MockStream::new(response);
+        let client = mock_client!(mock_stream);
+        enum Authenticate {
+            Auth,
+        };
+        impl Authenticator for Authenticate {
+            type Response = Vec<u8>;
+            fn process(&self, challenge: &[u8]) -> Self::Response {
+                assert!(challenge == b"bar", "Invalid authenticate challenge");
+                b"foo".to_vec()
+            }
+        }
+        let session = client
+            .authenticate("PLAIN", &Authenticate::Auth)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn authenticate_sasl_ir() {
+        let response = b"* CAPABILITY IMAP4rev1 SASL-IR AUTH=PLAIN AUTH=EXTERNAL\r\n\
+            A0001 OK done\r\n\
+            A0002 OK Logged in\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        client.capabilities().await.unwrap();
+        let session = client
+            .authenticate_plain(None, "user", "secret")
+            .await
+            .ok()
+            .unwrap();
+        let command = format!(
+            "A0001 CAPABILITY\r\nA0002 AUTHENTICATE PLAIN {}\r\n",
+            base64::encode("\0user\0secret")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+
+        // an empty initial response is sent as `=`
+        let response = b"* CAPABILITY IMAP4rev1 SASL-IR\r\n\
+            A0001 OK done\r\n\
+            A0002 OK Logged in\r\n"
+            .to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        client.capabilities().await.unwrap();
+        let session = client.authenticate_external(None).await.ok().unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 AUTHENTICATE EXTERNAL =\r\n",
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn authenticate_plain() {
+        let response = b"+ \r\nA0001 OK Logged in\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let session = client
+            .authenticate_plain(Some("user"), "admin", "secret")
+            .await
+            .ok()
+            .unwrap();
+        let command = format!(
+            "A0001 AUTHENTICATE PLAIN\r\n{}\r\n",
+            base64::encode("user\0admin\0secret")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn authenticate_external() {
+        let response = b"+ \r\nA0001 OK Logged in\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let session = client.authenticate_external(None).await.ok().unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 AUTHENTICATE EXTERNAL\r\n\r\n",
+            "Invalid authenticate command"
+        );
+
+        let client = mock_client!(MockStream::new(b"+ \r\nA0001 OK Logged in\r\n".to_vec()));
+        let session = client
+            .authenticate_external(Some("user"))
+            .await
+            .ok()
+            .unwrap();
+        let command = format!(
+            "A0001 AUTHENTICATE EXTERNAL\r\n{}\r\n",
+            base64::encode("user")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn authenticate_xoauth2_rejected() {
+        let error = r#"{"status":"401","schemes":"bearer"}"#;
+        let response = format!(
+            "+ \r\n+ {}\r\nA0001 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n",
+            base64::encode(error)
+        );
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let auth = crate::sasl::XOAuth2::new("me@example.com", "token");
+        let client = match client.authenticate("XOAUTH2", &auth).await {
+            Err((Error::Auth(AuthError::AuthenticationFailed(msg)), client)) => {
+                assert_eq!(msg, "Invalid credentials");
+                client
+            }
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        };
+        assert_eq!(auth.error().as_deref(), Some(error));
+        let command = format!(
+            "A0001 AUTHENTICATE XOAUTH2\r\n{}\r\n\r\n",
+            base64::encode("user=me@example.com\x01auth=Bearer token\x01\x01")
+        );
+        assert_eq_bytes!(
+            &client.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn authenticate_oauth_expired() {
+        use crate::sasl::{OAuthBearer, TokenFuture, TokenProvider};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Hands out a new token each time, and remembers which ones expired.
+        #[derive(Default)]
+        struct Tokens {
+            issued: AtomicUsize,
+            expired: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl TokenProvider for Arc<Tokens> {
+            fn token(&self) -> TokenFuture<'_> {
+                let n = self.issued.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move { Ok(format!("token{}", n)) })
+            }
+
+            fn expired(&self, token: &str) {
+                self.expired.lock().unwrap().push(token.to_string());
+            }
+        }
+
+        let error = r#"{"status":"invalid_token"}"#;
+        let response = format!(
+            "+ \r\n+ {}\r\nA0001 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n\
+            + \r\nA0002 OK Success\r\n",
+            base64::encode(error)
+        );
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let tokens = Arc::new(Tokens::default());
+        let auth = OAuthBearer::with_provider("me@example.com", tokens.clone());
+        let session = client.authenticate_oauth(&auth).await.unwrap();
+        assert_eq!(*tokens.expired.lock().unwrap(), vec!["token0".to_string()]);
+        assert_eq!(auth.error(), None);
+        let command = format!(
+            "A0001 AUTHENTICATE OAUTHBEARER\r\n{}\r\n{}\r\n\
+            A0002 AUTHENTICATE OAUTHBEARER\r\n{}\r\n",
+            base64::encode("n,a=me@example.com,\x01auth=Bearer token0\x01\x01"),
+            base64::encode("\x01"),
+            base64::encode("n,a=me@example.com,\x01auth=Bearer token1\x01\x01")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn authenticate_cancelled() {
+        /// Does not trust the server's second challenge.
+        struct Suspicious;
+
+        impl Authenticator for Suspicious {
+            type Response = Vec<u8>;
+
+            fn process(&self, _challenge: &[u8]) -> Self::Response {
+                b"hello".to_vec()
+            }
+
+            fn cancelled(&self) -> bool {
+                true
+            }
+        }
+
+        let response = b"+ \r\nA0001 BAD cancelled\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let client = match client.authenticate("X-SUSPICIOUS", &Suspicious).await {
+            Err((Error::Bad(_), client)) => client,
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        };
+        assert_eq_bytes!(
+            &client.stream.inner.written_buf,
+            b"A0001 AUTHENTICATE X-SUSPICIOUS\r\n*\r\n",
+            "Invalid authenticate command"
+        );
+    }
+
+    #[cfg(feature = "scram")]
+    #[async_attributes::test]
+    async fn authenticate_scram_plus() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=SCRAM-SHA-1 AUTH=SCRAM-SHA-256-PLUS\r\n\
+            A0001 OK done\r\n\
+            + \r\n\
+            A0002 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let client = match client.authenticate_scram("user", "pencil").await {
+            Err((Error::Auth(AuthError::AuthenticationFailed(_)), client)) => client,
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        };
+
+        // the mock stream provides `tls-server-end-point` data, see `channel_binding`
+        let written = String::from_utf8(client.stream.inner.written_buf.clone()).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(lines.next(), Some("A0001 CAPABILITY"));
+        assert_eq!(lines.next(), Some("A0002 AUTHENTICATE SCRAM-SHA-256-PLUS"));
+        let client_first = base64::decode(lines.next().unwrap()).unwrap();
+        assert!(client_first.starts_with(b"p=tls-server-end-point,,n=user,r="));
+    }
+
+    #[cfg(feature = "scram")]
+    #[async_attributes::test]
+    async fn authenticate_scram_unverified() {
+        // the server accepts the client without sending its signature in a server-final-message
+        let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+            s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        let response = format!(
+            "+ \r\n+ {}\r\nA0001 OK Logged in\r\n",
+            base64::encode(server_first)
+        );
+        let client = mock_client!(MockStream::new(response.into_bytes()));
+        let mut auth = crate::sasl::Scram::sha256("user", "pencil");
+        auth.nonce = Some("rOprNGfwEbeRWgbNEkqO".into());
+        match client.authenticate(auth.mechanism(), &auth).await {
+            Err((Error::Auth(AuthError::Other(_)), _)) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        assert!(!auth.cancelled());
+        assert!(!auth.completed());
+    }
+
+    #[test]
+    fn channel_binding() {
+        impl ChannelBinding for MockStream {
+            fn channel_binding(&self, kind: ChannelBindingType) -> Result<Option<Vec<u8>>> {
+                match kind {
+                    ChannelBindingType::TlsServerEndPoint => Ok(Some(b"cert hash".to_vec())),
+                    ChannelBindingType::TlsExporter | ChannelBindingType::TlsUnique => Ok(None),
+                }
+            }
+        }
+
+        let client = mock_client!(MockStream::default());
+        assert_eq!(
+            client
+                .channel_binding(ChannelBindingType::TlsServerEndPoint)
+                .unwrap(),
+            Some(b"cert hash".to_vec())
+        );
+        assert_eq!(
+            client
+                .channel_binding(ChannelBindingType::TlsExporter)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[async_attributes::test]
+    async fn prepare_starttls() {
+        let response = b"A0001 OK Begin TLS negotiation now\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let stream = client.prepare_starttls().await.unwrap();
+        assert_eq_bytes!(
+            &stream.written_buf,
+            b"A0001 STARTTLS\r\n",
+            "Invalid starttls command"
+        );
+
+        let response = b"A0001 NO STARTTLS not available\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        match client.prepare_starttls().await {
+            Err(Error::No(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn starttls_keeps_settings() {
+        let mut client = mock_client!(MockStream::new(Vec::new()));
+        client.debug = true;
+        client.max_command_len = Some(1000);
+        client.unsolicited_capacity = 5;
+        client.capability_trust = CapabilityTrust::TrustGreeting;
+        client.stream.protocol.set_buffer_sizes(128, 4096);
+        client.set_max_consecutive_responses(None);
+        #[cfg(feature = "runtime")]
+        {
+            client.idle_logout = Some(Duration::from_secs(60));
+        }
+
+        // the upgraded client is set up the same way
+        let upgraded = Client::with_settings(MockStream::new(Vec::new()), client.settings());
+        assert!(upgraded.debug);
+        assert_eq!(upgraded.max_command_len, Some(1000));
+        assert_eq!(upgraded.unsolicited_capacity, 5);
+        assert_eq!(upgraded.capability_trust, CapabilityTrust::TrustGreeting);
+        assert_eq!(upgraded.stream.protocol.buffer_sizes(), (128, 4096));
+        assert_eq!(upgraded.stream.protocol.max_consecutive(), None);
+        #[cfg(feature = "runtime")]
+        assert_eq!(upgraded.idle_logout, Some(Duration::from_secs(60)));
+    }
+
+    #[async_attributes::test]
+    async fn prepare_starttls_injection() {
+        let response = b"A0001 OK Begin TLS negotiation now\r\n\
+            * OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] injected\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        assert!(client.prepare_starttls().await.is_err());
+    }
+
+    #[async_attributes::test]
+    async fn login() {
+        let response = b"A0001 OK Logged in\r\n".to_vec();
+        let username = "username";
+        let password = "password";
+        let command = format!("A0001 LOGIN {} {}\r\n", quote!(username), quote!(password));
+        let mock_stream = MockStream::new(response);
+        let client = mock_client!(mock_stream);
+        if let Ok(session) = client.login(username, password).await {
+            assert_eq!(
+                session.stream.inner.written_buf,
+                command.as_bytes().to_vec(),
+                "Invalid login command"
+            );
+        } else {
+            unreachable!("invalid login");
+        }
+    }
+
+    #[async_attributes::test]
+    async fn from_stream_without_debug() {
+        use futures::task::{Context, Poll};
+
+        // a transport that does not implement `Debug`
+        struct Pipe(MockStream);
+
+        impl AsyncRead for Pipe {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.0).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for Pipe {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.0).poll_write(cx, buf)
+            }
+
+            fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.0).poll_flush(cx)
+            }
+
+            fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.0).poll_close(cx)
+            }
+        }
+
+        let response = b"* OK IMAP4rev1 ready\r\nA0001 OK Logged in\r\n".to_vec();
+        let client = Client::from_stream(Pipe(MockStream::new(response)))
+            .await
+            .unwrap();
+        let session = client
+            .login("username", "password")
+            .await
+            .map_err(|e| e.0)
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.0.written_buf,
+            b"A0001 LOGIN \"username\" \"password\"\r\n".to_vec()
+        );
+    }
+
+    #[async_attributes::test]
+    async fn greeting_capabilities() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 SASL-IR AUTH=PLAIN IDLE] ready\r\n".to_vec();
+        let mut client = Client::from_stream(MockStream::new(response))
+            .await
+            .unwrap();
+        client.capability_trust = CapabilityTrust::TrustGreeting;
+        let capabilities = client.capabilities().await.unwrap();
+        assert!(capabilities.has_str("AUTH=PLAIN"));
+        assert!(capabilities.has_str("IDLE"));
+        assert!(capabilities.has(&Capability::Imap4rev1));
+        assert!(client.sasl_ir);
+        // no `CAPABILITY` command is needed
+        assert!(client.stream.inner.written_buf.is_empty());
+
+        // the mechanism is picked from the greeting as well
+        let response = b"* OK [CAPABILITY IMAP4rev1 SASL-IR AUTH=PLAIN] ready\r\n\
+            A0001 OK Logged in\r\n"
+            .to_vec();
+        let mut client = Client::from_stream(MockStream::new(response))
+            .await
+            .unwrap();
+        client.capability_trust = CapabilityTrust::TrustGreeting;
+        let session = client
+            .authenticate_plain(None, "user", "secret")
+            .await
+            .ok()
+            .unwrap();
+        let command = format!(
+            "A0001 AUTHENTICATE PLAIN {}\r\n",
+            base64::encode("\0user\0secret")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn greeting_capabilities_refetched() {
+        // the greeting claims more than the server supports once asked
+        let response = b"* OK [CAPABILITY IMAP4rev1 SASL-IR AUTH=PLAIN AUTH=LOGIN] ready\r\n\
+            * CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n\
+            A0001 OK done\r\n\
+            + \r\n\
+            A0002 OK Logged in\r\n"
+            .to_vec();
+        let mut client = Client::from_stream(MockStream::new(response))
+            .await
+            .unwrap();
+        assert_eq!(client.capability_trust, CapabilityTrust::Refetch);
+        let capabilities = client.capabilities().await.unwrap();
+        assert!(capabilities.has_str("AUTH=PLAIN"));
+        assert!(!capabilities.has_str("AUTH=LOGIN"));
+        assert!(!client.sasl_ir);
+        let session = client
+            .authenticate_plain(None, "user", "secret")
+            .await
+            .ok()
+            .unwrap();
+        let command = format!(
+            "A0001 CAPABILITY\r\nA0002 AUTHENTICATE PLAIN\r\n{}\r\n",
+            base64::encode("\0user\0secret")
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+
+        // `SASL-IR` in the greeting alone is not enough to send an initial response
+        let response = b"* OK [CAPABILITY IMAP4rev1 SASL-IR] ready\r\n\
+            + \r\n\
+            A0001 OK Logged in\r\n"
+            .to_vec();
+        let client = Client::from_stream(MockStream::new(response))
+            .await
+            .unwrap();
+        let session = client.authenticate_external(None).await.ok().unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 AUTHENTICATE EXTERNAL\r\n\r\n",
+            "Invalid authenticate command"
+        );
+    }
+
+    #[cfg(feature = "runtime")]
+    #[async_attributes::test]
+    async fn park() {
+        let session = mock_session!(MockStream::new(Vec::new()));
+        let session = session.park().unpark().await.unwrap();
+        assert!(session.stream.inner.written_buf.is_empty());
+
+        let mut session = session;
+        session.idle_logout = Some(Duration::from_secs(3600));
+        let session = session.park().unpark().await.unwrap();
+        assert!(session.stream.inner.written_buf.is_empty());
+
+        let response = b"A0001 OK Logout completed.\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.idle_logout = Some(Duration::from_millis(10));
+        let parked = session.park();
+        async_std::task::sleep(Duration::from_millis(200)).await;
+        assert!(parked.unpark().await.is_none());
+    }
+
+    #[cfg(feature = "runtime")]
+    #[async_attributes::test]
+    async fn logout_if_idle() {
+        let response = b"A0001 OK Logout completed.\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(!session.logout_if_idle().await.unwrap());
+
+        session.idle_logout = Some(Duration::from_secs(3600));
+        assert!(!session.logout_if_idle().await.unwrap());
+        assert!(session.stream.inner.written_buf.is_empty());
+
+        session.idle_logout = Some(Duration::from_secs(0));
+        assert!(session.logout_if_idle().await.unwrap());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 LOGOUT\r\n".to_vec()
+        );
+    }
+
+    #[async_attributes::test]
+    async fn logout() {
+        let response = b"A0001 OK Logout completed.\r\n".to_vec();
+        let command = format!("A0001 LOGOUT\r\n");
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.logout().await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid logout command"
+        );
+    }
+
+    #[cfg(feature = "runtime")]
+    #[async_attributes::test]
+    async fn shutdown() {
+        let response = b"* BYE Logging out\r\nA0001 OK Logout completed.\r\n".to_vec();
+        let session = mock_session!(MockStream::new(response.clone()));
+        let stats = session.shutdown().await;
+        assert_eq!(
+            stats,
+            ConnectionStats {
+                bytes_read: response.len() as u64,
+                bytes_written: b"A0001 LOGOUT\r\n".len() as u64,
+                commands: 1,
+                max_in_flight: 1,
+            }
+        );
+    }
+
+    #[async_attributes::test]
+    async fn idle_connection_closed() {
+        let response = b"+ idling\r\n* 3 EXISTS\r\n".to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+        let (wait, _interrupt) = idle.wait();
+        match wait.await.unwrap().into_unsolicited() {
+            Some(UnsolicitedResponse::Exists(3)) => {}
+            res => panic!("unexpected response: {:?}", res),
+        }
+        assert!(!idle.is_terminated());
+
+        // waiting again must not mistake the closed connection for an interrupt
+        for _ in 0..2 {
+            let (wait, _interrupt) = idle.wait();
+            match wait.await {
+                Err(Error::ConnectionLost) => {}
+                res => panic!("closed connection not reported: {:?}", res),
+            }
+        }
+        assert!(idle.is_terminated());
+        assert!(idle.next().await.is_none());
+    }
+
+    #[cfg(feature = "runtime")]
+    #[async_attributes::test]
+    async fn idle_shutdown() {
+        let response = b"+ idling\r\n\
+            A0001 OK IDLE terminated\r\n\
+            * BYE Logging out\r\n\
+            A0002 OK Logout completed.\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+        let stats = idle.shutdown().await;
+        assert_eq!(stats.commands, 2);
+        assert_eq!(
+            stats.bytes_written,
+            b"A0001 IDLE\r\nDONE\r\nA0002 LOGOUT\r\n".len() as u64
+        );
+    }
+
+    #[async_attributes::test]
+    async fn rename() {
+        let response = b"A0001 OK RENAME completed\r\n".to_vec();
+        let current_mailbox_name = "INBOX";
+        let new_mailbox_name = "NEWINBOX";
+        let command = format!(
+            "A0001 RENAME {} {}\r\n",
+            quote!(current_mailbox_name),
+            quote!(new_mailbox_name)
+        );
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session
+            .rename(current_mailbox_name, new_mailbox_name)
+            .await
+            .unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid rename command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn subscribe() {
+        let response = b"A0001 OK SUBSCRIBE completed\r\n".to_vec();
+        let mailbox = "INBOX";
+        let command = format!("A0001 SUBSCRIBE {}\r\n", quote!(mailbox));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.subscribe(mailbox).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid subscribe command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn unsubscribe() {
+        let response = b"A0001 OK UNSUBSCRIBE completed\r\n".to_vec();
+        let mailbox = "INBOX";
+        let command = format!("A0001 UNSUBSCRIBE {}\r\n", quote!(mailbox));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.unsubscribe(mailbox).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid unsubscribe command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn expunge() {
+        let response = b"A0001 OK EXPUNGE completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.expunge().await.unwrap().collect::<Vec<_>>().await;
+        assert!(
+            session.stream.inner.written_buf == b"A0001 EXPUNGE\r\n".to_vec(),
+            "Invalid expunge command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn uid_expunge() {
+        let response = b"* 2 EXPUNGE\r\n\
+            * 3 EXPUNGE\r\n\
+            * 4 EXPUNGE\r\n\
+            A0001 OK UID EXPUNGE completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session
+            .uid_expunge("2:4")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert!(
+            session.stream.inner.written_buf == b"A0001 UID EXPUNGE 2:4\r\n".to_vec(),
+            "Invalid expunge command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn compressed_sequence_sets() {
+        let response = b"A0001 OK [COPYUID 38505 1:3,5 8:11] COPY completed\r\n\
+            A0002 OK SEARCH completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let copy_uid = session.uid_copy("5,1,2,3", "Archive").await.unwrap();
+        assert_eq!(
+            copy_uid.as_ref().unwrap().pairs().collect::<Vec<_>>(),
+            vec![(1, 8), (2, 9), (3, 10), (5, 11)]
+        );
+        session.search("UID 1,2,4 SUBJECT \"1,2\"").await.unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.inner.written_buf.clone()).unwrap(),
+            "A0001 UID COPY 1:3,5 Archive\r\nA0002 SEARCH UID 1:2,4 SUBJECT \"1,2\"\r\n"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn command_too_long() {
+        let mut session = mock_session!(MockStream::default());
+        session.max_command_len = Some(20);
+        match session.uid_fetch("1,3,5,7,9", "FLAGS").await {
+            Err(Error::CommandTooLong(len)) => assert_eq!(len, 33),
+            Err(err) => panic!("unexpected error: {:?}", err),
+            Ok(_) => panic!("command was sent"),
+        }
+        assert!(session.stream.inner.written_buf.is_empty());
+
+        session.enqueue("NOOP").unwrap();
+        session
+            .enqueue("UID STORE 1,3,5,7,9 +FLAGS (\\Seen)")
+            .unwrap();
+        assert!(session.flush().await.is_err());
+        assert_eq!(session.queued_commands(), 2);
+    }
+
+    #[async_attributes::test]
+    async fn check() {
+        let response = b"A0001 OK CHECK completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.check().await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 CHECK\r\n".to_vec(),
+            "Invalid check command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn examine() {
+        let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+            * OK [PERMANENTFLAGS ()] Read-only mailbox.\r\n\
+            * 1 EXISTS\r\n\
+            * 1 RECENT\r\n\
+            * OK [UNSEEN 1] First unseen.\r\n\
+            * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
+            * OK [UIDNEXT 2] Predicted next UID\r\n\
+            A0001 OK [READ-ONLY] Select completed.\r\n"
+            .to_vec();
+        let expected_mailbox = Mailbox {
+            flags: vec![
+                Flag::Answered,
+                Flag::Flagged,
+                Flag::Deleted,
+                Flag::Seen,
+                Flag::Draft,
+            ]
+            .into_iter()
+            .collect(),
+            exists: 1,
+            recent: 1,
+            unseen: Some(1),
+            permanent_flags: Some(FlagSet::new()),
+            uid_next: Some(2),
+            uid_validity: Some(1257842737),
+            highest_modseq: None,
+        };
+        let mailbox_name = "INBOX";
+        let command = format!("A0001 EXAMINE {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let mailbox = session.examine(mailbox_name).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid examine command"
+        );
+        assert_eq!(*mailbox, expected_mailbox);
+    }
+
+    #[async_attributes::test]
+    async fn select() {
+        let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+            * OK [PERMANENTFLAGS (\\* \\Answered \\Flagged \\Deleted \\Draft \\Seen)] \
+              Read-only mailbox.\r\n\
+            * 1 EXISTS\r\n\
+            * 1 RECENT\r\n\
+            * OK [UNSEEN 1] First unseen.\r\n\
+            * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
+            * OK [UIDNEXT 2] Predicted next UID\r\n\
+            A0001 OK [READ-ONLY] Select completed.\r\n"
+            .to_vec();
+        let expected_mailbox = Mailbox {
+            flags: vec![
+                Flag::Answered,
+                Flag::Flagged,
+                Flag::Deleted,
+                Flag::Seen,
+                Flag::Draft,
+            ]
+            .into_iter()
+            .collect(),
+            exists: 1,
+            recent: 1,
+            unseen: Some(1),
+            permanent_flags: Some(
+                vec![
+                    Flag::MayCreate,
+                    Flag::Answered,
+                    Flag::Flagged,
+                    Flag::Deleted,
+                    Flag::Draft,
+                    Flag::Seen,
+                ]
+                .into_iter()
+                .collect(),
+            ),
+            uid_next: Some(2),
+            uid_validity: Some(1257842737),
+            highest_modseq: None,
+        };
+        let mailbox_name = "INBOX";
+        let command = format!("A0001 SELECT {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let mailbox = session.select(mailbox_name).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid select command"
+        );
+        assert_eq!(*mailbox, expected_mailbox);
+        assert_eq!(mailbox.code(), Some(&imap_proto::ResponseCode::ReadOnly));
+        assert_eq!(mailbox.information(), Some("Select completed."));
+    }
+
+    #[async_attributes::test]
+    async fn select_no() {
+        let response = b"* 1 EXISTS\r\n\
+            A0001 NO Unknown Mailbox\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        match session.select("Nope").await {
+            Err(Error::No(msg)) => assert!(msg.contains("Unknown Mailbox")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[async_attributes::test]
+    async fn list_streams_names() {
+        // the tagged completion never arrives, so names must be yielded as they are read
+        let response = b"* LIST () \"/\" \"Lists/rust\"\r\n\
+            * LIST (\\HasNoChildren) \"/\" \"Lists/go\"\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let pattern = Name::depth_pattern(Some("Lists"), "/", 1);
+        let names = session
+            .list(None, Some(&pattern))
+            .await
+            .unwrap()
+            .map(|name| name.unwrap().name().to_string())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(names, vec!["Lists/rust", "Lists/go"]);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 LIST \"\" \"Lists/%\"\r\n",
+            "Invalid list command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn list_quoted_names() {
+        let response = b"* LIST () \"\\\\\" \"Lists\\\\rust \\\"stable\\\"\"\r\n\
+            * LIST () \"/\" {9}\r\nR&-D\\2020\r\n\
+            * LIST (\\HasNoChildren) \"/\" Entw&APw-rfe\r\n\
+            A0001 OK LIST completed\r\n\
+            * STATUS \"Lists\\\\rust \\\"stable\\\"\" (MESSAGES 2)\r\n\
+            A0002 OK [READ-WRITE] SELECT completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names = session
+            .list(None, Some("*"))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        // quoted strings are unescaped, including the delimiter
+        assert_eq!(names[0].name(), "Lists\\rust \"stable\"");
+        assert_eq!(names[0].delimiter(), Some("\\"));
+        // but literals are not
+        assert_eq!(names[1].name(), "R&-D\\2020");
+        assert_eq!(names[1].decoded_name(), "R&D\\2020");
+        assert_eq!(names[2].name(), "Entw&APw-rfe");
+        assert_eq!(names[2].decoded_name(), "Entwürfe");
+
+        session.select(names[0].name()).await.unwrap();
+        match session.unsolicited_responses.try_next().unwrap() {
+            Some(UnsolicitedResponse::Status { mailbox, .. }) => {
+                assert_eq!(mailbox, names[0].name())
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 LIST \"\" *\r\n\
+            A0002 SELECT \"Lists\\\\rust \\\"stable\\\"\"\r\n",
+            "Invalid select command"
+        );
+    }
+
+    #[test]
+    fn list_select_round_trip() {
+        use futures::executor::block_on;
+        use quickcheck::{QuickCheck, TestResult};
+
+        // any name that is listed by the server can be selected again, and decodes to the name
+        // it was created with
+        fn round_trip(name: String) -> TestResult {
+            // imap-proto spells the INBOX in upper case, whatever the server sent
+            if name.is_empty() || name.eq_ignore_ascii_case("INBOX") {
+                return TestResult::discard();
+            }
+            let quoted = validate_str(&encode_utf7(&name)).unwrap();
+            let response = format!(
+                "* LIST () \"/\" {}\r\nA0001 OK LIST completed\r\nA0002 OK SELECT completed\r\n",
+                quoted
+            );
+            let mut session = mock_session!(MockStream::new(response.into_bytes()));
+            let names: Vec<Name> = block_on(async {
+                let names = session.list(None, Some("*")).await.unwrap();
+                names.try_collect::<Vec<_>>().await.unwrap()
+            });
+            block_on(session.select(names[0].name())).unwrap();
+
+            let expected = format!("A0001 LIST \"\" *\r\nA0002 SELECT {}\r\n", quoted);
+            TestResult::from_bool(
+                names.len() == 1
+                    && names[0].decoded_name() == name
+                    && session.stream.inner.written_buf == expected.as_bytes(),
+            )
+        }
+        QuickCheck::new()
+            .tests(500)
+            .quickcheck(round_trip as fn(String) -> TestResult);
+    }
+
+    #[async_attributes::test]
+    async fn compress() {
+        use crate::compress::Deflate;
+
+        let mut server = Deflate::new(Vec::new());
+        let mut response = b"A0001 OK DEFLATE active\r\n".to_vec();
+        response.extend(server.compress(b"* 1 EXISTS\r\nA0002 OK done\r\n").unwrap());
+        let mut session = mock_session!(MockStream::new(response));
+        session.compress().await.unwrap();
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.unsolicited_responses.try_next().unwrap(),
+            Some(UnsolicitedResponse::Exists(1))
+        );
+
+        let written = &session.stream.inner.written_buf;
+        let command = b"A0001 COMPRESS DEFLATE\r\n";
+        assert_eq!(&written[..command.len()], &command[..]);
+        let mut client = Deflate::new(written[command.len()..].to_vec());
+        let mut buf = [0; 64];
+        let n = futures::future::poll_fn(|cx| {
+            client.poll_read(&mut MockStream::default(), cx, &mut buf)
+        })
+        .await
+        .unwrap();
+        assert_eq!(&buf[..n], &b"A0002 NOOP\r\n"[..]);
+    }
+
+    #[async_attributes::test]
+    async fn compress_broken() {
+        let response = b"* ID (\"name\" \"X-Broken\" \"version\" \"1.2\")\r\n\
+            A0001 OK ID completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.id(&[]).await.unwrap();
+        assert!(session.quirks().broken_compress);
+        match session.compress().await {
+            Err(Error::BrokenExtension(extension)) => assert_eq!(extension, "COMPRESS=DEFLATE"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 ID NIL\r\n".to_vec()
+        );
+
+        // the application knows better
+        session.set_quirks(Quirks::default());
+        assert!(session.compress().await.is_err());
+        assert!(session
+            .stream
+            .inner
+            .written_buf
+            .ends_with(b"A0002 COMPRESS DEFLATE\r\n"));
+    }
+
+    #[async_attributes::test]
+    async fn extension_data() {
+        let response = b"* ENABLED CONDSTORE\r\n\
+            A0001 OK enabled\r\n\
+            * QUOTA \"\" (STORAGE 10 512)\r\n\
+            * ACL INBOX Fred rwipslxetad\r\n\
+            * ID (\"name\" \"Dovecot\")\r\n\
+            A0002 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.enable(&["CONDSTORE", "QRESYNC"]).await.unwrap();
+        session.noop().await.unwrap();
+
+        let mut unsolicited = Vec::new();
+        while let Ok(Some(response)) = session.unsolicited_responses.try_next() {
+            unsolicited.push(response);
+        }
+        assert_eq!(unsolicited.len(), 4);
+        assert_eq!(
+            unsolicited[0],
+            UnsolicitedResponse::Enabled(vec!["CONDSTORE".into()])
+        );
+        assert_eq!(
+            unsolicited[1],
+            UnsolicitedResponse::Quota {
+                root: "".into(),
+                resources: vec![QuotaResource {
+                    name: "STORAGE".into(),
+                    usage: 10,
+                    limit: 512,
+                }],
+            }
+        );
+        assert_eq!(
+            unsolicited[2],
+            UnsolicitedResponse::Acl {
+                mailbox: "INBOX".into(),
+                rights: vec![("Fred".into(), "rwipslxetad".parse().unwrap())],
             }
         );
+        assert!(
+            matches!(unsolicited[3], UnsolicitedResponse::Id(ref id) if id.name() == Some("Dovecot"))
+        );
+    }
+
+    #[async_attributes::test]
+    async fn select_qresync() {
+        let response = b"A0001 OK QRESYNC enabled\r\n\
+            * 3 EXISTS\r\n\
+            * OK [UIDVALIDITY 67890007] UIDs valid\r\n\
+            * OK [HIGHESTMODSEQ 90060115205545359] Highest\r\n\
+            * VANISHED (EARLIER) 41,43:116,118\r\n\
+            * VANISHED (EARLIER) 120:121\r\n\
+            * 2 FETCH (UID 117 FLAGS (\\Seen) MODSEQ (90060115194045001))\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n\
+            * VANISHED 117\r\n\
+            A0003 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.enable(&["QRESYNC"]).await.unwrap();
+        let res = session
+            .select_qresync("INBOX", 67890007, 90060115194045000, Some("41,42,43:211"))
+            .await
+            .unwrap();
+        let (mailbox, vanished) = res.into_inner();
+        assert_eq!(mailbox.exists, 3);
+        assert_eq!(mailbox.highest_modseq, Some(90060115205545359));
+        assert!(vanished.earlier);
+        assert_eq!(vanished.uids, vec![41..=41, 43..=116, 118..=118, 120..=121]);
+
+        session.noop().await.unwrap();
+        let mut unsolicited = Vec::new();
+        while let Ok(Some(response)) = session.unsolicited_responses.try_next() {
+            unsolicited.push(response);
+        }
+        assert!(matches!(
+            unsolicited.last(),
+            Some(UnsolicitedResponse::Vanished(Vanished { earlier: false, uids }))
+                if *uids == vec![117..=117]
+        ));
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 ENABLE QRESYNC\r\n\
+              A0002 SELECT \"INBOX\" (QRESYNC (67890007 90060115194045000 41:211))\r\n\
+              A0003 NOOP\r\n",
+            "Invalid select command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn append_with_date() {
+        let response = b"+ Ready for literal data\r\n\
+            A0001 OK [APPENDUID 38505 3955] APPEND completed\r\n"
+            .to_vec();
+        use chrono::TimeZone;
+
+        let mut session = mock_session!(MockStream::new(response));
+        let date = chrono::FixedOffset::east(3600)
+            .ymd(1994, 2, 1)
+            .and_hms(8, 30, 0);
+        let append_uid = session
+            .append_with_date("Drafts", &date, "Subject: hi\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            *append_uid,
+            Some(AppendUid {
+                uid_validity: 38505,
+                uid: 3955
+            })
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 APPEND \"Drafts\" \"01-Feb-1994 08:30:00 +0100\" {15}\r\n\
+              Subject: hi\r\n\r\n\r\n",
+            "Invalid append command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn select_condstore() {
+        let response = b"* FLAGS (\\Seen)\r\n\
+            * 2 EXISTS\r\n\
+            * OK [UIDVALIDITY 3857529045] UIDs valid\r\n\
+            * OK [HIGHESTMODSEQ 715194045007] Highest\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * 2 FETCH (UID 7 MODSEQ (624140003) FLAGS (\\Seen))\r\n\
+            A0002 OK Fetch completed.\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mailbox = session.select_condstore("INBOX").await.unwrap();
+        assert_eq!(mailbox.exists, 2);
+        assert_eq!(mailbox.uid_validity, Some(3857529045));
+        assert_eq!(mailbox.highest_modseq, Some(715194045007));
+
+        let fetches = session
+            .uid_fetch("7", "(FLAGS MODSEQ)")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(fetches.len(), 1);
+        let fetch = fetches[0].as_ref().unwrap();
+        assert_eq!(fetch.uid, Some(7));
+        assert_eq!(fetch.modseq, Some(624140003));
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 SELECT \"INBOX\" (CONDSTORE)\r\nA0002 UID FETCH 7 (FLAGS MODSEQ)\r\n",
+            "Invalid select command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn check_selectable() {
+        let response = b"* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n\
+            A0001 OK LIST completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.check_selectable("inbox").await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 LIST \"\" \"inbox\"\r\n".to_vec(),
+            "Invalid list command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn check_selectable_noselect() {
+        let response = b"* LIST (\\NoSelect \\HasChildren) \".\" \"Archive\"\r\n\
+            A0001 OK LIST completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        match session.check_selectable("Archive").await {
+            Err(Error::NotSelectable(msg)) => assert!(msg.contains("\\Noselect container")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[async_attributes::test]
+    async fn check_selectable_missing() {
+        let response = b"A0001 OK LIST completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        match session.check_selectable("Nope").await {
+            Err(Error::NotSelectable(msg)) => assert!(msg.contains("does not exist")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[async_attributes::test]
+    async fn idle_not_started() {
+        let session = mock_session!(MockStream::default());
+        let mut idle = session.idle();
+        let res = {
+            let (wait, _interrupt) = idle.wait();
+            wait.await
+        };
+        match res {
+            Err(Error::IdleNotStarted) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // nothing to terminate, so nothing is sent
+        let session = idle.done().await.unwrap();
+        assert!(session.stream.inner.written_buf.is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn idle_new_data() {
+        let response = b"+ idling\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK IDLE terminated\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+        let response = {
+            let (wait, _interrupt) = idle.wait();
+            wait.await.unwrap()
+        };
+        assert_eq!(
+            response.into_unsolicited(),
+            Some(UnsolicitedResponse::Exists(3))
+        );
+
+        let session = idle.done().await.unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 IDLE\r\nDONE\r\n",
+            "Invalid idle commands"
+        );
+    }
+
+    #[cfg(feature = "runtime")]
+    #[async_attributes::test]
+    async fn idle_keepalive() {
+        // the server stays silent after the first continuation, until `DONE` is sent
+        let idling = b"+ idling\r\n";
+        let mut response = idling.to_vec();
+        response.extend_from_slice(
+            b"A0001 OK IDLE terminated\r\n\
+            + idling\r\n\
+            * 4 EXISTS\r\n\
+            A0002 OK IDLE terminated\r\n",
+        );
+        let mut session = mock_session!(MockStream::new(response).with_pause_at(idling.len()));
+        session.idle_keepalive.interval = Duration::from_millis(10);
+        let mut idle = session.idle();
+        idle.init().await.unwrap();
+        let response = {
+            let (wait, _interrupt) = idle.wait_keepalive();
+            wait.await.unwrap()
+        };
+        assert_eq!(
+            response.into_unsolicited(),
+            Some(UnsolicitedResponse::Exists(4))
+        );
+
+        let session = idle.done().await.unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 IDLE\r\nDONE\r\nA0002 IDLE\r\nDONE\r\n",
+            "Invalid idle commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn select_synced() {
+        use crate::sync_state::MemoryStore;
+
+        let response = b"* 1 EXISTS\r\n\
+            * OK [UIDVALIDITY 2] UIDs valid\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * 1 EXISTS\r\n\
+            * OK [UIDVALIDITY 2] UIDs valid\r\n\
+            A0002 OK [READ-WRITE] Select completed.\r\n"
+            .to_vec();
+        let mut store = MemoryStore::new();
+        store.set_uid_validity("INBOX", 1).unwrap();
+        store.set_flags("INBOX", 5, vec![Flag::Seen]).unwrap();
+
+        let mut session = mock_session!(MockStream::new(response));
+        session.select_synced("INBOX", &mut store).await.unwrap();
+        // UIDVALIDITY changed, so the known messages were dropped
+        assert_eq!(store.uid_validity("INBOX").unwrap(), Some(2));
+        assert!(store.known_uids("INBOX").unwrap().is_empty());
+
+        store.set_flags("INBOX", 5, vec![Flag::Seen]).unwrap();
+        session.select_synced("INBOX", &mut store).await.unwrap();
+        assert_eq!(store.known_uids("INBOX").unwrap(), vec![5]);
+    }
+
+    #[async_attributes::test]
+    async fn search() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            A0001 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.search("Unseen").await.unwrap();
+        let ids: HashSet<u32> = ids.iter().cloned().collect();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[async_attributes::test]
+    async fn search_detailed() {
+        let response = b"* SEARCH 7 3 5\r\n\
+            * SEARCH 5 1\r\n\
+            * ESEARCH (TAG \"A0001\") PARTIAL (1:2 2,9)\r\n\
+            * ESEARCH (TAG \"A0000\") ALL 100\r\n\
+            A0001 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let result = session
+            .search_detailed("RETURN (PARTIAL 1:2) Unseen")
+            .await
+            .unwrap();
+        assert_eq!(result.ids().collect::<Vec<_>>(), vec![1, 2, 3, 5, 7, 9]);
+        assert_eq!(result.lines(), 3);
+    }
+
+    #[async_attributes::test]
+    async fn search_return() {
+        let response = b"* CAPABILITY IMAP4rev1 ESEARCH\r\n\
+            A0001 OK Capability completed\r\n\
+            * ESEARCH (TAG \"A0002\") MIN 2 COUNT 150000\r\n\
+            A0002 OK Search completed\r\n\
+            * ESEARCH (TAG \"A0003\") UID ALL 1:100000,100002:150001\r\n\
+            A0003 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let result = session
+            .search_return(&[SearchReturn::Min, SearchReturn::Count], "UNSEEN")
+            .await
+            .unwrap();
+        assert_eq!(result.min, Some(2));
+        assert_eq!(result.count, Some(150000));
+        assert!(result.all.is_empty());
+
+        let result = session.uid_search_return(&[], "ALL").await.unwrap();
+        assert!(result.uid);
+        assert_eq!(result.all, vec![1..=100000, 100002..=150001]);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 SEARCH RETURN (MIN COUNT) UNSEEN\r\n\
+            A0003 UID SEARCH RETURN () ALL\r\n",
+            "Invalid search return commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn sort() {
+        let response = b"* SORT 5 3 4\r\n\
+            A0001 OK Sort completed\r\n\
+            * CAPABILITY IMAP4rev1 SORT\r\n\
+            A0002 OK Capability completed\r\n\
+            * SORT 9 7 8\r\n\
+            A0003 OK Sort completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.sort("REVERSE DATE", "UTF-8", "ALL").await.unwrap();
+        assert_eq!(*ids, vec![5, 3, 4]);
+
+        // without ESORT, the result is computed from a plain SORT
+        let result = session
+            .uid_sort_return(&[SearchReturn::Partial(2, 3)], "SUBJECT", "UTF-8", "UNSEEN")
+            .await
+            .unwrap();
+        assert_eq!(result.partial, vec![7..=8]);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 SORT (REVERSE DATE) UTF-8 ALL\r\n\
+            A0002 CAPABILITY\r\n\
+            A0003 UID SORT (SUBJECT) UTF-8 UNSEEN\r\n",
+            "Invalid sort commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn sort_return() {
+        let response = b"* CAPABILITY IMAP4rev1 SORT ESORT CONTEXT=SORT\r\n\
+            A0001 OK Capability completed\r\n\
+            * ESEARCH (TAG \"A0002\") UID COUNT 300 PARTIAL (1:3 90,88,85)\r\n\
+            A0002 OK Sort completed\r\n\
+            * ESEARCH (TAG \"A0002\") UID ADDTO (1 91)\r\n\
+            A0003 OK Updates cancelled\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let options = [
+            SearchReturn::Count,
+            SearchReturn::Partial(1, 3),
+            SearchReturn::Update,
+        ];
+        let page = session
+            .uid_sort_return(&options, "REVERSE ARRIVAL", "UTF-8", "ALL")
+            .await
+            .unwrap();
+        assert_eq!(page.count, Some(300));
+        assert_eq!(page.partial, vec![90..=90, 88..=88, 85..=85]);
+
+        let tag = page.tag().cloned().unwrap();
+        session.cancel_update(&[tag]).await.unwrap();
+        assert_eq!(
+            session.unsolicited_responses.try_next().unwrap(),
+            Some(UnsolicitedResponse::ContextUpdate(ContextUpdate {
+                tag: "A0002".into(),
+                uid: true,
+                added: vec![(1, vec![91..=91])],
+                removed: vec![],
+            }))
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 UID SORT RETURN (COUNT PARTIAL 1:3 UPDATE) (REVERSE ARRIVAL) UTF-8 ALL\r\n\
+            A0003 CANCELUPDATE \"A0002\"\r\n",
+            "Invalid sort commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn search_return_without_esearch() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK Capability completed\r\n\
+            * SEARCH 9 3 4 5\r\n\
+            A0002 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let result = session
+            .search_return(&[SearchReturn::Max, SearchReturn::All], "UNSEEN")
+            .await
+            .unwrap();
+        assert_eq!(result.max, Some(9));
+        assert_eq!(result.count, None);
+        assert_eq!(result.all, vec![3..=5, 9..=9]);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 SEARCH UNSEEN\r\n",
+            "Invalid search command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn search_within() {
+        let response = b"* SEARCH 4\r\n\
+            A0001 OK Search completed\r\n\
+            A0002 BAD Unknown search key\r\n\
+            * CAPABILITY IMAP4rev1\r\n\
+            A0003 OK Capability completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let hour = std::time::Duration::from_secs(3600);
+        let ids = session
+            .uid_search(format!("UNSEEN {}", younger(hour)))
+            .await
+            .unwrap();
+        assert_eq!(ids.iter().cloned().collect::<Vec<_>>(), vec![4]);
+        match session.search(older(hour)).await {
+            Err(Error::MissingCapability { required, .. }) => assert_eq!(required, "WITHIN"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 UID SEARCH UNSEEN YOUNGER 3600\r\n\
+            A0002 SEARCH OLDER 3600\r\n\
+            A0003 CAPABILITY\r\n",
+            "Invalid search commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn search_save() {
+        let response = b"* CAPABILITY IMAP4rev1 ESEARCH SEARCHRES\r\n\
+            A0001 OK Capability completed\r\n\
+            A0002 OK Search completed\r\n\
+            A0003 OK Copy completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let result = session
+            .uid_search_return(&[SearchReturn::Save], "UID 1,2,3 UNSEEN")
+            .await
+            .unwrap();
+        assert!(result.all.is_empty() && result.count.is_none());
+        session.uid_copy(SAVED_RESULT, "Archive").await.unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 UID SEARCH RETURN (SAVE) UID 1:3 UNSEEN\r\n\
+            A0003 UID COPY $ Archive\r\n",
+            "Invalid search commands"
+        );
+
+        // without SEARCHRES, the search is still sent, since it can not be emulated
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK Capability completed\r\n\
+            A0002 BAD Unknown option\r\n\
+            * CAPABILITY IMAP4rev1\r\n\
+            A0003 OK Capability completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session
+            .search_return(&[SearchReturn::Count, SearchReturn::Save], "UNSEEN")
+            .await
+        {
+            Err(Error::MissingCapability { required, .. }) => assert_eq!(required, "SEARCHRES"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 SEARCH RETURN (COUNT SAVE) UNSEEN\r\n\
+            A0003 CAPABILITY\r\n",
+            "Invalid search commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn uid_search() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            A0001 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.uid_search("Unseen").await.unwrap();
+        let ids: HashSet<Uid> = ids.iter().cloned().collect();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[async_attributes::test]
+    async fn uid_search_unordered() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            A0002 OK CAPABILITY completed\r\n\
+            A0001 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.uid_search("Unseen").await.unwrap();
+        let ids: HashSet<Uid> = ids.iter().cloned().collect();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[async_attributes::test]
+    async fn capability() {
+        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let capabilities = session.capabilities().await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == b"A0001 CAPABILITY\r\n".to_vec(),
+            "Invalid capability command"
+        );
+        assert_eq!(capabilities.len(), 4);
+        for e in expected_capabilities {
+            assert!(capabilities.has_str(e));
+        }
     }
 
     #[async_attributes::test]
-    async fn readline_eof() {
-        let mock_stream = MockStream::default().with_eof();
-        let mut client = mock_client!(mock_stream);
-        let res = client.read_response().await;
-        println!("{:?}", res);
-        assert!(res.is_none());
+    async fn create() {
+        let response = b"A0001 OK CREATE completed\r\n".to_vec();
+        let mailbox_name = "INBOX";
+        let command = format!("A0001 CREATE {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.create(mailbox_name).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid create command"
+        );
     }
 
     #[async_attributes::test]
-    #[should_panic]
-    async fn readline_err() {
-        // TODO Check the error test
-        let mock_stream = MockStream::default().with_err();
-        let mut client = mock_client!(mock_stream);
-        client.read_response().await.unwrap().unwrap();
+    async fn delete() {
+        let response = b"A0001 OK DELETE completed\r\n".to_vec();
+        let mailbox_name = "INBOX";
+        let command = format!("A0001 DELETE {}\r\n", quote!(mailbox_name));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.delete(mailbox_name).await.unwrap();
+        assert!(
+            session.stream.inner.written_buf == command.as_bytes().to_vec(),
+            "Invalid delete command"
+        );
     }
 
     #[async_attributes::test]
-    async fn authenticate() {
-        let response = b"+ YmFy\r\n\
-                         A0001 OK Logged in\r\n"
+    async fn retry_policy() {
+        let response = b"A0001 NO [UNAVAILABLE] try again later\r\n\
+            * STATUS INBOX (MESSAGES 3)\r\n\
+            A0002 OK STATUS completed\r\n\
+            A0003 NO [UNAVAILABLE] try again later\r\n\
+            A0004 NO [UNAVAILABLE] try again later\r\n\
+            A0005 OK NOOP completed\r\n"
             .to_vec();
-        let command = "A0001 AUTHENTICATE PLAIN\r\n\
-                       Zm9v\r\n";
         let mock_stream = MockStream::new(response);
-        let client = mock_client!(mock_stream);
-        enum Authenticate {
-            Auth,
-        };
-        impl Authenticator for Authenticate {
-            type Response = Vec<u8>;
-            fn process(&self, challenge: &[u8]) -> Self::Response {
-                assert!(challenge == b"bar", "Invalid authenticate challenge");
-                b"foo".to_vec()
-            }
+        let mut session = mock_session!(mock_stream);
+        let policy =
+            RetryPolicy::new(2).backoff(Duration::from_millis(0), Duration::from_millis(0));
+        session.set_retry_policy(Some(policy.clone()));
+
+        let mailbox = session.status("INBOX", "(MESSAGES)").await.unwrap();
+        assert_eq!(mailbox.exists, 3);
+        // creating a mailbox twice fails, so it is not retried
+        match session.create("INBOX").await {
+            Err(Error::No(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
         }
-        let session = client
-            .authenticate("PLAIN", &Authenticate::Auth)
+        session
+            .retry(&policy, |session| session.noop().boxed())
             .await
-            .ok()
             .unwrap();
         assert_eq_bytes!(
             &session.stream.inner.written_buf,
-            command.as_bytes(),
-            "Invalid authenticate command"
+            b"A0001 STATUS \"INBOX\" (MESSAGES)\r\n\
+            A0002 STATUS \"INBOX\" (MESSAGES)\r\n\
+            A0003 CREATE \"INBOX\"\r\n\
+            A0004 NOOP\r\n\
+            A0005 NOOP\r\n",
+            "Invalid retried commands"
         );
     }
 
     #[async_attributes::test]
-    async fn login() {
-        let response = b"A0001 OK Logged in\r\n".to_vec();
-        let username = "username";
-        let password = "password";
-        let command = format!("A0001 LOGIN {} {}\r\n", quote!(username), quote!(password));
+    async fn thread() {
+        let response = b"* THREAD (2)(3 6 (4 23)(44 7 96))\r\n\
+            A0001 OK Thread completed\r\n\
+            * THREAD\r\n\
+            A0002 OK Thread completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
-        let client = mock_client!(mock_stream);
-        if let Ok(session) = client.login(username, password).await {
-            assert_eq!(
-                session.stream.inner.written_buf,
-                command.as_bytes().to_vec(),
-                "Invalid login command"
-            );
-        } else {
-            unreachable!("invalid login");
+        let mut session = mock_session!(mock_stream);
+        let threads = session
+            .thread("REFERENCES", "UTF-8", "SINCE 1-Feb-1994")
+            .await
+            .unwrap();
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].ids(), vec![2]);
+        assert_eq!(threads[1].ids(), vec![3, 6, 4, 23, 44, 7, 96]);
+        assert_eq!(threads[1].children[0].children.len(), 2);
+
+        let threads = session
+            .uid_thread("ORDEREDSUBJECT", "US-ASCII", "ALL")
+            .await
+            .unwrap();
+        assert!(threads.is_empty());
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 THREAD REFERENCES UTF-8 SINCE 1-Feb-1994\r\n\
+            A0002 UID THREAD ORDEREDSUBJECT US-ASCII ALL\r\n",
+            "Invalid thread commands"
+        );
+        // the temporary raw response channel has been removed again
+        assert!(session.stream.protocol.unrecognized.is_none());
+    }
+    #[async_attributes::test]
+    async fn namespace() {
+        let response = b"* NAMESPACE ((\"INBOX.\" \".\")) NIL ((\"#shared.\" \".\"))\r\n\
+            A0001 OK NAMESPACE completed\r\n\
+            A0002 NO Unknown command\r\n\
+            * CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0003 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let namespaces = session.namespace().await.unwrap();
+        assert_eq!(namespaces.personal().unwrap().prefix, "INBOX.");
+        assert_eq!(namespaces.personal().unwrap().mailbox("Sent"), "INBOX.Sent");
+        assert_eq!(namespaces.shared[0].prefix, "#shared.");
+
+        match session.namespace().await {
+            Err(Error::MissingCapability { required, .. }) => assert_eq!(required, "NAMESPACE"),
+            res => panic!("unexpected result: {:?}", res),
         }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 NAMESPACE\r\nA0002 NAMESPACE\r\nA0003 CAPABILITY\r\n",
+            "Invalid namespace commands"
+        );
     }
 
     #[async_attributes::test]
-    async fn logout() {
-        let response = b"A0001 OK Logout completed.\r\n".to_vec();
-        let command = format!("A0001 LOGOUT\r\n");
+    async fn quota() {
+        let response = b"* QUOTAROOT INBOX \"\" group\r\n\
+            * QUOTA \"\" (STORAGE 460 512)\r\n\
+            * ACL INBOX Fred lr\r\n\
+            * QUOTA group (MESSAGE 3 1000 STORAGE 10 100000)\r\n\
+            A0001 OK Getquotaroot completed\r\n\
+            * QUOTA group (MESSAGE 3 1000)\r\n\
+            A0002 OK Getquota completed\r\n\
+            * QUOTA \"\" (STORAGE 460 1024)\r\n\
+            A0003 OK Setquota completed\r\n\
+            A0004 NO Unknown command\r\n\
+            * CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0005 OK done\r\n\
+            * QUOTA \"\" (STORAGE 470 1024)\r\n\
+            A0006 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let root = session.get_quota_root("INBOX").await.unwrap();
+        assert_eq!(root.mailbox, "INBOX");
+        assert_eq!(root.roots, vec!["".to_string(), "group".to_string()]);
+        assert_eq!(root.quotas.len(), 2);
+        let storage = root.quota("").unwrap().resource("STORAGE").unwrap();
+        assert_eq!((storage.usage, storage.limit), (460, 512));
+        assert_eq!(root.quota("group").unwrap().resources.len(), 2);
+
+        let quota = session.get_quota("group").await.unwrap();
+        assert_eq!(
+            *quota,
+            Quota {
+                root: "group".into(),
+                resources: vec![QuotaResource {
+                    name: "MESSAGE".into(),
+                    usage: 3,
+                    limit: 1000,
+                }],
+            }
+        );
+
+        let quota = session.set_quota("", &[("STORAGE", 1024)]).await.unwrap();
+        assert_eq!(quota.resource("STORAGE").unwrap().limit, 1024);
+
+        match session.get_quota("").await {
+            Err(Error::MissingCapability { required, .. }) => assert_eq!(required, "QUOTA"),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // quota updates outside of the quota commands are unsolicited
+        session.noop().await.unwrap();
+        let mut unsolicited = Vec::new();
+        while let Ok(Some(response)) = session.unsolicited_responses.try_next() {
+            unsolicited.push(response);
+        }
+        assert_eq!(unsolicited.len(), 2);
+        assert_eq!(
+            unsolicited[0],
+            UnsolicitedResponse::Acl {
+                mailbox: "INBOX".into(),
+                rights: vec![("Fred".into(), Rights::LOOKUP | Rights::READ)],
+            }
+        );
+        match unsolicited[1] {
+            UnsolicitedResponse::Quota { ref resources, .. } => assert_eq!(resources[0].usage, 470),
+            ref response => panic!("unexpected response: {:?}", response),
+        }
+        assert!(session.stream.protocol.extension_data.is_none());
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 GETQUOTAROOT \"INBOX\"\r\n\
+            A0002 GETQUOTA \"group\"\r\n\
+            A0003 SETQUOTA \"\" (STORAGE 1024)\r\n\
+            A0004 GETQUOTA \"\"\r\n\
+            A0005 CAPABILITY\r\n\
+            A0006 NOOP\r\n",
+            "Invalid quota commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn noop() {
+        let response = b"A0001 OK NOOP completed\r\n".to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.logout().await.unwrap();
+        session.noop().await.unwrap();
         assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid logout command"
+            session.stream.inner.written_buf == b"A0001 NOOP\r\n".to_vec(),
+            "Invalid noop command"
         );
     }
 
     #[async_attributes::test]
-    async fn rename() {
-        let response = b"A0001 OK RENAME completed\r\n".to_vec();
-        let current_mailbox_name = "INBOX";
-        let new_mailbox_name = "NEWINBOX";
-        let command = format!(
-            "A0001 RENAME {} {}\r\n",
-            quote!(current_mailbox_name),
-            quote!(new_mailbox_name)
-        );
+    async fn id() {
+        let response = b"* ID (\"name\" \"Dovecot\" \"version\" NIL)\r\n\
+            A0001 OK ID completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session
-            .rename(current_mailbox_name, new_mailbox_name)
-            .await
-            .unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid rename command"
+        let id = session.id(&[("name", "async-imap")]).await.unwrap();
+        assert_eq!(id.name(), Some("Dovecot"));
+        assert_eq!(id.version(), None);
+        assert_eq!(session.server_id(), Some(&*id));
+        assert_eq!(session.quirks(), &Quirks::default());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 ID (\"name\" \"async-imap\")\r\n".to_vec()
         );
     }
 
+    #[cfg(feature = "self-test")]
     #[async_attributes::test]
-    async fn subscribe() {
-        let response = b"A0001 OK SUBSCRIBE completed\r\n".to_vec();
-        let mailbox = "INBOX";
-        let command = format!("A0001 SUBSCRIBE {}\r\n", quote!(mailbox));
+    async fn self_test() {
+        let response = b"* CAPABILITY IMAP4rev1 IDLE AUTH=PLAIN\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 3 EXISTS\r\n\
+            * XSTATE idle\r\n\
+            A0002 OK NOOP completed\r\n\
+            * LIST (\\Noselect) \"/\" \"\"\r\n\
+            A0003 OK LIST completed\r\n\
+            * STATUS \"Inbox\" (MESSAGES 2 UIDNEXT 3 UIDVALIDITY 0)\r\n\
+            A0004 OK STATUS completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.subscribe(mailbox).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid subscribe command"
+        let report = session.self_test().await.unwrap();
+        assert_eq!(report.capabilities, ["IMAP4rev1", "IDLE", "AUTH=PLAIN"]);
+        assert!(!report.passed());
+        assert!(report.checks[0].passed());
+        assert_eq!(
+            report.checks[1].deviations,
+            ["unrecognized response: * XSTATE idle"]
+        );
+        assert!(report.checks[2].passed());
+        assert_eq!(
+            report.checks[3].deviations,
+            [
+                "UIDVALIDITY is 0, which is not a valid value",
+                "UNSEEN are missing"
+            ]
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_next().unwrap(),
+            Some(UnsolicitedResponse::Exists(3))
+        );
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 NOOP\r\nA0003 LIST \"\" \"\"\r\n\
+            A0004 STATUS INBOX (MESSAGES UIDNEXT UIDVALIDITY UNSEEN)\r\n",
+            "Invalid self-test commands"
         );
+
+        let report = report.to_string();
+        assert!(report.contains("\nNOOP: deviates\n  - unrecognized response: * XSTATE idle\n"));
+        assert!(report.contains("\nCAPABILITY: ok\n"));
     }
 
     #[async_attributes::test]
-    async fn unsubscribe() {
-        let response = b"A0001 OK UNSUBSCRIBE completed\r\n".to_vec();
-        let mailbox = "INBOX";
-        let command = format!("A0001 UNSUBSCRIBE {}\r\n", quote!(mailbox));
+    async fn raw_command() {
+        let response = b"* SEARCH 1 2\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK [ALERT] Search completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.unsubscribe(mailbox).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid unsubscribe command"
-        );
+        let tag = session.run_command("SEARCH ALL").await.unwrap();
+        let responses: Vec<_> = session.responses(&tag).try_collect().await.unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].parsed(), &Response::IDs(vec![1, 2]));
+        let done = session.check_done(&tag).await.unwrap();
+        assert_eq!(done.information(), Some("Search completed"));
+        assert!(session.stream.inner.read_pos_at_end());
     }
 
     #[async_attributes::test]
-    async fn expunge() {
-        let response = b"A0001 OK EXPUNGE completed\r\n".to_vec();
+    async fn raw_command_check_done() {
+        let response = b"* 3 EXISTS\r\n\
+            A0001 NO Unknown command\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.expunge().await.unwrap().collect::<Vec<_>>().await;
-        assert!(
-            session.stream.inner.written_buf == b"A0001 EXPUNGE\r\n".to_vec(),
-            "Invalid expunge command"
+        let tag = session.run_command("XFOO").await.unwrap();
+        match session.check_done(&tag).await {
+            Err(Error::No(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            session.drain_unsolicited(),
+            vec![UnsolicitedResponse::Exists(3)]
         );
     }
 
     #[async_attributes::test]
-    async fn uid_expunge() {
-        let response = b"* 2 EXPUNGE\r\n\
-            * 3 EXPUNGE\r\n\
-            * 4 EXPUNGE\r\n\
-            A0001 OK UID EXPUNGE completed\r\n"
+    async fn drain_unsolicited() {
+        let response = b"* 1 RECENT\r\n\
+            * 2 EXISTS\r\n\
+            A0001 OK NOOP completed\r\n"
             .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.drain_unsolicited(),
+            vec![
+                UnsolicitedResponse::Recent(1),
+                UnsolicitedResponse::Exists(2)
+            ]
+        );
+        assert!(session.drain_unsolicited().is_empty());
+    }
+
+    #[async_attributes::test]
+    async fn flag_events() {
+        let response = b"* 1 FETCH (FLAGS (\\Seen))\r\n\
+            A0001 OK STORE completed\r\n\
+            * 2 FETCH (UID 9 FLAGS (\\Flagged $Important))\r\n\
+            * 3 FETCH (UID 10)\r\n\
+            A0002 OK NOOP completed\r\n\
+            A0003 OK [READ-WRITE] SELECT completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut events = session.flag_events();
         session
-            .uid_expunge("2:4")
+            .store("1", "+FLAGS (\\Seen)")
             .await
             .unwrap()
             .collect::<Vec<_>>()
             .await;
-        assert!(
-            session.stream.inner.written_buf == b"A0001 UID EXPUNGE 2:4\r\n".to_vec(),
-            "Invalid expunge command"
-        );
-    }
+        session.noop().await.unwrap();
+        session.select("Archive").await.unwrap();
 
-    #[async_attributes::test]
-    async fn check() {
-        let response = b"A0001 OK CHECK completed\r\n".to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.check().await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 CHECK\r\n".to_vec(),
-            "Invalid check command"
+        assert_eq!(
+            events.next().await,
+            Some(FlagEvent {
+                message: 1,
+                uid: None,
+                flags: vec![Flag::Seen].into_iter().collect(),
+            })
+        );
+        assert_eq!(
+            events.next().await,
+            Some(FlagEvent {
+                message: 2,
+                uid: Some(9),
+                flags: vec![Flag::Flagged, Flag::Custom("$Important".into())]
+                    .into_iter()
+                    .collect(),
+            })
         );
+        // selecting another mailbox ends the stream
+        assert_eq!(events.next().await, None);
     }
 
     #[async_attributes::test]
-    async fn examine() {
-        let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
-            * OK [PERMANENTFLAGS ()] Read-only mailbox.\r\n\
-            * 1 EXISTS\r\n\
-            * 1 RECENT\r\n\
-            * OK [UNSEEN 1] First unseen.\r\n\
-            * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
-            * OK [UIDNEXT 2] Predicted next UID\r\n\
-            A0001 OK [READ-ONLY] Select completed.\r\n"
+    async fn track_uids() {
+        let response = b"* SEARCH 4 7 9\r\n\
+            A0001 OK SEARCH completed\r\n\
+            * 2 EXPUNGE\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK NOOP completed\r\n\
+            * 3 FETCH (UID 12 FLAGS ())\r\n\
+            A0003 OK FETCH completed\r\n"
             .to_vec();
-        let expected_mailbox = Mailbox {
-            flags: vec![
-                Flag::Answered,
-                Flag::Flagged,
-                Flag::Deleted,
-                Flag::Seen,
-                Flag::Draft,
-            ],
-            exists: 1,
-            recent: 1,
-            unseen: Some(1),
-            permanent_flags: vec![],
-            uid_next: Some(2),
-            uid_validity: Some(1257842737),
-        };
-        let mailbox_name = "INBOX";
-        let command = format!("A0001 EXAMINE {}\r\n", quote!(mailbox_name));
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let mailbox = session.examine(mailbox_name).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid examine command"
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.uid_for_seq(1), None);
+        session.track_uids().await.unwrap();
+        assert_eq!(session.uid_for_seq(2), Some(7));
+        assert_eq!(session.seq_for_uid(9), Some(3));
+
+        session.noop().await.unwrap();
+        assert_eq!(session.uid_for_seq(2), Some(9));
+        assert_eq!(session.seq_for_uid(7), None);
+        assert_eq!(session.uid_for_seq(3), None);
+
+        session
+            .uid_fetch("12", "FLAGS")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(session.seq_for_uid(12), Some(3));
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 UID SEARCH ALL\r\nA0002 NOOP\r\nA0003 UID FETCH 12 FLAGS\r\n",
+            "Invalid commands"
         );
-        assert_eq!(mailbox, expected_mailbox);
     }
 
-    #[async_attributes::test]
-    async fn select() {
-        let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
-            * OK [PERMANENTFLAGS (\\* \\Answered \\Flagged \\Deleted \\Draft \\Seen)] \
-              Read-only mailbox.\r\n\
-            * 1 EXISTS\r\n\
-            * 1 RECENT\r\n\
-            * OK [UNSEEN 1] First unseen.\r\n\
-            * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
-            * OK [UIDNEXT 2] Predicted next UID\r\n\
-            A0001 OK [READ-ONLY] Select completed.\r\n"
+    #[async_attributes::test]
+    async fn replay_unsolicited() {
+        let response = b"* 1 RECENT\r\n\
+            * 2 EXISTS\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK NOOP completed\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK [READ-WRITE] SELECT completed\r\n\
+            * 4 EXISTS\r\n\
+            A0003 OK NOOP completed\r\n"
             .to_vec();
-        let expected_mailbox = Mailbox {
-            flags: vec![
-                Flag::Answered,
-                Flag::Flagged,
-                Flag::Deleted,
-                Flag::Seen,
-                Flag::Draft,
-            ],
-            exists: 1,
-            recent: 1,
-            unseen: Some(1),
-            permanent_flags: vec![
-                Flag::MayCreate,
-                Flag::Answered,
-                Flag::Flagged,
-                Flag::Deleted,
-                Flag::Draft,
-                Flag::Seen,
-            ],
-            uid_next: Some(2),
-            uid_validity: Some(1257842737),
-        };
-        let mailbox_name = "INBOX";
-        let command = format!("A0001 SELECT {}\r\n", quote!(mailbox_name));
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        let mailbox = session.select(mailbox_name).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid select command"
+        session.set_replay_capacity(2);
+        session.noop().await.unwrap();
+        assert_eq!(session.drain_unsolicited().len(), 3);
+        assert_eq!(
+            session.replay_unsolicited(),
+            vec![
+                UnsolicitedResponse::Exists(2),
+                UnsolicitedResponse::Exists(3)
+            ]
+        );
+
+        session.select("INBOX").await.unwrap();
+        assert!(session.replay_unsolicited().is_empty());
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.replay_unsolicited(),
+            vec![UnsolicitedResponse::Exists(4)]
         );
-        assert_eq!(mailbox, expected_mailbox);
     }
 
     #[async_attributes::test]
-    async fn search() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            A0001 OK Search completed\r\n"
+    async fn pending_commands() {
+        let response = b"A0001 OK done\r\nA0002 OK done\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let first = session.run_command("NOOP").await.unwrap();
+        let second = session.run_command("CHECK").await.unwrap();
+        assert_eq!(session.pending_commands(), &[first, second.clone()]);
+        session.read_response().await.unwrap().unwrap();
+        assert_eq!(session.pending_commands(), &[second]);
+        session.read_response().await.unwrap().unwrap();
+        assert!(session.pending_commands().is_empty());
+        assert_eq!(session.stats().max_in_flight, 2);
+    }
+
+    #[async_attributes::test]
+    async fn enqueue_flush() {
+        let response = b"* 2 FETCH (FLAGS (\\Deleted))\r\n\
+            A0001 OK STORE completed\r\n\
+            * 2 EXPUNGE\r\n\
+            A0002 OK EXPUNGE completed\r\n"
             .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        let ids = session.search("Unseen").await.unwrap();
-        let ids: HashSet<u32> = ids.iter().cloned().collect();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
+        session
+            .enqueue("STORE 2 +FLAGS.SILENT (\\Deleted)")
+            .unwrap();
+        session.enqueue("EXPUNGE").unwrap();
+        assert_eq!(session.queued_commands(), 2);
+        // nothing is sent before flushing
+        assert!(session.stream.inner.written_buf.is_empty());
+        assert!(session.pending_commands().is_empty());
+
+        session.flush().await.unwrap();
+        assert_eq!(session.queued_commands(), 0);
+        assert!(session.pending_commands().is_empty());
+        assert_eq!(session.stats().max_in_flight, 2);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 STORE 2 +FLAGS.SILENT (\\Deleted)\r\nA0002 EXPUNGE\r\n",
+            "Invalid queued commands"
+        );
+        assert_eq!(
+            session.unsolicited_responses.next().await.unwrap(),
+            UnsolicitedResponse::Expunge(2)
         );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
     }
 
     #[async_attributes::test]
-    async fn uid_search() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            A0001 OK Search completed\r\n"
+    async fn enqueue_from_tasks() {
+        let response = b"A0001 OK STORE completed\r\n\
+            A0002 OK EXPUNGE completed\r\n"
             .to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let ids = session.uid_search("Unseen").await.unwrap();
-        let ids: HashSet<Uid> = ids.iter().cloned().collect();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
+        let session = Arc::new(Mutex::new(mock_session!(MockStream::new(response))));
+
+        let store = {
+            let session = session.clone();
+            async_std::task::spawn(async move {
+                session
+                    .lock()
+                    .await
+                    .enqueue("STORE 2 +FLAGS (\\Deleted)")
+                    .unwrap()
+            })
+        };
+        let id = store.await;
+        let expunge = {
+            let session = session.clone();
+            async_std::task::spawn(async move {
+                let mut session = session.lock().await;
+                session.enqueue("EXPUNGE").unwrap();
+                session.flush().await
+            })
+        };
+        expunge.await.unwrap();
+
+        assert_eq!(id, RequestId("A0001".into()));
+        assert_eq_bytes!(
+            &session.lock().await.stream.inner.written_buf,
+            b"A0001 STORE 2 +FLAGS (\\Deleted)\r\nA0002 EXPUNGE\r\n",
+            "Invalid queued commands"
         );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
     }
 
     #[async_attributes::test]
-    async fn uid_search_unordered() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            A0002 OK CAPABILITY completed\r\n\
-            A0001 OK Search completed\r\n"
+    async fn flush_reports_first_error() {
+        let response = b"A0002 OK NOOP completed\r\n\
+            A0001 NO [TRYCREATE] Mailbox doesn't exist\r\n\
+            A0003 BAD Syntax error\r\n"
             .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        let ids = session.uid_search("Unseen").await.unwrap();
-        let ids: HashSet<Uid> = ids.iter().cloned().collect();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 UID SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
-        );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+        session.enqueue("COPY 1 Nope").unwrap();
+        session.enqueue("NOOP").unwrap();
+        session.enqueue("FOO").unwrap();
+        match session.flush().await {
+            Err(Error::No(_)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        // all completions have been consumed
+        assert!(session.stream.inner.read_pos_at_end());
     }
 
     #[async_attributes::test]
-    async fn capability() {
-        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n\
-            A0001 OK CAPABILITY completed\r\n"
+    async fn estimate_mailbox_size_status() {
+        let response = b"* CAPABILITY IMAP4rev1 STATUS=SIZE\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * STATUS INBOX (MESSAGES 3 SIZE 12345)\r\n\
+            A0002 OK STATUS completed\r\n"
             .to_vec();
-        let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        let capabilities = session.capabilities().await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 CAPABILITY\r\n".to_vec(),
-            "Invalid capability command"
+        let size = session.estimate_mailbox_size("INBOX").await.unwrap();
+        assert_eq!(size, MailboxSize::exact(3, 12345));
+        assert!(size.is_exact());
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 STATUS \"INBOX\" (MESSAGES SIZE)\r\n",
+            "Invalid status size command"
         );
-        assert_eq!(capabilities.len(), 4);
-        for e in expected_capabilities {
-            assert!(capabilities.has_str(e));
-        }
+        // the temporary raw response channel has been removed again
+        assert!(session.stream.protocol.unrecognized.is_none());
     }
 
     #[async_attributes::test]
-    async fn create() {
-        let response = b"A0001 OK CREATE completed\r\n".to_vec();
-        let mailbox_name = "INBOX";
-        let command = format!("A0001 CREATE {}\r\n", quote!(mailbox_name));
+    async fn unread_counts_status() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * STATUS INBOX (MESSAGES 3 UNSEEN 1)\r\n\
+            A0002 OK STATUS completed\r\n\
+            A0003 NO Mailbox does not exist\r\n\
+            * STATUS Sent (MESSAGES 7 UNSEEN 0)\r\n\
+            A0004 OK STATUS completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.create(mailbox_name).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid create command"
+        let counts = session
+            .unread_counts(&["INBOX", "Missing", "Sent"])
+            .await
+            .unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(
+            counts["INBOX"],
+            UnreadCount {
+                messages: 3,
+                unseen: 1
+            }
+        );
+        assert_eq!(counts["Sent"].messages, 7);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+              A0002 STATUS \"INBOX\" (MESSAGES UNSEEN)\r\n\
+              A0003 STATUS \"Missing\" (MESSAGES UNSEEN)\r\n\
+              A0004 STATUS \"Sent\" (MESSAGES UNSEEN)\r\n",
+            "Invalid status commands"
         );
     }
 
     #[async_attributes::test]
-    async fn delete() {
-        let response = b"A0001 OK DELETE completed\r\n".to_vec();
-        let mailbox_name = "INBOX";
-        let command = format!("A0001 DELETE {}\r\n", quote!(mailbox_name));
+    async fn unread_counts_list_status() {
+        let response = b"* CAPABILITY IMAP4rev1 LIST-EXTENDED LIST-STATUS\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * LIST () \"/\" INBOX\r\n\
+            * STATUS INBOX (MESSAGES 3 UNSEEN 1)\r\n\
+            A0002 OK LIST completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.delete(mailbox_name).await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == command.as_bytes().to_vec(),
-            "Invalid delete command"
+        let counts = session.unread_counts(vec!["INBOX"]).await.unwrap();
+        assert_eq!(counts["INBOX"].unseen, 1);
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+              A0002 LIST \"\" (\"INBOX\") RETURN (STATUS (MESSAGES UNSEEN))\r\n",
+            "Invalid list command"
         );
     }
 
     #[async_attributes::test]
-    async fn noop() {
-        let response = b"A0001 OK NOOP completed\r\n".to_vec();
+    async fn estimate_mailbox_size_fetch() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK CAPABILITY completed\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK [READ-ONLY] EXAMINE completed\r\n\
+            * 1 FETCH (RFC822.SIZE 100)\r\n\
+            * 2 FETCH (RFC822.SIZE 200)\r\n\
+            * 3 FETCH (RFC822.SIZE 300)\r\n\
+            A0003 OK FETCH completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.noop().await.unwrap();
-        assert!(
-            session.stream.inner.written_buf == b"A0001 NOOP\r\n".to_vec(),
-            "Invalid noop command"
+        let size = session.estimate_mailbox_size("INBOX").await.unwrap();
+        assert_eq!(size.messages, 3);
+        assert_eq!(size.bytes, 600);
+        assert_eq!(size.margin, 0);
+        assert_eq!(size.sampled, 3);
+        assert!(size.is_exact());
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 EXAMINE \"INBOX\"\r\nA0003 FETCH 1:* RFC822.SIZE\r\n",
+            "Invalid size sampling commands"
         );
     }
 
+    #[test]
+    fn mailbox_size_from_sample() {
+        // messages of the same size leave no margin, but the rest was not measured
+        let size = MailboxSize::from_sample(1000, &[500; 200]);
+        assert_eq!(size.bytes, 500_000);
+        assert_eq!(size.margin, 0);
+        assert!(!size.is_exact());
+
+        // without a single size, nothing is known
+        let size = MailboxSize::from_sample(1000, &[]);
+        assert!(!size.is_exact());
+        assert_eq!(size.min(), 0);
+        assert_eq!(size.max(), u64::MAX);
+
+        let size = MailboxSize::from_sample(2, &[100, 200]);
+        assert_eq!(size.bytes, 300);
+        assert!(size.is_exact());
+
+        let size = MailboxSize::from_sample(1000, &[100, 300, 100, 300]);
+        assert_eq!(size.bytes, 200_000);
+        assert!(size.min() < 200_000 && size.max() > 200_000);
+        assert!(!size.is_exact());
+    }
+
     #[async_attributes::test]
     async fn close() {
         let response = b"A0001 OK CLOSE completed\r\n".to_vec();
@@ -1851,6 +7315,205 @@ mod tests {
         .await;
     }
 
+    #[async_attributes::test]
+    async fn mark_keywords() {
+        let response = b"A0001 OK STORE completed\r\n\
+            A0002 OK STORE completed\r\n\
+            A0003 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.mark_forwarded("3,4,5").await.unwrap();
+        session.mark_junk("7", false).await.unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 UID STORE 3:5 +FLAGS.SILENT ($Forwarded)\r\n\
+            A0002 UID STORE 7 -FLAGS.SILENT ($Junk)\r\n\
+            A0003 UID STORE 7 +FLAGS.SILENT ($NotJunk)\r\n",
+            "Invalid store commands"
+        );
+        assert_eq!(Flag::from("$MDNSent"), Flag::MDN_SENT);
+        assert_eq!(Flag::Seen.to_string(), "\\Seen");
+    }
+
+    #[async_attributes::test]
+    async fn mark_seen_and_delete() {
+        let response = b"A0001 OK STORE completed\r\n\
+            A0002 OK STORE completed\r\n\
+            A0003 OK STORE completed\r\n\
+            * CAPABILITY IMAP4rev1 UIDPLUS\r\n\
+            A0004 OK CAPABILITY completed\r\n\
+            * 3 EXPUNGE\r\n\
+            A0005 OK EXPUNGE completed\r\n\
+            A0006 OK STORE completed\r\n\
+            A0007 OK EXPUNGE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.mark_seen("1,2").await.unwrap();
+        session.mark_unseen("3").await.unwrap();
+        session.delete_messages("4", true).await.unwrap();
+        // the capabilities are only asked for once
+        session.delete_messages("5", true).await.unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 UID STORE 1:2 +FLAGS.SILENT (\\Seen)\r\n\
+            A0002 UID STORE 3 -FLAGS.SILENT (\\Seen)\r\n\
+            A0003 UID STORE 4 +FLAGS.SILENT (\\Deleted)\r\n\
+            A0004 CAPABILITY\r\n\
+            A0005 UID EXPUNGE 4\r\n\
+            A0006 UID STORE 5 +FLAGS.SILENT (\\Deleted)\r\n\
+            A0007 UID EXPUNGE 5\r\n",
+            "Invalid commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn archive() {
+        let response = b"* LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+            * LIST (\\HasNoChildren \\Archive) \"/\" \"Old Mail\"\r\n\
+            A0001 OK LIST completed\r\n\
+            A0002 OK MOVE completed\r\n\
+            A0003 BAD unknown command\r\n\
+            * CAPABILITY IMAP4rev1\r\n\
+            A0004 OK CAPABILITY completed\r\n\
+            A0005 OK COPY completed\r\n\
+            * CAPABILITY IMAP4rev1\r\n\
+            A0006 OK CAPABILITY completed\r\n\
+            A0007 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.archive("1").await.unwrap();
+        // without MOVE, the messages are copied and flagged as deleted, but without UIDPLUS, they
+        // are not expunged, which would remove any other deleted message as well
+        session.archive("2").await.unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 LIST \"\" \"*\"\r\n\
+            A0002 UID MOVE 1 \"Old Mail\"\r\n\
+            A0003 UID MOVE 2 \"Old Mail\"\r\n\
+            A0004 CAPABILITY\r\n\
+            A0005 UID COPY 2 \"Old Mail\"\r\n\
+            A0006 CAPABILITY\r\n\
+            A0007 UID STORE 2 +FLAGS.SILENT (\\Deleted)\r\n",
+            "Invalid commands"
+        );
+
+        let response = b"* LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+            A0001 OK LIST completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.archive("1").await {
+            Err(Error::NoSpecialUse(attr)) => assert_eq!(attr, "\\Archive"),
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[async_attributes::test]
+    async fn archive_profiles() {
+        for &profile in Profile::ALL.iter() {
+            let mut script = Script::default();
+            let list = "LIST \"\" \"*\"";
+            script.exchange(list, profile.list_response(), "OK LIST completed");
+            let mailbox = match profile {
+                Profile::Gmail => {
+                    script.exchange("CAPABILITY", &profile.capability_response(), "OK done");
+                    script.exchange(list, profile.list_response(), "OK LIST completed");
+                    "\"[Gmail]/All Mail\""
+                }
+                Profile::Courier => {
+                    script.exchange("CAPABILITY", &profile.capability_response(), "OK done");
+                    let mut session = mock_session!(script.stream());
+                    match session.archive("7").await {
+                        Err(Error::NoSpecialUse(attr)) => assert_eq!(attr, "\\Archive"),
+                        res => panic!("unexpected result: {:?}", res.map(|_| ())),
+                    }
+                    assert_eq_bytes!(
+                        &session.stream.inner.written_buf,
+                        script.commands(),
+                        "Invalid commands"
+                    );
+                    continue;
+                }
+                _ => "Archive",
+            };
+
+            if profile.has("MOVE") {
+                for uid in &["7", "8"] {
+                    let command = format!("UID MOVE {} {}", uid, mailbox);
+                    script.exchange(&command, "* 1 EXPUNGE\r\n", "OK MOVE completed");
+                }
+            } else {
+                let command = format!("UID MOVE 7 {}", mailbox);
+                script.exchange(&command, "", "BAD Unknown command");
+                script.exchange("CAPABILITY", &profile.capability_response(), "OK done");
+                for (i, uid) in ["7", "8"].iter().enumerate() {
+                    let copy = format!("UID COPY {} {}", uid, mailbox);
+                    let store = format!("UID STORE {} +FLAGS.SILENT (\\Deleted)", uid);
+                    script.exchange(&copy, "", "OK COPY completed");
+                    if i == 0 {
+                        let capabilities = profile.capability_response();
+                        script.exchange("CAPABILITY", &capabilities, "OK done");
+                    }
+                    script.exchange(&store, "", "OK STORE completed");
+                    if profile.has("UIDPLUS") {
+                        let expunge = format!("UID EXPUNGE {}", uid);
+                        script.exchange(&expunge, "* 1 EXPUNGE\r\n", "OK EXPUNGE completed");
+                    }
+                }
+            }
+
+            let mut session = mock_session!(script.stream());
+            session.archive("7").await.unwrap();
+            // a missing MOVE is only found out once
+            session.archive("8").await.unwrap();
+            assert_eq!(
+                str::from_utf8(&session.stream.inner.written_buf).unwrap(),
+                str::from_utf8(script.commands()).unwrap(),
+                "Invalid commands for {:?}",
+                profile
+            );
+        }
+    }
+
+    #[async_attributes::test]
+    async fn fetch_matching_profiles() {
+        for &profile in Profile::ALL.iter() {
+            let mut script = Script::default();
+            script.exchange("CAPABILITY", &profile.capability_response(), "OK done");
+            if profile.has("SEARCHRES") {
+                script.exchange("UID SEARCH RETURN (SAVE) UNSEEN", "", "OK SEARCH completed");
+                script.exchange(
+                    "UID FETCH $ FLAGS",
+                    "* 1 FETCH (UID 7 FLAGS ())\r\n",
+                    "OK done",
+                );
+            } else {
+                script.exchange("UID SEARCH UNSEEN", "* SEARCH 7\r\n", "OK SEARCH completed");
+                script.exchange(
+                    "UID FETCH 7 FLAGS",
+                    "* 1 FETCH (UID 7 FLAGS ())\r\n",
+                    "OK done",
+                );
+            }
+
+            let mut session = mock_session!(script.stream());
+            let fetches: Vec<_> = session
+                .fetch_matching("UNSEEN", "FLAGS")
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(fetches.len(), 1);
+            assert_eq!(fetches[0].uid, Some(7));
+            assert_eq!(
+                str::from_utf8(&session.stream.inner.written_buf).unwrap(),
+                str::from_utf8(script.commands()).unwrap(),
+                "Invalid commands for {:?}",
+                profile
+            );
+        }
+    }
+
     async fn generic_store<'a, F, T, K>(prefix: &'a str, op: F)
     where
         F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
@@ -1909,11 +7572,26 @@ mod tests {
         let command = format!("A0001 MOVE 1:2 {}\r\n", quote!(mailbox_name));
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.mv("1:2", mailbox_name).await.unwrap();
+        let moved = session.mv("1:2", mailbox_name).await.unwrap();
         assert!(
             session.stream.inner.written_buf == command.as_bytes().to_vec(),
             "Invalid move command"
         );
+        assert_eq!(moved.expunged, vec![2, 1]);
+        let copy_uid = moved.copy_uid.as_ref().unwrap();
+        assert_eq!(copy_uid.uid_validity, 1511554416);
+        assert_eq!(
+            copy_uid.pairs().collect::<Vec<_>>(),
+            vec![(142, 41), (399, 42)]
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_next().unwrap(),
+            Some(UnsolicitedResponse::Expunge(2))
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_next().unwrap(),
+            Some(UnsolicitedResponse::Expunge(1))
+        );
     }
 
     #[async_attributes::test]
@@ -1927,11 +7605,48 @@ mod tests {
         let command = format!("A0001 UID MOVE 41:42 {}\r\n", quote!(mailbox_name));
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.uid_mv("41:42", mailbox_name).await.unwrap();
+        let moved = session.uid_mv("41:42", mailbox_name).await.unwrap();
         assert!(
             session.stream.inner.written_buf == command.as_bytes().to_vec(),
             "Invalid uid move command"
         );
+        assert_eq!(moved.expunged, vec![2, 1]);
+        assert_eq!(moved.copy_uid.as_ref().unwrap().destination, vec![41..=42]);
+    }
+
+    #[async_attributes::test]
+    async fn mv_missing_capability() {
+        let response = b"A0001 BAD Unknown command\r\n\
+            * CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0002 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.mv("1:2", "Archive").await {
+            Err(Error::MissingCapability { required, present }) => {
+                assert_eq!(required, "MOVE");
+                assert_eq!(present, vec!["IDLE", "IMAP4rev1"]);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 MOVE 1:2 \"Archive\"\r\nA0002 CAPABILITY\r\n",
+            "Invalid move command"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn idle_missing_capability() {
+        let response = b"A0001 BAD Unknown command\r\n\
+            * CAPABILITY IMAP4rev1 MOVE\r\n\
+            A0002 OK done\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut idle = session.idle();
+        match idle.init().await {
+            Err(Error::MissingCapability { required, .. }) => assert_eq!(required, "IDLE"),
+            res => panic!("unexpected result: {:?}", res),
+        }
     }
 
     #[async_attributes::test]
@@ -1963,6 +7678,171 @@ mod tests {
         .await;
     }
 
+    #[async_attributes::test]
+    async fn unsolicited_capacity() {
+        let response = b"* 3 EXISTS\r\n* 1 RECENT\r\nA0001 OK done\r\n".to_vec();
+        let mut client = mock_client!(MockStream::new(response));
+        client.unsolicited_capacity = 4;
+        let mut session = Session::new(client.conn);
+        assert_eq!(session.unsolicited_responses.capacity(), 4);
+        assert!(session.unsolicited_responses.is_empty());
+
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_responses.len(), 2);
+        session.unsolicited_responses.try_next().unwrap();
+        assert_eq!(session.unsolicited_responses.len(), 1);
+        assert!(session.unsolicited_responses.next().await.is_some());
+        assert!(session.unsolicited_responses.is_empty());
+    }
+
+    #[test]
+    fn fetch_yields_to_unsolicited() {
+        use futures::executor::LocalPool;
+        use futures::task::LocalSpawnExt;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut response = Vec::new();
+        for i in 1..=200 {
+            response.extend(format!("* {} FETCH (FLAGS ())\r\n", i).into_bytes());
+            if i % 50 == 0 {
+                response.extend(format!("* {} EXISTS\r\n", 200 + i).into_bytes());
+            }
+        }
+        response.extend(b"A0001 OK done\r\n");
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_max_consecutive_responses(Some(8));
+        let replay = std::sync::Arc::new(std::sync::Mutex::new(ReplayBuffer::new(0)));
+        let mut unsolicited = std::mem::replace(
+            &mut session.unsolicited_responses,
+            unsolicited_channel(1, replay).1,
+        );
+
+        // both tasks run on the same thread, so the unsolicited responses are only seen before
+        // the fetch has completed if the fetch yields in between
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut pool = LocalPool::new();
+        let fetch_log = log.clone();
+        pool.spawner()
+            .spawn_local(async move {
+                let fetches = session.fetch("1:200", "FLAGS").await.unwrap();
+                fetches
+                    .for_each(|fetch| {
+                        fetch.unwrap();
+                        fetch_log.borrow_mut().push('F');
+                        future::ready(())
+                    })
+                    .await;
+            })
+            .unwrap();
+        let unsolicited_log = log.clone();
+        pool.spawner()
+            .spawn_local(async move {
+                while unsolicited.next().await.is_some() {
+                    unsolicited_log.borrow_mut().push('U');
+                }
+            })
+            .unwrap();
+        pool.run();
+
+        let log = log.borrow();
+        assert_eq!(log.iter().filter(|c| **c == 'U').count(), 4);
+        let first = log.iter().position(|c| *c == 'U').unwrap();
+        assert!(first < 70, "unsolicited responses starved: {:?}", first);
+    }
+
+    #[async_attributes::test]
+    async fn fetch_select() {
+        let response = b"* 1 FETCH (FLAGS ())\r\n\
+            * 2 FETCH (FLAGS (\\Seen))\r\n\
+            A0001 OK done\r\n\
+            A0002 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let (_stop_tx, mut stop) = mpsc::channel::<()>(1);
+        let fetches = session.fetch("1:2", "FLAGS").await.unwrap();
+        futures::pin_mut!(fetches);
+
+        let mut seen = Vec::new();
+        loop {
+            futures::select! {
+                fetch = fetches.next() => match fetch {
+                    Some(fetch) => seen.push(fetch.unwrap().message),
+                    None => break,
+                },
+                _ = stop.next() => unreachable!(),
+            }
+        }
+        assert_eq!(seen, vec![1, 2]);
+
+        // polling after the end neither panics nor reads the responses to the next command
+        assert!(fetches.is_terminated());
+        assert!(fetches.next().await.is_none());
+        assert!(fetches.next().await.is_none());
+        session.noop().await.unwrap();
+    }
+
+    #[async_attributes::test]
+    async fn fetch_matching_searchres() {
+        let response = b"* CAPABILITY IMAP4rev1 SEARCHRES\r\n\
+            A0001 OK done\r\n\
+            A0002 OK search saved\r\n\
+            * 3 FETCH (UID 7 FLAGS (\\Seen))\r\n\
+            A0003 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session
+            .fetch_matching("UNSEEN", "FLAGS")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].as_ref().unwrap().uid, Some(7));
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 UID SEARCH RETURN (SAVE) UNSEEN\r\n\
+            A0003 UID FETCH $ FLAGS\r\n",
+            "Invalid fetch_matching commands"
+        );
+    }
+
+    #[async_attributes::test]
+    async fn fetch_matching_fallback() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK done\r\n\
+            * SEARCH 9 7 8\r\n\
+            A0002 OK done\r\n\
+            A0003 OK done\r\n\
+            * SEARCH\r\n\
+            A0004 OK done\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session
+            .fetch_matching("UNSEEN", "FLAGS")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        // no matches, so nothing is fetched
+        let fetches = session
+            .fetch_matching("DELETED", "FLAGS")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert!(fetches.is_empty());
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 UID SEARCH UNSEEN\r\n\
+            A0003 UID FETCH 7:9 FLAGS\r\n\
+            A0004 UID SEARCH DELETED\r\n",
+            "Invalid fetch_matching commands"
+        );
+    }
+
     async fn generic_fetch<'a, F, T, K>(prefix: &'a str, op: F)
     where
         F: 'a + FnOnce(Arc<Mutex<Session<MockStream>>>, &'a str, &'a str) -> K,
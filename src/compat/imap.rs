@@ -0,0 +1,439 @@
+//! The method names and result types of the synchronous [`imap`](https://docs.rs/imap/2) crate,
+//! so that code written against it can move to this crate without being rewritten at once.
+//!
+//! For most programs, migrating means replacing `imap::` with `async_imap::compat::imap::`, and
+//! the `native_tls` connector with the one of `async_native_tls`:
+//!
+//! ```no_run
+//! use async_imap::compat::imap;
+//!
+//! # fn main() -> imap::error::Result<()> {
+//! let tls = async_native_tls::TlsConnector::new();
+//! let client = imap::connect(("imap.example.org", 993), "imap.example.org", tls)?;
+//! let mut imap_session = client.login("user", "password").map_err(|e| e.0)?;
+//! imap_session.select("INBOX")?;
+//! let messages = imap_session.fetch("1", "RFC822")?;
+//! if let Some(body) = messages.iter().next().and_then(|m| m.body()) {
+//!     println!("{}", String::from_utf8_lossy(body));
+//! }
+//! imap_session.logout()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each method blocks until the command has completed, and is implemented by its counterpart in
+//! [`blocking`](crate::blocking), which runs the asynchronous [`crate::Session`] on a thread of
+//! its own. The differences to the `imap` crate are:
+//!
+//! - Streams are asynchronous, i.e. implement `AsyncRead` and `AsyncWrite` from `futures`, and
+//!   [`connect`] takes an `async_native_tls::TlsConnector` by value.
+//! - Commands that return several responses, such as [`Session::fetch`] and [`Session::list`],
+//!   return a `Vec` rather than a `ZeroCopy` wrapper. Both dereference to a slice, so the
+//!   results are used the same way.
+//! - Errors are this crate's [`Error`], whose variants are not the same, e.g.
+//!   [`Error::MissingCapability`] for extension commands the server does not support.
+//! - Unsolicited responses are moved to [`Session::unsolicited_responses`] once the command
+//!   during which they were received has completed, rather than as they arrive. Responses the
+//!   receiver is not read from are kept until the session is dropped.
+//! - `IDLE`, `STARTTLS` and the `debug` output have no counterpart here. Use the asynchronous
+//!   session for them, e.g. through [`Session::into_inner`].
+//!
+//! The response codes of completed commands, e.g. the UIDs assigned by `APPEND`, are dropped to
+//! match the result types of the `imap` crate. The [`blocking`](crate::blocking) module, which
+//! returns them, is the way forward once the code compiles against this one.
+//!
+//! This module requires the `compat` feature.
+
+use std::collections::HashSet;
+use std::sync::mpsc;
+
+#[cfg(feature = "tls-native")]
+use async_native_tls::{TlsConnector, TlsStream};
+#[cfg(feature = "tls-native")]
+use async_std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "tls-native")]
+use async_std::task::block_on;
+use futures::future::FutureExt;
+use futures::io::{AsyncRead, AsyncWrite};
+
+pub use crate::authenticator::Authenticator;
+pub use crate::error::{self, Error, Result};
+pub use crate::types;
+
+use crate::blocking;
+use crate::types::{
+    Capabilities, CommandResult, Fetch, Mailbox, Name, Seq, Uid, UnsolicitedResponse,
+};
+
+/// Connects to `addr` over TLS, and reads the greeting of the server, like `imap::connect`.
+#[cfg(feature = "tls-native")]
+pub fn connect<A: ToSocketAddrs, S: AsRef<str>>(
+    addr: A,
+    domain: S,
+    ssl_connector: TlsConnector,
+) -> Result<Client<TlsStream<TcpStream>>> {
+    let client = block_on(crate::connect(addr, domain, ssl_connector))?;
+    Ok(Client::from(blocking::Client::from(client)))
+}
+
+/// A connection that has not been authenticated yet, like `imap::Client`.
+#[derive(Debug)]
+pub struct Client<T: AsyncRead + AsyncWrite + Unpin> {
+    inner: blocking::Client<T>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> From<blocking::Client<T>> for Client<T> {
+    fn from(inner: blocking::Client<T>) -> Self {
+        Client { inner }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
+    /// Creates a client over `stream`, without reading the greeting of the server.
+    pub fn new(stream: T) -> Client<T> {
+        Client::from(blocking::Client::from(crate::Client::new(stream)))
+    }
+
+    /// Logs in with `LOGIN`. On failure, the client is returned with the error, so that it can
+    /// be tried again.
+    pub fn login<U: AsRef<str>, P: AsRef<str>>(
+        self,
+        username: U,
+        password: P,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        self.inner
+            .login(username, password)
+            .map(Session::from)
+            .map_err(|(err, inner)| (err, Client { inner }))
+    }
+
+    /// Logs in with `AUTHENTICATE`, answering the challenges of the server with
+    /// `authenticator`.
+    pub fn authenticate<A: Authenticator + ?Sized, S: AsRef<str>>(
+        self,
+        auth_type: S,
+        authenticator: &A,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        self.inner
+            .authenticate(auth_type, authenticator)
+            .map(Session::from)
+            .map_err(|(err, inner)| (err, Client { inner }))
+    }
+
+    /// The capabilities of the server, from the `CAPABILITY` command.
+    pub fn capabilities(&mut self) -> Result<Capabilities> {
+        self.inner.capabilities().map(CommandResult::into_inner)
+    }
+}
+
+/// An authenticated connection, like `imap::Session`.
+#[derive(Debug)]
+pub struct Session<T: AsyncRead + AsyncWrite + Unpin> {
+    inner: blocking::Session<T>,
+    unsolicited_responses_tx: mpsc::Sender<UnsolicitedResponse>,
+    /// The responses that the server sent without being asked, once the command during which
+    /// they arrived has completed.
+    pub unsolicited_responses: mpsc::Receiver<UnsolicitedResponse>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> From<blocking::Session<T>> for Session<T> {
+    fn from(inner: blocking::Session<T>) -> Self {
+        let (unsolicited_responses_tx, unsolicited_responses) = mpsc::channel();
+        Session {
+            inner,
+            unsolicited_responses_tx,
+            unsolicited_responses,
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Session<T> {
+    /// Moves the unsolicited responses received so far to `unsolicited_responses`, and passes
+    /// on `res` without its response code.
+    fn forward<R>(&mut self, res: Result<CommandResult<R>>) -> Result<R> {
+        self.forward_plain(res).map(CommandResult::into_inner)
+    }
+
+    /// Like [`Session::forward`], for commands that do not return a response code.
+    fn forward_plain<R>(&mut self, res: Result<R>) -> Result<R> {
+        for response in self.inner.drain_unsolicited() {
+            // the receiver is a field of `self`, so it is still there
+            let _ = self.unsolicited_responses_tx.send(response);
+        }
+        res
+    }
+
+    /// Selects a mailbox with `SELECT`.
+    pub fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        let res = self.inner.select(mailbox_name);
+        self.forward(res)
+    }
+
+    /// Selects a mailbox read-only with `EXAMINE`.
+    pub fn examine<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        let res = self.inner.examine(mailbox_name);
+        self.forward(res)
+    }
+
+    /// Fetches the messages with the sequence numbers in `sequence_set`.
+    pub fn fetch<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let res = self.inner.fetch(sequence_set, query);
+        self.forward_plain(res)
+    }
+
+    /// Fetches the messages with the UIDs in `uid_set`.
+    pub fn uid_fetch<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let res = self.inner.uid_fetch(uid_set, query);
+        self.forward_plain(res)
+    }
+
+    /// Sends a `NOOP`, e.g. to receive updates of the selected mailbox.
+    pub fn noop(&mut self) -> Result<()> {
+        let res = self.inner.noop();
+        self.forward(res)
+    }
+
+    /// Logs out with `LOGOUT`.
+    pub fn logout(&mut self) -> Result<()> {
+        let res = self.inner.logout();
+        self.forward_plain(res)
+    }
+
+    /// Creates a mailbox with `CREATE`.
+    pub fn create<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+        let res = self.inner.create(mailbox_name);
+        self.forward(res)
+    }
+
+    /// Deletes a mailbox with `DELETE`.
+    pub fn delete<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+        let res = self.inner.delete(mailbox_name);
+        self.forward(res)
+    }
+
+    /// Renames a mailbox with `RENAME`.
+    pub fn rename<S1: AsRef<str>, S2: AsRef<str>>(&mut self, from: S1, to: S2) -> Result<()> {
+        let res = self.inner.rename(from, to);
+        self.forward(res)
+    }
+
+    /// Subscribes to a mailbox with `SUBSCRIBE`.
+    pub fn subscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
+        let res = self.inner.subscribe(mailbox);
+        self.forward(res)
+    }
+
+    /// Unsubscribes from a mailbox with `UNSUBSCRIBE`.
+    pub fn unsubscribe<S: AsRef<str>>(&mut self, mailbox: S) -> Result<()> {
+        let res = self.inner.unsubscribe(mailbox);
+        self.forward(res)
+    }
+
+    /// The capabilities of the server, from the `CAPABILITY` command.
+    pub fn capabilities(&mut self) -> Result<Capabilities> {
+        let res = self.inner.capabilities();
+        self.forward(res)
+    }
+
+    /// Removes the messages flagged `\Deleted` with `EXPUNGE`, and returns their sequence
+    /// numbers.
+    pub fn expunge(&mut self) -> Result<Vec<Seq>> {
+        let res = self.inner.expunge();
+        self.forward_plain(res)
+    }
+
+    /// Removes the messages in `uid_set` that are flagged `\Deleted` with `UID EXPUNGE`.
+    pub fn uid_expunge<S: AsRef<str>>(&mut self, uid_set: S) -> Result<Vec<Uid>> {
+        let res = self.inner.uid_expunge(uid_set);
+        self.forward_plain(res)
+    }
+
+    /// Requests a checkpoint of the selected mailbox with `CHECK`.
+    pub fn check(&mut self) -> Result<()> {
+        let res = self
+            .inner
+            .run_fallible(|s| async move { s.check().await }.boxed_local());
+        self.forward(res)
+    }
+
+    /// Closes the selected mailbox with `CLOSE`, which removes the messages flagged `\Deleted`.
+    pub fn close(&mut self) -> Result<()> {
+        let res = self.inner.close();
+        self.forward(res)
+    }
+
+    /// Changes the flags of the messages with the sequence numbers in `sequence_set`.
+    pub fn store<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let res = self.inner.store(sequence_set, query);
+        self.forward_plain(res)
+    }
+
+    /// Changes the flags of the messages with the UIDs in `uid_set`.
+    pub fn uid_store<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let res = self.inner.uid_store(uid_set, query);
+        self.forward_plain(res)
+    }
+
+    /// Copies the messages with the sequence numbers in `sequence_set` to `mailbox_name`.
+    pub fn copy<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        let res = self.inner.copy(sequence_set, mailbox_name);
+        self.forward(res).map(drop)
+    }
+
+    /// Copies the messages with the UIDs in `uid_set` to `mailbox_name`.
+    pub fn uid_copy<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        let res = self.inner.uid_copy(uid_set, mailbox_name);
+        self.forward(res).map(drop)
+    }
+
+    /// Moves the messages with the sequence numbers in `sequence_set` to `mailbox_name`.
+    pub fn mv<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        let res = self.inner.mv(sequence_set, mailbox_name);
+        self.forward(res).map(drop)
+    }
+
+    /// Moves the messages with the UIDs in `uid_set` to `mailbox_name`.
+    pub fn uid_mv<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        let res = self.inner.uid_mv(uid_set, mailbox_name);
+        self.forward(res).map(drop)
+    }
+
+    /// Lists the mailboxes that match `mailbox_pattern` with `LIST`.
+    pub fn list(
+        &mut self,
+        reference_name: Option<&str>,
+        mailbox_pattern: Option<&str>,
+    ) -> Result<Vec<Name>> {
+        let res = self.inner.list(reference_name, mailbox_pattern);
+        self.forward_plain(res)
+    }
+
+    /// Lists the subscribed mailboxes that match `mailbox_pattern` with `LSUB`.
+    pub fn lsub(
+        &mut self,
+        reference_name: Option<&str>,
+        mailbox_pattern: Option<&str>,
+    ) -> Result<Vec<Name>> {
+        let res = self.inner.lsub(reference_name, mailbox_pattern);
+        self.forward_plain(res)
+    }
+
+    /// Asks for the status of a mailbox with `STATUS`, e.g. `(MESSAGES UNSEEN)`.
+    pub fn status<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        mailbox_name: S1,
+        data_items: S2,
+    ) -> Result<Mailbox> {
+        let res = self.inner.status(mailbox_name, data_items);
+        self.forward(res)
+    }
+
+    /// Appends a message to `mailbox` with `APPEND`.
+    pub fn append<S: AsRef<str>, B: AsRef<[u8]>>(&mut self, mailbox: S, content: B) -> Result<()> {
+        let res = self.inner.append(mailbox, content);
+        self.forward(res).map(drop)
+    }
+
+    /// Searches the selected mailbox, and returns the sequence numbers of the matching
+    /// messages.
+    pub fn search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Seq>> {
+        let res = self.inner.search(query);
+        self.forward(res)
+    }
+
+    /// Searches the selected mailbox, and returns the UIDs of the matching messages.
+    pub fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Uid>> {
+        let res = self.inner.uid_search(query);
+        self.forward(res)
+    }
+
+    /// Runs `command`, and checks that the server completes it with `OK`.
+    pub fn run_command_and_check_ok<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
+        let command = command.as_ref().to_string();
+        let res = self.inner.run_fallible(move |s| {
+            async move { s.run_command_and_check_ok(command).await }.boxed_local()
+        });
+        self.forward(res)
+    }
+
+    /// The blocking session, whose methods return the response codes as well, and which gives
+    /// access to the asynchronous session.
+    pub fn into_inner(self) -> blocking::Session<T> {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_stream::MockStream;
+
+    #[test]
+    fn migrated_session() {
+        let response = b"A0001 OK Logged in\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK [READ-WRITE] SELECT completed\r\n\
+            * 1 FETCH (UID 7)\r\n\
+            A0003 OK FETCH completed\r\n\
+            A0004 OK [COPYUID 38505 7 3956] COPY completed\r\n\
+            * 4 EXISTS\r\n\
+            A0005 OK CHECK completed\r\n"
+            .to_vec();
+        let client = Client::new(MockStream::new(response));
+        let mut session = client.login("user", "pass").map_err(|e| e.0).unwrap();
+
+        let mailbox = session.select("INBOX").unwrap();
+        assert_eq!(mailbox.exists, 3);
+        let fetches = session.fetch("1", "UID").unwrap();
+        assert_eq!(fetches[0].uid, Some(7));
+        session.copy("1", "Archive").unwrap();
+        session.check().unwrap();
+
+        assert_eq!(
+            session.unsolicited_responses.try_iter().collect::<Vec<_>>(),
+            vec![UnsolicitedResponse::Exists(4)]
+        );
+        assert_eq!(
+            session.into_inner().into_inner().stream.inner.written_buf,
+            b"A0001 LOGIN \"user\" \"pass\"\r\n\
+            A0002 SELECT \"INBOX\"\r\n\
+            A0003 FETCH 1 UID\r\n\
+            A0004 COPY 1 \"Archive\"\r\n\
+            A0005 CHECK\r\n"
+                .to_vec()
+        );
+    }
+}
@@ -0,0 +1,4 @@
+//! Interfaces that mirror other IMAP crates, to ease moving existing code to this one.
+//!
+//! This module requires the `compat` feature.
+pub mod imap;
@@ -0,0 +1,148 @@
+use std::pin::Pin;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use futures::io::{self, AsyncRead};
+use futures::task::{Context, Poll};
+
+/// The size of the buffer for compressed data received from the server.
+const INPUT_CAPACITY: usize = 1024 * 4;
+
+/// The state of a connection after [`COMPRESS DEFLATE`](https://tools.ietf.org/html/rfc4978)
+/// has been negotiated, see `Session::compress`. Both directions carry a raw deflate stream.
+pub(crate) struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+    /// Compressed data that has been received, but not inflated yet.
+    input: Vec<u8>,
+    /// How much of `input` has been inflated.
+    input_pos: usize,
+}
+
+impl std::fmt::Debug for Deflate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Deflate")
+            .field("total_in", &self.decompress.total_in())
+            .field("total_out", &self.compress.total_out())
+            .field("input", &(self.input.len() - self.input_pos))
+            .finish()
+    }
+}
+
+impl Deflate {
+    /// Starts compression. `buffered` is data that has already been received after the
+    /// response to the `COMPRESS` command, and is therefore compressed.
+    pub(crate) fn new(buffered: Vec<u8>) -> Self {
+        Deflate {
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            input: buffered,
+            input_pos: 0,
+        }
+    }
+
+    /// Compresses `data`, and flushes the compressor so that the server can inflate all of it
+    /// right away.
+    pub(crate) fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let mut consumed = 0;
+        loop {
+            if out.capacity() - out.len() < 64 {
+                out.reserve(std::cmp::max(out.capacity(), 64));
+            }
+            let before = self.compress.total_in();
+            self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            consumed += (self.compress.total_in() - before) as usize;
+            // the flush is complete once it no longer fills the output
+            if consumed == data.len() && out.len() < out.capacity() {
+                return Ok(out);
+            }
+        }
+    }
+
+    /// Reads compressed data from `inner`, and inflates it into `buf`.
+    pub(crate) fn poll_read<R: AsyncRead + Unpin>(
+        &mut self,
+        inner: &mut R,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if self.input_pos < self.input.len() {
+                let (before_in, before_out) =
+                    (self.decompress.total_in(), self.decompress.total_out());
+                let status = self
+                    .decompress
+                    .decompress(&self.input[self.input_pos..], buf, FlushDecompress::None)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                self.input_pos += (self.decompress.total_in() - before_in) as usize;
+                let produced = (self.decompress.total_out() - before_out) as usize;
+                if produced > 0 {
+                    return Poll::Ready(Ok(produced));
+                }
+                if status == Status::StreamEnd {
+                    // the server ended the deflate stream, so nothing can follow
+                    return Poll::Ready(Ok(0));
+                }
+                if self.input_pos < self.input.len() {
+                    // no progress although input is left, i.e. `buf` is empty
+                    return Poll::Ready(Ok(0));
+                }
+            }
+
+            self.input.clear();
+            self.input.resize(INPUT_CAPACITY, 0);
+            self.input_pos = 0;
+            match Pin::new(&mut *inner).poll_read(cx, &mut self.input) {
+                Poll::Ready(Ok(n)) => {
+                    self.input.truncate(n);
+                    if n == 0 {
+                        return Poll::Ready(Ok(0));
+                    }
+                }
+                Poll::Ready(Err(err)) => {
+                    self.input.clear();
+                    return Poll::Ready(Err(err));
+                }
+                Poll::Pending => {
+                    self.input.clear();
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future::poll_fn;
+
+    use crate::mock_stream::MockStream;
+
+    #[async_attributes::test]
+    async fn round_trip() {
+        let mut client = Deflate::new(Vec::new());
+        let mut compressed = client.compress(b"A0001 NOOP\r\n").unwrap();
+        compressed.extend(client.compress(b"A0002 LOGOUT\r\n").unwrap());
+
+        // split the compressed data, so that part of it is buffered already
+        let rest = compressed.split_off(5);
+        let mut server = Deflate::new(compressed);
+        let mut stream = MockStream::new(rest);
+        let mut out = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = poll_fn(|cx| server.poll_read(&mut stream, cx, &mut buf))
+                .await
+                .unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, b"A0001 NOOP\r\nA0002 LOGOUT\r\n".to_vec());
+    }
+}
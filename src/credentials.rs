@@ -0,0 +1,99 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::error::Result;
+use crate::secret::Secret;
+
+/// The future returned by [`CredentialProvider::credentials`].
+pub type CredentialsFuture<'a> = Pin<Box<dyn Future<Output = Result<Credentials>> + Send + 'a>>;
+
+/// What a session logs in with.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// A user name and password, sent with `LOGIN`.
+    Password {
+        /// The user name.
+        username: String,
+        /// The password.
+        password: Secret,
+    },
+    /// An OAuth 2.0 access token, sent with the [`XOAuth2`](crate::sasl::XOAuth2) mechanism.
+    XOAuth2 {
+        /// The user, usually the email address.
+        user: String,
+        /// The access token.
+        access_token: Secret,
+    },
+    /// An OAuth 2.0 access token, sent with the [`OAuthBearer`](crate::sasl::OAuthBearer)
+    /// mechanism.
+    OAuthBearer {
+        /// The user, usually the email address.
+        user: String,
+        /// The access token.
+        access_token: Secret,
+    },
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Password { username, .. } => f
+                .debug_struct("Credentials::Password")
+                .field("username", username)
+                .finish(),
+            Credentials::XOAuth2 { user, .. } => f
+                .debug_struct("Credentials::XOAuth2")
+                .field("user", user)
+                .finish(),
+            Credentials::OAuthBearer { user, .. } => f
+                .debug_struct("Credentials::OAuthBearer")
+                .field("user", user)
+                .finish(),
+        }
+    }
+}
+
+/// Supplies fresh [`Credentials`] each time a session logs in, e.g. by refreshing an OAuth
+/// access token that has expired since the last login.
+///
+/// [`SessionTemplate::with_credentials`](crate::SessionTemplate::with_credentials) calls the
+/// provider for every session it spawns, so long-lived applications that reconnect do not keep
+/// using the token that was valid when they started.
+///
+/// ```
+/// use async_imap::{CredentialProvider, Credentials, CredentialsFuture};
+///
+/// struct Refreshing {
+///     user: String,
+/// }
+///
+/// impl CredentialProvider for Refreshing {
+///     fn credentials(&self) -> CredentialsFuture<'_> {
+///         Box::pin(async move {
+///             // ask the authorization server for a new access token here
+///             let access_token = "<access_token>".into();
+///             Ok(Credentials::OAuthBearer {
+///                 user: self.user.clone(),
+///                 access_token,
+///             })
+///         })
+///     }
+/// }
+/// ```
+pub trait CredentialProvider: Send + Sync {
+    /// Returns the credentials for the next login.
+    fn credentials(&self) -> CredentialsFuture<'_>;
+
+    /// Called when the server rejected `credentials`, so that e.g. a cached token is not handed
+    /// out again.
+    fn rejected(&self, _credentials: &Credentials) {}
+}
+
+/// The same credentials for every login.
+impl CredentialProvider for Credentials {
+    fn credentials(&self) -> CredentialsFuture<'_> {
+        let credentials = self.clone();
+        Box::pin(async move { Ok(credentials) })
+    }
+}
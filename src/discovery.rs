@@ -0,0 +1,211 @@
+//! Locating the IMAP server of a mail domain using DNS SRV records, as described in [RFC
+//! 6186](https://tools.ietf.org/html/rfc6186).
+//!
+//! This allows connecting with just an email address:
+//!
+//! ```no_run
+//! # fn main() -> async_imap::error::Result<()> {
+//! # async_std::task::block_on(async {
+//! let client = async_imap::discovery::connect("user@example.org").await?;
+//! # Ok(())
+//! # }) }
+//! ```
+//!
+//! Note that DNS responses are usually not authenticated. The TLS certificate is verified
+//! against the host name from the SRV record, so an attacker who can forge DNS responses can
+//! direct the client to any server with a valid certificate for its own name. [RFC 6186 section
+//! 6](https://tools.ietf.org/html/rfc6186#section-6) recommends asking the user to confirm
+//! servers that are not within the mail domain, see [`Endpoint::host`].
+//!
+//! This module requires the `discovery` feature.
+
+use std::io;
+
+use async_std_resolver::{resolver_from_system_conf, AsyncStdResolver, ResolveErrorKind};
+use rand::Rng;
+
+use crate::builder::{ClientBuilder, MaybeTlsStream};
+use crate::client::Client;
+use crate::endpoint::{Endpoint, TlsMode};
+use crate::error::{Error, Result};
+
+/// A server advertised in an SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    priority: u16,
+    weight: u16,
+    endpoint: Endpoint,
+}
+
+/// Looks up the IMAP servers of the domain of `address`, which is either an email address or a
+/// domain name, most preferred first.
+///
+/// Both `_imaps._tcp` (implicit TLS) and `_imap._tcp` (`STARTTLS`) records are considered.
+/// Among records of the same priority, implicit TLS is preferred as recommended by [RFC
+/// 8314](https://tools.ietf.org/html/rfc8314#section-5.1), and servers are ordered at random,
+/// with a chance of coming first in proportion to their weight, as described in [RFC
+/// 2782](https://tools.ietf.org/html/rfc2782). An empty list is returned if the domain does not
+/// advertise any server.
+pub async fn discover(address: &str) -> Result<Vec<Endpoint>> {
+    let domain = address.rsplit('@').next().unwrap_or(address);
+    let resolver = resolver_from_system_conf()
+        .await
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::Other, e)))?;
+
+    let mut records = lookup(&resolver, "_imaps._tcp", domain, TlsMode::Implicit).await?;
+    records.extend(lookup(&resolver, "_imap._tcp", domain, TlsMode::StartTls).await?);
+
+    Ok(order(records, &mut rand::thread_rng()))
+}
+
+/// Connects to the most preferred server of the domain of `address` that can be reached,
+/// see [`discover`].
+pub async fn connect(address: &str) -> Result<Client<MaybeTlsStream>> {
+    let mut last_err = None;
+    for endpoint in discover(address).await? {
+        match ClientBuilder::from_endpoint(endpoint).connect().await {
+            Ok(client) => return Ok(client),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no IMAP server advertised in DNS",
+        ))
+    }))
+}
+
+async fn lookup(
+    resolver: &AsyncStdResolver,
+    service: &str,
+    domain: &str,
+    tls_mode: TlsMode,
+) -> Result<Vec<Record>> {
+    let name = format!("{}.{}.", service, domain.trim_end_matches('.'));
+    let lookup = match resolver.srv_lookup(name.as_str()).await {
+        Ok(lookup) => lookup,
+        Err(err) => match err.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => return Ok(Vec::new()),
+            _ => return Err(Error::Io(io::Error::new(io::ErrorKind::Other, err))),
+        },
+    };
+
+    Ok(lookup
+        .iter()
+        .filter_map(|srv| {
+            let target = srv.target().to_utf8();
+            let host = target.trim_end_matches('.');
+            // a target of "." means that the service is decidedly not available
+            if host.is_empty() {
+                return None;
+            }
+            Some(Record {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                endpoint: Endpoint::new(host, srv.port(), tls_mode),
+            })
+        })
+        .collect())
+}
+
+/// Orders records by priority, then prefers implicit TLS, then picks among the remaining
+/// records at random, weighted as described in RFC 2782.
+fn order<R: Rng + ?Sized>(mut records: Vec<Record>, rng: &mut R) -> Vec<Endpoint> {
+    let group = |r: &Record| (r.priority, r.endpoint.tls_mode() != TlsMode::Implicit);
+    // records with a weight of 0 go first, so that they have a small chance of being picked
+    records.sort_by_key(|r| (group(r), r.weight != 0));
+
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let key = group(&records[0]);
+        let len = records.iter().take_while(|r| group(r) == key).count();
+        let mut candidates: Vec<_> = records.drain(..len).collect();
+        while !candidates.is_empty() {
+            let total: u32 = candidates.iter().map(|r| u32::from(r.weight)).sum();
+            // the first record whose running sum of weights reaches the pick is chosen
+            let mut pick = rng.gen_range(0, total + 1);
+            let i = candidates
+                .iter()
+                .position(|r| {
+                    let weight = u32::from(r.weight);
+                    if pick <= weight {
+                        return true;
+                    }
+                    pick -= weight;
+                    false
+                })
+                .unwrap_or(0);
+            ordered.push(candidates.remove(i).endpoint);
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn record(priority: u16, weight: u16, host: &str, tls_mode: TlsMode) -> Record {
+        Record {
+            priority,
+            weight,
+            endpoint: Endpoint::new(host, 993, tls_mode),
+        }
+    }
+
+    fn hosts<R: Rng>(records: Vec<Record>, rng: &mut R) -> Vec<String> {
+        order(records, rng)
+            .iter()
+            .map(|e| e.host().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn ordering() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let records = vec![
+                record(10, 0, "backup", TlsMode::Implicit),
+                record(0, 1, "plain", TlsMode::StartTls),
+                record(0, 1, "light", TlsMode::Implicit),
+                record(0, 5, "heavy", TlsMode::Implicit),
+            ];
+            let hosts = hosts(records, &mut rng);
+            assert_eq!(hosts.len(), 4);
+            assert!(hosts[..2].contains(&"heavy".to_string()));
+            assert!(hosts[..2].contains(&"light".to_string()));
+            assert_eq!(hosts[2..], ["plain", "backup"]);
+        }
+    }
+
+    #[test]
+    fn weighted_ordering() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut heavy_first = 0;
+        let mut unweighted_first = 0;
+        for _ in 0..1000 {
+            let records = vec![
+                record(0, 0, "unweighted", TlsMode::Implicit),
+                record(0, 1, "light", TlsMode::Implicit),
+                record(0, 8, "heavy", TlsMode::Implicit),
+            ];
+            match hosts(records, &mut rng)[0].as_str() {
+                "heavy" => heavy_first += 1,
+                "unweighted" => unweighted_first += 1,
+                _ => {}
+            }
+        }
+        // the chances are 8 in 10 and 1 in 10
+        assert!(heavy_first > 700 && heavy_first < 900, "{}", heavy_first);
+        assert!(
+            unweighted_first > 50 && unweighted_first < 150,
+            "{}",
+            unweighted_first
+        );
+    }
+}
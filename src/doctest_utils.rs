@@ -0,0 +1,39 @@
+//! A mock server for the examples in the documentation, which run against it when the
+//! `doctest-utils` feature is enabled:
+//!
+//! ```text
+//! cargo test --doc --features doctest-utils
+//! ```
+//!
+//! Without the feature, the examples that need a server are left out of the doctests. Not part
+//! of the public API.
+
+use std::future::Future;
+
+use futures::executor::block_on;
+
+use crate::error::Result;
+pub use crate::mock_stream::MockStream;
+use crate::{Client, Session};
+
+/// Runs `example` with a session that has just logged in to a server which answers with
+/// `responses`, no matter what the session sends. The commands of the session are tagged
+/// `A0001`, `A0002` and so on, so the completions in `responses` have to use these tags.
+///
+/// Panics if `example` fails, or if the session reads past the end of `responses`.
+pub fn run<F, Fut>(responses: &str, example: F)
+where
+    F: FnOnce(Session<MockStream>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let stream = MockStream::new(responses.as_bytes().to_vec());
+    let session = Session::new(Client::new(stream).conn);
+    if let Err(err) = block_on(example(session)) {
+        panic!("the example failed: {}", err);
+    }
+}
+
+/// The commands that `session` has sent so far, as they were written to the server.
+pub fn commands(session: &Session<MockStream>) -> &str {
+    std::str::from_utf8(&session.stream.inner.written_buf).expect("commands are not UTF-8")
+}
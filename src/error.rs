@@ -29,9 +29,60 @@ pub enum Error {
     /// strings](https://tools.ietf.org/html/rfc3501#section-4.3).
     Validate(ValidateError),
     /// `native_tls` error
+    #[cfg(feature = "tls-native")]
     NativeTlsError(async_native_tls::Error),
     /// Error appending an e-mail.
     Append,
+    /// A connection endpoint string could not be parsed.
+    InvalidEndpoint(String),
+    /// The mailbox cannot be selected according to its `LIST` attributes, e.g. because it is a
+    /// `\Noselect` container or does not exist.
+    NotSelectable(String),
+    /// [`Handle::wait`](crate::extensions::idle::Handle::wait) was called before the `IDLE`
+    /// command was sent with `Handle::init`.
+    IdleNotStarted,
+    /// The server does not advertise a capability that is needed for the operation, either
+    /// because it was required using
+    /// [`ClientBuilder::require_capability`](crate::ClientBuilder::require_capability), or because
+    /// an extension command such as `MOVE` or `IDLE` was rejected by a server that does not
+    /// support it.
+    MissingCapability {
+        /// The capability that is needed, e.g. `"MOVE"`.
+        required: String,
+        /// The capabilities the server does advertise, sorted.
+        present: Vec<String>,
+    },
+    /// A command was not sent because it would have been longer than
+    /// [`Connection::max_command_len`](crate::Connection::max_command_len). Contains the length
+    /// the command would have had.
+    CommandTooLong(usize),
+    /// A step of establishing the connection did not complete in time, see
+    /// [`ClientBuilder::connect_timeout`](crate::ClientBuilder::connect_timeout). Contains the
+    /// step that timed out, e.g. `"TLS handshake"`.
+    Timeout(String),
+    /// The policy configured with
+    /// [`ClientBuilder::tls_min_version`](crate::ClientBuilder::tls_min_version) or
+    /// [`ClientBuilder::tls_cipher_suites`](crate::ClientBuilder::tls_cipher_suites) can not be
+    /// enforced by the TLS library or connector in use.
+    TlsPolicy(String),
+    /// The connection was configured to be upgraded with
+    /// [`TlsMode::StartTls`](crate::TlsMode::StartTls), but the server does not advertise the
+    /// `STARTTLS` capability or rejected the command. The connection is closed rather than used
+    /// without TLS.
+    StarttlsNotSupported,
+    /// No mailbox has the [special use](https://tools.ietf.org/html/rfc6154) that an operation
+    /// needs, e.g. `\Archive` for [`Session::archive`](crate::Session::archive).
+    NoSpecialUse(String),
+    /// The server refused a `LOGIN` or `AUTHENTICATE` command. Tells why, as far as the server
+    /// said so.
+    Auth(AuthError),
+    /// A command was not sent because it is not allowed in the state the connection is in, e.g.
+    /// `STARTTLS` on a [`Session`](crate::Session), which has already logged in. Contains the
+    /// command.
+    WrongState(String),
+    /// An extension was not used because the server is known to implement it incorrectly, see
+    /// [`Quirks`](crate::quirks::Quirks). Contains the extension, e.g. `"COMPRESS=DEFLATE"`.
+    BrokenExtension(String),
     #[doc(hidden)]
     __Nonexhaustive,
 }
@@ -54,6 +105,7 @@ impl<'a> From<&'a Response<'a>> for Error {
     }
 }
 
+#[cfg(feature = "tls-native")]
 impl From<async_native_tls::Error> for Error {
     fn from(err: async_native_tls::Error) -> Error {
         Error::NativeTlsError(err)
@@ -65,9 +117,31 @@ impl fmt::Display for Error {
         match *self {
             Error::Io(ref e) => fmt::Display::fmt(e, f),
             Error::Validate(ref e) => fmt::Display::fmt(e, f),
-            Error::No(ref data) | Error::Bad(ref data) => {
+            Error::Auth(ref e) => fmt::Display::fmt(e, f),
+            Error::No(ref data)
+            | Error::Bad(ref data)
+            | Error::InvalidEndpoint(ref data)
+            | Error::NotSelectable(ref data)
+            | Error::NoSpecialUse(ref data)
+            | Error::WrongState(ref data)
+            | Error::BrokenExtension(ref data)
+            | Error::Timeout(ref data)
+            | Error::TlsPolicy(ref data) => {
                 write!(f, "{}: {}", &String::from(self.description()), data)
             }
+            Error::MissingCapability {
+                ref required,
+                ref present,
+            } => write!(
+                f,
+                "{}: {} (server offers {})",
+                &String::from(self.description()),
+                required,
+                present.join(" ")
+            ),
+            Error::CommandTooLong(len) => {
+                write!(f, "{}: {} bytes", &String::from(self.description()), len)
+            }
             ref e => f.write_str(e.description()),
         }
     }
@@ -79,19 +153,36 @@ impl StdError for Error {
             Error::Io(ref e) => e.description(),
             Error::Parse(ref e) => e.description(),
             Error::Validate(ref e) => e.description(),
+            #[cfg(feature = "tls-native")]
             Error::NativeTlsError(ref e) => e.description(),
             Error::Bad(_) => "Bad Response",
             Error::No(_) => "No Response",
             Error::ConnectionLost => "Connection lost",
             Error::Append => "Could not append mail to mailbox",
+            Error::InvalidEndpoint(_) => "Invalid connection endpoint",
+            Error::NotSelectable(_) => "Mailbox is not selectable",
+            Error::IdleNotStarted => "IDLE has not been started",
+            Error::MissingCapability { .. } => "Required capability not supported by server",
+            Error::CommandTooLong(_) => "Command too long",
+            Error::Timeout(_) => "Timed out",
+            Error::TlsPolicy(_) => "TLS policy violated",
+            Error::StarttlsNotSupported => "STARTTLS not supported by server",
+            Error::NoSpecialUse(_) => "No mailbox with this special use",
+            Error::Auth(ref e) => e.description(),
+            Error::WrongState(_) => "Command not allowed in this state",
+            Error::BrokenExtension(_) => "Extension known to be broken on this server",
             Error::__Nonexhaustive => "Unknown",
         }
     }
 
-    fn cause(&self) -> Option<&dyn StdError> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match *self {
             Error::Io(ref e) => Some(e),
-            Error::Parse(ParseError::DataNotUtf8(_, ref e)) => Some(e),
+            Error::Parse(ref e) => Some(e),
+            Error::Validate(ref e) => Some(e),
+            #[cfg(feature = "tls-native")]
+            Error::NativeTlsError(ref e) => Some(e),
+            Error::Auth(ref e) => Some(e),
             _ => None,
         }
     }
@@ -128,9 +219,10 @@ impl StdError for ParseError {
         }
     }
 
-    fn cause(&self) -> Option<&dyn StdError> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match *self {
             ParseError::Authentication(_, Some(ref e)) => Some(e),
+            ParseError::DataNotUtf8(_, ref e) => Some(e),
             _ => None,
         }
     }
@@ -153,7 +245,119 @@ impl StdError for ValidateError {
         "Invalid character in input"
     }
 
-    fn cause(&self) -> Option<&dyn StdError> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         None
     }
 }
+
+/// Why the server refused to log in, according to the [response
+/// code](https://tools.ietf.org/html/rfc5530) of its `NO` response. Each variant holds the
+/// human-readable text of the response.
+///
+/// This tells whether asking the user for their credentials again can help, or refreshing an
+/// access token, or neither.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// `AUTHENTICATIONFAILED`: the credentials are wrong, e.g. a mistyped password or an expired
+    /// access token.
+    AuthenticationFailed(String),
+    /// `AUTHORIZATIONFAILED`: the credentials are valid, but do not allow logging in as the
+    /// requested authorization identity.
+    AuthorizationFailed(String),
+    /// `EXPIRED`: the credentials are valid, but have expired, and have to be changed elsewhere
+    /// before they can be used again.
+    Expired(String),
+    /// `PRIVACYREQUIRED`: the server only accepts the credentials over an encrypted connection.
+    PrivacyRequired(String),
+    /// `CONTACTADMIN`: the account can not be used until the user contacts the administrator.
+    ContactAdmin(String),
+    /// Any other response code, or none at all.
+    Other(String),
+}
+
+impl AuthError {
+    /// Classifies a `NO` response to `LOGIN` or `AUTHENTICATE`. Response codes that imap-proto
+    /// does not know are left at the start of `information`.
+    pub(crate) fn new(
+        code: &Option<imap_proto::ResponseCode<'_>>,
+        information: &Option<&str>,
+    ) -> AuthError {
+        let text = information.unwrap_or_default().trim_start();
+        let (atom, message) = match (code, text.find(']')) {
+            (None, Some(end)) if text.starts_with('[') => {
+                (&text[1..end], text[end + 1..].trim_start())
+            }
+            _ => ("", text),
+        };
+        let message = message.to_string();
+        match atom.to_ascii_uppercase().as_str() {
+            "AUTHENTICATIONFAILED" => AuthError::AuthenticationFailed(message),
+            "AUTHORIZATIONFAILED" => AuthError::AuthorizationFailed(message),
+            "EXPIRED" => AuthError::Expired(message),
+            "PRIVACYREQUIRED" => AuthError::PrivacyRequired(message),
+            "CONTACTADMIN" => AuthError::ContactAdmin(message),
+            _ => AuthError::Other(message),
+        }
+    }
+
+    /// The human-readable text of the response.
+    pub fn message(&self) -> &str {
+        match self {
+            AuthError::AuthenticationFailed(message)
+            | AuthError::AuthorizationFailed(message)
+            | AuthError::Expired(message)
+            | AuthError::PrivacyRequired(message)
+            | AuthError::ContactAdmin(message)
+            | AuthError::Other(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.description(), self.message())
+    }
+}
+
+impl StdError for AuthError {
+    fn description(&self) -> &str {
+        match self {
+            AuthError::AuthenticationFailed(_) => "Invalid credentials",
+            AuthError::AuthorizationFailed(_) => "Not authorized to log in as this user",
+            AuthError::Expired(_) => "Credentials expired",
+            AuthError::PrivacyRequired(_) => "Encryption required to log in",
+            AuthError::ContactAdmin(_) => "Account blocked, contact the administrator",
+            AuthError::Other(_) => "Login refused",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_error() {
+        assert_eq!(
+            AuthError::new(&None, &Some("[AUTHENTICATIONFAILED] Invalid credentials")),
+            AuthError::AuthenticationFailed("Invalid credentials".into())
+        );
+        assert_eq!(
+            AuthError::new(&None, &Some("[expired] Change your password")),
+            AuthError::Expired("Change your password".into())
+        );
+        assert_eq!(
+            AuthError::new(&None, &Some("[UNKNOWN-CODE] Nope")),
+            AuthError::Other("Nope".into())
+        );
+        assert_eq!(
+            AuthError::new(&None, &Some("Nope")),
+            AuthError::Other("Nope".into())
+        );
+        assert_eq!(AuthError::new(&None, &None), AuthError::Other("".into()));
+    }
+}
@@ -1,19 +1,19 @@
 //! Adds support for the IMAP IDLE command specificed in [RFC2177](https://tools.ietf.org/html/rfc2177).
 
-use std::fmt;
 use std::pin::Pin;
-use std::time::Duration;
+#[cfg(feature = "runtime")]
+use std::time::{Duration, SystemTime};
 
-use async_std::io::{self, Read, Write};
-use async_std::prelude::*;
-use async_std::stream::Stream;
+use futures::io::{self, AsyncRead, AsyncWrite};
+use futures::prelude::*;
+use futures::stream::FusedStream;
 use futures::task::{Context, Poll};
 use imap_proto::{RequestId, Response, Status};
 
 use crate::client::Session;
-use crate::error::Result;
-use crate::parse::handle_unilateral;
-use crate::types::ResponseData;
+use crate::error::{Error, Result};
+use crate::parse::{handle_unilateral, to_unsolicited};
+use crate::types::{ResponseData, UnsolicitedResponse};
 
 /// `Handle` allows a client to block waiting for changes to the remote mailbox.
 ///
@@ -29,16 +29,20 @@ use crate::types::ResponseData;
 /// does this. This still allows a client to receive immediate mailbox updates even though it need
 /// only "poll" at half hour intervals.
 ///
+/// Some firewalls and NAT gateways drop connections that have been silent for a few minutes,
+/// even while idling. [`Connection::idle_keepalive`](crate::Connection::idle_keepalive)
+/// configures [`Handle::wait_keepalive`] to restart `IDLE` more often on such networks.
+///
 /// As long as a [`Handle`] is active, the mailbox cannot be otherwise accessed.
 #[derive(Debug)]
-pub struct Handle<T: Read + Write + Unpin + fmt::Debug> {
+pub struct Handle<T: AsyncRead + AsyncWrite + Unpin> {
     session: Session<T>,
     id: Option<RequestId>,
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> Unpin for Handle<T> {}
+impl<T: AsyncRead + AsyncWrite + Unpin> Unpin for Handle<T> {}
 
-impl<T: Read + Write + Unpin + fmt::Debug> Stream for Handle<T> {
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for Handle<T> {
     type Item = io::Result<ResponseData>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
@@ -46,6 +50,13 @@ impl<T: Read + Write + Unpin + fmt::Debug> Stream for Handle<T> {
     }
 }
 
+impl<T: AsyncRead + AsyncWrite + Unpin> FusedStream for Handle<T> {
+    /// True once the server closed the connection.
+    fn is_terminated(&self) -> bool {
+        self.session.stream.is_terminated()
+    }
+}
+
 /// A stream of server responses after sending `IDLE`. Created using [Handle::stream].
 #[derive(Debug)]
 #[must_use = "futures do nothing unless polled"]
@@ -63,7 +74,7 @@ impl<'a, St: Stream + Unpin> IdleStream<'a, St> {
     }
 }
 
-impl<St: futures::stream::FusedStream + Unpin> futures::stream::FusedStream for IdleStream<'_, St> {
+impl<St: FusedStream + Unpin> FusedStream for IdleStream<'_, St> {
     fn is_terminated(&self) -> bool {
         self.stream.is_terminated()
     }
@@ -88,7 +99,64 @@ pub enum IdleResponse {
     NewData(ResponseData),
 }
 
-impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
+impl IdleResponse {
+    /// Interprets the data received in [`IdleResponse::NewData`], e.g. as an
+    /// [`UnsolicitedResponse::Exists`] when a new message arrived. Returns `None` for the other
+    /// variants.
+    pub fn into_unsolicited(self) -> Option<UnsolicitedResponse> {
+        match self {
+            IdleResponse::NewData(data) => Some(to_unsolicited(data)),
+            IdleResponse::ManualInterrupt | IdleResponse::Timeout => None,
+        }
+    }
+}
+
+/// How [`Handle::wait_keepalive`] keeps an idling connection alive.
+#[cfg(feature = "runtime")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long to idle before ending `IDLE` with `DONE` and starting it again. Defaults to 29
+    /// minutes, as recommended by [RFC 2177](https://tools.ietf.org/html/rfc2177#section-3);
+    /// connections through middleboxes that drop silent connections need a shorter interval.
+    pub interval: Duration,
+    /// Up to this much is subtracted at random from each interval, so that many connections
+    /// that were started together do not all send traffic at the same time. Defaults to zero.
+    pub jitter: Duration,
+    /// Whether to also send `NOOP` between `DONE` and the next `IDLE`, for servers that only
+    /// reset their inactivity timer on complete commands. Defaults to `false`.
+    pub noop: bool,
+}
+
+#[cfg(feature = "runtime")]
+impl Default for Keepalive {
+    fn default() -> Self {
+        Keepalive {
+            interval: Duration::from_secs(29 * 60),
+            jitter: Duration::from_secs(0),
+            noop: false,
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl Keepalive {
+    /// The time to idle until the next restart, with the jitter applied.
+    fn next_interval(&self) -> Duration {
+        let jitter = self.jitter.as_millis() as u64;
+        if jitter == 0 {
+            return self.interval;
+        }
+        // the clock is random enough to spread out connections
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or_default();
+        let offset = Duration::from_millis(nanos % (jitter + 1));
+        self.interval.checked_sub(offset).unwrap_or_default()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Handle<T> {
     unsafe_pinned!(session: Session<T>);
 
     pub(crate) fn new(session: Session<T>) -> Handle<T> {
@@ -96,26 +164,45 @@ impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
     }
 
     /// Start listening to the server side resonses.
-    /// Must be called after [Handle::init].
+    /// Must be called after [Handle::init], otherwise the returned future resolves to
+    /// [`Error::IdleNotStarted`]. If the server closes the connection, it resolves to
+    /// [`Error::ConnectionLost`], and so does every later call.
     pub fn wait(
         &mut self,
     ) -> (
         impl Future<Output = Result<IdleResponse>> + '_,
         stop_token::StopSource,
     ) {
-        assert!(
-            self.id.is_some(),
-            "Cannot listen to response without starting IDLE"
-        );
+        let interrupt = stop_token::StopSource::new();
+        let fut = self.wait_until(interrupt.stop_token());
+
+        (fut, interrupt)
+    }
+
+    /// Listens to the server side responses until `stop` is triggered.
+    fn wait_until(
+        &mut self,
+        stop: stop_token::StopToken,
+    ) -> impl Future<Output = Result<IdleResponse>> + '_ {
+        let started = self.id.is_some();
         let sender = self.session.unsolicited_responses_tx.clone();
 
-        let interrupt = stop_token::StopSource::new();
-        let raw_stream = IdleStream::new(self);
-        let mut interruptible_stream = interrupt.stop_token().stop_stream(raw_stream);
+        // the end of the responses is marked, since it means the connection was closed, while
+        // the stop token ends the stream without a mark
+        let raw_stream = IdleStream::new(self)
+            .map(Some)
+            .chain(stream::once(future::ready(None)));
+        let mut interruptible_stream = stop.stop_stream(raw_stream);
 
-        let fut = async move {
+        async move {
+            if !started {
+                return Err(Error::IdleNotStarted);
+            }
             while let Some(resp) = interruptible_stream.next().await {
-                let resp = resp?;
+                let resp = match resp {
+                    Some(resp) => resp?,
+                    None => return Err(Error::ConnectionLost),
+                };
                 match resp.parsed() {
                     Response::Data { status, .. } if status == &Status::Ok => {
                         // all good continue
@@ -131,13 +218,13 @@ impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
             }
 
             Ok(IdleResponse::ManualInterrupt)
-        };
-
-        (fut, interrupt)
+        }
     }
 
     /// Start listening to the server side resonses, stops latest after the passed in `timeout`.
-    /// Must be called after [Handle::init].
+    /// Must be called after [Handle::init], otherwise the returned future resolves to
+    /// [`Error::IdleNotStarted`].
+    #[cfg(feature = "runtime")]
     pub fn wait_with_timeout(
         &mut self,
         timeout: Duration,
@@ -145,11 +232,6 @@ impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
         impl Future<Output = Result<IdleResponse>> + '_,
         stop_token::StopSource,
     ) {
-        assert!(
-            self.id.is_some(),
-            "Cannot listen to response without starting IDLE"
-        );
-
         let (waiter, interrupt) = self.wait();
         let fut = async move {
             match async_std::future::timeout(timeout, waiter).await {
@@ -161,6 +243,53 @@ impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
         (fut, interrupt)
     }
 
+    /// Start listening to the server side responses, restarting `IDLE` as configured by
+    /// [`Connection::idle_keepalive`](crate::Connection::idle_keepalive) until the server sends
+    /// new data or the returned `StopSource` is dropped. Responses that arrive while `IDLE` is
+    /// being restarted are sent to the unsolicited responses channel of the [`Session`].
+    /// Must be called after [Handle::init], otherwise the returned future resolves to
+    /// [`Error::IdleNotStarted`].
+    #[cfg(feature = "runtime")]
+    pub fn wait_keepalive(
+        &mut self,
+    ) -> (
+        impl Future<Output = Result<IdleResponse>> + '_,
+        stop_token::StopSource,
+    ) {
+        let interrupt = stop_token::StopSource::new();
+        let stop = interrupt.stop_token();
+        let fut = async move {
+            loop {
+                let keepalive = self.session.idle_keepalive.clone();
+                let res = async_std::future::timeout(
+                    keepalive.next_interval(),
+                    self.wait_until(stop.clone()),
+                )
+                .await;
+                match res {
+                    Ok(res) => return res,
+                    Err(_timeout) => self.restart(keepalive.noop).await?,
+                }
+            }
+        };
+
+        (fut, interrupt)
+    }
+
+    /// Ends `IDLE` and starts it again, optionally sending `NOOP` in between.
+    #[cfg(feature = "runtime")]
+    async fn restart(&mut self, noop: bool) -> Result<()> {
+        let id = self.id.take().ok_or(Error::IdleNotStarted)?;
+        log::debug!("idle: restarting to keep the connection alive");
+        self.session.run_command_untagged("DONE").await?;
+        let sender = self.session.unsolicited_responses_tx.clone();
+        self.session.check_ok(id, Some(sender)).await?;
+        if noop {
+            self.session.noop().await?;
+        }
+        self.init().await
+    }
+
     /// Initialise the idle connection by sending the `IDLE` command to the server.
     pub async fn init(&mut self) -> Result<()> {
         let id = self.session.run_command("IDLE").await?;
@@ -177,13 +306,17 @@ impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
                     information,
                     ..
                 } => {
-                    if tag == self.id.as_ref().unwrap() {
+                    if Some(tag) == self.id.as_ref() {
                         if let Status::Bad = status {
-                            return Err(io::Error::new(
+                            let err = io::Error::new(
                                 io::ErrorKind::ConnectionRefused,
-                                information.unwrap().to_string(),
-                            )
-                            .into());
+                                information.unwrap_or("").to_string(),
+                            );
+                            self.id = None;
+                            return Err(match self.session.missing_capability("IDLE").await {
+                                Some(missing) => missing,
+                                None => err.into(),
+                            });
                         }
                     }
                     handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await;
@@ -197,18 +330,28 @@ impl<T: Read + Write + Unpin + fmt::Debug> Handle<T> {
         Err(io::Error::new(io::ErrorKind::ConnectionRefused, "").into())
     }
 
+    /// Ends the `IDLE` command, if it was started, without waiting for the server to confirm it,
+    /// and shuts the session down, see [`Session::shutdown`].
+    #[cfg(feature = "runtime")]
+    pub async fn shutdown(mut self) -> crate::types::ConnectionStats {
+        if self.id.take().is_some() {
+            // the completion of `IDLE` is skipped while waiting for the one of `LOGOUT`
+            let _ = self.session.run_command_untagged("DONE").await;
+        }
+        self.session.shutdown().await
+    }
+
     /// Signal that we want to exit the idle connection, by sending the `DONE`
-    /// command to the server.
+    /// command to the server. If [Handle::init] was never called, the session is returned
+    /// unchanged.
     pub async fn done(mut self) -> Result<Session<T>> {
-        assert!(
-            self.id.is_some(),
-            "Cannot call DONE on a non initialized idle connection"
-        );
+        let id = match self.id.take() {
+            Some(id) => id,
+            None => return Ok(self.session),
+        };
         self.session.run_command_untagged("DONE").await?;
         let sender = self.session.unsolicited_responses_tx.clone();
-        self.session
-            .check_ok(self.id.expect("invalid setup"), Some(sender))
-            .await?;
+        self.session.check_ok(id, Some(sender)).await?;
 
         Ok(self.session)
     }
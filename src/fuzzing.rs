@@ -0,0 +1,111 @@
+//! Entry points for the fuzz targets in `fuzz/`. Not part of the public API: this module is only
+//! built for the fuzzer, which sets `--cfg fuzzing`, and for the tests that replay the corpora.
+//!
+//! Everything that parses data received from the server must return an error rather than panic,
+//! and must not allocate memory out of proportion to the data received, whatever the server
+//! sends. Inputs that violated this are kept in `fuzz/regressions`, and are replayed by the tests
+//! together with the seed corpus in `fuzz/corpus`.
+
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::future;
+use imap_proto::Response;
+
+use crate::error::AuthError;
+use crate::parse::{handle_unilateral, parse_status_size};
+use crate::protocol::Protocol;
+use crate::replay::{ReplayBuffer, UnsolicitedSender};
+use crate::types::{
+    parse_namespace, parse_sort, parse_thread, AppendUid, Capabilities, CopyUid, ResponseData,
+};
+use crate::uid_map::UidMap;
+
+/// Passes `data` through the decoder the way a session receives it from the server, with all
+/// trackers enabled, and hands each response to `handle_unilateral`. The first byte is not part
+/// of the data, but the size of the chunks it is fed in, minus one, so that the fuzzer also
+/// covers responses split across reads.
+pub fn decode(data: &[u8]) {
+    let (&chunk, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let (tx, mut rx) = mpsc::channel(16);
+    let unsolicited = UnsolicitedSender::new(tx, Arc::new(Mutex::new(ReplayBuffer::new(4))));
+    let (flags_tx, _flags_rx) = mpsc::unbounded();
+    let (raw_tx, mut raw_rx) = mpsc::unbounded();
+
+    let mut protocol = Protocol::new();
+    protocol.unsolicited = Some(unsolicited.clone());
+    protocol.flag_events = Some(flags_tx);
+    protocol.unrecognized = Some(raw_tx);
+    protocol.uid_map = Some(UidMap::default());
+
+    for data in data.chunks(usize::from(chunk) + 1) {
+        if protocol.feed(data).is_err() {
+            return;
+        }
+        loop {
+            // make room, so that sending does not wait for a receiver that never comes
+            while let Ok(Some(_)) = rx.try_next() {}
+            block_on(future::poll_fn(|cx| protocol.poll_unsolicited(cx)));
+            let response = match protocol.decode() {
+                Ok(Some(response)) => response,
+                Ok(None) if protocol.has_queued_unsolicited() => continue,
+                Ok(None) => break,
+                Err(_) => return,
+            };
+            response_codes(&response);
+            while let Ok(Some(_)) = rx.try_next() {}
+            block_on(handle_unilateral(response, unsolicited.clone()));
+        }
+        // the commands that capture raw responses parse them like this
+        while let Ok(Some(raw)) = raw_rx.try_next() {
+            parse_status_size(raw.as_bytes());
+            parse_sort(raw.as_bytes());
+            parse_thread(raw.as_bytes());
+            parse_namespace(raw.as_bytes());
+        }
+    }
+}
+
+/// Parses the response codes that the client looks for in status responses and completions.
+fn response_codes(response: &ResponseData) {
+    match response.parsed() {
+        Response::Data {
+            code, information, ..
+        }
+        | Response::Done {
+            code, information, ..
+        } => {
+            Capabilities::from_response_code(*information);
+            AppendUid::parse(code.as_ref(), *information);
+            CopyUid::parse(code.as_ref(), *information);
+            AuthError::new(code, information);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn corpora() {
+        let fuzz = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz");
+        for dir in &["corpus/decode", "regressions/decode"] {
+            for entry in fs::read_dir(fuzz.join(dir)).unwrap() {
+                let path = entry.unwrap().path();
+                // a panic is reported with the input that caused it
+                println!("{}", path.display());
+                decode(&fs::read(&path).unwrap());
+            }
+        }
+    }
+}
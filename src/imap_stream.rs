@@ -1,110 +1,62 @@
-use std::fmt;
 use std::pin::Pin;
 
-use async_std::io::{self, Read, Write};
-use async_std::prelude::*;
-use async_std::stream::Stream;
-use async_std::sync::Arc;
-use byte_pool::{Block, BytePool};
+use futures::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+use futures::stream::{FusedStream, Stream};
 use futures::task::{Context, Poll};
-use nom::Needed;
 
+use crate::compress::Deflate;
+use crate::protocol::Protocol;
 use crate::types::{Request, ResponseData};
 
-const INITIAL_CAPACITY: usize = 1024 * 4;
-const MAX_CAPACITY: usize = 512 * 1024 * 1024; // 512 MiB
-
-lazy_static::lazy_static! {
-    /// The global buffer pool we use for storing incoming data.
-    pub(crate) static ref POOL: Arc<BytePool> = Arc::new(BytePool::new());
-}
-
 /// Wraps a stream, and parses incoming data as imap server messages. Writes outgoing data
 /// as imap client messages.
+///
+/// This only moves bytes between the stream and the [`Protocol`], which does the actual work.
 #[derive(Debug)]
-pub struct ImapStream<R: Read + Write> {
-    // TODO: write some buffering logic
+pub struct ImapStream<R: AsyncRead + AsyncWrite> {
     /// The underlying stream
     pub(crate) inner: R,
-    /// Buffer for the already read, but not yet parsed data.
-    buffer: Block<'static>,
-    /// Position of valid read data into buffer.
-    current: Position,
-    /// How many bytes do we need to finishe the currrent element that is being decoded.
-    decode_needs: usize,
-    /// Whether we should attempt to decode whatever is currently inside the buffer.
-    /// False indicates that we know for certain that the buffer is incomplete.
-    initial_decode: bool,
-}
-
-/// A semantically explicit slice of a buffer.
-#[derive(Eq, PartialEq, Debug, Copy, Clone)]
-struct Position {
-    start: usize,
-    end: usize,
-}
-
-impl Position {
-    const ZERO: Position = Position { start: 0, end: 0 };
-
-    const fn new(start: usize, end: usize) -> Position {
-        Position { start, end }
-    }
-}
-
-enum DecodeResult {
-    Some {
-        /// The parsed response.
-        response: ResponseData,
-        /// Remaining data.
-        buffer: Block<'static>,
-        /// How many bytes are actually valid data in `buffer`.
-        used: usize,
-    },
-    None(Block<'static>),
-}
-
-impl fmt::Debug for DecodeResult {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DecodeResult::Some {
-                response,
-                buffer,
-                used,
-            } => f
-                .debug_struct("DecodeResult::Some")
-                .field("response", response)
-                .field("block", &buffer.len())
-                .field("used", used)
-                .finish(),
-            DecodeResult::None(block) => write!(f, "DecodeResult::None({})", block.len()),
-        }
-    }
+    /// The state of the connection.
+    pub(crate) protocol: Protocol,
+    /// The deflate streams in both directions, once `COMPRESS DEFLATE` is active.
+    pub(crate) compression: Option<Deflate>,
+    /// Whether the underlying stream was closed, after which nothing is read from it anymore.
+    closed: bool,
 }
 
-impl<R: Read + Write + Unpin> ImapStream<R> {
+impl<R: AsyncRead + AsyncWrite + Unpin> ImapStream<R> {
     /// Creates a new `ImapStream` based on the given `Read`er.
     pub fn new(inner: R) -> Self {
         ImapStream {
             inner,
-            buffer: POOL.alloc(INITIAL_CAPACITY),
-            current: Position::ZERO,
-            decode_needs: 0,
-            initial_decode: false, // buffer is empty initially, nothing to decode
+            protocol: Protocol::new(),
+            compression: None,
+            closed: false,
         }
     }
 
     pub async fn encode(&mut self, msg: Request) -> Result<(), io::Error> {
-        log::trace!("encode: input: {:?}", msg);
+        let data = self.protocol.encode(msg.0.as_ref(), &msg.1);
+        self.write_all(&data).await
+    }
 
-        if let Some(tag) = msg.0 {
-            self.inner.write_all(tag.as_bytes()).await?;
-            self.inner.write(b" ").await?;
+    /// Writes `data` to the underlying stream, compressing it if `COMPRESS DEFLATE` is active.
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        match self.compression {
+            Some(ref mut deflate) => {
+                let data = deflate.compress(data)?;
+                self.inner.write_all(&data).await
+            }
+            None => self.inner.write_all(data).await,
         }
-        self.inner.write_all(&msg.1).await?;
-        self.inner.write_all(b"\r\n").await?;
+    }
 
-        Ok(())
+    /// Compresses everything that is sent and received from now on. Data that has already been
+    /// received, but not parsed yet, follows the response to the `COMPRESS` command, so it is
+    /// compressed as well.
+    pub(crate) fn start_compression(&mut self) {
+        let buffered = self.protocol.take_buffered();
+        self.compression = Some(Deflate::new(buffered));
     }
 
     pub fn into_inner(self) -> R {
@@ -121,158 +73,282 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
     }
 }
 
-impl<R: Read + Write + Unpin> ImapStream<R> {
-    fn decode(
-        &mut self,
-        buf: Block<'static>,
-        start: usize,
-        end: usize,
-    ) -> io::Result<DecodeResult> {
-        log::trace!("decode: input: {:?}", std::str::from_utf8(&buf[start..end]));
-
-        let mut rest = None;
-        let mut used = 0;
-        let res = ResponseData::try_new(buf, |buf| {
-            match imap_proto::parse_response(&buf[start..end]) {
-                Ok((remaining, response)) => {
-                    // TODO: figure out if we can shrink to the minimum required size.
-                    self.decode_needs = 0;
-
-                    let mut buf = POOL.alloc(std::cmp::max(remaining.len(), INITIAL_CAPACITY));
-                    buf[..remaining.len()].copy_from_slice(remaining);
-                    used = remaining.len();
-
-                    rest = Some(buf);
-
-                    Ok(response)
-                }
-                Err(nom::Err::Incomplete(Needed::Size(min))) => {
-                    log::trace!("decode: incomplete data, need minimum {} bytes", min);
-                    self.decode_needs = min;
-                    Err(None)
-                }
-                Err(nom::Err::Incomplete(_)) => {
-                    log::trace!("decode: incomplete data, need unknown number of bytes");
-                    Err(None)
-                }
-                Err(err) => Err(Some(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("{:?} during parsing of {:?}", err, &buf[start..end]),
-                ))),
-            }
-        });
-
-        match res {
-            Ok(response) => Ok(DecodeResult::Some {
-                response,
-                buffer: rest.unwrap(),
-                used,
-            }),
-            Err(rental::RentalError(err, buf)) => match err {
-                Some(err) => Err(err),
-                None => Ok(DecodeResult::None(buf)),
-            },
-        }
-    }
-}
-
-impl<R: Read + Write + Unpin> Stream for ImapStream<R> {
+impl<R: AsyncRead + AsyncWrite + Unpin> Stream for ImapStream<R> {
     type Item = io::Result<ResponseData>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // The `poll_next` method must strive to be as idempotent as possible if the underlying
-        // future/stream is not yet ready to produce results. It means that we must be careful
-        // to persist the state of polling between calls to `poll_next`, specifically,
-        // we must always restore the buffer and the current position before any `return`s.
-
+        // All state lives in the protocol, so returning `Pending` at any point is safe.
         let this = &mut *self;
+        if this.closed {
+            return Poll::Ready(None);
+        }
 
-        let mut n = std::mem::replace(&mut this.current, Position::ZERO);
-        let buffer = std::mem::replace(&mut this.buffer, POOL.alloc(INITIAL_CAPACITY));
-
-        let mut buffer = if (n.end - n.start) > 0 && this.initial_decode {
-            match this.decode(buffer, n.start, n.end)? {
-                DecodeResult::Some {
-                    response,
-                    buffer,
-                    used,
-                } => {
-                    // initial_decode is still true
-                    std::mem::replace(&mut this.buffer, buffer);
-                    this.current = Position::new(0, used);
-                    return Poll::Ready(Some(Ok(response)));
-                }
-                DecodeResult::None(buffer) => buffer,
-            }
-        } else {
-            buffer
-        };
+        if !this.protocol.take_budget() {
+            // give other tasks a chance to run before returning more buffered responses
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
 
         loop {
-            if (n.end - n.start) + this.decode_needs >= buffer.capacity() {
-                if buffer.capacity() + this.decode_needs < MAX_CAPACITY {
-                    buffer.realloc(buffer.capacity() + this.decode_needs);
-                } else {
-                    std::mem::replace(&mut this.buffer, buffer);
-                    this.current = n;
-                    return Poll::Ready(Some(Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "incoming data too large",
-                    ))));
-                }
+            // responses that the protocol found on its own are passed on before the ones that
+            // follow them, waiting for room in the channel like the others
+            if this.protocol.poll_unsolicited(cx).is_pending() {
+                this.protocol.reset_budget();
+                return Poll::Pending;
+            }
+            if let Some(response) = this.protocol.decode()? {
+                return Poll::Ready(Some(Ok(response)));
+            }
+            if this.protocol.has_queued_unsolicited() {
+                continue;
             }
 
-            let bytes_read = match Pin::new(&mut this.inner).poll_read(cx, &mut buffer[n.end..]) {
+            let buf = this.protocol.read_buf()?;
+            let read = match this.compression {
+                Some(ref mut deflate) => deflate.poll_read(&mut this.inner, cx, buf),
+                None => Pin::new(&mut this.inner).poll_read(cx, buf),
+            };
+            let bytes_read = match read {
                 Poll::Ready(result) => result?,
                 Poll::Pending => {
-                    // if we're here, it means that we need more data but there is none yet,
-                    // so no decoding attempts are necessary until we get more data
-                    this.initial_decode = false;
-
-                    std::mem::replace(&mut this.buffer, buffer);
-                    this.current = n;
+                    this.protocol.reset_budget();
                     return Poll::Pending;
                 }
             };
-            n.end += bytes_read;
-
-            match this.decode(buffer, n.start, n.end)? {
-                DecodeResult::Some {
-                    response,
-                    buffer,
-                    used,
-                } => {
-                    // current buffer might now contain more data inside, so we need to attempt
-                    // to decode it next time
-                    this.initial_decode = true;
-
-                    std::mem::replace(&mut this.buffer, buffer);
-                    this.current = Position::new(0, used);
-                    return Poll::Ready(Some(Ok(response)));
-                }
-                DecodeResult::None(buf) => {
-                    buffer = buf;
-
-                    if this.buffer.is_empty() || n == Position::ZERO {
-                        // "logical buffer" is empty, there is nothing to decode on the next step
-                        this.initial_decode = false;
-
-                        std::mem::replace(&mut this.buffer, buffer);
-                        this.current = n;
-                        return Poll::Ready(None);
-                    } else if (n.end - n.start) == 0 {
-                        // "logical buffer" is empty, there is nothing to decode on the next step
-                        this.initial_decode = false;
-
-                        std::mem::replace(&mut this.buffer, buffer);
-                        this.current = n;
-                        return Poll::Ready(Some(Err(io::Error::new(
-                            io::ErrorKind::UnexpectedEof,
-                            "bytes remaining in stream",
-                        ))));
-                    }
+
+            if bytes_read == 0 {
+                // the underlying stream was closed
+                this.closed = true;
+                if !this.protocol.has_buffered_data() {
+                    return Poll::Ready(None);
                 }
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "bytes remaining in stream",
+                ))));
             }
+            this.protocol.advance(bytes_read);
         }
     }
 }
+
+impl<R: AsyncRead + AsyncWrite + Unpin> FusedStream for ImapStream<R> {
+    fn is_terminated(&self) -> bool {
+        self.closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use futures::channel::mpsc;
+    use futures::{FutureExt, StreamExt};
+    use imap_proto::{RequestId, Response};
+
+    use crate::mock_stream::MockStream;
+    use crate::protocol::LiteralLimit;
+    use crate::types::{Fetch, LiteralPolicy, RawResponse, UnsolicitedResponse, Vanished};
+
+    fn literal_response(size: usize) -> Vec<u8> {
+        let mut data = format!("* 1 FETCH (BODY[] {{{}}}\r\n", size).into_bytes();
+        data.extend(std::iter::repeat(b'a').take(size));
+        data.extend_from_slice(b")\r\nA0001 OK FETCH completed\r\n");
+        data
+    }
+
+    async fn read_large_literal(size: usize, chunk_size: usize) {
+        let mock_stream = MockStream::new(literal_response(size)).with_chunks(chunk_size);
+        let mut stream = ImapStream::new(mock_stream);
+
+        let fetch = Fetch::new(stream.next().await.unwrap().unwrap());
+        let body = fetch.body().unwrap();
+        assert_eq!(body.len(), size);
+        assert!(body.iter().all(|b| *b == b'a'));
+
+        let done = stream.next().await.unwrap().unwrap();
+        match done.parsed() {
+            Response::Done { .. } => {}
+            res => panic!("unexpected response: {:?}", res),
+        }
+    }
+
+    #[async_attributes::test]
+    async fn large_literal() {
+        read_large_literal(10 * 1024 * 1024, 64 * 1024).await;
+    }
+
+    #[async_attributes::test]
+    async fn large_literal_small_reads() {
+        read_large_literal(1024 * 1024, 1000).await;
+    }
+
+    #[async_attributes::test]
+    async fn closed() {
+        let mut stream = ImapStream::new(MockStream::new(b"* 1 EXISTS\r\n".to_vec()));
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(!stream.is_terminated());
+        assert!(stream.next().await.is_none());
+        assert!(stream.is_terminated());
+        assert!(stream.next().await.is_none());
+
+        // a truncated response is reported once
+        let mut stream = ImapStream::new(MockStream::new(b"* 1 FETCH (FLAGS".to_vec()));
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(stream.is_terminated());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<std::sync::Mutex<(Vec<u8>, Vec<u8>, bool)>>);
+
+    impl crate::types::LiteralSink for Collect {
+        fn begin(&mut self, prefix: &[u8], size: usize) {
+            let mut state = self.0.lock().unwrap();
+            state.0 = prefix.to_vec();
+            state.1.reserve(size);
+        }
+
+        fn data(&mut self, data: &[u8]) {
+            self.0.lock().unwrap().1.extend_from_slice(data);
+        }
+
+        fn end(&mut self) {
+            self.0.lock().unwrap().2 = true;
+        }
+    }
+
+    #[async_attributes::test]
+    async fn oversized_literal_sink() {
+        let size = 100 * 1024;
+        let mock_stream = MockStream::new(literal_response(size)).with_chunks(1000);
+        let mut stream = ImapStream::new(mock_stream);
+        let sink = Collect::default();
+        stream.protocol.literal_limit = Some(LiteralLimit {
+            tag: RequestId("A0001".into()),
+            max_size: 1024,
+            policy: LiteralPolicy::Sink(Box::new(sink.clone())),
+        });
+
+        let fetch = Fetch::new(stream.next().await.unwrap().unwrap());
+        assert_eq!(fetch.message, 1);
+        assert_eq!(fetch.body(), None);
+        {
+            let state = sink.0.lock().unwrap();
+            assert_eq!(state.0, b"* 1 FETCH (BODY[] ".to_vec());
+            assert_eq!(state.1.len(), size);
+            assert!(state.1.iter().all(|b| *b == b'a'));
+            assert!(state.2);
+        }
+
+        stream.next().await.unwrap().unwrap();
+        assert!(stream.protocol.literal_limit.is_none());
+    }
+
+    #[async_attributes::test]
+    async fn oversized_literal_abort() {
+        let mut stream = ImapStream::new(MockStream::new(literal_response(2048)));
+        stream.protocol.literal_limit = Some(LiteralLimit {
+            tag: RequestId("A0001".into()),
+            max_size: 1024,
+            policy: LiteralPolicy::Abort,
+        });
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    #[async_attributes::test]
+    async fn unrecognized_response() {
+        let data = b"* XSTATE foo bar\r\n* 1 EXISTS\r\n".to_vec();
+        let mut stream = ImapStream::new(MockStream::new(data));
+        let (tx, mut rx) = mpsc::unbounded();
+        stream.protocol.unrecognized = Some(tx);
+
+        let res = stream.next().await.unwrap().unwrap();
+        assert_eq!(
+            res.parsed(),
+            &Response::MailboxData(imap_proto::MailboxDatum::Exists(1))
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(RawResponse(b"* XSTATE foo bar\r\n".to_vec()))
+        );
+    }
+
+    #[async_attributes::test]
+    async fn vanished_waits_for_room() {
+        let data = b"* VANISHED 1\r\n* VANISHED 2\r\n* VANISHED 3\r\n* 4 EXISTS\r\n".to_vec();
+        let mut stream = ImapStream::new(MockStream::new(data));
+        // room for a single response
+        let (tx, mut rx) = mpsc::channel(0);
+        stream.protocol.unsolicited = Some(tx.into());
+
+        let mut vanished = Vec::new();
+        let exists = loop {
+            if let Some(res) = stream.next().now_or_never() {
+                break res.unwrap().unwrap();
+            }
+            // the stream waits until there is room again, instead of dropping the response
+            vanished.push(rx.try_next().unwrap().unwrap());
+        };
+        assert!(matches!(
+            exists.parsed(),
+            Response::MailboxData(imap_proto::MailboxDatum::Exists(4))
+        ));
+        while let Ok(Some(response)) = rx.try_next() {
+            vanished.push(response);
+        }
+        let uids: Vec<_> = vanished
+            .into_iter()
+            .map(|response| match response {
+                UnsolicitedResponse::Vanished(vanished) => vanished.uids,
+                response => panic!("unexpected response: {:?}", response),
+            })
+            .collect();
+        assert_eq!(uids, vec![vec![1..=1], vec![2..=2], vec![3..=3]]);
+    }
+
+    #[async_attributes::test]
+    async fn exists_inconsistent() {
+        let data = b"* 5 EXISTS\r\n* 2 EXPUNGE\r\n* 4 EXISTS\r\n\
+            * VANISHED 1:2\r\n* VANISHED (EARLIER) 7\r\n* 1 EXISTS\r\n"
+            .to_vec();
+        let mut stream = ImapStream::new(MockStream::new(data));
+        let (tx, _unrecognized) = mpsc::unbounded();
+        stream.protocol.unrecognized = Some(tx);
+        let (tx, mut rx) = mpsc::channel(10);
+        stream.protocol.unsolicited = Some(tx.into());
+
+        while let Some(res) = stream.next().await {
+            res.unwrap();
+        }
+        drop(stream);
+        assert_eq!(
+            rx.next().await,
+            Some(UnsolicitedResponse::Vanished(Vanished {
+                earlier: false,
+                uids: vec![1..=2],
+            }))
+        );
+        assert_eq!(
+            rx.next().await,
+            Some(UnsolicitedResponse::ExistsInconsistent { old: 2, new: 1 })
+        );
+        assert_eq!(rx.next().await, None);
+    }
+
+    #[async_attributes::test]
+    async fn unrecognized_response_error() {
+        let data = b"* XSTATE foo bar\r\n".to_vec();
+        let mut stream = ImapStream::new(MockStream::new(data));
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    #[async_attributes::test]
+    #[ignore]
+    async fn huge_literal() {
+        read_large_literal(100 * 1024 * 1024, 1024 * 1024).await;
+    }
+}
@@ -1,17 +1,21 @@
 use std::cmp::min;
 use std::pin::Pin;
 
-use async_std::io::{Error, ErrorKind, Read, Result, Write};
+use futures::io::{AsyncRead, AsyncWrite, Error, ErrorKind, Result};
 use futures::task::{Context, Poll};
 
+/// A transport that returns prepared data to reads, and records what is written to it.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct MockStream {
     read_buf: Vec<u8>,
     read_pos: usize,
+    /// Everything that has been written so far.
     pub written_buf: Vec<u8>,
     err_on_read: bool,
     eof_on_read: bool,
     read_delay: usize,
+    chunk_size: usize,
+    pause_at: Option<usize>,
 }
 
 impl Default for MockStream {
@@ -23,37 +27,62 @@ impl Default for MockStream {
             err_on_read: false,
             eof_on_read: false,
             read_delay: 0,
+            chunk_size: 0,
+            pause_at: None,
         }
     }
 }
 
 impl MockStream {
+    /// A stream from which `read_buf` is read, followed by an `UnexpectedEof` error.
     pub fn new(read_buf: Vec<u8>) -> MockStream {
         MockStream::default().with_buf(read_buf)
     }
 
+    /// Replaces the data to read.
     pub fn with_buf(mut self, read_buf: Vec<u8>) -> MockStream {
         self.read_buf = read_buf;
         self
     }
 
+    /// Ends every read with the end of the stream.
     pub fn with_eof(mut self) -> MockStream {
         self.eof_on_read = true;
         self
     }
 
+    /// Fails every read.
     pub fn with_err(mut self) -> MockStream {
         self.err_on_read = true;
         self
     }
 
+    /// Returns a single byte from the first read.
     pub fn with_delay(mut self) -> MockStream {
         self.read_delay = 1;
         self
     }
+
+    /// Whether all data has been read.
+    pub fn read_pos_at_end(&self) -> bool {
+        self.read_pos >= self.read_buf.len()
+    }
+
+    /// Return at most `chunk_size` bytes from each read.
+    pub fn with_chunks(mut self, chunk_size: usize) -> MockStream {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Stop returning data at `pos` until something is written, as a server that waits for the
+    /// client would.
+    pub fn with_pause_at(mut self, pos: usize) -> MockStream {
+        self.pause_at = Some(pos);
+        self
+    }
 }
 
-impl Read for MockStream {
+impl AsyncRead for MockStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
         _cx: &mut Context<'_>,
@@ -69,26 +98,34 @@ impl Read for MockStream {
             return Poll::Ready(Err(Error::new(ErrorKind::UnexpectedEof, "EOF")));
         }
         let mut write_len = min(buf.len(), self.read_buf.len() - self.read_pos);
+        if let Some(pause_at) = self.pause_at {
+            if self.read_pos >= pause_at {
+                return Poll::Pending;
+            }
+            write_len = min(write_len, pause_at - self.read_pos);
+        }
         if self.read_delay > 0 {
             self.read_delay -= 1;
             write_len = min(write_len, 1);
         }
-        let max_pos = self.read_pos + write_len;
-        for x in self.read_pos..max_pos {
-            buf[x - self.read_pos] = self.read_buf[x];
+        if self.chunk_size > 0 {
+            write_len = min(write_len, self.chunk_size);
         }
+        let max_pos = self.read_pos + write_len;
+        buf[..write_len].copy_from_slice(&self.read_buf[self.read_pos..max_pos]);
         self.read_pos += write_len;
         Poll::Ready(Ok(write_len))
     }
 }
 
-impl Write for MockStream {
+impl AsyncWrite for MockStream {
     fn poll_write(
         mut self: Pin<&mut Self>,
         _cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize>> {
         self.written_buf.extend_from_slice(buf);
+        self.pause_at = None;
         Poll::Ready(Ok(buf.len()))
     }
 
@@ -100,3 +137,118 @@ impl Write for MockStream {
         Poll::Ready(Ok(()))
     }
 }
+
+/// The capabilities and mailboxes of widely used servers, to test how the high-level APIs fall
+/// back when an extension is missing. The capabilities are those advertised after logging in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    Gmail,
+    Office365,
+    Dovecot,
+    Courier,
+    ICloud,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 5] = [
+        Profile::Gmail,
+        Profile::Office365,
+        Profile::Dovecot,
+        Profile::Courier,
+        Profile::ICloud,
+    ];
+
+    pub fn capabilities(self) -> &'static str {
+        match self {
+            Profile::Gmail => {
+                "IMAP4rev1 UNSELECT IDLE NAMESPACE QUOTA ID XLIST CHILDREN X-GM-EXT-1 UIDPLUS \
+                 COMPRESS=DEFLATE ENABLE MOVE CONDSTORE ESEARCH UTF8=ACCEPT LIST-EXTENDED \
+                 LIST-STATUS LITERAL- SPECIAL-USE APPENDLIMIT=35651584"
+            }
+            Profile::Office365 => {
+                "IMAP4 IMAP4rev1 AUTH=PLAIN AUTH=XOAUTH2 SASL-IR UIDPLUS MOVE ID UNSELECT \
+                 CHILDREN IDLE NAMESPACE LITERAL+"
+            }
+            Profile::Dovecot => {
+                "IMAP4rev1 SASL-IR LOGIN-REFERRALS ID ENABLE IDLE SORT SORT=DISPLAY \
+                 THREAD=REFERENCES THREAD=REFS THREAD=ORDEREDSUBJECT MULTIAPPEND URL-PARTIAL \
+                 CATENATE UNSELECT CHILDREN NAMESPACE UIDPLUS LIST-EXTENDED I18NLEVEL=1 CONDSTORE \
+                 QRESYNC ESEARCH ESORT SEARCHRES WITHIN CONTEXT=SEARCH LIST-STATUS BINARY MOVE \
+                 SNIPPET=FUZZY LITERAL+ NOTIFY SPECIAL-USE"
+            }
+            Profile::Courier => {
+                "IMAP4rev1 UIDPLUS CHILDREN NAMESPACE THREAD=ORDEREDSUBJECT THREAD=REFERENCES \
+                 SORT QUOTA IDLE ACL ACL2=UNION"
+            }
+            Profile::ICloud => {
+                "IMAP4rev1 SASL-IR XLIST ID ENABLE IDLE NAMESPACE QUOTA UIDPLUS CONDSTORE QRESYNC \
+                 ESEARCH UNSELECT CHILDREN"
+            }
+        }
+    }
+
+    pub fn has(self, capability: &str) -> bool {
+        self.capabilities()
+            .split(' ')
+            .any(|c| c.eq_ignore_ascii_case(capability))
+    }
+
+    /// The response to `CAPABILITY`, without the completion.
+    pub fn capability_response(self) -> String {
+        format!("* CAPABILITY {}\r\n", self.capabilities())
+    }
+
+    /// The response to `LIST "" "*"`, without the completion. Gmail marks its archive as `\All`,
+    /// and Courier does not support special-use mailboxes at all.
+    pub fn list_response(self) -> &'static str {
+        match self {
+            Profile::Gmail => {
+                "* LIST (\\HasNoChildren) \"/\" \"INBOX\"\r\n\
+                 * LIST (\\All \\HasNoChildren) \"/\" \"[Gmail]/All Mail\"\r\n"
+            }
+            Profile::Office365 | Profile::ICloud => {
+                "* LIST (\\HasNoChildren) \"/\" INBOX\r\n\
+                 * LIST (\\Archive \\HasNoChildren) \"/\" Archive\r\n"
+            }
+            Profile::Dovecot => {
+                "* LIST (\\HasNoChildren) \".\" INBOX\r\n\
+                 * LIST (\\HasNoChildren \\Archive) \".\" Archive\r\n"
+            }
+            Profile::Courier => {
+                "* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n\
+                 * LIST (\\HasNoChildren) \".\" \"INBOX.Archive\"\r\n"
+            }
+        }
+    }
+}
+
+/// A scripted conversation with a server: the commands the client is expected to send, and the
+/// responses to them, tagged in order like the commands of a new session.
+#[derive(Debug, Default)]
+pub struct Script {
+    commands: String,
+    responses: String,
+    tags: u32,
+}
+
+impl Script {
+    /// Expects `command`, and answers it with the untagged `data`, followed by the completion
+    /// `status`, e.g. `OK done`.
+    pub fn exchange(&mut self, command: &str, data: &str, status: &str) -> &mut Self {
+        self.tags += 1;
+        let tag = format!("A{:04}", self.tags);
+        self.commands.push_str(&format!("{} {}\r\n", tag, command));
+        self.responses.push_str(data);
+        self.responses.push_str(&format!("{} {}\r\n", tag, status));
+        self
+    }
+
+    pub fn stream(&self) -> MockStream {
+        MockStream::new(self.responses.as_bytes().to_vec())
+    }
+
+    /// The commands that were expected so far.
+    pub fn commands(&self) -> &[u8] {
+        self.commands.as_bytes()
+    }
+}
@@ -1,23 +1,21 @@
 use std::collections::HashSet;
 
-use async_std::io;
-use async_std::prelude::*;
-use async_std::stream::Stream;
-use async_std::sync;
+use futures::io;
+use futures::prelude::*;
+use futures::stream::{FusedStream, StreamExt};
 use imap_proto::{self, MailboxDatum, RequestId, Response};
 
-use crate::error::{Error, Result};
+use crate::error::{AuthError, Error, Result};
+use crate::replay::UnsolicitedSender;
 use crate::types::ResponseData;
 use crate::types::*;
 
 pub(crate) fn parse_names<'a, T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &'a mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> impl Stream<Item = Result<Name>> + 'a {
-    use futures::StreamExt;
-
-    StreamExt::filter_map(
+) -> impl Stream<Item = Result<Name>> + FusedStream + 'a {
+    let responses = StreamExt::filter_map(
         StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
         move |resp| {
             let unsolicited = unsolicited.clone();
@@ -25,8 +23,7 @@ pub(crate) fn parse_names<'a, T: Stream<Item = io::Result<ResponseData>> + Unpin
                 match resp {
                     Ok(resp) => match resp.parsed() {
                         Response::MailboxData(MailboxDatum::List { .. }) => {
-                            let name = Name::from_mailbox_data(resp);
-                            Some(Ok(name))
+                            Some(Name::from_mailbox_data(resp))
                         }
                         _ => {
                             handle_unilateral(resp, unsolicited).await;
@@ -37,32 +34,28 @@ pub(crate) fn parse_names<'a, T: Stream<Item = io::Result<ResponseData>> + Unpin
                 }
             }
         },
-    )
+    );
+    // the stream ends with the completion, and must not read past it when polled again
+    StreamExt::fuse(responses)
 }
 
 fn filter(res: &io::Result<ResponseData>, command_tag: &RequestId) -> impl Future<Output = bool> {
-    let val = filter_sync(res, command_tag);
-    futures::future::ready(val)
-}
-
-fn filter_sync(res: &io::Result<ResponseData>, command_tag: &RequestId) -> bool {
-    match res {
+    let val = match res {
         Ok(res) => match res.parsed() {
             Response::Done { tag, .. } => tag != command_tag,
             _ => true,
         },
         Err(_err) => false,
-    }
+    };
+    futures::future::ready(val)
 }
 
 pub(crate) fn parse_fetches<'a, T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &'a mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> impl Stream<Item = Result<Fetch>> + 'a {
-    use futures::StreamExt;
-
-    StreamExt::filter_map(
+) -> impl Stream<Item = Result<Fetch>> + FusedStream + 'a {
+    let responses = StreamExt::filter_map(
         StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
         move |resp| {
             let unsolicited = unsolicited.clone();
@@ -80,17 +73,16 @@ pub(crate) fn parse_fetches<'a, T: Stream<Item = io::Result<ResponseData>> + Unp
                 }
             }
         },
-    )
+    );
+    StreamExt::fuse(responses)
 }
 
 pub(crate) fn parse_expunge<'a, T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &'a mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> impl Stream<Item = Result<u32>> + 'a {
-    use futures::StreamExt;
-
-    StreamExt::filter_map(
+) -> impl Stream<Item = Result<u32>> + FusedStream + 'a {
+    let responses = StreamExt::filter_map(
         StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
         move |resp| {
             let unsolicited = unsolicited.clone();
@@ -108,22 +100,22 @@ pub(crate) fn parse_expunge<'a, T: Stream<Item = io::Result<ResponseData>> + Unp
                 }
             }
         },
-    )
+    );
+    StreamExt::fuse(responses)
 }
 
 pub(crate) async fn parse_capabilities<'a, T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &'a mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> Result<Capabilities> {
+) -> Result<CommandResult<Capabilities>> {
     let mut caps: HashSet<Capability> = HashSet::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
+        if resp.request_id() == Some(&command_tag) {
+            return complete(Capabilities(caps), resp);
+        }
         match resp.parsed() {
             Response::Capabilities(cs) => {
                 for c in cs {
@@ -136,39 +128,78 @@ pub(crate) async fn parse_capabilities<'a, T: Stream<Item = io::Result<ResponseD
         }
     }
 
-    Ok(Capabilities(caps))
+    Ok(CommandResult::new(Capabilities(caps), None))
 }
 
 pub(crate) async fn parse_noop<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> Result<()> {
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+) -> Result<CommandResult<()>> {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
+        if resp.request_id() == Some(&command_tag) {
+            return complete((), resp);
+        }
+        handle_unilateral(resp, unsolicited.clone()).await;
+    }
+
+    Ok(CommandResult::new((), None))
+}
+
+pub(crate) async fn parse_move<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+) -> Result<CommandResult<Moved>> {
+    let mut moved = Moved::default();
+
+    while let Some(resp) = stream.next().await {
+        let resp = resp?;
+        match resp.parsed() {
+            Response::Done {
+                tag,
+                code,
+                information,
+                ..
+            } if tag == &command_tag => {
+                // some servers only include COPYUID in the completion, as for `UID COPY`
+                if moved.copy_uid.is_none() {
+                    moved.copy_uid = CopyUid::parse(code.as_ref(), *information);
+                }
+                return complete(moved, resp);
+            }
+            Response::Data {
+                status: imap_proto::Status::Ok,
+                code,
+                information,
+            } => {
+                if let Some(copy_uid) = CopyUid::parse(code.as_ref(), *information) {
+                    moved.copy_uid = Some(copy_uid);
+                    continue;
+                }
+            }
+            Response::Expunge(n) => moved.expunged.push(*n),
+            _ => {}
+        }
         handle_unilateral(resp, unsolicited.clone()).await;
     }
 
-    Ok(())
+    Err(Error::ConnectionLost)
 }
 
 pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> Result<Mailbox> {
+) -> Result<CommandResult<Mailbox>> {
     let mut mailbox = Mailbox::default();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
+        if resp.request_id() == Some(&command_tag) {
+            return complete(mailbox, resp);
+        }
         match resp.parsed() {
             Response::Data {
                 status,
@@ -190,10 +221,13 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                             Some(ResponseCode::Unseen(n)) => {
                                 mailbox.unseen = Some(*n);
                             }
+                            Some(ResponseCode::HighestModSeq(modseq)) => {
+                                mailbox.highest_modseq = Some(*modseq);
+                            }
                             Some(ResponseCode::PermanentFlags(flags)) => {
-                                mailbox
-                                    .permanent_flags
-                                    .extend(flags.iter().map(|s| (*s).to_string()).map(Flag::from));
+                                // each response replaces the previous one
+                                mailbox.permanent_flags =
+                                    Some(flags.iter().map(|s| Flag::from(*s)).collect());
                             }
                             _ => {}
                         }
@@ -230,9 +264,7 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                     mailbox.recent = *r;
                 }
                 MailboxDatum::Flags(flags) => {
-                    mailbox
-                        .flags
-                        .extend(flags.iter().map(|s| (*s).to_string()).map(Flag::from));
+                    mailbox.flags = flags.iter().map(|s| Flag::from(*s)).collect();
                 }
                 MailboxDatum::List { .. } => {}
                 MailboxDatum::MetadataSolicited { .. } => {}
@@ -244,22 +276,21 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
         }
     }
 
-    Ok(mailbox)
+    Ok(CommandResult::new(mailbox, None))
 }
 
 pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
     stream: &mut T,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> Result<HashSet<u32>> {
+) -> Result<CommandResult<HashSet<u32>>> {
     let mut ids: HashSet<u32> = HashSet::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter_sync(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = stream.next().await {
         let resp = resp?;
+        if resp.request_id() == Some(&command_tag) {
+            return complete(ids, resp);
+        }
         match resp.parsed() {
             Response::IDs(cs) => {
                 for c in cs {
@@ -272,58 +303,168 @@ pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin
         }
     }
 
-    Ok(ids)
+    Ok(CommandResult::new(ids, None))
+}
+
+pub(crate) async fn parse_search<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+) -> Result<CommandResult<SearchResult>> {
+    let mut result = SearchResult::default();
+
+    while let Some(resp) = stream.next().await {
+        let resp = resp?;
+        if resp.request_id() == Some(&command_tag) {
+            return complete(result, resp);
+        }
+        match resp.parsed() {
+            Response::IDs(ids) => result.merge(ids.iter().cloned()),
+            _ => {
+                handle_unilateral(resp, unsolicited.clone()).await;
+            }
+        }
+    }
+
+    Ok(CommandResult::new(result, None))
+}
+
+/// Turns the tagged completion of a command into a [`CommandResult`] holding `value`, or into an
+/// error if the command failed.
+pub(crate) fn complete<T>(value: T, done: ResponseData) -> Result<CommandResult<T>> {
+    if let Response::Done {
+        status,
+        code,
+        information,
+        ..
+    } = done.parsed()
+    {
+        status_to_result(status, code, information)?;
+    }
+
+    Ok(CommandResult::new(value, Some(done)))
+}
+
+/// Like [`complete`], but for the completion of `LOGIN` or `AUTHENTICATE`, whose `NO` is turned
+/// into an [`Error::Auth`] that tells why logging in failed.
+pub(crate) fn complete_auth(done: ResponseData) -> Result<CommandResult<()>> {
+    if let Response::Done {
+        status: imap_proto::Status::No,
+        code,
+        information,
+        ..
+    } = done.parsed()
+    {
+        return Err(Error::Auth(AuthError::new(code, information)));
+    }
+
+    complete((), done)
+}
+
+/// Converts the status of a tagged completion into a `Result`.
+pub(crate) fn status_to_result(
+    status: &imap_proto::Status,
+    code: &Option<imap_proto::ResponseCode<'_>>,
+    information: &Option<&str>,
+) -> Result<()> {
+    use imap_proto::Status;
+
+    match status {
+        Status::Ok => Ok(()),
+        Status::Bad => Err(Error::Bad(format!(
+            "code: {:?}, info: {:?}",
+            code, information
+        ))),
+        Status::No => Err(Error::No(format!(
+            "code: {:?}, info: {:?}",
+            code, information
+        ))),
+        _ => Err(Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "status: {:?}, code: {:?}, information: {:?}",
+                status, code, information
+            ),
+        ))),
+    }
 }
 
 // check if this is simply a unilateral server response
 // (see Section 7 of RFC 3501):
-pub(crate) async fn handle_unilateral(
-    res: ResponseData,
-    unsolicited: sync::Sender<UnsolicitedResponse>,
-) {
+pub(crate) async fn handle_unilateral(res: ResponseData, mut unsolicited: UnsolicitedSender) {
+    // the receiver lives in the `Session`, so this only fails once it is gone
+    unsolicited.send(to_unsolicited(res)).await;
+}
+
+/// Converts a response into the matching [`UnsolicitedResponse`].
+pub(crate) fn to_unsolicited(res: ResponseData) -> UnsolicitedResponse {
     match res.parsed() {
         Response::MailboxData(MailboxDatum::Status { mailbox, status }) => {
-            unsolicited
-                .send(UnsolicitedResponse::Status {
-                    mailbox: (*mailbox).into(),
-                    attributes: status
-                        .iter()
-                        .map(|s| match s {
-                            // Fake clone
-                            StatusAttribute::HighestModSeq(a) => StatusAttribute::HighestModSeq(*a),
-                            StatusAttribute::Messages(a) => StatusAttribute::Messages(*a),
-                            StatusAttribute::Recent(a) => StatusAttribute::Recent(*a),
-                            StatusAttribute::UidNext(a) => StatusAttribute::UidNext(*a),
-                            StatusAttribute::UidValidity(a) => StatusAttribute::UidValidity(*a),
-                            StatusAttribute::Unseen(a) => StatusAttribute::Unseen(*a),
-                        })
-                        .collect(),
-                })
-                .await;
-        }
-        Response::MailboxData(MailboxDatum::Recent(n)) => {
-            unsolicited.send(UnsolicitedResponse::Recent(*n)).await;
-        }
-        Response::MailboxData(MailboxDatum::Exists(n)) => {
-            unsolicited.send(UnsolicitedResponse::Exists(*n)).await;
+            UnsolicitedResponse::Status {
+                mailbox: res.unquoted(mailbox).into_owned(),
+                attributes: status
+                    .iter()
+                    .map(|s| match s {
+                        // Fake clone
+                        StatusAttribute::HighestModSeq(a) => StatusAttribute::HighestModSeq(*a),
+                        StatusAttribute::Messages(a) => StatusAttribute::Messages(*a),
+                        StatusAttribute::Recent(a) => StatusAttribute::Recent(*a),
+                        StatusAttribute::UidNext(a) => StatusAttribute::UidNext(*a),
+                        StatusAttribute::UidValidity(a) => StatusAttribute::UidValidity(*a),
+                        StatusAttribute::Unseen(a) => StatusAttribute::Unseen(*a),
+                    })
+                    .collect(),
+            }
         }
-        Response::Expunge(n) => {
-            unsolicited.send(UnsolicitedResponse::Expunge(*n)).await;
+        Response::MailboxData(MailboxDatum::Recent(n)) => UnsolicitedResponse::Recent(*n),
+        Response::MailboxData(MailboxDatum::Exists(n)) => UnsolicitedResponse::Exists(*n),
+        Response::Expunge(n) => UnsolicitedResponse::Expunge(*n),
+        Response::MailboxData(MailboxDatum::Flags(flags)) => {
+            UnsolicitedResponse::Flags(flags.iter().map(|s| Flag::from(*s)).collect())
         }
-        _ => {
-            unsolicited.send(UnsolicitedResponse::Other(res)).await;
+        Response::Data {
+            status: imap_proto::Status::Ok,
+            code: Some(imap_proto::ResponseCode::PermanentFlags(flags)),
+            ..
+        } => UnsolicitedResponse::PermanentFlags(flags.iter().map(|s| Flag::from(*s)).collect()),
+        _ => UnsolicitedResponse::Other(res),
+    }
+}
+
+/// Extracts the `MESSAGES` and `SIZE` attributes from a raw [`STATUS`
+/// response](https://tools.ietf.org/html/rfc8438) that imap-proto could not parse.
+pub(crate) fn parse_status_size(raw: &[u8]) -> Option<(u32, u64)> {
+    let raw = std::str::from_utf8(raw).ok()?;
+    if !raw.starts_with("* STATUS ") {
+        return None;
+    }
+    let attrs = &raw[raw.rfind('(')? + 1..];
+    let attrs = &attrs[..attrs.find(')')?];
+
+    let mut messages = None;
+    let mut size = None;
+    let mut tokens = attrs.split_whitespace();
+    while let (Some(name), Some(value)) = (tokens.next(), tokens.next()) {
+        if name.eq_ignore_ascii_case("MESSAGES") {
+            messages = Some(value.parse().ok()?);
+        } else if name.eq_ignore_ascii_case("SIZE") {
+            size = Some(value.parse().ok()?);
         }
     }
+
+    Some((messages?, size?))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::channel::mpsc;
+    use futures::stream;
 
     fn input_stream(data: &[&str]) -> Vec<io::Result<ResponseData>> {
         data.iter()
             .map(|line| {
-                let mut block = crate::imap_stream::POOL.alloc(line.as_bytes().len());
+                let mut block = crate::protocol::POOL.alloc(line.as_bytes().len());
                 block.copy_from_slice(line.as_bytes());
                 ResponseData::try_new(block, |bytes| -> io::Result<_> {
                     let (remaining, response) = imap_proto::parse_response(bytes).unwrap();
@@ -342,12 +483,14 @@ mod tests {
             "* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n",
         ]);
 
-        let mut stream = async_std::stream::from_iter(responses);
-        let (send, recv) = sync::channel(10);
+        let mut stream = stream::iter(responses);
+        let (send, mut recv) = mpsc::channel(10);
         let id = RequestId("A0001".into());
-        let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
+        let capabilities = parse_capabilities(&mut stream, send.into(), id)
+            .await
+            .unwrap();
         // shouldn't be any unexpected responses parsed
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
         assert_eq!(capabilities.len(), 4);
         for e in expected_capabilities {
             assert!(capabilities.has_str(e));
@@ -359,14 +502,16 @@ mod tests {
         // Test that "IMAP4REV1" (instead of "IMAP4rev1") is accepted
         let expected_capabilities = vec!["IMAP4rev1", "STARTTLS"];
         let responses = input_stream(&vec!["* CAPABILITY IMAP4REV1 STARTTLS\r\n"]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let id = RequestId("A0001".into());
-        let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
+        let capabilities = parse_capabilities(&mut stream, send.into(), id)
+            .await
+            .unwrap();
 
         // shouldn't be any unexpected responses parsed
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
         assert_eq!(capabilities.len(), 2);
         for e in expected_capabilities {
             assert!(capabilities.has_str(e));
@@ -376,31 +521,31 @@ mod tests {
     #[async_attributes::test]
     #[should_panic]
     async fn parse_capability_invalid_test() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![
             "* JUNK IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n",
         ]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let id = RequestId("A0001".into());
-        parse_capabilities(&mut stream, send.clone(), id)
+        parse_capabilities(&mut stream, send.into(), id)
             .await
             .unwrap();
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
     }
 
     #[async_attributes::test]
     async fn parse_names_test() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec!["* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n"]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let id = RequestId("A0001".into());
-        let names: Vec<_> = parse_names(&mut stream, send, id)
-            .collect::<Result<Vec<Name>>>()
+        let names: Vec<_> = parse_names(&mut stream, send.into(), id)
+            .try_collect::<Vec<Name>>()
             .await
             .unwrap();
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
         assert_eq!(names.len(), 1);
         assert_eq!(
             names[0].attributes(),
@@ -412,43 +557,49 @@ mod tests {
 
     #[async_attributes::test]
     async fn parse_fetches_empty() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
         let id = RequestId("a".into());
 
-        let fetches = parse_fetches(&mut stream, send, id)
-            .collect::<Result<Vec<_>>>()
+        let fetches = parse_fetches(&mut stream, send.into(), id)
+            .try_collect::<Vec<_>>()
             .await
             .unwrap();
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
         assert!(fetches.is_empty());
     }
 
     #[async_attributes::test]
     async fn parse_fetches_test() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![
             "* 24 FETCH (FLAGS (\\Seen) UID 4827943)\r\n",
             "* 25 FETCH (FLAGS (\\Seen))\r\n",
         ]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
         let id = RequestId("a".into());
 
-        let fetches = parse_fetches(&mut stream, send, id)
-            .collect::<Result<Vec<_>>>()
+        let fetches = parse_fetches(&mut stream, send.into(), id)
+            .try_collect::<Vec<_>>()
             .await
             .unwrap();
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
 
         assert_eq!(fetches.len(), 2);
         assert_eq!(fetches[0].message, 24);
-        assert_eq!(fetches[0].flags().collect::<Vec<_>>(), vec![Flag::Seen]);
+        assert_eq!(
+            fetches[0].flags().iter().collect::<Vec<_>>(),
+            vec![Flag::Seen]
+        );
         assert_eq!(fetches[0].uid, Some(4827943));
         assert_eq!(fetches[0].body(), None);
         assert_eq!(fetches[0].header(), None);
         assert_eq!(fetches[1].message, 25);
-        assert_eq!(fetches[1].flags().collect::<Vec<_>>(), vec![Flag::Seen]);
+        assert_eq!(
+            fetches[1].flags().iter().collect::<Vec<_>>(),
+            vec![Flag::Seen]
+        );
         assert_eq!(fetches[1].uid, None);
         assert_eq!(fetches[1].body(), None);
         assert_eq!(fetches[1].header(), None);
@@ -457,16 +608,16 @@ mod tests {
     #[async_attributes::test]
     async fn parse_fetches_w_unilateral() {
         // https://github.com/mattnenterprise/rust-imap/issues/81
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec!["* 37 FETCH (UID 74)\r\n", "* 1 RECENT\r\n"]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
         let id = RequestId("a".into());
 
-        let fetches = parse_fetches(&mut stream, send, id)
-            .collect::<Result<Vec<_>>>()
+        let fetches = parse_fetches(&mut stream, send.into(), id)
+            .try_collect::<Vec<_>>()
             .await
             .unwrap();
-        assert_eq!(recv.recv().await, Some(UnsolicitedResponse::Recent(1)));
+        assert_eq!(recv.next().await, Some(UnsolicitedResponse::Recent(1)));
 
         assert_eq!(fetches.len(), 1);
         assert_eq!(fetches[0].message, 37);
@@ -475,20 +626,20 @@ mod tests {
 
     #[async_attributes::test]
     async fn parse_names_w_unilateral() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![
             "* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n",
             "* 4 EXPUNGE\r\n",
         ]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let id = RequestId("A0001".into());
-        let names = parse_names(&mut stream, send, id)
-            .collect::<Result<Vec<_>>>()
+        let names = parse_names(&mut stream, send.into(), id)
+            .try_collect::<Vec<_>>()
             .await
             .unwrap();
 
-        assert_eq!(recv.recv().await, Some(UnsolicitedResponse::Expunge(4)));
+        assert_eq!(recv.next().await, Some(UnsolicitedResponse::Expunge(4)));
 
         assert_eq!(names.len(), 1);
         assert_eq!(
@@ -501,18 +652,20 @@ mod tests {
 
     #[async_attributes::test]
     async fn parse_capabilities_w_unilateral() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![
             "* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n",
             "* STATUS dev.github (MESSAGES 10 UIDNEXT 11 UIDVALIDITY 1408806928 UNSEEN 0)\r\n",
             "* 4 EXISTS\r\n",
         ]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
 
         let id = RequestId("A0001".into());
-        let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
+        let capabilities = parse_capabilities(&mut stream, send.into(), id)
+            .await
+            .unwrap();
 
         assert_eq!(capabilities.len(), 4);
         for e in expected_capabilities {
@@ -520,7 +673,7 @@ mod tests {
         }
 
         assert_eq!(
-            recv.recv().await.unwrap(),
+            recv.next().await.unwrap(),
             UnsolicitedResponse::Status {
                 mailbox: "dev.github".to_string(),
                 attributes: vec![
@@ -531,27 +684,27 @@ mod tests {
                 ]
             }
         );
-        assert_eq!(recv.recv().await.unwrap(), UnsolicitedResponse::Exists(4));
+        assert_eq!(recv.next().await.unwrap(), UnsolicitedResponse::Exists(4));
     }
 
     #[async_attributes::test]
     async fn parse_ids_w_unilateral() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![
             "* SEARCH 23 42 4711\r\n",
             "* 1 RECENT\r\n",
             "* STATUS INBOX (MESSAGES 10 UIDNEXT 11 UIDVALIDITY 1408806928 UNSEEN 0)\r\n",
         ]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let id = RequestId("A0001".into());
-        let ids = parse_ids(&mut stream, send, id).await.unwrap();
+        let ids = parse_ids(&mut stream, send.into(), id).await.unwrap();
 
-        assert_eq!(ids, [23, 42, 4711].iter().cloned().collect());
+        assert_eq!(*ids, [23, 42, 4711].iter().cloned().collect());
 
-        assert_eq!(recv.recv().await.unwrap(), UnsolicitedResponse::Recent(1));
+        assert_eq!(recv.next().await.unwrap(), UnsolicitedResponse::Recent(1));
         assert_eq!(
-            recv.recv().await.unwrap(),
+            recv.next().await.unwrap(),
             UnsolicitedResponse::Status {
                 mailbox: "INBOX".to_string(),
                 attributes: vec![
@@ -566,17 +719,17 @@ mod tests {
 
     #[async_attributes::test]
     async fn parse_ids_test() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec![
                 "* SEARCH 1600 1698 1739 1781 1795 1885 1891 1892 1893 1898 1899 1901 1911 1926 1932 1933 1993 1994 2007 2032 2033 2041 2053 2062 2063 2065 2066 2072 2078 2079 2082 2084 2095 2100 2101 2102 2103 2104 2107 2116 2120 2135 2138 2154 2163 2168 2172 2189 2193 2198 2199 2205 2212 2213 2221 2227 2267 2275 2276 2295 2300 2328 2330 2332 2333 2334\r\n",
                 "* SEARCH 2335 2336 2337 2338 2339 2341 2342 2347 2349 2350 2358 2359 2362 2369 2371 2372 2373 2374 2375 2376 2377 2378 2379 2380 2381 2382 2383 2384 2385 2386 2390 2392 2397 2400 2401 2403 2405 2409 2411 2414 2417 2419 2420 2424 2426 2428 2439 2454 2456 2467 2468 2469 2490 2515 2519 2520 2521\r\n",
             ]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let id = RequestId("A0001".into());
-        let ids = parse_ids(&mut stream, send, id).await.unwrap();
+        let ids = parse_ids(&mut stream, send.into(), id).await.unwrap();
 
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
         let ids: HashSet<u32> = ids.iter().cloned().collect();
         assert_eq!(
             ids,
@@ -599,15 +752,82 @@ mod tests {
 
     #[async_attributes::test]
     async fn parse_ids_search() {
-        let (send, recv) = sync::channel(10);
+        let (send, mut recv) = mpsc::channel(10);
         let responses = input_stream(&vec!["* SEARCH\r\n"]);
-        let mut stream = async_std::stream::from_iter(responses);
+        let mut stream = stream::iter(responses);
 
         let id = RequestId("A0001".into());
-        let ids = parse_ids(&mut stream, send, id).await.unwrap();
+        let ids = parse_ids(&mut stream, send.into(), id).await.unwrap();
 
-        assert!(recv.is_empty());
+        assert!(recv.next().await.is_none());
         let ids: HashSet<u32> = ids.iter().cloned().collect();
         assert_eq!(ids, HashSet::<u32>::new());
     }
+
+    #[async_attributes::test]
+    async fn parse_mailbox_replaces_flags() {
+        let (send, mut recv) = mpsc::channel(10);
+        let responses = input_stream(&vec![
+            "* FLAGS (\\Seen \\Deleted)\r\n",
+            "* OK [PERMANENTFLAGS (\\Seen)] Limited\r\n",
+            "* OK [PERMANENTFLAGS (\\Seen \\Deleted)] Limited\r\n",
+            "* FLAGS (\\Seen $Junk)\r\n",
+            "A0001 OK done\r\n",
+        ]);
+        let mut stream = stream::iter(responses);
+
+        let id = RequestId("A0001".into());
+        let mut mailbox = parse_mailbox(&mut stream, send.clone().into(), id)
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(recv.try_next().is_err());
+        assert_eq!(mailbox.flags.len(), 2);
+        assert!(!mailbox.flags.contains(&Flag::Deleted));
+        assert!(mailbox.flags.contains(&Flag::from("$Junk")));
+        assert_eq!(mailbox.permanent_flags.as_ref().map(FlagSet::len), Some(2));
+        assert!(mailbox.is_permanent(&Flag::Deleted));
+        assert!(!mailbox.is_permanent(&Flag::Flagged));
+        assert!(!mailbox.is_permanent(&Flag::from("$Junk")));
+
+        let update = to_unsolicited(
+            input_stream(&vec!["* OK [PERMANENTFLAGS (\\Seen \\*)] More\r\n"])
+                .pop()
+                .unwrap()
+                .unwrap(),
+        );
+        assert!(mailbox.update(&update));
+        assert_eq!(mailbox.permanent_flags.as_ref().map(FlagSet::len), Some(2));
+        assert!(mailbox.is_permanent(&Flag::from("$Junk")));
+        assert!(!mailbox.is_permanent(&Flag::Deleted));
+        assert!(!mailbox.is_permanent(&Flag::Recent));
+
+        // a flag that is no longer listed goes away
+        let update = to_unsolicited(
+            input_stream(&vec!["* FLAGS (\\Seen)\r\n"])
+                .pop()
+                .unwrap()
+                .unwrap(),
+        );
+        assert!(mailbox.update(&update));
+        assert_eq!(mailbox.flags.len(), 1);
+        assert!(!mailbox.flags.contains(&Flag::from("$Junk")));
+    }
+
+    #[test]
+    fn parse_status_size_raw() {
+        assert_eq!(
+            parse_status_size(b"* STATUS \"Sent (old)\" (MESSAGES 12 SIZE 4294967296)\r\n"),
+            Some((12, 4294967296))
+        );
+        assert_eq!(
+            parse_status_size(b"* STATUS INBOX (SIZE 10 MESSAGES 1)\r\n"),
+            Some((1, 10))
+        );
+        assert_eq!(parse_status_size(b"* STATUS INBOX (MESSAGES 1)\r\n"), None);
+        assert_eq!(
+            parse_status_size(b"* XSTATE (SIZE 10 MESSAGES 1)\r\n"),
+            None
+        );
+    }
 }
@@ -0,0 +1,172 @@
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use imap_proto::RequestId;
+
+use crate::compress::Deflate;
+use crate::error::{Error, Result};
+use crate::imap_stream::ImapStream;
+use crate::protocol::Protocol;
+use crate::types::RawResponse;
+
+/// A connection that forwards commands and responses as raw bytes, for building IMAP proxies,
+/// gateways and filters on top of the crate's framing.
+///
+/// Commands passed to [`send`](Passthrough::send) are written unchanged, with whatever tags the
+/// caller chose, and [`read_response`](Passthrough::read_response) returns each complete
+/// response exactly as the server sent it, literals included. Nothing is interpreted beyond what
+/// is needed to tell where responses end and to know which commands are still
+/// [in flight](Passthrough::in_flight). A response can still be inspected with
+/// [`RawResponse::to_response_data`] before it is forwarded.
+///
+/// ```no_run
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// # let upstream: async_std::net::TcpStream = unimplemented!();
+/// use async_imap::Passthrough;
+///
+/// let mut proxy = Passthrough::new(upstream);
+/// // the greeting
+/// let greeting = proxy.read_response().await.transpose()?;
+/// proxy.send(b"x1 CAPABILITY\r\n").await?;
+/// while let Some(response) = proxy.read_response().await.transpose()? {
+///     // forward `response.as_bytes()` to the downstream client here
+///     if response.tag() == Some("x1") {
+///         break;
+///     }
+/// }
+/// # Ok(())
+/// # }) }
+/// ```
+#[derive(Debug)]
+pub struct Passthrough<T: AsyncRead + AsyncWrite + Unpin> {
+    inner: T,
+    protocol: Protocol,
+    /// The deflate streams of a session that enabled `COMPRESS DEFLATE`, which stay in effect.
+    compression: Option<Deflate>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Passthrough<T> {
+    /// Creates a passthrough connection over `stream`. The server greeting has not been read
+    /// yet, and is returned by the first call to [`read_response`](Passthrough::read_response).
+    pub fn new(stream: T) -> Self {
+        Passthrough {
+            inner: stream,
+            protocol: Protocol::new(),
+            compression: None,
+        }
+    }
+
+    /// Takes over the connection of a [`Client`](crate::Client) or
+    /// [`Session`](crate::Session), including any data that has been received but not read yet.
+    pub(crate) fn from_stream(stream: ImapStream<T>) -> Self {
+        let mut protocol = stream.protocol;
+        protocol.unrecognized = None;
+        protocol.flag_events = None;
+        protocol.unsolicited = None;
+        Passthrough {
+            inner: stream.inner,
+            protocol,
+            compression: stream.compression,
+        }
+    }
+
+    /// Writes `data` to the server as is. It may contain any number of commands, or parts of
+    /// them, including literals and the continuation lines of `AUTHENTICATE` and `IDLE`.
+    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.protocol.track_raw_command(data);
+        match self.compression {
+            Some(ref mut deflate) => {
+                let data = deflate.compress(data)?;
+                self.inner.write_all(&data).await?;
+            }
+            None => self.inner.write_all(data).await?,
+        }
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    /// Reads the next complete response, or returns `None` once the server has closed the
+    /// connection.
+    pub async fn read_response(&mut self) -> Option<Result<RawResponse>> {
+        loop {
+            match self.protocol.decode_raw() {
+                Ok(Some(response)) => return Some(Ok(response)),
+                Ok(None) => {}
+                Err(err) => return Some(Err(err.into())),
+            }
+
+            let buf = match self.protocol.read_buf() {
+                Ok(buf) => buf,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let read = match self.compression {
+                Some(ref mut deflate) => {
+                    let inner = &mut self.inner;
+                    poll_fn(|cx| deflate.poll_read(inner, cx, buf)).await
+                }
+                None => self.inner.read(buf).await,
+            };
+            match read {
+                Ok(0) if self.protocol.has_buffered_data() => {
+                    return Some(Err(Error::ConnectionLost))
+                }
+                Ok(0) => return None,
+                Ok(n) => self.protocol.advance(n),
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+
+    /// The tags of the commands that have been sent, but not completed yet, oldest first.
+    pub fn in_flight(&self) -> &[RequestId] {
+        self.protocol.in_flight()
+    }
+
+    /// The underlying stream. Data that has been received but not returned yet is lost, and so
+    /// is the state of `COMPRESS DEFLATE`, if the session had enabled it.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_stream::MockStream;
+
+    #[async_attributes::test]
+    async fn session_passthrough() {
+        let response = b"A0001 OK Logged in\r\n\
+            * 2 EXISTS\r\n\
+            * XSTATE 7\r\n\
+            x1 OK done\r\n"
+            .to_vec();
+        let client = crate::Client::new(MockStream::new(response));
+        let session = client.login("user", "pass").await.map_err(|e| e.0).unwrap();
+        let mut proxy = session.into_passthrough();
+
+        proxy.send(b"x1 NOOP\r\n").await.unwrap();
+        assert_eq!(proxy.in_flight(), &[RequestId("x1".into())]);
+        let mut responses = Vec::new();
+        while let Some(response) = proxy.read_response().await {
+            responses.push(response.unwrap());
+        }
+        assert_eq!(
+            responses,
+            vec![
+                RawResponse(b"* 2 EXISTS\r\n".to_vec()),
+                RawResponse(b"* XSTATE 7\r\n".to_vec()),
+                RawResponse(b"x1 OK done\r\n".to_vec()),
+            ]
+        );
+        assert!(responses[0].to_response_data().is_some());
+        assert!(responses[1].to_response_data().is_none());
+        assert_eq!(responses[2].tag(), Some("x1"));
+        assert!(proxy.in_flight().is_empty());
+        assert_eq!(
+            proxy.into_inner().written_buf,
+            b"A0001 LOGIN \"user\" \"pass\"\r\nx1 NOOP\r\n".to_vec()
+        );
+    }
+}
@@ -0,0 +1,1599 @@
+//! The IMAP protocol logic, independent of any I/O.
+//!
+//! [`Protocol`] turns commands into bytes to be written to the server, and bytes read from the
+//! server into responses. It keeps track of command tags, of where one response ends and the
+//! next one starts, and of the state that can be derived from the responses, such as the number
+//! of messages in the selected mailbox. It never reads or writes by itself, so it can be driven
+//! by any I/O backend, including blocking sockets, and by tests that feed it fixed data:
+//!
+//! ```
+//! use async_imap::protocol::{Event, Protocol};
+//!
+//! let mut protocol = Protocol::new();
+//! let tag = protocol.next_tag();
+//! let command = protocol.encode(Some(&tag), b"NOOP");
+//! assert_eq!(command, b"A0001 NOOP\r\n".to_vec());
+//!
+//! protocol.feed(b"* 3 EXISTS\r\nA0001 OK done\r\n").unwrap();
+//! assert!(matches!(protocol.next_event().unwrap(), Some(Event::Data(_))));
+//! assert!(matches!(protocol.next_event().unwrap(), Some(Event::Completed(t, _)) if t == tag));
+//! assert!(protocol.next_event().unwrap().is_none());
+//! ```
+//!
+//! The asynchronous [`Client`](crate::Client) and [`Session`](crate::Session) are built on top
+//! of it.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+#[cfg(feature = "runtime")]
+use std::time::Instant;
+
+use byte_pool::{Block, BytePool};
+use futures::channel::mpsc;
+use futures::task::{Context, Poll};
+
+use imap_proto::{MailboxDatum, RequestId, Response, Status};
+
+use crate::replay::UnsolicitedSender;
+use crate::types::{
+    parse_esearch, parse_extension_data, ConnectionStats, Esearch, EsearchResult, FlagEvent,
+    IdGenerator, LiteralPolicy, RawResponse, ResponseData, ServerId, UnsolicitedResponse, Vanished,
+};
+use crate::uid_map::UidMap;
+
+/// The default initial size of the read buffer.
+pub(crate) const INITIAL_CAPACITY: usize = 1024 * 4;
+/// The default maximum size of a single response.
+pub(crate) const MAX_CAPACITY: usize = 512 * 1024 * 1024; // 512 MiB
+/// The default number of responses returned in a row before yielding to other tasks.
+pub(crate) const MAX_CONSECUTIVE: usize = 32;
+/// How many completed commands are remembered to recognize duplicate tagged responses.
+const COMPLETED_TAGS: usize = 32;
+
+lazy_static::lazy_static! {
+    /// The global buffer pool we use for storing incoming data.
+    pub(crate) static ref POOL: Arc<BytePool> = Arc::new(BytePool::new());
+}
+
+/// A response from the server, classified by how it relates to the commands that were sent.
+#[derive(Debug)]
+pub enum Event {
+    /// The tagged response that completes the command with the given tag.
+    Completed(RequestId, ResponseData),
+    /// A continuation request, asking for the rest of the current command.
+    Continue(ResponseData),
+    /// An untagged response. It either belongs to the current command, or is an unsolicited
+    /// update about the state of the server, which only the caller can tell apart.
+    Data(ResponseData),
+}
+
+impl Event {
+    /// Classifies `response`.
+    pub fn new(response: ResponseData) -> Self {
+        let tag = match response.parsed() {
+            Response::Done { tag, .. } => Some(tag.clone()),
+            Response::Continue { .. } => return Event::Continue(response),
+            _ => None,
+        };
+        match tag {
+            Some(tag) => Event::Completed(tag, response),
+            None => Event::Data(response),
+        }
+    }
+
+    /// The response, regardless of its classification.
+    pub fn into_response(self) -> ResponseData {
+        match self {
+            Event::Completed(_, response) | Event::Continue(response) | Event::Data(response) => {
+                response
+            }
+        }
+    }
+}
+
+/// The state of an IMAP connection, without the connection itself.
+///
+/// Data read from the server is passed in either with [`feed`](Protocol::feed), or by reading
+/// directly into [`read_buf`](Protocol::read_buf) and calling [`advance`](Protocol::advance).
+/// Complete responses are then taken out with [`decode`](Protocol::decode) or
+/// [`next_event`](Protocol::next_event), until they return `None` and more data is needed.
+#[derive(Debug)]
+pub struct Protocol {
+    /// Generates the tags of new commands.
+    tags: IdGenerator,
+    /// The tags of the commands that have been sent, but not completed yet.
+    in_flight: Vec<RequestId>,
+    /// The tags of the most recently completed commands, and whether they completed with `OK`.
+    completed: VecDeque<(RequestId, bool)>,
+    /// Why the connection can not be used anymore, if the server sent contradicting tagged
+    /// responses for the same command.
+    poisoned: Option<String>,
+    /// Buffer for the already read, but not yet parsed data.
+    buffer: Block<'static>,
+    /// Position of valid read data into buffer.
+    current: Position,
+    /// How many bytes do we need to finishe the currrent element that is being decoded.
+    decode_needs: usize,
+    /// Offset into the valid data up to which the framing of the next response has already been
+    /// scanned. May point past the end of the valid data while a literal is being received.
+    frame_pos: usize,
+    /// Whether we should attempt to decode whatever is currently inside the buffer.
+    /// False indicates that we know for certain that the buffer is incomplete.
+    initial_decode: bool,
+    /// If set, untagged responses that can not be parsed are sent here instead of producing
+    /// an error.
+    pub(crate) unrecognized: Option<mpsc::UnboundedSender<RawResponse>>,
+    /// If set, the flags of every `FETCH` response that includes them are sent here, until a
+    /// command changes the selected mailbox.
+    pub(crate) flag_events: Option<mpsc::UnboundedSender<FlagEvent>>,
+    /// If set, [`UnsolicitedResponse::ExistsInconsistent`] is sent here when the number of
+    /// messages shrinks without being announced by `EXPUNGE` or `VANISHED`, and
+    /// [`UnsolicitedResponse::DuplicateCompletion`] when a command is completed twice. The
+    /// untagged responses that imap-proto can not parse, but this crate can, such as `VANISHED`
+    /// or `QUOTA`, are sent here as well.
+    pub(crate) unsolicited: Option<UnsolicitedSender>,
+    /// The responses for `unsolicited` that were found while decoding. They are passed on by
+    /// [`poll_unsolicited`](Protocol::poll_unsolicited) before decoding goes on, waiting for room
+    /// in the channel like the responses that the session passes on.
+    queued_unsolicited: VecDeque<UnsolicitedResponse>,
+    /// The number of messages in the selected mailbox, as far as it can be derived from the
+    /// responses seen so far.
+    exists: Option<u32>,
+    /// If set, the UIDs of the messages in the selected mailbox are tracked here, see
+    /// `Session::track_uids`.
+    pub(crate) uid_map: Option<UidMap>,
+    /// The most recent `ID` response, which imap-proto can not parse.
+    pub(crate) server_id: Option<ServerId>,
+    /// Whether the last command was an `ID` command, so that an `ID` response is its answer
+    /// rather than an unsolicited one.
+    id_requested: bool,
+    /// The `ESEARCH` responses to the last command, which imap-proto can not parse, as the tag
+    /// they refer to and their results.
+    pub(crate) esearch: Vec<(Option<String>, EsearchResult)>,
+    /// The `VANISHED (EARLIER)` responses to the last command, which imap-proto can not parse.
+    pub(crate) vanished: Vec<Vanished>,
+    /// If set, the responses that `parse_extension_data` understands, such as `QUOTA`, are
+    /// collected here instead of being sent to `unsolicited`, so that the command that asked for
+    /// them can return them.
+    pub(crate) extension_data: Option<Vec<UnsolicitedResponse>>,
+    /// The size of newly allocated read buffers.
+    initial_capacity: usize,
+    /// The maximum size of a single response, including its literals.
+    max_capacity: usize,
+    /// The limit on literal sizes for the responses to a single command.
+    pub(crate) literal_limit: Option<LiteralLimit>,
+    /// The oversized literal that is currently being received, if any.
+    skipping: Option<Skipping>,
+    /// Where the raw commands passed to [`track_raw_command`](Protocol::track_raw_command) are.
+    raw_command: RawCommand,
+    /// How many responses may be returned in a row before yielding to other tasks.
+    max_consecutive: Option<usize>,
+    /// How many responses have been returned since the last time the stream was not ready.
+    consecutive: usize,
+    /// The traffic on the connection so far.
+    stats: ConnectionStats,
+    /// When the last command was sent.
+    #[cfg(feature = "runtime")]
+    pub(crate) last_used: Instant,
+}
+
+/// Limits the size of literals in the responses to the command tagged `tag`, see
+/// `Session::fetch_limited`.
+#[derive(Debug)]
+pub(crate) struct LiteralLimit {
+    pub(crate) tag: RequestId,
+    pub(crate) max_size: usize,
+    pub(crate) policy: LiteralPolicy,
+}
+
+/// An oversized literal that is received without being buffered.
+#[derive(Debug)]
+struct Skipping {
+    /// Offset into the valid data where the rest of the literal starts.
+    at: usize,
+    /// The number of bytes of the literal that have not been received yet.
+    remaining: usize,
+}
+
+/// How far the raw commands passed to `Protocol::track_raw_command` have been scanned.
+#[derive(Default)]
+struct RawCommand {
+    /// The start of a line that has not been completed yet.
+    line: Vec<u8>,
+    /// The number of bytes of a literal that are still to come.
+    literal: usize,
+    /// Whether the next line continues a command after a literal.
+    continued: bool,
+    /// The tag of an `AUTHENTICATE` or `IDLE` command, whose continuation lines are not
+    /// commands of their own.
+    continuation: Option<RequestId>,
+}
+
+impl fmt::Debug for RawCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the line may be a `LOGIN` with a password
+        f.debug_struct("RawCommand")
+            .field("line", &format_args!("<{} bytes>", self.line.len()))
+            .field("literal", &self.literal)
+            .field("continued", &self.continued)
+            .field("continuation", &self.continuation)
+            .finish()
+    }
+}
+
+/// A semantically explicit slice of a buffer.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+struct Position {
+    start: usize,
+    end: usize,
+}
+
+impl Position {
+    const ZERO: Position = Position { start: 0, end: 0 };
+
+    const fn new(start: usize, end: usize) -> Position {
+        Position { start, end }
+    }
+}
+
+enum DecodeResult {
+    Some {
+        /// The parsed response.
+        response: ResponseData,
+        /// Remaining data.
+        buffer: Block<'static>,
+        /// How many bytes are actually valid data in `buffer`.
+        used: usize,
+    },
+    /// An unrecognized response was skipped.
+    Skipped {
+        /// Remaining data.
+        buffer: Block<'static>,
+        /// How many bytes are actually valid data in `buffer`.
+        used: usize,
+    },
+    None(Block<'static>),
+}
+
+impl fmt::Debug for DecodeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeResult::Some {
+                response,
+                buffer,
+                used,
+            } => f
+                .debug_struct("DecodeResult::Some")
+                .field("response", response)
+                .field("block", &buffer.len())
+                .field("used", used)
+                .finish(),
+            DecodeResult::Skipped { buffer, used } => f
+                .debug_struct("DecodeResult::Skipped")
+                .field("block", &buffer.len())
+                .field("used", used)
+                .finish(),
+            DecodeResult::None(block) => write!(f, "DecodeResult::None({})", block.len()),
+        }
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Protocol {
+    /// Creates the state of a new connection, before the greeting has been received.
+    pub fn new() -> Self {
+        Protocol {
+            tags: IdGenerator::new(),
+            in_flight: Vec::new(),
+            completed: VecDeque::with_capacity(COMPLETED_TAGS),
+            poisoned: None,
+            buffer: POOL.alloc(INITIAL_CAPACITY),
+            current: Position::ZERO,
+            decode_needs: 0,
+            frame_pos: 0,
+            initial_decode: false, // buffer is empty initially, nothing to decode
+            unrecognized: None,
+            flag_events: None,
+            unsolicited: None,
+            queued_unsolicited: VecDeque::new(),
+            exists: None,
+            uid_map: None,
+            server_id: None,
+            id_requested: false,
+            esearch: Vec::new(),
+            vanished: Vec::new(),
+            extension_data: None,
+            initial_capacity: INITIAL_CAPACITY,
+            max_capacity: MAX_CAPACITY,
+            literal_limit: None,
+            skipping: None,
+            raw_command: RawCommand::default(),
+            max_consecutive: Some(MAX_CONSECUTIVE),
+            consecutive: 0,
+            stats: ConnectionStats::default(),
+            #[cfg(feature = "runtime")]
+            last_used: Instant::now(),
+        }
+    }
+
+    /// Sets the initial size of the read buffer, and the maximum size of a single response.
+    pub fn set_buffer_sizes(&mut self, initial_capacity: usize, max_capacity: usize) {
+        self.initial_capacity = initial_capacity;
+        self.max_capacity = max_capacity;
+        if self.current == Position::ZERO {
+            self.buffer = POOL.alloc(initial_capacity);
+        }
+    }
+
+    /// The initial size of the read buffer, and the maximum size of a single response.
+    pub(crate) fn buffer_sizes(&self) -> (usize, usize) {
+        (self.initial_capacity, self.max_capacity)
+    }
+
+    /// See [`set_max_consecutive`](Protocol::set_max_consecutive).
+    pub(crate) fn max_consecutive(&self) -> Option<usize> {
+        self.max_consecutive
+    }
+
+    /// Limits how many buffered responses are returned in a row before the stream yields to other
+    /// tasks, so that a large fetch can not starve e.g. the consumer of unsolicited responses on
+    /// the same executor. `None` never yields while responses are buffered.
+    pub fn set_max_consecutive(&mut self, max: Option<usize>) {
+        self.max_consecutive = max;
+    }
+
+    /// Counts a response that is about to be returned, and returns `false` if the budget of
+    /// consecutive responses is used up, in which case the caller should yield first.
+    pub(crate) fn take_budget(&mut self) -> bool {
+        match self.max_consecutive {
+            Some(max) if self.consecutive >= max => {
+                self.consecutive = 0;
+                false
+            }
+            _ => {
+                self.consecutive += 1;
+                true
+            }
+        }
+    }
+
+    /// Resets the budget of consecutive responses, once the stream was not ready.
+    pub(crate) fn reset_budget(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// Returns the tag for the next command.
+    pub fn next_tag(&mut self) -> RequestId {
+        self.tags.next().unwrap() // safe: never returns None
+    }
+
+    /// The tags of the commands that have been encoded, but not completed yet, oldest first.
+    pub fn in_flight(&self) -> &[RequestId] {
+        &self.in_flight
+    }
+
+    /// Returns the bytes to send to the server for `command`, which is tagged with `tag`, or sent
+    /// as is if `tag` is `None`, e.g. for the continuation of a command.
+    pub fn encode(&mut self, tag: Option<&RequestId>, command: &[u8]) -> Vec<u8> {
+        log::trace!(
+            "encode: input: {:?} {:?}",
+            tag,
+            loggable(tag.is_some(), command)
+        );
+
+        if changes_mailbox(command) {
+            self.mailbox_changed();
+        }
+        if tag.is_some() {
+            self.id_requested = is_id_command(command);
+        }
+        self.esearch.clear();
+        self.vanished.clear();
+        #[cfg(feature = "runtime")]
+        {
+            self.last_used = Instant::now();
+        }
+
+        let mut out = Vec::with_capacity(command.len() + 8);
+        if let Some(tag) = tag {
+            out.extend_from_slice(tag.as_bytes());
+            out.push(b' ');
+            self.in_flight.push(tag.clone());
+            // a reused tag refers to the new command from now on
+            self.completed.retain(|(t, _)| t != tag);
+            self.count_command();
+        }
+        out.extend_from_slice(command);
+        out.extend_from_slice(b"\r\n");
+        self.stats.bytes_written += out.len() as u64;
+        out
+    }
+
+    /// Counts a command that was just added to the commands in flight.
+    fn count_command(&mut self) {
+        self.stats.commands += 1;
+        self.stats.max_in_flight =
+            std::cmp::max(self.stats.max_in_flight, self.in_flight.len() as u64);
+    }
+
+    /// Counts `n` bytes that were sent to the server without being encoded, e.g. a literal.
+    pub(crate) fn count_written(&mut self, n: usize) {
+        self.stats.bytes_written += n as u64;
+    }
+
+    /// The traffic on the connection so far.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    /// Keeps track of `data`, which is sent to the server as is instead of being encoded with
+    /// [`encode`](Protocol::encode), e.g. by a proxy that forwards the commands of its own
+    /// clients. The tags of the commands in `data` are added to
+    /// [`in_flight`](Protocol::in_flight), and literals are skipped. `data` does not need to end
+    /// at a line or command boundary.
+    pub fn track_raw_command(&mut self, mut data: &[u8]) {
+        self.stats.bytes_written += data.len() as u64;
+        while !data.is_empty() {
+            if self.raw_command.literal > 0 {
+                let n = std::cmp::min(self.raw_command.literal, data.len());
+                self.raw_command.literal -= n;
+                data = &data[n..];
+                continue;
+            }
+
+            match data.iter().position(|b| *b == b'\n') {
+                Some(i) => {
+                    let mut line = std::mem::replace(&mut self.raw_command.line, Vec::new());
+                    line.extend_from_slice(&data[..i]);
+                    data = &data[i + 1..];
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                    self.track_raw_command_line(&line);
+                }
+                None => {
+                    self.raw_command.line.extend_from_slice(data);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn track_raw_command_line(&mut self, line: &[u8]) {
+        let continued = self.raw_command.continued;
+        self.raw_command.continued = false;
+        if let Some(len) = literal_len(line) {
+            self.raw_command.literal = len;
+            self.raw_command.continued = true;
+        }
+        if continued || self.raw_command.continuation.is_some() {
+            return;
+        }
+
+        let space = match line.iter().position(|b| *b == b' ') {
+            Some(space) => space,
+            None => return,
+        };
+        let tag = RequestId(String::from_utf8_lossy(&line[..space]).into_owned());
+        let command = &line[space + 1..];
+        if changes_mailbox(command) {
+            self.mailbox_changed();
+        }
+        let name = command.split(|&b| b == b' ').next().unwrap_or_default();
+        if name.eq_ignore_ascii_case(b"AUTHENTICATE") || name.eq_ignore_ascii_case(b"IDLE") {
+            self.raw_command.continuation = Some(tag.clone());
+        }
+        self.in_flight.push(tag);
+        self.count_command();
+    }
+
+    /// Returns the raw bytes of the next complete response, or `None` if more data is needed.
+    ///
+    /// Unlike [`decode`](Protocol::decode), this does not parse the response, so responses that
+    /// imap-proto does not understand are returned as well. Only the tags of completed commands
+    /// are tracked. The two must not be mixed while a response is partially received.
+    pub fn decode_raw(&mut self) -> io::Result<Option<RawResponse>> {
+        let Position { start, end } = self.current;
+        if start == end {
+            return Ok(None);
+        }
+
+        match frame(
+            &self.buffer[start..end],
+            self.frame_pos,
+            self.max_capacity,
+            None,
+        )? {
+            Framing::Complete(len) => {
+                let raw = self.buffer[start..start + len].to_vec();
+                self.buffer.copy_within(start + len..end, 0);
+                self.current = Position::new(0, end - start - len);
+                self.frame_pos = 0;
+                self.decode_needs = 0;
+
+                if !raw.starts_with(b"* ") && !raw.starts_with(b"+") {
+                    let tag = raw.split(|&b| b == b' ').next().unwrap_or_default();
+                    let tag = RequestId(String::from_utf8_lossy(tag).into_owned());
+                    if self.raw_command.continuation.as_ref() == Some(&tag) {
+                        self.raw_command.continuation = None;
+                    }
+                    self.in_flight.retain(|t| *t != tag);
+                }
+                Ok(Some(RawResponse(raw)))
+            }
+            Framing::Incomplete { resume, needs } => {
+                self.frame_pos = resume;
+                self.decode_needs = needs;
+                Ok(None)
+            }
+            // literals are not limited here, so this is a bug in `frame`
+            Framing::Oversized { len, .. } => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("unexpected oversized literal of {} bytes", len),
+            )),
+        }
+    }
+
+    /// Returns the part of the read buffer that data from the server should be read into. Call
+    /// [`advance`](Protocol::advance) with the number of bytes read afterwards.
+    ///
+    /// Fails if the next response would exceed the maximum size.
+    pub fn read_buf(&mut self) -> io::Result<&mut [u8]> {
+        let needed = self.current.end + std::cmp::max(self.decode_needs, 1);
+        if needed > self.buffer.len() {
+            if needed > self.max_capacity {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "incoming data too large",
+                ));
+            }
+
+            // Grow geometrically, so that a large literal arriving in many small reads does not
+            // cause the buffer to be copied over and over again. The buffer does not grow to the
+            // announced size of a literal right away though: a short response that announces a
+            // huge literal must not allocate memory for data that may never arrive.
+            if self.current.end == self.buffer.len() {
+                let new_len = std::cmp::min(
+                    std::cmp::max(self.buffer.len() * 2, self.current.end + 1),
+                    self.max_capacity,
+                );
+                self.buffer.resize(new_len, 0);
+            }
+        }
+
+        Ok(&mut self.buffer[self.current.end..])
+    }
+
+    /// Marks `n` bytes at the start of [`read_buf`](Protocol::read_buf) as received.
+    pub fn advance(&mut self, n: usize) {
+        self.current.end += n;
+        self.stats.bytes_read += n as u64;
+        if n > 0 {
+            self.initial_decode = true;
+        }
+    }
+
+    /// Passes data received from the server in.
+    pub fn feed(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let buf = self.read_buf()?;
+            let n = std::cmp::min(buf.len(), data.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            self.advance(n);
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Returns the next complete response, or `None` if more data is needed.
+    ///
+    /// A tagged response for a command that has already been completed is not returned, so that
+    /// it can not be mistaken for the completion of a later command. If it contradicts the first
+    /// completion, the result of that command can not be trusted, and this and all later calls
+    /// fail.
+    pub fn decode(&mut self) -> io::Result<Option<ResponseData>> {
+        if let Some(ref reason) = self.poisoned {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, reason.clone()));
+        }
+        while self.initial_decode && self.has_buffered_data() {
+            let mut n = std::mem::replace(&mut self.current, Position::ZERO);
+            let buffer = std::mem::replace(&mut self.buffer, POOL.alloc(self.initial_capacity));
+
+            match self.try_decode(buffer, &mut n)? {
+                DecodeResult::Some {
+                    response,
+                    buffer,
+                    used,
+                } => {
+                    // the buffer might contain more responses, so initial_decode stays true
+                    self.buffer = buffer;
+                    self.current = Position::new(0, used);
+                    if self.is_duplicate(&response)? {
+                        if !self.queued_unsolicited.is_empty() {
+                            // the report is passed on before the responses that follow
+                            return Ok(None);
+                        }
+                        continue;
+                    }
+                    self.track_exists(&response);
+                    if let Some(ref mut uid_map) = self.uid_map {
+                        uid_map.track(&response);
+                    }
+                    self.track_flags(&response);
+                    self.track_completion(&response);
+                    return Ok(Some(response));
+                }
+                DecodeResult::Skipped { buffer, used } => {
+                    // more responses might be left in the buffer
+                    self.buffer = buffer;
+                    self.current = Position::new(0, used);
+                    if !self.queued_unsolicited.is_empty() {
+                        // the responses found so far are passed on before the ones that follow
+                        return Ok(None);
+                    }
+                }
+                DecodeResult::None(buffer) => {
+                    // nothing to decode until more data is received
+                    self.initial_decode = false;
+                    self.buffer = buffer;
+                    self.current = n;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Passes on the responses that were found while decoding to `unsolicited`, oldest first.
+    /// Returns `Poll::Pending` while the channel is full, and wakes the task once there is room.
+    pub(crate) fn poll_unsolicited(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match self.unsolicited {
+            Some(ref mut unsolicited) => {
+                unsolicited.poll_send_queued(cx, &mut self.queued_unsolicited)
+            }
+            None => {
+                self.queued_unsolicited.clear();
+                Poll::Ready(())
+            }
+        }
+    }
+
+    /// Whether responses that were found while decoding still have to be passed on, see
+    /// [`poll_unsolicited`](Protocol::poll_unsolicited).
+    pub(crate) fn has_queued_unsolicited(&self) -> bool {
+        !self.queued_unsolicited.is_empty()
+    }
+
+    /// Queues `response` for `unsolicited`, if it is set.
+    fn queue_unsolicited(&mut self, response: UnsolicitedResponse) {
+        if self.unsolicited.is_some() {
+            self.queued_unsolicited.push_back(response);
+        }
+    }
+
+    /// Returns the next complete response as an [`Event`], or `None` if more data is needed.
+    pub fn next_event(&mut self) -> io::Result<Option<Event>> {
+        Ok(self.decode()?.map(Event::new))
+    }
+
+    /// Keeps track of the number of messages in the selected mailbox, and reports an `EXISTS`
+    /// response that makes it shrink.
+    fn track_exists(&mut self, response: &ResponseData) {
+        match response.parsed() {
+            Response::MailboxData(MailboxDatum::Exists(new)) => {
+                let new = *new;
+                match self.exists {
+                    Some(old) if new < old => {
+                        log::warn!("EXISTS decreased from {} to {} without EXPUNGE", old, new);
+                        self.queue_unsolicited(UnsolicitedResponse::ExistsInconsistent {
+                            old,
+                            new,
+                        });
+                    }
+                    _ => {}
+                }
+                self.exists = Some(new);
+            }
+            Response::Expunge(_) => {
+                self.exists = self.exists.map(|n| n.saturating_sub(1));
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets the state of the selected mailbox, and ends the stream of flag events, which only
+    /// applies to that mailbox.
+    fn mailbox_changed(&mut self) {
+        self.exists = None;
+        if let Some(ref mut uid_map) = self.uid_map {
+            *uid_map = UidMap::default();
+        }
+        self.flag_events = None;
+    }
+
+    /// Reports the flags of a `FETCH` response on the flag event channel, if there is one.
+    fn track_flags(&mut self, response: &ResponseData) {
+        if let Some(ref tx) = self.flag_events {
+            if let Some(event) = FlagEvent::from_response(response) {
+                if tx.unbounded_send(event).is_err() {
+                    // the receiver is gone
+                    self.flag_events = None;
+                }
+            }
+        }
+    }
+
+    /// Lifts the literal limit and forgets the tag once the command it applies to has completed.
+    fn track_completion(&mut self, response: &ResponseData) {
+        let done = match (&self.literal_limit, response.request_id()) {
+            (Some(limit), Some(tag)) => *tag == limit.tag,
+            _ => false,
+        };
+        if done {
+            self.literal_limit = None;
+        }
+        if let Response::Done { tag, status, .. } = response.parsed() {
+            let len = self.in_flight.len();
+            self.in_flight.retain(|t| t != tag);
+            if self.in_flight.len() < len {
+                if self.completed.len() == COMPLETED_TAGS {
+                    self.completed.pop_front();
+                }
+                self.completed
+                    .push_back((tag.clone(), matches!(status, Status::Ok)));
+            }
+        }
+    }
+
+    /// Checks whether `response` completes a command that has already been completed. The
+    /// duplicate is reported on the unsolicited channel, and poisons the connection if its status
+    /// differs from the first completion.
+    fn is_duplicate(&mut self, response: &ResponseData) -> io::Result<bool> {
+        let (tag, ok) = match response.parsed() {
+            Response::Done { tag, status, .. } => (tag, matches!(status, Status::Ok)),
+            _ => return Ok(false),
+        };
+        if self.in_flight.contains(tag) {
+            return Ok(false);
+        }
+        let first = match self.completed.iter().find(|(t, _)| t == tag) {
+            Some((_, first)) => *first,
+            None => return Ok(false),
+        };
+
+        let conflicting = first != ok;
+        self.queue_unsolicited(UnsolicitedResponse::DuplicateCompletion {
+            tag: tag.clone(),
+            conflicting,
+        });
+        if !conflicting {
+            log::warn!("ignoring duplicate completion of {:?}", tag.0);
+            return Ok(true);
+        }
+
+        let reason = format!(
+            "the server completed {:?} twice with different results, giving up on the connection",
+            tag.0
+        );
+        log::error!("{}", reason);
+        self.poisoned = Some(reason.clone());
+        Err(io::Error::new(io::ErrorKind::InvalidData, reason))
+    }
+
+    /// Accounts for a `VANISHED` response, which imap-proto does not parse.
+    fn track_vanished(&mut self, raw: &[u8]) {
+        if let (Some(exists), Some(vanished)) = (self.exists, vanished_count(raw)) {
+            self.exists = Some(exists.saturating_sub(vanished));
+        }
+        if let Some(ref mut uid_map) = self.uid_map {
+            if let Some(vanished) = Vanished::parse(raw) {
+                uid_map.vanished(&vanished);
+            }
+        }
+    }
+
+    /// Whether data has been received that has not been returned as a response yet.
+    pub fn has_buffered_data(&self) -> bool {
+        self.current.end > self.current.start
+    }
+
+    /// Removes the data that has been received, but not returned as a response yet, e.g.
+    /// because it is compressed after `COMPRESS DEFLATE`.
+    pub(crate) fn take_buffered(&mut self) -> Vec<u8> {
+        let data = self.buffer[self.current.start..self.current.end].to_vec();
+        self.current = Position::ZERO;
+        self.decode_needs = 0;
+        self.frame_pos = 0;
+        self.initial_decode = false;
+        data
+    }
+}
+
+/// Result of scanning buffered data for the end of the next response.
+#[derive(Debug, PartialEq, Eq)]
+enum Framing {
+    /// A complete response of the given length is available.
+    Complete(usize),
+    /// A literal that is larger than the limit is announced.
+    Oversized {
+        /// Where the announcement (`{<size>}`, or `~{<size>}` for a literal8) starts.
+        brace: usize,
+        /// Where the contents of the literal start.
+        data: usize,
+        /// The announced size.
+        len: usize,
+    },
+    /// The response is not complete yet.
+    Incomplete {
+        /// Where scanning should resume once more data is available.
+        resume: usize,
+        /// The minimum number of additional bytes required.
+        needs: usize,
+    },
+}
+
+/// Finds the end of the next response in `buf`, starting the scan at `pos`.
+///
+/// A response ends at the first CRLF that is not part of a literal. Literals are announced by a
+/// line ending in `{<size>}`, so their contents can be skipped without inspecting them. This
+/// allows large literals to be received across many reads without handing the incomplete
+/// response to the parser again after each read. Literals larger than `literal_limit` are
+/// reported as soon as they are announced.
+fn frame(
+    buf: &[u8],
+    mut pos: usize,
+    max_len: usize,
+    literal_limit: Option<usize>,
+) -> io::Result<Framing> {
+    loop {
+        if pos >= buf.len() {
+            return Ok(Framing::Incomplete {
+                resume: pos,
+                needs: pos - buf.len() + 2,
+            });
+        }
+
+        let line_end = match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+            Some(i) => pos + i,
+            None => {
+                // keep a trailing `\r` so that a CRLF split across reads is found
+                return Ok(Framing::Incomplete {
+                    resume: std::cmp::max(pos, buf.len() - 1),
+                    needs: 1,
+                });
+            }
+        };
+
+        match literal_len(&buf[pos..line_end]) {
+            Some(len) if literal_limit.map_or(false, |limit| len > limit) => {
+                let brace = buf[pos..line_end].iter().rposition(|b| *b == b'{');
+                let mut brace = pos
+                    + brace.ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "literal announcement without brace",
+                        )
+                    })?;
+                // a literal8 (RFC 3516) is announced as `~{<size>}`
+                if brace > pos && buf[brace - 1] == b'~' {
+                    brace -= 1;
+                }
+                return Ok(Framing::Oversized {
+                    brace,
+                    data: line_end + 2,
+                    len,
+                });
+            }
+            Some(len) => {
+                pos = (line_end + 2)
+                    .checked_add(len)
+                    .filter(|end| *end <= max_len)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::Other, "incoming data too large")
+                    })?;
+            }
+            None => return Ok(Framing::Complete(line_end + 2)),
+        }
+    }
+}
+
+/// Returns the announced size if `line` ends with a literal announcement such as `{123}`.
+fn literal_len(line: &[u8]) -> Option<usize> {
+    if !line.ends_with(b"}") {
+        return None;
+    }
+    let open = line.iter().rposition(|b| *b == b'{')?;
+    let digits = &line[open + 1..line.len() - 1];
+    let digits = if digits.ends_with(b"+") {
+        &digits[..digits.len() - 1]
+    } else {
+        digits
+    };
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// The part of `command` that can be logged. The arguments of `LOGIN` and the initial response
+/// of `AUTHENTICATE` are left out, and so are continuation lines other than `DONE`, which may be
+/// responses to SASL challenges.
+fn loggable(tagged: bool, command: &[u8]) -> String {
+    let text = String::from_utf8_lossy(command);
+    if !tagged {
+        return if text.eq_ignore_ascii_case("DONE") {
+            text.into_owned()
+        } else {
+            format!("<{} bytes>", command.len())
+        };
+    }
+
+    let words: Vec<&str> = text.splitn(3, ' ').collect();
+    match words.as_slice() {
+        [verb, _, ..] if verb.eq_ignore_ascii_case("LOGIN") => format!("{} <redacted>", verb),
+        [verb, mechanism, _] if verb.eq_ignore_ascii_case("AUTHENTICATE") => {
+            format!("{} {} <redacted>", verb, mechanism)
+        }
+        _ => text.into_owned(),
+    }
+}
+
+/// Whether `command` selects or deselects a mailbox, which invalidates the message count.
+fn changes_mailbox(command: &[u8]) -> bool {
+    let name = command.split(|&b| b == b' ').next().unwrap_or_default();
+    [&b"SELECT"[..], b"EXAMINE", b"UNSELECT", b"CLOSE"]
+        .iter()
+        .any(|c| name.eq_ignore_ascii_case(c))
+}
+
+/// Whether `command` is an `ID` command.
+fn is_id_command(command: &[u8]) -> bool {
+    let name = command.split(|&b| b == b' ').next().unwrap_or_default();
+    name.eq_ignore_ascii_case(b"ID")
+}
+
+/// The number of messages removed by a `VANISHED` response, or `None` if `raw` is some other
+/// response. `VANISHED (EARLIER)` refers to messages that are already gone, and counts as zero.
+fn vanished_count(raw: &[u8]) -> Option<u32> {
+    let vanished = Vanished::parse(raw)?;
+    if vanished.earlier {
+        return Some(0);
+    }
+    Some(vanished.count())
+}
+
+impl Protocol {
+    fn parse(
+        &mut self,
+        buf: Block<'static>,
+        start: usize,
+        end: usize,
+        len: usize,
+    ) -> io::Result<DecodeResult> {
+        log::trace!("parse: input: {:?}", std::str::from_utf8(&buf[start..end]));
+
+        let mut rest = None;
+        let mut used = 0;
+        // Only the framed response is handed to the parser: it is complete, so a parser that asks
+        // for more data can not be satisfied by waiting, which would hang the connection.
+        let res = ResponseData::try_new(buf, |buf| {
+            match imap_proto::parse_response(&buf[start..start + len]) {
+                Ok((remaining, response)) => {
+                    // TODO: figure out if we can shrink to the minimum required size.
+                    self.decode_needs = 0;
+
+                    let following = &buf[start + len..end];
+                    used = remaining.len() + following.len();
+                    let mut buf = POOL.alloc(std::cmp::max(used, self.initial_capacity));
+                    buf[..remaining.len()].copy_from_slice(remaining);
+                    buf[remaining.len()..used].copy_from_slice(following);
+
+                    rest = Some(buf);
+
+                    Ok(response)
+                }
+                Err(nom::Err::Incomplete(needed)) => {
+                    self.decode_needs = 0;
+                    Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!(
+                            "incomplete response ({:?}) during parsing of {:?}",
+                            needed,
+                            &buf[start..start + len]
+                        ),
+                    ))
+                }
+                Err(err) => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("{:?} during parsing of {:?}", err, &buf[start..start + len]),
+                )),
+            }
+        });
+
+        match res {
+            Ok(response) => Ok(DecodeResult::Some {
+                response,
+                buffer: rest.unwrap(),
+                used,
+            }),
+            Err(rental::RentalError(err, buf)) => {
+                let raw = &buf[start..start + len];
+                self.track_vanished(raw);
+                if let Some(server_id) = ServerId::parse(raw) {
+                    if !self.id_requested {
+                        self.queue_unsolicited(UnsolicitedResponse::Id(server_id.clone()));
+                    }
+                    self.server_id = Some(server_id);
+                    let (buffer, used) = self.skip(&buf[start..end], len);
+                    return Ok(DecodeResult::Skipped { buffer, used });
+                }
+                if let Some(esearch) = parse_esearch(raw) {
+                    match esearch {
+                        Esearch::Result(tag, result) => self.esearch.push((tag, result)),
+                        Esearch::Update(update) => {
+                            self.queue_unsolicited(UnsolicitedResponse::ContextUpdate(update));
+                        }
+                    }
+                    let (buffer, used) = self.skip(&buf[start..end], len);
+                    return Ok(DecodeResult::Skipped { buffer, used });
+                }
+                if let Some(vanished) = Vanished::parse(raw) {
+                    if vanished.earlier {
+                        self.vanished.push(vanished);
+                    } else {
+                        self.queue_unsolicited(UnsolicitedResponse::Vanished(vanished));
+                    }
+                    let (buffer, used) = self.skip(&buf[start..end], len);
+                    return Ok(DecodeResult::Skipped { buffer, used });
+                }
+                if let Some(response) = parse_extension_data(raw) {
+                    if let Some(ref mut captured) = self.extension_data {
+                        captured.push(response);
+                    } else {
+                        self.queue_unsolicited(response);
+                    }
+                    let (buffer, used) = self.skip(&buf[start..end], len);
+                    return Ok(DecodeResult::Skipped { buffer, used });
+                }
+                match self.skip_unrecognized(&buf[start..end], len) {
+                    Some((buffer, used)) => Ok(DecodeResult::Skipped { buffer, used }),
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
+    /// Forwards an unparsable untagged response of `len` bytes at the start of `data` to the
+    /// channel registered in `unrecognized`, and returns the data following it.
+    fn skip_unrecognized(&mut self, data: &[u8], len: usize) -> Option<(Block<'static>, usize)> {
+        let raw = &data[..len];
+        if !raw.starts_with(b"* ") {
+            return None;
+        }
+        self.unrecognized
+            .as_ref()?
+            .unbounded_send(RawResponse(raw.to_vec()))
+            .ok()?;
+        log::debug!(
+            "decode: forwarded unrecognized response {:?}",
+            String::from_utf8_lossy(raw)
+        );
+
+        Some(self.skip(data, len))
+    }
+
+    /// Drops the response of `len` bytes at the start of `data`, and returns the data following
+    /// it.
+    fn skip(&mut self, data: &[u8], len: usize) -> (Block<'static>, usize) {
+        self.decode_needs = 0;
+        let rest = &data[len..];
+        let mut buffer = POOL.alloc(std::cmp::max(rest.len(), self.initial_capacity));
+        buffer[..rest.len()].copy_from_slice(rest);
+
+        (buffer, rest.len())
+    }
+
+    /// Attempts to decode a response, but only once the framing indicates that a complete
+    /// response is available.
+    fn try_decode(
+        &mut self,
+        mut buf: Block<'static>,
+        n: &mut Position,
+    ) -> io::Result<DecodeResult> {
+        loop {
+            if !self.skip_literal(&mut buf, n) {
+                self.decode_needs = 0;
+                return Ok(DecodeResult::None(buf));
+            }
+
+            let literal_limit = self.literal_limit.as_ref().map(|l| l.max_size);
+            match frame(
+                &buf[n.start..n.end],
+                self.frame_pos,
+                self.max_capacity,
+                literal_limit,
+            )? {
+                Framing::Incomplete { resume, needs } => {
+                    self.frame_pos = resume;
+                    self.decode_needs = needs;
+                    return Ok(DecodeResult::None(buf));
+                }
+                Framing::Complete(len) => {
+                    self.frame_pos = 0;
+                    return self.parse(buf, n.start, n.end, len);
+                }
+                Framing::Oversized { brace, data, len } => {
+                    self.begin_skip(&mut buf, n, brace, data, len)?;
+                }
+            }
+        }
+    }
+
+    /// Starts receiving the oversized literal of `len` bytes announced at offset `brace`, whose
+    /// data starts at offset `data`. The announcement is replaced with `NIL`, so that the
+    /// response can still be parsed once the literal has been received.
+    fn begin_skip(
+        &mut self,
+        buf: &mut Block<'static>,
+        n: &mut Position,
+        brace: usize,
+        data: usize,
+        len: usize,
+    ) -> io::Result<()> {
+        let limit = self.literal_limit.as_mut().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "oversized literal detected without a limit",
+            )
+        })?;
+        match limit.policy {
+            LiteralPolicy::Abort => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "literal of {} bytes exceeds the limit of {} bytes",
+                        len, limit.max_size
+                    ),
+                ));
+            }
+            LiteralPolicy::Sink(ref mut sink) => sink.begin(&buf[n.start..n.start + brace], len),
+            LiteralPolicy::Skip => {}
+        }
+        log::debug!("decode: skipping literal of {} bytes", len);
+
+        let (brace, data) = (n.start + brace, n.start + data);
+        buf[brace..brace + 3].copy_from_slice(b"NIL");
+        buf.copy_within(data..n.end, brace + 3);
+        n.end -= data - (brace + 3);
+
+        self.skipping = Some(Skipping {
+            at: brace + 3 - n.start,
+            remaining: len,
+        });
+        self.frame_pos = brace + 3 - n.start;
+        Ok(())
+    }
+
+    /// Removes the part of the oversized literal that is being received from the valid data,
+    /// passing it to the sink if there is one. Returns whether the literal is complete.
+    fn skip_literal(&mut self, buf: &mut Block<'static>, n: &mut Position) -> bool {
+        let skipping = match self.skipping {
+            Some(ref mut skipping) => skipping,
+            None => return true,
+        };
+        let mut sink = match self.literal_limit {
+            Some(LiteralLimit {
+                policy: LiteralPolicy::Sink(ref mut sink),
+                ..
+            }) => Some(sink),
+            _ => None,
+        };
+
+        let at = n.start + skipping.at;
+        let take = std::cmp::min(n.end - at, skipping.remaining);
+        if let Some(ref mut sink) = sink {
+            sink.data(&buf[at..at + take]);
+        }
+        buf.copy_within(at + take..n.end, at);
+        n.end -= take;
+        skipping.remaining -= take;
+
+        if skipping.remaining > 0 {
+            return false;
+        }
+        self.skipping = None;
+        if let Some(sink) = sink {
+            sink.end();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_lines() {
+        assert_eq!(
+            frame(b"* 1 EXISTS\r\n* 2 EXISTS\r\n", 0, MAX_CAPACITY, None).unwrap(),
+            Framing::Complete(12)
+        );
+        assert_eq!(
+            frame(b"* 1 EXISTS\r", 0, MAX_CAPACITY, None).unwrap(),
+            Framing::Incomplete {
+                resume: 10,
+                needs: 1
+            }
+        );
+        assert_eq!(
+            frame(b"* 1 EXISTS\r\n", 10, MAX_CAPACITY, None).unwrap(),
+            Framing::Complete(12)
+        );
+    }
+
+    #[test]
+    fn frame_literals() {
+        let data = b"* 1 FETCH (BODY[] {3}\r\nfoo)\r\n";
+        assert_eq!(
+            frame(data, 0, MAX_CAPACITY, None).unwrap(),
+            Framing::Complete(data.len())
+        );
+
+        // the literal has not been received completely, scanning resumes after it
+        assert_eq!(
+            frame(b"* 1 FETCH (BODY[] {10}\r\nfoo", 0, MAX_CAPACITY, None).unwrap(),
+            Framing::Incomplete {
+                resume: 34,
+                needs: 9
+            }
+        );
+
+        // curly braces in the middle of a line do not announce a literal
+        let data = b"* OK {3} braces\r\n";
+        assert_eq!(
+            frame(data, 0, MAX_CAPACITY, None).unwrap(),
+            Framing::Complete(data.len())
+        );
+    }
+
+    #[test]
+    fn frame_oversized_literals() {
+        let data = b"* 1 FETCH (BODY[] {12}\r\nhello\r\nworld)\r\n";
+        assert_eq!(
+            frame(data, 0, MAX_CAPACITY, Some(4)).unwrap(),
+            Framing::Oversized {
+                brace: 18,
+                data: 24,
+                len: 12
+            }
+        );
+
+        // the `~` of a literal8 is part of the announcement
+        let data = b"* 1 FETCH (BINARY[] ~{12}\r\nhello\r\nworld)\r\n";
+        assert_eq!(
+            frame(data, 0, MAX_CAPACITY, Some(4)).unwrap(),
+            Framing::Oversized {
+                brace: 20,
+                data: 27,
+                len: 12
+            }
+        );
+    }
+
+    #[test]
+    fn frame_literal_too_large() {
+        assert!(frame(
+            b"* 1 FETCH (BODY[] {99999999999999}\r\n",
+            0,
+            MAX_CAPACITY,
+            None
+        )
+        .is_err());
+
+        // announcing a literal does not allocate memory for it before it arrives
+        let mut protocol = Protocol::new();
+        protocol.feed(b"* 1 FETCH (BODY[] {500000000}\r\n").unwrap();
+        assert!(protocol.decode().unwrap().is_none());
+        assert!(protocol.read_buf().unwrap().len() < INITIAL_CAPACITY);
+    }
+
+    #[test]
+    fn loggable_commands() {
+        assert_eq!(
+            loggable(true, b"LOGIN \"user\" \"hunter2\""),
+            "LOGIN <redacted>"
+        );
+        assert_eq!(
+            loggable(true, b"AUTHENTICATE PLAIN AHVzZXIAaHVudGVyMg=="),
+            "AUTHENTICATE PLAIN <redacted>"
+        );
+        assert_eq!(loggable(true, b"AUTHENTICATE PLAIN"), "AUTHENTICATE PLAIN");
+        assert_eq!(loggable(false, b"AHVzZXIAaHVudGVyMg=="), "<20 bytes>");
+        assert_eq!(loggable(false, b"DONE"), "DONE");
+        assert_eq!(loggable(true, b"SELECT INBOX"), "SELECT INBOX");
+
+        let mut protocol = Protocol::new();
+        protocol.track_raw_command(b"a1 LOGIN user hunt");
+        assert!(!format!("{:?}", protocol).contains("hunt"));
+    }
+
+    #[test]
+    fn vanished_counts() {
+        assert_eq!(vanished_count(b"* VANISHED 1:3,5\r\n"), Some(4));
+        assert_eq!(vanished_count(b"* VANISHED (EARLIER) 1:3\r\n"), Some(0));
+        assert_eq!(vanished_count(b"* XSTATE 1:3\r\n"), None);
+        assert_eq!(
+            vanished_count(b"* VANISHED 0:4294967295\r\n"),
+            Some(u32::max_value())
+        );
+    }
+
+    #[test]
+    fn feed_byte_by_byte() {
+        let mut protocol = Protocol::new();
+        let tag = protocol.next_tag();
+        assert_eq!(
+            protocol.encode(Some(&tag), b"FETCH 1 BODY[]"),
+            b"A0001 FETCH 1 BODY[]\r\n".to_vec()
+        );
+        assert_eq!(protocol.in_flight(), &[tag.clone()]);
+
+        let (tx, _unrecognized) = mpsc::unbounded();
+        protocol.unrecognized = Some(tx);
+        let data = b"* XSTATE foo\r\n* 1 FETCH (BODY[] {3}\r\nfoo)\r\n+ go on\r\nA0001 OK done\r\n";
+        let mut events = Vec::new();
+        for b in data.iter() {
+            protocol.feed(&[*b]).unwrap();
+            while let Some(event) = protocol.next_event().unwrap() {
+                events.push(event);
+            }
+        }
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], Event::Data(_)));
+        assert!(matches!(events[1], Event::Continue(_)));
+        assert!(matches!(events[2], Event::Completed(ref t, _) if *t == tag));
+        assert!(protocol.in_flight().is_empty());
+        assert!(!protocol.has_buffered_data());
+    }
+
+    /// Responses that exercise the framing: literals, including empty ones and ones that contain
+    /// CRLF or look like literal announcements, and continuation requests.
+    const CHUNKING_CORPUS: &[u8] = b"* 1 FETCH (UID 7 BODY[] {5}\r\nhello FLAGS (\\Seen))\r\n\
+        * 2 FETCH (BODY[HEADER] {0}\r\n BODY[TEXT] {7}\r\n\r\n{1}\r\n)\r\n\
+        + go on\r\n\
+        * 3 EXISTS\r\n\
+        * 4 FETCH (BODY[] {2}\r\n\r\n)\r\n\
+        A0001 OK done\r\n";
+
+    /// A deterministic pseudo-random sequence of chunk sizes between 1 and `max`.
+    fn chunk_sizes(mut seed: u32, max: u32) -> impl Iterator<Item = usize> {
+        std::iter::repeat_with(move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((seed >> 16) % max + 1) as usize
+        })
+    }
+
+    /// Feeds `data` in chunks of the given sizes, and decodes everything in between.
+    fn decode_chunked<I: Iterator<Item = usize>>(data: &[u8], sizes: I) -> Vec<String> {
+        let mut protocol = Protocol::new();
+        let mut responses = Vec::new();
+        let mut rest = data;
+        for size in sizes {
+            if rest.is_empty() {
+                break;
+            }
+            let n = std::cmp::min(size, rest.len());
+            protocol.feed(&rest[..n]).unwrap();
+            rest = &rest[n..];
+            while let Some(response) = protocol.decode().unwrap() {
+                responses.push(format!("{:?}", response.parsed()));
+            }
+        }
+        assert!(!protocol.has_buffered_data(), "data left over");
+        responses
+    }
+
+    /// Like `decode_chunked`, but frames the responses without parsing them.
+    fn decode_raw_chunked<I: Iterator<Item = usize>>(data: &[u8], sizes: I) -> Vec<Vec<u8>> {
+        let mut protocol = Protocol::new();
+        let mut responses = Vec::new();
+        let mut rest = data;
+        for size in sizes {
+            if rest.is_empty() {
+                break;
+            }
+            let n = std::cmp::min(size, rest.len());
+            protocol.feed(&rest[..n]).unwrap();
+            rest = &rest[n..];
+            while let Some(response) = protocol.decode_raw().unwrap() {
+                responses.push(response.0);
+            }
+        }
+        assert!(!protocol.has_buffered_data(), "data left over");
+        responses
+    }
+
+    #[test]
+    fn chunking() {
+        let expected = decode_chunked(CHUNKING_CORPUS, std::iter::once(CHUNKING_CORPUS.len()));
+        assert_eq!(expected.len(), 6);
+        let expected_raw =
+            decode_raw_chunked(CHUNKING_CORPUS, std::iter::once(CHUNKING_CORPUS.len()));
+        assert_eq!(expected_raw.concat(), CHUNKING_CORPUS.to_vec());
+
+        // every fixed chunk size up to the longest response, including one byte at a time
+        for size in 1..=48 {
+            let sizes = std::iter::repeat(size);
+            assert_eq!(decode_chunked(CHUNKING_CORPUS, sizes.clone()), expected);
+            assert_eq!(decode_raw_chunked(CHUNKING_CORPUS, sizes), expected_raw);
+        }
+        // and irregular chunks, which split literal announcements and CRLFs in all places
+        for seed in 0..200 {
+            assert_eq!(
+                decode_chunked(CHUNKING_CORPUS, chunk_sizes(seed, 9)),
+                expected,
+                "seed {}",
+                seed
+            );
+            assert_eq!(
+                decode_raw_chunked(CHUNKING_CORPUS, chunk_sizes(seed, 9)),
+                expected_raw,
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn chunking_literal_limit() {
+        // oversized literals are skipped the same way, however the data arrives
+        let data = b"* 1 FETCH (UID 3 BODY[] {12}\r\nhello\r\nworld)\r\nA0001 OK done\r\n";
+        for seed in 0..100 {
+            let mut protocol = Protocol::new();
+            protocol.literal_limit = Some(LiteralLimit {
+                tag: RequestId("A0001".into()),
+                max_size: 4,
+                policy: LiteralPolicy::Skip,
+            });
+            let mut responses = Vec::new();
+            let mut rest = &data[..];
+            for size in chunk_sizes(seed, 7) {
+                if rest.is_empty() {
+                    break;
+                }
+                let n = std::cmp::min(size, rest.len());
+                protocol.feed(&rest[..n]).unwrap();
+                rest = &rest[n..];
+                while let Some(response) = protocol.decode().unwrap() {
+                    responses.push(response);
+                }
+            }
+            assert_eq!(responses.len(), 2, "seed {}", seed);
+            match responses[0].parsed() {
+                Response::Fetch(1, attrs) => assert_eq!(attrs.len(), 2, "seed {}", seed),
+                other => panic!("unexpected response {:?}", other),
+            }
+            assert!(protocol.literal_limit.is_none());
+        }
+    }
+
+    #[test]
+    fn literal8_limit() {
+        // an oversized literal8 is replaced with `NIL` like any other literal
+        let mut protocol = Protocol::new();
+        protocol.literal_limit = Some(LiteralLimit {
+            tag: RequestId("A0001".into()),
+            max_size: 4,
+            policy: LiteralPolicy::Skip,
+        });
+        protocol
+            .feed(b"* 1 FETCH (UID 3 BODY[] ~{12}\r\nhello\r\nworld)\r\n")
+            .unwrap();
+        let response = protocol.decode().unwrap().unwrap();
+        match response.parsed() {
+            Response::Fetch(1, attrs) => assert_eq!(attrs.len(), 2),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn framed_but_unparsable() {
+        // a complete response that the parser rejects fails instead of waiting for more data
+        let mut protocol = Protocol::new();
+        protocol.feed(b"* 1 FETCH (FLAGS (\\Seen)\r\n").unwrap();
+        assert!(protocol.decode().is_err());
+    }
+
+    #[test]
+    fn duplicate_completion() {
+        let mut protocol = Protocol::new();
+        let (tx, mut rx) = mpsc::channel(10);
+        protocol.unsolicited = Some(tx.into());
+        for _ in 0..2 {
+            let tag = protocol.next_tag();
+            protocol.encode(Some(&tag), b"NOOP");
+        }
+
+        protocol
+            .feed(b"A0001 OK done\r\nA0001 OK done again\r\n* 1 EXISTS\r\nA0002 OK done\r\n")
+            .unwrap();
+        let mut tags = Vec::new();
+        while let Some(response) = protocol.decode().unwrap() {
+            tags.push(response.request_id().cloned());
+        }
+        assert_eq!(
+            tags,
+            vec![
+                Some(RequestId("A0001".into())),
+                None,
+                Some(RequestId("A0002".into()))
+            ]
+        );
+        assert_eq!(
+            rx.try_next().unwrap(),
+            Some(UnsolicitedResponse::DuplicateCompletion {
+                tag: RequestId("A0001".into()),
+                conflicting: false,
+            })
+        );
+
+        // a contradicting completion makes the connection unusable
+        protocol.feed(b"A0002 NO failed\r\n* 2 EXISTS\r\n").unwrap();
+        assert!(protocol.decode().is_err());
+        assert!(protocol.decode().is_err());
+        assert_eq!(
+            rx.try_next().unwrap(),
+            Some(UnsolicitedResponse::DuplicateCompletion {
+                tag: RequestId("A0002".into()),
+                conflicting: true,
+            })
+        );
+    }
+
+    #[test]
+    fn raw_passthrough() {
+        let mut protocol = Protocol::new();
+        protocol.track_raw_command(b"a1 APPEND INBOX {5}\r\n");
+        protocol.track_raw_command(b"x1 ab");
+        protocol.track_raw_command(b" (\\Seen)\r\nb2 IDLE\r\nDONE\r");
+        protocol.track_raw_command(b"\n");
+        assert_eq!(
+            protocol.in_flight(),
+            &[RequestId("a1".into()), RequestId("b2".into())]
+        );
+
+        let data = b"* XSTATE foo\r\n* 1 FETCH (BODY[] {3}\r\nfoo)\r\n+ idling\r\nb2 OK done\r\n";
+        protocol.feed(data).unwrap();
+        let mut responses = Vec::new();
+        while let Some(response) = protocol.decode_raw().unwrap() {
+            responses.push(response);
+        }
+        assert_eq!(
+            responses,
+            vec![
+                RawResponse(b"* XSTATE foo\r\n".to_vec()),
+                RawResponse(b"* 1 FETCH (BODY[] {3}\r\nfoo)\r\n".to_vec()),
+                RawResponse(b"+ idling\r\n".to_vec()),
+                RawResponse(b"b2 OK done\r\n".to_vec()),
+            ]
+        );
+        assert_eq!(protocol.in_flight(), &[RequestId("a1".into())]);
+        assert!(!protocol.has_buffered_data());
+
+        // the continuation of `IDLE` has ended
+        protocol.track_raw_command(b"d4 NOOP\r\n");
+        assert_eq!(
+            protocol.in_flight(),
+            &[RequestId("a1".into()), RequestId("d4".into())]
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Tunneling connections through proxies.
+//!
+//! [`socks5_connect`] establishes a TCP connection through a [SOCKS5
+//! proxy](https://tools.ietf.org/html/rfc1928), such as the one provided by Tor. The host name is
+//! resolved by the proxy, so no DNS requests leak to the local network. The returned stream can
+//! be secured with TLS and wrapped in a [`Client`](crate::Client), or used through
+//! [`connect_via_socks5`](crate::connect_via_socks5) directly.
+//!
+//! [`http_connect`] does the same through an HTTP proxy using the [`CONNECT`
+//! method](https://tools.ietf.org/html/rfc7231#section-4.3.6), which is often the only way out of
+//! corporate networks. Its counterpart that also sets up TLS is
+//! [`connect_via_http_proxy`](crate::connect_via_http_proxy).
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use async_std::net::{TcpStream, ToSocketAddrs};
+use futures::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::Result;
+use crate::secret::Secret;
+
+const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Proxies that send more than this before the tunnel is established are not to be trusted.
+const MAX_HTTP_RESPONSE_HEAD: usize = 8 * 1024;
+
+/// Credentials for [username/password authentication](https://tools.ietf.org/html/rfc1929)
+/// with a SOCKS5 proxy.
+///
+/// Tor uses these to isolate streams: connections with different credentials are routed through
+/// different circuits.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Socks5Auth {
+    /// The user name, at most 255 bytes.
+    pub username: String,
+    /// The password, at most 255 bytes.
+    pub password: Secret,
+}
+
+impl std::fmt::Debug for Socks5Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Socks5Auth")
+            .field("username", &self.username)
+            .field("password", &self.password)
+            .finish()
+    }
+}
+
+/// Credentials for `Basic` authentication with an HTTP proxy, sent in the `Proxy-Authorization`
+/// header.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HttpProxyAuth {
+    /// The user name, which must not contain a colon.
+    pub username: String,
+    /// The password.
+    pub password: Secret,
+}
+
+impl std::fmt::Debug for HttpProxyAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpProxyAuth")
+            .field("username", &self.username)
+            .field("password", &self.password)
+            .finish()
+    }
+}
+
+/// Connects to `host:port` through the SOCKS5 proxy at `proxy_addr`.
+pub async fn socks5_connect<A: ToSocketAddrs>(
+    proxy_addr: A,
+    auth: Option<&Socks5Auth>,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    socks5_handshake(&mut stream, auth, host, port).await?;
+
+    Ok(stream)
+}
+
+/// Asks the SOCKS5 proxy on the other end of `stream` to connect to `host:port`.
+pub(crate) async fn socks5_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: Option<&Socks5Auth>,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    // method negotiation
+    let request: &[u8] = match auth {
+        Some(_) => &[SOCKS_VERSION, 2, METHOD_NONE, METHOD_USERNAME_PASSWORD],
+        None => &[SOCKS_VERSION, 1, METHOD_NONE],
+    };
+    stream.write_all(request).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(socks_error("invalid SOCKS version in reply"));
+    }
+    match (reply[1], auth) {
+        (METHOD_NONE, _) => {}
+        (METHOD_USERNAME_PASSWORD, Some(auth)) => authenticate(stream, auth).await?,
+        (METHOD_NOT_ACCEPTABLE, _) => {
+            return Err(socks_error("no acceptable authentication method"));
+        }
+        _ => return Err(socks_error("unsupported authentication method")),
+    }
+
+    // connect request
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    if let Ok(ip) = host.parse::<Ipv4Addr>() {
+        request.push(ATYP_IPV4);
+        request.extend_from_slice(&ip.octets());
+    } else if let Ok(ip) = host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<Ipv6Addr>()
+    {
+        request.push(ATYP_IPV6);
+        request.extend_from_slice(&ip.octets());
+    } else {
+        if host.is_empty() || host.len() > 255 {
+            return Err(socks_error("host name must be between 1 and 255 bytes"));
+        }
+        request.push(ATYP_DOMAIN);
+        request.push(host.len() as u8);
+        request.extend_from_slice(host.as_bytes());
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(socks_error("invalid SOCKS version in reply"));
+    }
+    if reply[1] != 0x00 {
+        return Err(socks_error(match reply[1] {
+            0x01 => "general SOCKS server failure",
+            0x02 => "connection not allowed by ruleset",
+            0x03 => "network unreachable",
+            0x04 => "host unreachable",
+            0x05 => "connection refused",
+            0x06 => "TTL expired",
+            0x07 => "command not supported",
+            0x08 => "address type not supported",
+            _ => "unknown SOCKS error",
+        }));
+    }
+
+    // the bound address is of no interest, but has to be consumed
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len).await?;
+            usize::from(len[0])
+        }
+        _ => return Err(socks_error("invalid address type in reply")),
+    };
+    let mut bound = vec![0; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// Connects to `host:port` through the HTTP proxy at `proxy_addr`.
+pub async fn http_connect<A: ToSocketAddrs>(
+    proxy_addr: A,
+    auth: Option<&HttpProxyAuth>,
+    host: &str,
+    port: u16,
+) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    http_connect_handshake(&mut stream, auth, host, port).await?;
+
+    Ok(stream)
+}
+
+/// Asks the HTTP proxy on the other end of `stream` to open a tunnel to `host:port`.
+pub(crate) async fn http_connect_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: Option<&HttpProxyAuth>,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    // the host ends up in the request head, where e.g. a CRLF would inject a header
+    if host.is_empty() || host.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "HTTP proxy: invalid host name",
+        ));
+    }
+
+    let authority = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(auth) = auth {
+        let credentials = format!("{}:{}", auth.username, auth.password.expose());
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode(&credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    // Read the response head one byte at a time, so that nothing the server sends through the
+    // tunnel afterwards is consumed.
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HTTP_RESPONSE_HEAD {
+            return Err(http_error("response head too large"));
+        }
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let (version, status) = (parts.next().unwrap_or_default(), parts.next());
+    if !version.starts_with("HTTP/1.") {
+        return Err(http_error("invalid response from proxy"));
+    }
+    match status.and_then(|s| s.parse::<u16>().ok()) {
+        Some(200..=299) => Ok(()),
+        Some(407) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("HTTP proxy: {}", status_line),
+        )),
+        Some(_) => Err(http_error(status_line)),
+        None => Err(http_error("invalid response from proxy")),
+    }
+}
+
+/// Performs username/password authentication according to RFC 1929.
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: &Socks5Auth,
+) -> io::Result<()> {
+    let (username, password) = (auth.username.as_bytes(), auth.password.expose().as_bytes());
+    if username.len() > 255 || password.len() > 255 {
+        return Err(socks_error(
+            "user name and password must be at most 255 bytes",
+        ));
+    }
+
+    let mut request = vec![0x01, username.len() as u8];
+    request.extend_from_slice(username);
+    request.push(password.len() as u8);
+    request.extend_from_slice(password);
+    stream.write_all(&request).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "SOCKS5 authentication failed",
+        ));
+    }
+
+    Ok(())
+}
+
+fn socks_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("SOCKS5: {}", msg))
+}
+
+fn http_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("HTTP proxy: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock_stream::MockStream;
+
+    #[async_attributes::test]
+    async fn socks5_domain() {
+        let mut stream = MockStream::new(vec![
+            5, 0, // no authentication
+            5, 0, 0, 1, 127, 0, 0, 1, 0, 80, // succeeded, bound to 127.0.0.1:80
+        ]);
+        socks5_handshake(&mut stream, None, "imap.example.org", 993)
+            .await
+            .unwrap();
+
+        let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 16];
+        expected.extend_from_slice(b"imap.example.org");
+        expected.extend_from_slice(&[0x03, 0xe1]);
+        assert_eq!(stream.written_buf, expected);
+    }
+
+    #[async_attributes::test]
+    async fn socks5_auth_ipv6() {
+        let mut stream = MockStream::new(vec![
+            5, 2, // username/password
+            1, 0, // authenticated
+            5, 0, 0, 3, 4, b'h', b'o', b's', b't', 0, 80, // succeeded, bound to host:80
+        ]);
+        let auth = Socks5Auth {
+            username: "user".into(),
+            password: "pw".into(),
+        };
+        socks5_handshake(&mut stream, Some(&auth), "[::1]", 143)
+            .await
+            .unwrap();
+
+        let mut expected = vec![5, 2, 0, 2, 1, 4];
+        expected.extend_from_slice(b"user");
+        expected.push(2);
+        expected.extend_from_slice(b"pw");
+        expected.extend_from_slice(&[5, 1, 0, 4]);
+        expected.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        expected.extend_from_slice(&[0, 143]);
+        assert_eq!(stream.written_buf, expected);
+    }
+
+    #[async_attributes::test]
+    async fn socks5_failures() {
+        let mut stream = MockStream::new(vec![5, 0xff]);
+        assert!(socks5_handshake(&mut stream, None, "host", 993)
+            .await
+            .is_err());
+
+        let mut stream = MockStream::new(vec![5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+        let err = socks5_handshake(&mut stream, None, "host", 993)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("connection refused"));
+    }
+
+    #[async_attributes::test]
+    async fn http_connect_auth() {
+        let mut stream = MockStream::new(
+            b"HTTP/1.1 200 Connection established\r\nVia: proxy\r\n\r\n* OK ready\r\n".to_vec(),
+        );
+        let auth = HttpProxyAuth {
+            username: "user".into(),
+            password: "pw".into(),
+        };
+        http_connect_handshake(&mut stream, Some(&auth), "imap.example.org", 993)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(stream.written_buf.clone()).unwrap(),
+            "CONNECT imap.example.org:993 HTTP/1.1\r\n\
+             Host: imap.example.org:993\r\n\
+             Proxy-Authorization: Basic dXNlcjpwdw==\r\n\r\n"
+        );
+        // the greeting is left for the IMAP client
+        assert!(!stream.read_pos_at_end());
+    }
+
+    #[async_attributes::test]
+    async fn http_connect_failures() {
+        let mut stream =
+            MockStream::new(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec());
+        let err = http_connect_handshake(&mut stream, None, "::1", 143)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(String::from_utf8(stream.written_buf)
+            .unwrap()
+            .starts_with("CONNECT [::1]:143 HTTP/1.1\r\n"));
+
+        let mut stream = MockStream::new(b"HTTP/1.0 403 Forbidden\r\n\r\n".to_vec());
+        assert!(http_connect_handshake(&mut stream, None, "host", 993)
+            .await
+            .is_err());
+
+        for host in &["host\r\nX-Injected: 1", "host name", ""] {
+            let mut stream = MockStream::new(b"HTTP/1.1 200 OK\r\n\r\n".to_vec());
+            let err = http_connect_handshake(&mut stream, None, host, 993)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(stream.written_buf.is_empty());
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Workarounds for known server bugs.
+//!
+//! Some servers advertise extensions that they implement incorrectly. Since the capability list
+//! can not tell these apart from working implementations, the [`ID`
+//! response](https://tools.ietf.org/html/rfc2971) is used to identify the server software instead.
+//! [`Session::id`] derives the [`Quirks`] of the server from it, and features that are affected
+//! check them before use. Applications that know better can override them with
+//! [`Session::set_quirks`].
+//!
+//! [`Session::id`]: ../struct.Session.html#method.id
+//! [`Session::set_quirks`]: ../struct.Session.html#method.set_quirks
+
+use crate::types::ServerId;
+
+/// Identifies a range of versions of a server implementation.
+#[derive(Debug, Clone, Copy)]
+struct Affected {
+    /// The `name` field of the `ID` response, compared case-insensitively.
+    name: &'static str,
+    /// Prefix of the `version` field. An empty prefix matches all versions.
+    version_prefix: &'static str,
+}
+
+impl Affected {
+    fn matches(&self, id: &ServerId) -> bool {
+        let name_matches = id
+            .name()
+            .map_or(false, |name| name.eq_ignore_ascii_case(self.name));
+        let version_matches = self.version_prefix.is_empty()
+            || id
+                .version()
+                .map_or(false, |version| version.starts_with(self.version_prefix));
+        name_matches && version_matches
+    }
+}
+
+/// Servers on which `COMPRESS=DEFLATE` must not be used although it is advertised. Entries are
+/// added here once a breakage has been confirmed.
+#[cfg(not(test))]
+const BROKEN_COMPRESS: &[Affected] = &[];
+
+/// A made-up server, which stands in for the real ones in the tests.
+#[cfg(test)]
+const BROKEN_COMPRESS: &[Affected] = &[Affected {
+    name: "X-Broken",
+    version_prefix: "1.",
+}];
+
+/// The deviations from the standards that a server is known to have.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// `COMPRESS=DEFLATE` is advertised, but broken, so
+    /// [`Session::compress`](crate::Session::compress) refuses to enable it.
+    pub broken_compress: bool,
+}
+
+impl Quirks {
+    /// The quirks of the server that identified itself with `id`.
+    pub fn detect(id: &ServerId) -> Self {
+        Quirks {
+            broken_compress: BROKEN_COMPRESS.iter().any(|a| a.matches(id)),
+        }
+    }
+}
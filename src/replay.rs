@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+#[cfg(feature = "blocking")]
+use futures::future::{Future, FutureExt};
+use futures::sink::SinkExt;
+#[cfg(feature = "blocking")]
+use futures::stream::StreamExt;
+use futures::stream::{FusedStream, Stream};
+use futures::task::{Context, Poll};
+
+use crate::types::UnsolicitedResponse;
+
+/// The default number of unsolicited responses kept for [`Session::replay_unsolicited`].
+///
+/// [`Session::replay_unsolicited`]: crate::Session::replay_unsolicited
+pub(crate) const DEFAULT_REPLAY_CAPACITY: usize = 32;
+
+/// The default number of responses the [`UnsolicitedReceiver`] has room for.
+pub(crate) const DEFAULT_UNSOLICITED_CAPACITY: usize = 100;
+
+/// The most recent unsolicited responses since the mailbox was selected.
+#[derive(Debug)]
+pub(crate) struct ReplayBuffer {
+    responses: VecDeque<UnsolicitedResponse>,
+    capacity: usize,
+}
+
+impl ReplayBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ReplayBuffer {
+            responses: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Keeps a copy of `response`, dropping the oldest one if the buffer is full.
+    fn record(&mut self, response: &UnsolicitedResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(copy) = response.try_clone() {
+            if self.responses.len() == self.capacity {
+                self.responses.pop_front();
+            }
+            self.responses.push_back(copy);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.responses.clear();
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.responses.len() > capacity {
+            self.responses.pop_front();
+        }
+    }
+
+    /// Copies of the kept responses, oldest first.
+    pub(crate) fn responses(&self) -> Vec<UnsolicitedResponse> {
+        self.responses
+            .iter()
+            .filter_map(UnsolicitedResponse::try_clone)
+            .collect()
+    }
+}
+
+/// Creates the channel behind [`Session::unsolicited_responses`], with room for `capacity`
+/// responses, whose sender records the responses in `replay`.
+///
+/// [`Session::unsolicited_responses`]: crate::Session::unsolicited_responses
+pub(crate) fn unsolicited_channel(
+    capacity: usize,
+    replay: Arc<Mutex<ReplayBuffer>>,
+) -> (UnsolicitedSender, UnsolicitedReceiver) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let queued = Arc::new(AtomicUsize::new(0));
+    let tx = UnsolicitedSender {
+        tx,
+        replay: Some(replay),
+        queued: queued.clone(),
+    };
+    let rx = UnsolicitedReceiver {
+        rx,
+        queued,
+        capacity,
+        taken: VecDeque::new(),
+    };
+    (tx, rx)
+}
+
+/// Delivers unsolicited responses to [`Session::unsolicited_responses`], and records them in the
+/// replay buffer of the session, if there is one.
+///
+/// [`Session::unsolicited_responses`]: crate::Session::unsolicited_responses
+#[derive(Debug, Clone)]
+pub(crate) struct UnsolicitedSender {
+    tx: mpsc::Sender<UnsolicitedResponse>,
+    replay: Option<Arc<Mutex<ReplayBuffer>>>,
+    /// The number of responses in the channel, shared with the [`UnsolicitedReceiver`].
+    queued: Arc<AtomicUsize>,
+}
+
+impl UnsolicitedSender {
+    pub(crate) fn new(
+        tx: mpsc::Sender<UnsolicitedResponse>,
+        replay: Arc<Mutex<ReplayBuffer>>,
+    ) -> Self {
+        UnsolicitedSender {
+            tx,
+            replay: Some(replay),
+            queued: Default::default(),
+        }
+    }
+
+    fn record(&self, response: &UnsolicitedResponse) {
+        if let Some(ref replay) = self.replay {
+            replay.lock().unwrap().record(response);
+        }
+    }
+
+    /// Sends `response`, waiting for room in the channel.
+    pub(crate) async fn send(&mut self, response: UnsolicitedResponse) {
+        self.record(&response);
+        // counted before it can be received, so that the count never drops below zero
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let response = match self.tx.try_send(response) {
+            Ok(()) => return,
+            Err(err) if err.is_full() => {
+                log::warn!("unsolicited responses channel is full, waiting for the receiver");
+                err.into_inner()
+            }
+            Err(_) => {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+        };
+        if self.tx.send(response).await.is_err() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Sends the responses in `queued`, oldest first, as long as there is room in the channel.
+    /// Returns `Poll::Pending` if some are left, and wakes the task once there is room.
+    pub(crate) fn poll_send_queued(
+        &mut self,
+        cx: &mut Context<'_>,
+        queued: &mut VecDeque<UnsolicitedResponse>,
+    ) -> Poll<()> {
+        while let Some(response) = queued.pop_front() {
+            match self.tx.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    self.record(&response);
+                    self.queued.fetch_add(1, Ordering::SeqCst);
+                    if self.tx.start_send(response).is_err() {
+                        self.queued.fetch_sub(1, Ordering::SeqCst);
+                    }
+                }
+                // nobody receives them anymore, but they are still recorded, like in `send`
+                Poll::Ready(Err(_)) => self.record(&response),
+                Poll::Pending => {
+                    log::warn!("unsolicited responses channel is full, waiting for the receiver");
+                    queued.push_front(response);
+                    return Poll::Pending;
+                }
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+impl From<mpsc::Sender<UnsolicitedResponse>> for UnsolicitedSender {
+    fn from(tx: mpsc::Sender<UnsolicitedResponse>) -> Self {
+        UnsolicitedSender {
+            tx,
+            replay: None,
+            queued: Default::default(),
+        }
+    }
+}
+
+/// The receiving end of [`Session::unsolicited_responses`], a bounded channel of the responses
+/// that the server sent on its own or that belong to no command.
+///
+/// The session waits for room in the channel before it reads on, so when the channel is full,
+/// commands do not complete until responses are taken out of it. A task that runs commands must
+/// therefore not wait for them while the same task is supposed to drain the channel, e.g. by
+/// fetching in a loop and only reading the channel afterwards. The size can be chosen with [`ClientBuilder::unsolicited_capacity`] or
+/// [`Connection::unsolicited_capacity`], and how full the channel is can be checked with
+/// [`UnsolicitedReceiver::len`].
+///
+/// [`Session::unsolicited_responses`]: crate::Session::unsolicited_responses
+/// [`ClientBuilder::unsolicited_capacity`]: crate::ClientBuilder::unsolicited_capacity
+/// [`Connection::unsolicited_capacity`]: crate::Connection::unsolicited_capacity
+#[derive(Debug)]
+pub struct UnsolicitedReceiver {
+    rx: mpsc::Receiver<UnsolicitedResponse>,
+    queued: Arc<AtomicUsize>,
+    capacity: usize,
+    /// Responses that were taken out of the channel to make room, which are handed out first.
+    taken: VecDeque<UnsolicitedResponse>,
+}
+
+impl UnsolicitedReceiver {
+    /// The number of responses waiting in the channel.
+    pub fn len(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Whether no responses are waiting in the channel.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of responses the channel has room for. Since every part of the session that
+    /// sends responses has a slot of its own, a few more may fit.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Takes the next response out of the channel if there is one, without waiting. Fails if
+    /// the channel is empty, or if it was closed and is empty.
+    pub fn try_next(&mut self) -> Result<Option<UnsolicitedResponse>, mpsc::TryRecvError> {
+        if let Some(response) = self.taken.pop_front() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Ok(Some(response));
+        }
+        let res = self.rx.try_next();
+        if let Ok(Some(_)) = res {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        res
+    }
+
+    /// Closes the channel, so that the session drops unsolicited responses instead of waiting
+    /// for room. Responses that are already in the channel can still be taken out.
+    pub fn close(&mut self) {
+        self.rx.close();
+    }
+
+    /// Waits for `future`, and meanwhile takes the responses that arrive out of the channel, so
+    /// that the session does not wait for room while nobody can take them out. They are still
+    /// counted by [`len`](UnsolicitedReceiver::len), and handed out before the rest.
+    #[cfg(feature = "blocking")]
+    pub(crate) async fn collect_while<F: Future + Unpin>(&mut self, future: F) -> F::Output {
+        let mut future = future.fuse();
+        loop {
+            futures::select! {
+                output = future => return output,
+                response = self.rx.next() => {
+                    // once the channel is closed, only the future is left
+                    if let Some(response) = response {
+                        self.taken.push_back(response);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Stream for UnsolicitedReceiver {
+    type Item = UnsolicitedResponse;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(response) = self.taken.pop_front() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Poll::Ready(Some(response));
+        }
+        let res = Pin::new(&mut self.rx).poll_next(cx);
+        if let Poll::Ready(Some(_)) = res {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        res
+    }
+}
+
+impl FusedStream for UnsolicitedReceiver {
+    fn is_terminated(&self) -> bool {
+        self.taken.is_empty() && self.rx.is_terminated()
+    }
+}
@@ -0,0 +1,219 @@
+//! Trying commands again after failures that are expected to pass, such as a `NO [UNAVAILABLE]`
+//! from a server that is temporarily overloaded, or a dropped connection.
+//!
+//! A [`RetryPolicy`] can be set for a whole session with [`Session::set_retry_policy`], in which
+//! case commands that are safe to repeat, like `SELECT`, `STATUS` or `SEARCH`, are sent again
+//! when the server rejects them with a retryable response. Commands that change the mail store,
+//! like `APPEND`, `COPY` or `EXPUNGE`, are never retried this way, since it can not be known
+//! whether the first attempt had an effect.
+//!
+//! Individual operations can be retried with [`Session::retry`], and operations that should
+//! survive a dropped connection with [`SessionTemplate::retry`], which reconnects before trying
+//! again. Both leave it to the caller to only pass operations that may run more than once.
+//!
+//! [`Session::set_retry_policy`]: ../struct.Session.html#method.set_retry_policy
+//! [`Session::retry`]: ../struct.Session.html#method.retry
+//! [`SessionTemplate::retry`]: ../struct.SessionTemplate.html#method.retry
+
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// The commands that have no effect beyond their responses, or the same effect when they are
+/// repeated, so that a session-wide [`RetryPolicy`] may send them again.
+const IDEMPOTENT: &[&str] = &[
+    "CAPABILITY",
+    "CHECK",
+    "EXAMINE",
+    "FETCH",
+    "GETACL",
+    "GETMETADATA",
+    "GETQUOTA",
+    "GETQUOTAROOT",
+    "ID",
+    "LIST",
+    "LISTRIGHTS",
+    "LSUB",
+    "MYRIGHTS",
+    "NAMESPACE",
+    "NOOP",
+    "SEARCH",
+    "SELECT",
+    "SORT",
+    "STATUS",
+    "THREAD",
+];
+
+/// Decides whether an error is worth trying again, see [`RetryPolicy::retry_if`].
+pub type Classifier = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
+/// How often, how soon and on which errors a failed command is tried again.
+///
+/// The delay before the first retry is the initial backoff, and it doubles for every further
+/// retry, up to the maximum backoff. By default, a command is attempted up to 3 times, the
+/// backoff starts at 100 milliseconds and does not exceed 5 seconds, and the errors for which
+/// [`RetryPolicy::is_transient`] is true are retried.
+///
+/// Without the `runtime` feature, there is no timer to wait with, so retries are sent right
+/// away.
+///
+/// ```
+/// use std::time::Duration;
+/// use async_imap::retry::RetryPolicy;
+///
+/// let policy = RetryPolicy::new(5).backoff(Duration::from_millis(250), Duration::from_secs(10));
+/// assert_eq!(policy.delay(1), Duration::from_millis(250));
+/// assert_eq!(policy.delay(3), Duration::from_secs(1));
+/// assert_eq!(policy.delay(10), Duration::from_secs(10));
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retryable: Classifier,
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .finish()
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes up to `max_attempts` attempts in total, including the first one. With
+    /// `0` or `1`, nothing is retried.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retryable: Arc::new(RetryPolicy::is_transient),
+        }
+    }
+
+    /// Waits `initial` before the first retry, and twice as long before each further one, but
+    /// never longer than `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Only retries the errors for which `retryable` returns true, instead of those that
+    /// [`RetryPolicy::is_transient`] accepts.
+    pub fn retry_if<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    /// How many attempts are made in total.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// How long to wait before the retry that follows the `attempt`th failed attempt.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(0);
+        match self.initial_backoff.checked_mul(factor) {
+            Some(delay) if factor > 0 => std::cmp::min(delay, self.max_backoff),
+            _ => self.max_backoff,
+        }
+    }
+
+    /// Whether an operation that failed with `err` on its `attempt`th attempt is tried again.
+    pub fn should_retry(&self, err: &Error, attempt: u32) -> bool {
+        attempt < self.max_attempts && (self.retryable)(err)
+    }
+
+    /// The default classifier: a `NO` or `BAD` with the
+    /// [`UNAVAILABLE`](https://tools.ietf.org/html/rfc5530#section-3) response code, a lost
+    /// connection, and I/O errors that mean the connection broke or timed out.
+    pub fn is_transient(err: &Error) -> bool {
+        match err {
+            // imap-proto does not know the code, so it is at the start of the text
+            Error::No(msg) | Error::Bad(msg) => msg.contains("[UNAVAILABLE]"),
+            Error::ConnectionLost => true,
+            Error::Io(err) => match err.kind() {
+                ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::TimedOut
+                | ErrorKind::UnexpectedEof => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Waits before the retry that follows the `attempt`th failed attempt.
+    pub(crate) async fn wait(&self, attempt: u32) {
+        #[cfg(feature = "runtime")]
+        async_std::task::sleep(self.delay(attempt)).await;
+        #[cfg(not(feature = "runtime"))]
+        let _ = attempt;
+    }
+}
+
+/// Whether `err` means that the connection can no longer be used, so that only a new one can
+/// try again.
+pub(crate) fn is_connection_error(err: &Error) -> bool {
+    match err {
+        Error::Io(_) | Error::ConnectionLost => true,
+        _ => false,
+    }
+}
+
+/// Whether `command` may be sent again after it failed, see [`IDEMPOTENT`].
+pub(crate) fn is_idempotent(command: &str) -> bool {
+    let mut words = command.split_whitespace();
+    let verb = match words.next() {
+        Some(verb) if verb.eq_ignore_ascii_case("UID") => words.next(),
+        verb => verb,
+    };
+    verb.map_or(false, |verb| {
+        IDEMPOTENT
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(verb))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        assert!(is_idempotent("UID FETCH 1:* (FLAGS)"));
+        assert!(is_idempotent("select INBOX"));
+        assert!(!is_idempotent("UID EXPUNGE 3"));
+        assert!(!is_idempotent("APPEND INBOX {3}"));
+        assert!(!is_idempotent(""));
+
+        let policy = RetryPolicy::default();
+        let unavailable = Error::No("code: None, info: Some(\"[UNAVAILABLE] busy\")".into());
+        assert!(policy.should_retry(&unavailable, 2));
+        assert!(!policy.should_retry(&unavailable, 3));
+        assert!(!policy.should_retry(&Error::No("code: None, info: None".into()), 1));
+        assert!(policy.should_retry(&Error::ConnectionLost, 1));
+        assert!(!RetryPolicy::new(3)
+            .retry_if(|_| false)
+            .should_retry(&Error::ConnectionLost, 1));
+    }
+}
@@ -0,0 +1,613 @@
+//! Built-in [`Authenticator`]s for common [SASL](https://tools.ietf.org/html/rfc4422)
+//! mechanisms, to be passed to [`Client::authenticate`](crate::Client::authenticate) together
+//! with their [`MECHANISM`](XOAuth2::MECHANISM) name.
+//!
+//! The OAuth mechanisms remember the error that the server reports when it rejects a token, so
+//! that an expired token, which should be refreshed, can be told apart from a token that lacks
+//! the required scope, see [`OAuthError`]. Their access token can come from a
+//! [`TokenProvider`], which [`Client::authenticate_oauth`](crate::Client::authenticate_oauth)
+//! asks for a new token when the server reports that the last one has expired.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use crate::authenticator::Authenticator;
+use crate::error::Result;
+use crate::secret::Secret;
+
+#[cfg(feature = "scram")]
+mod scram;
+#[cfg(feature = "scram")]
+pub use self::scram::{Scram, ScramHash};
+
+/// The [`PLAIN`](https://tools.ietf.org/html/rfc4616) mechanism, which sends the password as
+/// is, like `LOGIN`. Unlike `LOGIN`, it can log in as another user (the authorization identity)
+/// with the credentials of an administrator or proxy (the authentication identity), and it is
+/// still available on servers that disable `LOGIN`. Only use it over TLS.
+///
+/// See also [`Client::authenticate_plain`](crate::Client::authenticate_plain).
+#[derive(Clone, PartialEq, Eq)]
+pub struct Plain {
+    authzid: Option<String>,
+    authcid: String,
+    password: Secret,
+}
+
+impl Plain {
+    /// The name of the mechanism.
+    pub const MECHANISM: &'static str = "PLAIN";
+
+    /// Logs in as `authzid`, or as `authcid` if `authzid` is `None`, with the credentials of
+    /// `authcid`.
+    pub fn new<C: Into<String>, P: Into<Secret>>(
+        authzid: Option<&str>,
+        authcid: C,
+        password: P,
+    ) -> Self {
+        Plain {
+            authzid: authzid.map(Into::into),
+            authcid: authcid.into(),
+            password: password.into(),
+        }
+    }
+}
+
+impl fmt::Debug for Plain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plain")
+            .field("authzid", &self.authzid)
+            .field("authcid", &self.authcid)
+            .finish()
+    }
+}
+
+impl Authenticator for Plain {
+    type Response = Vec<u8>;
+
+    fn process(&self, _challenge: &[u8]) -> Self::Response {
+        format!(
+            "{}\0{}\0{}",
+            self.authzid.as_deref().unwrap_or_default(),
+            self.authcid,
+            self.password.expose()
+        )
+        .into_bytes()
+    }
+
+    fn client_first(&self) -> bool {
+        true
+    }
+}
+
+/// The [`EXTERNAL`](https://tools.ietf.org/html/rfc4422#appendix-A) mechanism, which logs in
+/// with credentials that were established outside of IMAP, usually the client certificate of a
+/// mutual TLS connection. The certificate is presented by the TLS connector, see
+/// `ClientBuilder::tls_connector` and `ClientBuilder::rustls_connector`.
+///
+/// See also [`Client::authenticate_external`](crate::Client::authenticate_external).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct External {
+    authzid: Option<String>,
+}
+
+impl External {
+    /// The name of the mechanism.
+    pub const MECHANISM: &'static str = "EXTERNAL";
+
+    /// Logs in as `authzid`, or as the identity the server derives from the external
+    /// credentials if `authzid` is `None`.
+    pub fn new(authzid: Option<&str>) -> Self {
+        External {
+            authzid: authzid.map(Into::into),
+        }
+    }
+}
+
+impl Authenticator for External {
+    type Response = Vec<u8>;
+
+    fn process(&self, _challenge: &[u8]) -> Self::Response {
+        self.authzid.clone().unwrap_or_default().into_bytes()
+    }
+
+    fn client_first(&self) -> bool {
+        true
+    }
+}
+
+/// The future returned by [`TokenProvider::token`].
+pub type TokenFuture<'a> = Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>>;
+
+/// The future returned by [`OAuth::refresh`].
+pub type RefreshFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Supplies the access tokens of [`XOAuth2`] and [`OAuthBearer`], e.g. by refreshing them with
+/// the authorization server, so that an expired token does not fail the login.
+///
+/// ```
+/// use async_imap::sasl::{TokenFuture, TokenProvider, XOAuth2};
+///
+/// struct Refreshing;
+///
+/// impl TokenProvider for Refreshing {
+///     fn token(&self) -> TokenFuture<'_> {
+///         Box::pin(async move {
+///             // return a cached token, or ask the authorization server for a new one here
+///             Ok(String::from("<access_token>"))
+///         })
+///     }
+///
+///     fn expired(&self, _token: &str) {
+///         // forget the cached token here
+///     }
+/// }
+///
+/// let auth = XOAuth2::with_provider("me@example.com", Refreshing);
+/// ```
+pub trait TokenProvider: Send + Sync {
+    /// Returns the access token for the next attempt to log in.
+    fn token(&self) -> TokenFuture<'_>;
+
+    /// Called when the server rejected `token` because it has expired or was revoked, right
+    /// before [`token`](TokenProvider::token) is asked for a new one.
+    fn expired(&self, _token: &str) {}
+}
+
+/// The OAuth mechanisms, whose access token can come from a [`TokenProvider`], see
+/// [`Client::authenticate_oauth`](crate::Client::authenticate_oauth).
+pub trait OAuth: Authenticator {
+    /// The name of the mechanism.
+    fn mechanism(&self) -> &'static str;
+
+    /// Fetches the access token for the next attempt to log in from the [`TokenProvider`], if
+    /// there is one, and forgets the error of the previous attempt.
+    fn refresh(&self) -> RefreshFuture<'_>;
+
+    /// Whether the server rejected the token because it has expired or was revoked. If so, the
+    /// [`TokenProvider`] is told about it.
+    fn expired(&self) -> bool;
+}
+
+/// The access token of an OAuth mechanism, and the provider of new ones.
+struct Token {
+    current: Mutex<Secret>,
+    provider: Option<Arc<dyn TokenProvider>>,
+}
+
+impl Token {
+    fn fixed(token: Secret) -> Self {
+        Token {
+            current: Mutex::new(token),
+            provider: None,
+        }
+    }
+
+    fn provided(provider: Arc<dyn TokenProvider>) -> Self {
+        Token {
+            current: Mutex::new(Secret::default()),
+            provider: Some(provider),
+        }
+    }
+
+    fn get(&self) -> Secret {
+        self.current.lock().unwrap().clone()
+    }
+
+    async fn refresh(&self) -> Result<()> {
+        if let Some(ref provider) = self.provider {
+            let token = provider.token().await?;
+            *self.current.lock().unwrap() = Secret::from(token);
+        }
+        Ok(())
+    }
+
+    fn expired(&self, error: Option<OAuthError>) -> bool {
+        match error {
+            Some(OAuthError {
+                status: OAuthErrorStatus::InvalidToken,
+                ..
+            }) => {
+                if let Some(ref provider) = self.provider {
+                    provider.expired(self.get().expose());
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("provided", &self.provider.is_some())
+            .finish()
+    }
+}
+
+/// The [`XOAUTH2`](https://developers.google.com/gmail/imap/xoauth2-protocol) mechanism, which
+/// logs in with an OAuth 2.0 access token. It is required by Gmail and Outlook.com, among
+/// others.
+///
+/// ```no_run
+/// use async_imap::sasl::XOAuth2;
+///
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// # let client: async_imap::Client<async_std::net::TcpStream> = unimplemented!();
+/// let auth = XOAuth2::new("me@example.com", "<access_token>");
+/// match client.authenticate(XOAuth2::MECHANISM, &auth).await {
+///     Ok(session) => {
+///         // you are successfully authenticated!
+///     }
+///     Err((err, _client)) => {
+///         eprintln!("error authenticating: {}, details: {:?}", err, auth.error());
+///     }
+/// }
+/// # Ok(())
+/// # }) }
+/// ```
+#[derive(Debug)]
+pub struct XOAuth2 {
+    user: String,
+    token: Token,
+    error: Mutex<Option<String>>,
+}
+
+impl XOAuth2 {
+    /// The name of the mechanism.
+    pub const MECHANISM: &'static str = "XOAUTH2";
+
+    /// Logs in as `user`, usually the email address, with `access_token`.
+    pub fn new<U: Into<String>, T: Into<Secret>>(user: U, access_token: T) -> Self {
+        XOAuth2 {
+            user: user.into(),
+            token: Token::fixed(access_token.into()),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Logs in as `user` with the access tokens of `provider`, see
+    /// [`Client::authenticate_oauth`](crate::Client::authenticate_oauth).
+    pub fn with_provider<U: Into<String>, P: TokenProvider + 'static>(
+        user: U,
+        provider: P,
+    ) -> Self {
+        XOAuth2 {
+            user: user.into(),
+            token: Token::provided(Arc::new(provider)),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// The error that the server reported when it rejected the token, if any. This is a JSON
+    /// object such as `{"status":"401","schemes":"bearer","scope":"https://mail.google.com/"}`,
+    /// which tells more about the problem than the `NO` response that fails the command, see
+    /// [`OAuthError::parse`].
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+}
+
+impl Authenticator for XOAuth2 {
+    type Response = Vec<u8>;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        if challenge.is_empty() {
+            format!(
+                "user={}\x01auth=Bearer {}\x01\x01",
+                self.user,
+                self.token.get().expose()
+            )
+            .into_bytes()
+        } else {
+            // the server rejected the token, and expects an empty response before it fails the
+            // command
+            *self.error.lock().unwrap() = Some(String::from_utf8_lossy(challenge).into_owned());
+            Vec::new()
+        }
+    }
+
+    fn client_first(&self) -> bool {
+        true
+    }
+}
+
+impl OAuth for XOAuth2 {
+    fn mechanism(&self) -> &'static str {
+        XOAuth2::MECHANISM
+    }
+
+    fn refresh(&self) -> RefreshFuture<'_> {
+        Box::pin(async move {
+            self.token.refresh().await?;
+            *self.error.lock().unwrap() = None;
+            Ok(())
+        })
+    }
+
+    fn expired(&self) -> bool {
+        let error = self.error().as_deref().and_then(OAuthError::parse);
+        self.token.expired(error)
+    }
+}
+
+/// The [`OAUTHBEARER`](https://tools.ietf.org/html/rfc7628) mechanism, the standardized
+/// successor of [`XOAuth2`].
+#[derive(Debug)]
+pub struct OAuthBearer {
+    user: String,
+    token: Token,
+    host: Option<String>,
+    port: Option<u16>,
+    error: Mutex<Option<String>>,
+}
+
+impl OAuthBearer {
+    /// The name of the mechanism.
+    pub const MECHANISM: &'static str = "OAUTHBEARER";
+
+    /// Logs in as `user`, usually the email address, with `access_token`.
+    pub fn new<U: Into<String>, T: Into<Secret>>(user: U, access_token: T) -> Self {
+        OAuthBearer {
+            user: user.into(),
+            token: Token::fixed(access_token.into()),
+            host: None,
+            port: None,
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Logs in as `user` with the access tokens of `provider`, see
+    /// [`Client::authenticate_oauth`](crate::Client::authenticate_oauth).
+    pub fn with_provider<U: Into<String>, P: TokenProvider + 'static>(
+        user: U,
+        provider: P,
+    ) -> Self {
+        OAuthBearer {
+            user: user.into(),
+            token: Token::provided(Arc::new(provider)),
+            host: None,
+            port: None,
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Tells the server which host name and port the client connected to. Some servers require
+    /// this to validate the token.
+    pub fn server<H: Into<String>>(mut self, host: H, port: u16) -> Self {
+        self.host = Some(host.into());
+        self.port = Some(port);
+        self
+    }
+
+    /// The error that the server reported when it rejected the token, if any.
+    pub fn error(&self) -> Option<OAuthError> {
+        self.error
+            .lock()
+            .unwrap()
+            .as_deref()
+            .and_then(OAuthError::parse)
+    }
+}
+
+impl Authenticator for OAuthBearer {
+    type Response = Vec<u8>;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        if !challenge.is_empty() {
+            // the server rejected the token, and expects a single ^A before it fails the command
+            *self.error.lock().unwrap() = Some(String::from_utf8_lossy(challenge).into_owned());
+            return b"\x01".to_vec();
+        }
+
+        // `,` and `=` have to be escaped in the authorization identity
+        let user = self.user.replace('=', "=3D").replace(',', "=2C");
+        let mut response = format!("n,a={},\x01", user);
+        if let Some(ref host) = self.host {
+            response.push_str(&format!("host={}\x01", host));
+        }
+        if let Some(port) = self.port {
+            response.push_str(&format!("port={}\x01", port));
+        }
+        response.push_str(&format!(
+            "auth=Bearer {}\x01\x01",
+            self.token.get().expose()
+        ));
+        response.into_bytes()
+    }
+
+    fn client_first(&self) -> bool {
+        true
+    }
+}
+
+impl OAuth for OAuthBearer {
+    fn mechanism(&self) -> &'static str {
+        OAuthBearer::MECHANISM
+    }
+
+    fn refresh(&self) -> RefreshFuture<'_> {
+        Box::pin(async move {
+            self.token.refresh().await?;
+            *self.error.lock().unwrap() = None;
+            Ok(())
+        })
+    }
+
+    fn expired(&self) -> bool {
+        self.token.expired(self.error())
+    }
+}
+
+/// Why an OAuth token was rejected, as reported in the `status` of the error challenge of
+/// [`OAuthBearer`] or [`XOAuth2`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OAuthErrorStatus {
+    /// The token is expired, revoked or otherwise invalid, and has to be refreshed.
+    InvalidToken,
+    /// The token is valid, but was not granted the scope that is needed for IMAP.
+    InsufficientScope,
+    /// The request was malformed.
+    InvalidRequest,
+    /// Any other status.
+    Other(String),
+}
+
+/// The JSON error challenge that a server sends when it rejects an OAuth token, see [RFC 7628
+/// section 3.2.2](https://tools.ietf.org/html/rfc7628#section-3.2.2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OAuthError {
+    /// Why the token was rejected.
+    pub status: OAuthErrorStatus,
+    /// The scope that the token needs, if the server said so.
+    pub scope: Option<String>,
+    /// Where the OpenID Connect configuration of the authorization server can be found, if the
+    /// server said so.
+    pub openid_configuration: Option<String>,
+}
+
+impl OAuthError {
+    /// Parses an error challenge. The HTTP status codes that XOAUTH2 servers such as Gmail use
+    /// are mapped to the matching [`OAuthErrorStatus`]. Returns `None` if `json` is not an
+    /// object with a `status`.
+    pub fn parse(json: &str) -> Option<OAuthError> {
+        let fields = parse_flat_object(json)?;
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let status = match field("status")?.as_str() {
+            "invalid_token" | "401" => OAuthErrorStatus::InvalidToken,
+            "insufficient_scope" | "403" => OAuthErrorStatus::InsufficientScope,
+            "invalid_request" | "400" => OAuthErrorStatus::InvalidRequest,
+            other => OAuthErrorStatus::Other(other.to_string()),
+        };
+        Some(OAuthError {
+            status,
+            scope: field("scope"),
+            openid_configuration: field("openid-configuration"),
+        })
+    }
+}
+
+/// Parses a JSON object whose values are strings or other scalars, which is all that OAuth error
+/// challenges contain. Non-string values are returned as written.
+fn parse_flat_object(json: &str) -> Option<Vec<(String, String)>> {
+    let mut chars = json.trim().chars().peekable();
+    if chars.next()? != '{' {
+        return None;
+    }
+
+    let mut fields = Vec::new();
+    loop {
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            '}' if fields.is_empty() => return Some(fields),
+            '"' => {}
+            _ => return None,
+        }
+        let key = parse_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            parse_string(&mut chars)?
+        } else {
+            let mut value = String::new();
+            while let Some(c) = chars.peek() {
+                if *c == ',' || *c == '}' || c.is_whitespace() {
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+            value
+        };
+        fields.push((key, value));
+
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            ',' => {}
+            '}' => return Some(fields),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Parses the rest of a JSON string whose opening quote has been consumed.
+fn parse_string<I: Iterator<Item = char>>(chars: &mut I) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => s.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                }
+                c => c,
+            }),
+            c => s.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oauthbearer() {
+        let auth = OAuthBearer::new("user,1@example.com", "token").server("imap.example.com", 993);
+        assert_eq!(
+            auth.process(b""),
+            b"n,a=user=2C1@example.com,\x01host=imap.example.com\x01port=993\x01\
+              auth=Bearer token\x01\x01"
+                .to_vec()
+        );
+        assert_eq!(auth.error(), None);
+
+        let challenge = br#"{"status":"insufficient_scope","scope":"mail \"imap\"",
+            "openid-configuration":"https://example.com/.well-known/openid-configuration"}"#;
+        assert_eq!(auth.process(challenge), b"\x01".to_vec());
+        assert_eq!(
+            auth.error(),
+            Some(OAuthError {
+                status: OAuthErrorStatus::InsufficientScope,
+                scope: Some("mail \"imap\"".into()),
+                openid_configuration: Some(
+                    "https://example.com/.well-known/openid-configuration".into()
+                ),
+            })
+        );
+    }
+
+    #[test]
+    fn xoauth2_error() {
+        let error = OAuthError::parse(r#"{"status":"401","schemes":"bearer"}"#).unwrap();
+        assert_eq!(error.status, OAuthErrorStatus::InvalidToken);
+        assert_eq!(error.scope, None);
+        assert_eq!(OAuthError::parse("not json"), None);
+        assert_eq!(OAuthError::parse(r#"{"scope":"x"}"#), None);
+    }
+}
@@ -0,0 +1,514 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::authenticator::{Authenticator, ChannelBindingType};
+use crate::secret::Secret;
+
+/// The largest iteration count a server may ask for, so that it can't keep the client busy
+/// computing the salted password.
+const MAX_ITERATIONS: u32 = 1_000_000;
+
+/// The hash function of a [`Scram`] mechanism.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScramHash {
+    /// `SCRAM-SHA-1`, see [RFC 5802](https://tools.ietf.org/html/rfc5802).
+    Sha1,
+    /// `SCRAM-SHA-256`, see [RFC 7677](https://tools.ietf.org/html/rfc7677).
+    Sha256,
+}
+
+impl ScramHash {
+    /// The name of the mechanism.
+    pub fn mechanism(&self) -> &'static str {
+        match self {
+            ScramHash::Sha1 => "SCRAM-SHA-1",
+            ScramHash::Sha256 => "SCRAM-SHA-256",
+        }
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramHash::Sha1 => Sha1::digest(data).to_vec(),
+            ScramHash::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        // HMAC accepts keys of any length
+        match self {
+            ScramHash::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_varkey(key).unwrap();
+                mac.input(data);
+                mac.result().code().to_vec()
+            }
+            ScramHash::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_varkey(key).unwrap();
+                mac.input(data);
+                mac.result().code().to_vec()
+            }
+        }
+    }
+
+    /// The `Hi` function of RFC 5802, i.e. PBKDF2 with HMAC and a single block of output.
+    fn salted_password(&self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut block = salt.to_vec();
+        block.extend_from_slice(&1u32.to_be_bytes());
+        let mut u = self.hmac(password, &block);
+        let mut result = u.clone();
+        for _ in 1..iterations {
+            u = self.hmac(password, &u);
+            result.iter_mut().zip(&u).for_each(|(r, u)| *r ^= u);
+        }
+        result
+    }
+}
+
+/// Whether a [`Scram`] exchange is bound to the TLS connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Binding {
+    /// The client does not support channel binding.
+    None,
+    /// The client supports channel binding, but the server does not seem to.
+    NotOffered,
+    /// The exchange is bound with the given data.
+    Bound(ChannelBindingType, Vec<u8>),
+}
+
+/// Where a [`Scram`] exchange is.
+#[derive(Debug)]
+enum State {
+    /// Nothing has been sent yet.
+    Initial,
+    /// The client-first-message has been sent.
+    ClientFirst {
+        nonce: String,
+        client_first_bare: String,
+    },
+    /// The client-final-message has been sent.
+    ClientFinal { server_signature: Vec<u8> },
+    /// The exchange has ended, and the server decides whether it succeeded.
+    Done,
+    /// The server has proven that it knows the password.
+    Verified,
+    /// The exchange has to be cancelled, because the server could not be verified.
+    Cancelled,
+}
+
+/// The [SCRAM](https://tools.ietf.org/html/rfc5802) mechanisms, which prove knowledge of the
+/// password without sending it, and make the server prove that it knows the password as well.
+/// Dovecot and Cyrus prefer them over `PLAIN`.
+///
+/// If the server can not prove that it knows the password, the exchange is cancelled and
+/// [`Client::authenticate`](crate::Client::authenticate) fails, even if the server would have
+/// accepted the client. [`error`](Scram::error) then tells what went wrong.
+///
+/// ```no_run
+/// use async_imap::sasl::Scram;
+///
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// # let client: async_imap::Client<async_std::net::TcpStream> = unimplemented!();
+/// let auth = Scram::sha256("me@example.com", "password");
+/// let session = client
+///     .authenticate(auth.mechanism(), &auth)
+///     .await
+///     .map_err(|(err, _client)| err)?;
+/// # Ok(())
+/// # }) }
+/// ```
+///
+/// The password is used as is, without the SASLprep normalization of RFC 4013. This only
+/// makes a difference for passwords with unusual Unicode characters.
+///
+/// With [`channel_binding`](Scram::channel_binding), the `-PLUS` variants of the mechanisms
+/// also prove that client and server see the same TLS connection, so that a proxy that
+/// terminates TLS in between is detected even if it has a valid certificate.
+/// [`Client::authenticate_scram`](crate::Client::authenticate_scram) picks the best variant
+/// that both sides support.
+///
+/// Requires the `scram` feature.
+pub struct Scram {
+    hash: ScramHash,
+    authzid: Option<String>,
+    user: String,
+    password: Secret,
+    binding: Binding,
+    /// Replaces the random client nonce in tests.
+    pub(crate) nonce: Option<String>,
+    state: Mutex<State>,
+    error: Mutex<Option<String>>,
+}
+
+impl Scram {
+    /// Logs in as `user` with `password`, using `hash`.
+    pub fn new<U: Into<String>, P: Into<Secret>>(hash: ScramHash, user: U, password: P) -> Self {
+        Scram {
+            hash,
+            authzid: None,
+            user: user.into(),
+            password: password.into(),
+            binding: Binding::None,
+            nonce: None,
+            state: Mutex::new(State::Initial),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// `SCRAM-SHA-1`.
+    pub fn sha1<U: Into<String>, P: Into<Secret>>(user: U, password: P) -> Self {
+        Scram::new(ScramHash::Sha1, user, password)
+    }
+
+    /// `SCRAM-SHA-256`.
+    pub fn sha256<U: Into<String>, P: Into<Secret>>(user: U, password: P) -> Self {
+        Scram::new(ScramHash::Sha256, user, password)
+    }
+
+    /// Logs in as `authzid` with the credentials of the user, like [`Plain`](super::Plain).
+    pub fn authzid<S: Into<String>>(mut self, authzid: S) -> Self {
+        self.authzid = Some(authzid.into());
+        self
+    }
+
+    /// Binds the exchange to the TLS connection, using the `-PLUS` variant of the mechanism.
+    /// `data` is the channel binding data of type `kind`, see
+    /// [`Client::channel_binding`](crate::Client).
+    pub fn channel_binding(mut self, kind: ChannelBindingType, data: Vec<u8>) -> Self {
+        self.binding = Binding::Bound(kind, data);
+        self
+    }
+
+    /// Tells the server that the client supports channel binding, but did not use it because
+    /// the server does not offer a `-PLUS` mechanism. A server that does offer one then knows
+    /// that its list of mechanisms was tampered with to downgrade the exchange, and fails it.
+    pub fn channel_binding_not_offered(mut self) -> Self {
+        self.binding = Binding::NotOffered;
+        self
+    }
+
+    /// The name of the mechanism, to be passed to
+    /// [`Client::authenticate`](crate::Client::authenticate).
+    pub fn mechanism(&self) -> &'static str {
+        match (self.hash, &self.binding) {
+            (ScramHash::Sha1, Binding::Bound(..)) => "SCRAM-SHA-1-PLUS",
+            (ScramHash::Sha256, Binding::Bound(..)) => "SCRAM-SHA-256-PLUS",
+            (hash, _) => hash.mechanism(),
+        }
+    }
+
+    /// Why the last exchange failed, e.g. because the server reported an error or could not be
+    /// verified.
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// The GS2 header, which says whether channel binding is used.
+    fn gs2_header(&self) -> String {
+        let flag = match self.binding {
+            Binding::None => "n".to_string(),
+            Binding::NotOffered => "y".to_string(),
+            Binding::Bound(kind, _) => format!("p={}", kind.name()),
+        };
+        match self.authzid {
+            Some(ref authzid) => format!("{},a={},", flag, escape(authzid)),
+            None => format!("{},,", flag),
+        }
+    }
+
+    /// The value of the `c` attribute: the GS2 header, followed by the channel binding data.
+    fn channel_binding_input(&self) -> String {
+        let mut input = self.gs2_header().into_bytes();
+        if let Binding::Bound(_, ref data) = self.binding {
+            input.extend_from_slice(data);
+        }
+        base64::encode(&input)
+    }
+
+    fn client_first(&self, state: &mut State) -> Vec<u8> {
+        let nonce = self.nonce.clone().unwrap_or_else(|| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(24)
+                .collect()
+        });
+        let client_first_bare = format!("n={},r={}", escape(&self.user), nonce);
+        let message = format!("{}{}", self.gs2_header(), client_first_bare);
+        *state = State::ClientFirst {
+            nonce,
+            client_first_bare,
+        };
+        message.into_bytes()
+    }
+
+    fn client_final(
+        &self,
+        nonce: &str,
+        client_first_bare: &str,
+        server_first: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), String> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| "server-first-message is not UTF-8".to_string())?;
+        let (mut server_nonce, mut salt, mut iterations) = (None, None, None);
+        for attribute in server_first.split(',') {
+            if let Some(value) = attribute.strip_prefix("r=") {
+                server_nonce = Some(value);
+            } else if let Some(value) = attribute.strip_prefix("s=") {
+                salt = base64::decode(value).ok();
+            } else if let Some(value) = attribute.strip_prefix("i=") {
+                iterations = value.parse::<u32>().ok();
+            } else if let Some(value) = attribute.strip_prefix("e=") {
+                return Err(value.to_string());
+            } else if attribute.starts_with("m=") {
+                // RFC 5802, section 5.1: an unknown mandatory extension must fail the exchange
+                return Err("unsupported mandatory extension".to_string());
+            }
+        }
+        let server_nonce = server_nonce.ok_or("missing nonce")?;
+        let salt = salt.ok_or("missing or invalid salt")?;
+        let iterations = iterations
+            .filter(|i| *i > 0)
+            .ok_or("missing or invalid iteration count")?;
+        if iterations > MAX_ITERATIONS {
+            return Err(format!("iteration count {} is too large", iterations));
+        }
+        if !server_nonce.starts_with(nonce) || server_nonce.len() == nonce.len() {
+            return Err("server nonce does not extend client nonce".to_string());
+        }
+
+        let salted_password =
+            self.hash
+                .salted_password(self.password.expose().as_bytes(), &salt, iterations);
+        let client_key = self.hash.hmac(&salted_password, b"Client Key");
+        let stored_key = self.hash.hash(&client_key);
+        let server_key = self.hash.hmac(&salted_password, b"Server Key");
+
+        let without_proof = format!("c={},r={}", self.channel_binding_input(), server_nonce);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let client_signature = self.hash.hmac(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(&client_signature)
+            .map(|(k, s)| k ^ s)
+            .collect();
+        let server_signature = self.hash.hmac(&server_key, auth_message.as_bytes());
+
+        let message = format!("{},p={}", without_proof, base64::encode(&proof));
+        Ok((message.into_bytes(), server_signature))
+    }
+
+    /// Records `error`, and cancels the exchange unless the server reported the error itself.
+    fn fail(&self, state: &mut State, error: String, cancel: bool) -> Vec<u8> {
+        log::debug!("SCRAM exchange failed: {}", error);
+        *self.error.lock().unwrap() = Some(error);
+        *state = if cancel {
+            State::Cancelled
+        } else {
+            State::Done
+        };
+        Vec::new()
+    }
+}
+
+impl fmt::Debug for Scram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scram")
+            .field("hash", &self.hash)
+            .field("authzid", &self.authzid)
+            .field("user", &self.user)
+            .finish()
+    }
+}
+
+impl Authenticator for Scram {
+    type Response = Vec<u8>;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        let mut state = self.state.lock().unwrap();
+        if challenge.is_empty() {
+            // the server asks for the client-first-message, so a new exchange starts
+            *self.error.lock().unwrap() = None;
+            return self.client_first(&mut state);
+        }
+
+        match std::mem::replace(&mut *state, State::Done) {
+            State::ClientFirst {
+                nonce,
+                client_first_bare,
+            } => match self.client_final(&nonce, &client_first_bare, challenge) {
+                Ok((message, server_signature)) => {
+                    *state = State::ClientFinal { server_signature };
+                    message
+                }
+                Err(error) => self.fail(&mut state, error, true),
+            },
+            State::ClientFinal { server_signature } => {
+                let server_final = String::from_utf8_lossy(challenge);
+                if let Some(error) = server_final.strip_prefix("e=") {
+                    // the server fails the command after this
+                    return self.fail(&mut state, error.to_string(), false);
+                }
+                let verifier = server_final
+                    .strip_prefix("v=")
+                    .and_then(|v| base64::decode(v.split(',').next().unwrap_or_default()).ok());
+                if verifier.as_deref() != Some(&server_signature[..]) {
+                    return self.fail(&mut state, "invalid server signature".to_string(), true);
+                }
+                *state = State::Verified;
+                Vec::new()
+            }
+            State::Initial | State::Done | State::Verified | State::Cancelled => {
+                self.fail(&mut state, "unexpected challenge".to_string(), true)
+            }
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        // an unverified server is not trusted, even if it would accept the client
+        match *self.state.lock().unwrap() {
+            State::Cancelled => true,
+            _ => false,
+        }
+    }
+
+    fn completed(&self) -> bool {
+        // a server that accepts the client without proving itself is not trusted either
+        match *self.state.lock().unwrap() {
+            State::Verified => true,
+            _ => false,
+        }
+    }
+
+    fn client_first(&self) -> bool {
+        true
+    }
+}
+
+/// Escapes `,` and `=` in a `saslname`.
+fn escape(name: &str) -> String {
+    name.replace('=', "=3D").replace(',', "=2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(auth: &Scram, server_first: &str, server_final: &str) -> String {
+        assert!(auth.process(b"").starts_with(b"n,,n=user,r="));
+        let client_final = auth.process(server_first.as_bytes());
+        assert!(!auth.cancelled());
+        assert!(!auth.completed());
+        assert_eq!(auth.process(server_final.as_bytes()), b"".to_vec());
+        assert!(auth.completed());
+        String::from_utf8(client_final).unwrap()
+    }
+
+    #[test]
+    fn sha1() {
+        // RFC 5802, section 5
+        let mut auth = Scram::sha1("user", "pencil");
+        auth.nonce = Some("fyko+d2lbbFgONRv9qkxdawL".into());
+        let client_final = exchange(
+            &auth,
+            "r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096",
+            "v=rmF9pqV8S7suAoZWja4dJRkFsKQ=",
+        );
+        assert_eq!(
+            client_final,
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        assert!(!auth.cancelled());
+        assert_eq!(auth.error(), None);
+    }
+
+    #[test]
+    fn sha256() {
+        // RFC 7677, section 3
+        let mut auth = Scram::sha256("user", "pencil");
+        auth.nonce = Some("rOprNGfwEbeRWgbNEkqO".into());
+        let client_final = exchange(
+            &auth,
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+            "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=",
+        );
+        assert_eq!(
+            client_final,
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert!(!auth.cancelled());
+    }
+
+    #[test]
+    fn plus() {
+        let mut auth = Scram::sha256("user", "pencil")
+            .authzid("admin")
+            .channel_binding(ChannelBindingType::TlsExporter, vec![1, 2, 3]);
+        auth.nonce = Some("rOprNGfwEbeRWgbNEkqO".into());
+        assert_eq!(auth.mechanism(), "SCRAM-SHA-256-PLUS");
+        assert_eq!(
+            auth.process(b""),
+            b"p=tls-exporter,a=admin,n=user,r=rOprNGfwEbeRWgbNEkqO".to_vec()
+        );
+        let client_final = auth.process(
+            b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+              s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        );
+        let expected = format!(
+            "c={},r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=",
+            base64::encode(b"p=tls-exporter,a=admin,\x01\x02\x03")
+        );
+        assert!(client_final.starts_with(expected.as_bytes()));
+
+        let auth = Scram::sha1("user", "pencil").channel_binding_not_offered();
+        assert_eq!(auth.mechanism(), "SCRAM-SHA-1");
+        assert!(auth.process(b"").starts_with(b"y,,n=user,r="));
+    }
+
+    #[test]
+    fn invalid_server_signature() {
+        let mut auth = Scram::sha256("user", "pencil");
+        auth.nonce = Some("rOprNGfwEbeRWgbNEkqO".into());
+        auth.process(b"");
+        auth.process(
+            b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+              s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096",
+        );
+        auth.process(b"v=AAAA");
+        assert!(auth.cancelled());
+        assert!(!auth.completed());
+        assert_eq!(auth.error(), Some("invalid server signature".into()));
+
+        // a new exchange starts over
+        auth.process(b"");
+        assert!(!auth.cancelled());
+        assert_eq!(auth.error(), None);
+    }
+
+    #[test]
+    fn invalid_server_first() {
+        let mut auth = Scram::sha256("user", "pencil");
+        auth.nonce = Some("rOprNGfwEbeRWgbNEkqO".into());
+        auth.process(b"");
+        assert_eq!(
+            auth.process(b"r=rOprNGfwEbeRWgbNEkqOxyz,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4294967295"),
+            b"".to_vec()
+        );
+        assert!(auth.cancelled());
+        assert_eq!(
+            auth.error(),
+            Some("iteration count 4294967295 is too large".into())
+        );
+
+        auth.process(b"");
+        auth.process(b"m=ext,r=rOprNGfwEbeRWgbNEkqOxyz,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096");
+        assert!(auth.cancelled());
+        assert_eq!(auth.error(), Some("unsupported mandatory extension".into()));
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+
+use zeroize::Zeroize;
+
+/// A password or access token, which is wiped from memory when it is dropped and never shows up
+/// in `Debug` output.
+///
+/// Copies that are made while the secret is sent, e.g. the encoded command, are not wiped, so
+/// this only limits how long it stays around.
+///
+/// ```
+/// use async_imap::Secret;
+///
+/// let password = Secret::from("hunter2");
+/// assert_eq!(format!("{:?}", password), "Secret(<redacted>)");
+/// assert_eq!(password.expose(), "hunter2");
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wraps `secret`.
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Secret(secret.into())
+    }
+
+    /// The secret itself.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Secret(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Secret(secret.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
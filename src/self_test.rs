@@ -0,0 +1,263 @@
+//! A check of how closely a server follows the protocol, see [`Session::self_test`].
+//!
+//! The self-test sends a few commands that do not change anything on the server, and looks at
+//! the responses for the deviations from [RFC 3501](https://tools.ietf.org/html/rfc3501) that
+//! are known to trip up clients. The resulting [`ConformanceReport`] prints as plain text, so it
+//! can be attached to a bug report against the server, or against this crate.
+//!
+//! [`Session::self_test`]: ../struct.Session.html#method.self_test
+
+use std::fmt;
+
+use futures::channel::mpsc;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::prelude::*;
+use imap_proto::types::Capability as CapabilityRef;
+use imap_proto::{MailboxDatum, Response};
+
+use crate::client::Session;
+use crate::error::{Error, Result};
+use crate::parse::handle_unilateral;
+use crate::quirks::Quirks;
+use crate::types::{ResponseData, ServerId, StatusAttribute};
+
+const CAPABILITY: &str = "CAPABILITY";
+const NOOP: &str = "NOOP";
+const LIST_ROOT: &str = "LIST \"\" \"\"";
+const STATUS_INBOX: &str = "STATUS INBOX (MESSAGES UIDNEXT UIDVALIDITY UNSEEN)";
+
+/// What [`Session::self_test`](crate::Session::self_test) found out about the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// The server's answer to the last `ID` command, if any. The self-test does not send one,
+    /// so call [`Session::id`](crate::Session::id) first to include it.
+    pub server_id: Option<ServerId>,
+    /// The quirks that were detected from `server_id`, or set by the application.
+    pub quirks: Quirks,
+    /// The capabilities the server advertised, in the order in which it sent them.
+    pub capabilities: Vec<String>,
+    /// The outcome of each command, in the order in which they were sent.
+    pub checks: Vec<Check>,
+}
+
+impl ConformanceReport {
+    /// Whether every command succeeded without any deviation.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(Check::passed)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "async-imap {} self-test", env!("CARGO_PKG_VERSION"))?;
+        match self.server_id {
+            Some(ref id) => {
+                write!(f, "server:")?;
+                for (key, value) in id.fields() {
+                    write!(f, " {}={}", key, value.unwrap_or("NIL"))?;
+                }
+                writeln!(f)?;
+            }
+            None => writeln!(f, "server: unknown, no ID was exchanged")?,
+        }
+        writeln!(f, "quirks: {:?}", self.quirks)?;
+        writeln!(f, "capabilities: {}", self.capabilities.join(" "))?;
+        for check in &self.checks {
+            write!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of one of the commands of the self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// The command, as it was sent, without its tag.
+    pub command: &'static str,
+    /// Why the server rejected the command with `NO` or `BAD`, if it did.
+    pub error: Option<String>,
+    /// How the responses deviated from the standard.
+    pub deviations: Vec<String>,
+}
+
+impl Check {
+    /// Whether the command succeeded without any deviation.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.deviations.is_empty()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error {
+            Some(ref error) => writeln!(f, "{}: rejected: {}", self.command, error)?,
+            None if self.deviations.is_empty() => writeln!(f, "{}: ok", self.command)?,
+            None => writeln!(f, "{}: deviates", self.command)?,
+        }
+        for deviation in &self.deviations {
+            writeln!(f, "  - {}", deviation)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) async fn run<T: AsyncRead + AsyncWrite + Unpin>(
+    session: &mut Session<T>,
+) -> Result<ConformanceReport> {
+    let mut report = ConformanceReport {
+        server_id: session.server_id().cloned(),
+        quirks: session.quirks().clone(),
+        capabilities: Vec::new(),
+        checks: Vec::new(),
+    };
+
+    for &command in &[CAPABILITY, NOOP, LIST_ROOT, STATUS_INBOX] {
+        let (responses, unrecognized, error) = exchange(session, command).await?;
+        let mut check = Check {
+            command,
+            error,
+            deviations: unrecognized
+                .into_iter()
+                .map(|raw| format!("unrecognized response: {}", raw))
+                .collect(),
+        };
+
+        let mut seen = 0;
+        for res in responses {
+            match res.parsed() {
+                Response::Capabilities(capabilities) if command == CAPABILITY => {
+                    seen += 1;
+                    report
+                        .capabilities
+                        .extend(capabilities.iter().map(capability_name));
+                }
+                Response::MailboxData(MailboxDatum::List { name, .. }) if command == LIST_ROOT => {
+                    seen += 1;
+                    if !name.is_empty() {
+                        check
+                            .deviations
+                            .push(format!("the root name is {:?} instead of empty", name));
+                    }
+                }
+                Response::MailboxData(MailboxDatum::Status { mailbox, status })
+                    if command == STATUS_INBOX =>
+                {
+                    seen += 1;
+                    if !mailbox.eq_ignore_ascii_case("INBOX") {
+                        check
+                            .deviations
+                            .push(format!("the STATUS response is for {:?}", mailbox));
+                    }
+                    check.deviations.extend(status_deviations(status));
+                }
+                _ => handle_unilateral(res, session.unsolicited_responses_tx.clone()).await,
+            }
+        }
+
+        if check.error.is_none() {
+            let expected = match command {
+                CAPABILITY => "CAPABILITY",
+                LIST_ROOT => "LIST",
+                STATUS_INBOX => "STATUS",
+                _ => "",
+            };
+            if !expected.is_empty() && seen != 1 {
+                check.deviations.push(format!(
+                    "{} untagged {} responses instead of one",
+                    seen, expected
+                ));
+            }
+        }
+        if command == CAPABILITY
+            && seen > 0
+            && !report.capabilities.iter().any(|c| c == "IMAP4rev1")
+        {
+            check
+                .deviations
+                .push("IMAP4rev1 is not advertised".to_string());
+        }
+        report.checks.push(check);
+    }
+
+    Ok(report)
+}
+
+/// Runs `command`, and returns the untagged responses to it, the ones that could not be parsed,
+/// and the reason why the server rejected the command, if it did. Responses that can not be
+/// parsed are captured in raw form while the command runs, and passed on to the channel of
+/// [`Session::unrecognized_responses`](crate::Session::unrecognized_responses) as well, if any.
+async fn exchange<T: AsyncRead + AsyncWrite + Unpin>(
+    session: &mut Session<T>,
+    command: &str,
+) -> Result<(Vec<ResponseData>, Vec<String>, Option<String>)> {
+    let (tx, mut rx) = mpsc::unbounded();
+    let previous = std::mem::replace(&mut session.conn.stream.protocol.unrecognized, Some(tx));
+    let res = collect(session, command).await;
+    session.conn.stream.protocol.unrecognized = previous;
+
+    let mut unrecognized = Vec::new();
+    while let Ok(Some(raw)) = rx.try_next() {
+        unrecognized.push(
+            String::from_utf8_lossy(raw.as_bytes())
+                .trim_end()
+                .to_string(),
+        );
+        if let Some(ref tx) = session.conn.stream.protocol.unrecognized {
+            let _ = tx.unbounded_send(raw);
+        }
+    }
+
+    let (responses, error) = res?;
+    Ok((responses, unrecognized, error))
+}
+
+async fn collect<T: AsyncRead + AsyncWrite + Unpin>(
+    session: &mut Session<T>,
+    command: &str,
+) -> Result<(Vec<ResponseData>, Option<String>)> {
+    let tag = session.run_command(command).await?;
+    let responses = session.responses(&tag).try_collect().await?;
+    match session.check_done(&tag).await {
+        Ok(_) => Ok((responses, None)),
+        Err(Error::Bad(information)) => Ok((responses, Some(format!("BAD {}", information)))),
+        Err(Error::No(information)) => Ok((responses, Some(format!("NO {}", information)))),
+        Err(err) => Err(err),
+    }
+}
+
+fn capability_name(capability: &CapabilityRef<'_>) -> String {
+    match capability {
+        CapabilityRef::Imap4rev1 => "IMAP4rev1".to_string(),
+        CapabilityRef::Auth(mechanism) => format!("AUTH={}", mechanism),
+        CapabilityRef::Atom(atom) => (*atom).to_string(),
+    }
+}
+
+/// The ways in which the attributes of a response to `STATUS_INBOX` deviate from what was asked
+/// for.
+fn status_deviations(status: &[StatusAttribute]) -> Vec<String> {
+    let mut deviations = Vec::new();
+    let mut missing = vec!["MESSAGES", "UIDNEXT", "UIDVALIDITY", "UNSEEN"];
+    for attribute in status {
+        let name = match attribute {
+            StatusAttribute::Messages(_) => "MESSAGES",
+            StatusAttribute::UidNext(0) => {
+                deviations.push("UIDNEXT is 0, which is not a valid UID".to_string());
+                "UIDNEXT"
+            }
+            StatusAttribute::UidValidity(0) => {
+                deviations.push("UIDVALIDITY is 0, which is not a valid value".to_string());
+                "UIDVALIDITY"
+            }
+            StatusAttribute::UidNext(_) => "UIDNEXT",
+            StatusAttribute::UidValidity(_) => "UIDVALIDITY",
+            StatusAttribute::Unseen(_) => "UNSEEN",
+            _ => continue,
+        };
+        missing.retain(|&m| m != name);
+    }
+    if !missing.is_empty() {
+        deviations.push(format!("{} are missing", missing.join(", ")));
+    }
+    deviations
+}
@@ -0,0 +1,164 @@
+//! Persistent per-mailbox synchronization state.
+//!
+//! Clients that keep a local copy of a mailbox need to remember what they have already seen: the
+//! `UIDVALIDITY` the local copy belongs to, the highest mod-sequence they have synchronized to (see
+//! [RFC 7162](https://tools.ietf.org/html/rfc7162)), and the UIDs and flags of the known messages.
+//! The [`SyncStateStore`] trait abstracts over where that state lives, so that applications can
+//! plug in a database backend while the protocol logic (e.g. [`Session::select_synced`]) stays in
+//! this crate. [`MemoryStore`] is a simple implementation that keeps everything in memory.
+//!
+//! [`Session::select_synced`]: ../struct.Session.html#method.select_synced
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use crate::types::{Flag, Uid};
+
+/// The synchronization state of a single mailbox.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MailboxState {
+    /// The `UIDVALIDITY` of the mailbox that the known UIDs belong to.
+    pub uid_validity: Option<u32>,
+
+    /// The highest mod-sequence that has been synchronized, if the server supports `CONDSTORE`.
+    pub highest_modseq: Option<u64>,
+
+    /// The flags of all known messages, by UID.
+    pub messages: BTreeMap<Uid, Vec<Flag<'static>>>,
+}
+
+/// A storage backend for [`MailboxState`].
+///
+/// All methods are keyed by mailbox name. Errors are reported as `io::Error`s; backends should
+/// wrap their own error types using `io::ErrorKind::Other`.
+pub trait SyncStateStore {
+    /// The stored `UIDVALIDITY` of `mailbox`, if any.
+    fn uid_validity(&self, mailbox: &str) -> io::Result<Option<u32>>;
+
+    /// Stores the `UIDVALIDITY` of `mailbox`.
+    fn set_uid_validity(&mut self, mailbox: &str, uid_validity: u32) -> io::Result<()>;
+
+    /// The highest mod-sequence that `mailbox` has been synchronized to, if any.
+    fn highest_modseq(&self, mailbox: &str) -> io::Result<Option<u64>>;
+
+    /// Stores the highest mod-sequence that `mailbox` has been synchronized to.
+    fn set_highest_modseq(&mut self, mailbox: &str, modseq: u64) -> io::Result<()>;
+
+    /// The UIDs of all known messages in `mailbox`, in ascending order.
+    fn known_uids(&self, mailbox: &str) -> io::Result<Vec<Uid>>;
+
+    /// The flags of the message with the given `uid`, or `None` if the message is unknown.
+    fn flags(&self, mailbox: &str, uid: Uid) -> io::Result<Option<Vec<Flag<'static>>>>;
+
+    /// Records the message with the given `uid` and its current flags.
+    fn set_flags(&mut self, mailbox: &str, uid: Uid, flags: Vec<Flag<'static>>) -> io::Result<()>;
+
+    /// Forgets the messages with the given UIDs, e.g. after they have been expunged.
+    fn remove(&mut self, mailbox: &str, uids: &[Uid]) -> io::Result<()>;
+
+    /// Forgets everything about `mailbox`. This is necessary when its `UIDVALIDITY` changes.
+    fn reset(&mut self, mailbox: &str) -> io::Result<()>;
+}
+
+/// A [`SyncStateStore`] that keeps all state in memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStore {
+    mailboxes: HashMap<String, MailboxState>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// The complete state of `mailbox`, if anything is known about it.
+    pub fn get(&self, mailbox: &str) -> Option<&MailboxState> {
+        self.mailboxes.get(mailbox)
+    }
+
+    fn entry(&mut self, mailbox: &str) -> &mut MailboxState {
+        self.mailboxes.entry(mailbox.to_string()).or_default()
+    }
+}
+
+impl SyncStateStore for MemoryStore {
+    fn uid_validity(&self, mailbox: &str) -> io::Result<Option<u32>> {
+        Ok(self.get(mailbox).and_then(|state| state.uid_validity))
+    }
+
+    fn set_uid_validity(&mut self, mailbox: &str, uid_validity: u32) -> io::Result<()> {
+        self.entry(mailbox).uid_validity = Some(uid_validity);
+        Ok(())
+    }
+
+    fn highest_modseq(&self, mailbox: &str) -> io::Result<Option<u64>> {
+        Ok(self.get(mailbox).and_then(|state| state.highest_modseq))
+    }
+
+    fn set_highest_modseq(&mut self, mailbox: &str, modseq: u64) -> io::Result<()> {
+        self.entry(mailbox).highest_modseq = Some(modseq);
+        Ok(())
+    }
+
+    fn known_uids(&self, mailbox: &str) -> io::Result<Vec<Uid>> {
+        Ok(self
+            .get(mailbox)
+            .map(|state| state.messages.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn flags(&self, mailbox: &str, uid: Uid) -> io::Result<Option<Vec<Flag<'static>>>> {
+        Ok(self
+            .get(mailbox)
+            .and_then(|state| state.messages.get(&uid))
+            .cloned())
+    }
+
+    fn set_flags(&mut self, mailbox: &str, uid: Uid, flags: Vec<Flag<'static>>) -> io::Result<()> {
+        self.entry(mailbox).messages.insert(uid, flags);
+        Ok(())
+    }
+
+    fn remove(&mut self, mailbox: &str, uids: &[Uid]) -> io::Result<()> {
+        if let Some(state) = self.mailboxes.get_mut(mailbox) {
+            for uid in uids {
+                state.messages.remove(uid);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, mailbox: &str) -> io::Result<()> {
+        self.mailboxes.remove(mailbox);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store() {
+        let mut store = MemoryStore::new();
+        assert_eq!(store.uid_validity("INBOX").unwrap(), None);
+        assert!(store.known_uids("INBOX").unwrap().is_empty());
+
+        store.set_uid_validity("INBOX", 42).unwrap();
+        store.set_highest_modseq("INBOX", 1000).unwrap();
+        store.set_flags("INBOX", 7, vec![Flag::Seen]).unwrap();
+        store.set_flags("INBOX", 3, vec![]).unwrap();
+        assert_eq!(store.uid_validity("INBOX").unwrap(), Some(42));
+        assert_eq!(store.highest_modseq("INBOX").unwrap(), Some(1000));
+        assert_eq!(store.known_uids("INBOX").unwrap(), vec![3, 7]);
+        assert_eq!(store.flags("INBOX", 7).unwrap(), Some(vec![Flag::Seen]));
+
+        store.remove("INBOX", &[3]).unwrap();
+        assert_eq!(store.known_uids("INBOX").unwrap(), vec![7]);
+        assert_eq!(store.flags("INBOX", 3).unwrap(), None);
+
+        store.reset("INBOX").unwrap();
+        assert_eq!(store.get("INBOX"), None);
+    }
+}
@@ -0,0 +1,453 @@
+use std::fmt;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use crate::authenticator::Authenticator;
+use crate::builder::{ClientBuilder, MaybeTlsStream};
+use crate::client::{Client, Session};
+use crate::credentials::{CredentialProvider, Credentials};
+use crate::error::{Error, Result};
+use crate::quirks::Quirks;
+use crate::retry::{self, RetryPolicy};
+use crate::sasl::{OAuth, OAuthBearer, XOAuth2};
+use crate::secret::Secret;
+
+/// How a [`SessionTemplate`] logs in.
+#[derive(Clone)]
+pub enum TemplateAuth {
+    /// [`Client::login`](crate::Client::login) with a user name and password.
+    Login {
+        /// The user name.
+        username: String,
+        /// The password.
+        password: Secret,
+    },
+    /// [`Client::authenticate`](crate::Client::authenticate) with a SASL mechanism.
+    Authenticate {
+        /// The name of the mechanism, e.g. `PLAIN`.
+        mechanism: String,
+        /// Answers the challenges of the server.
+        authenticator: Arc<dyn Authenticator<Response = Vec<u8>> + Send + Sync>,
+    },
+    /// [`Client::authenticate_oauth`](crate::Client::authenticate_oauth) with an OAuth
+    /// mechanism, which can refresh its token, see [`TokenProvider`](crate::sasl::TokenProvider).
+    OAuth(Arc<dyn OAuth<Response = Vec<u8>> + Send + Sync>),
+    /// Whatever the [`CredentialProvider`] returns at the time of each login.
+    Provider(Arc<dyn CredentialProvider>),
+}
+
+impl fmt::Debug for TemplateAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateAuth::Login { username, .. } => f
+                .debug_struct("TemplateAuth::Login")
+                .field("username", username)
+                .finish(),
+            TemplateAuth::Authenticate { mechanism, .. } => f
+                .debug_struct("TemplateAuth::Authenticate")
+                .field("mechanism", mechanism)
+                .finish(),
+            TemplateAuth::OAuth(auth) => f
+                .debug_struct("TemplateAuth::OAuth")
+                .field("mechanism", &auth.mechanism())
+                .finish(),
+            TemplateAuth::Provider(_) => write!(f, "TemplateAuth::Provider(..)"),
+        }
+    }
+}
+
+/// Turns the responses of any [`Authenticator`] into the `Vec<u8>` that
+/// [`TemplateAuth::Authenticate`] expects.
+struct ToVec<A>(A);
+
+impl<A: Authenticator> Authenticator for ToVec<A> {
+    type Response = Vec<u8>;
+
+    fn process(&self, challenge: &[u8]) -> Self::Response {
+        self.0.process(challenge).as_ref().to_vec()
+    }
+
+    fn cancelled(&self) -> bool {
+        self.0.cancelled()
+    }
+
+    fn completed(&self) -> bool {
+        self.0.completed()
+    }
+
+    fn client_first(&self) -> bool {
+        self.0.client_first()
+    }
+}
+
+/// Everything needed to open an authenticated [`Session`]: the server and connection settings of
+/// a [`ClientBuilder`], including TLS and timeouts, the credentials, the extensions to `ENABLE`
+/// and the server [`Quirks`].
+///
+/// Code that needs more connections of the same kind, e.g. to reconnect after an error or to
+/// watch several mailboxes at once, can [`spawn`](SessionTemplate::spawn) them from a shared
+/// template instead of being handed all the settings separately.
+///
+/// ```no_run
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// use async_imap::{ClientBuilder, SessionTemplate};
+///
+/// let template = SessionTemplate::login(
+///     ClientBuilder::new("imap.example.org", 993),
+///     "user",
+///     "password",
+/// )
+/// .enable("CONDSTORE");
+/// let mut inbox = template.spawn().await?;
+/// let mut sent = template.spawn().await?;
+/// # Ok(())
+/// # }) }
+/// ```
+#[derive(Debug)]
+pub struct SessionTemplate {
+    builder: ClientBuilder,
+    auth: TemplateAuth,
+    enable: Vec<String>,
+    quirks: Option<Quirks>,
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl SessionTemplate {
+    /// Creates a template for sessions that connect as configured by `builder`, and log in with
+    /// `username` and `password`.
+    pub fn login<U: Into<String>, P: Into<Secret>>(
+        builder: ClientBuilder,
+        username: U,
+        password: P,
+    ) -> Self {
+        SessionTemplate::new(
+            builder,
+            TemplateAuth::Login {
+                username: username.into(),
+                password: password.into(),
+            },
+        )
+    }
+
+    /// Creates a template for sessions that connect as configured by `builder`, and
+    /// authenticate with the SASL `mechanism` using `authenticator`.
+    pub fn authenticate<S, A>(builder: ClientBuilder, mechanism: S, authenticator: A) -> Self
+    where
+        S: Into<String>,
+        A: Authenticator + Send + Sync + 'static,
+    {
+        SessionTemplate::new(
+            builder,
+            TemplateAuth::Authenticate {
+                mechanism: mechanism.into(),
+                authenticator: Arc::new(ToVec(authenticator)),
+            },
+        )
+    }
+
+    /// Creates a template for sessions that connect as configured by `builder`, and
+    /// authenticate with the OAuth mechanism `auth`. If it was created with a
+    /// [`TokenProvider`](crate::sasl::TokenProvider), each session asks it for a token, so that
+    /// reconnecting does not fail once the first token has expired.
+    pub fn oauth<A>(builder: ClientBuilder, auth: A) -> Self
+    where
+        A: OAuth<Response = Vec<u8>> + Send + Sync + 'static,
+    {
+        SessionTemplate::new(builder, TemplateAuth::OAuth(Arc::new(auth)))
+    }
+
+    /// Creates a template for sessions that connect as configured by `builder`, and log in with
+    /// the credentials that `provider` returns for each session.
+    pub fn with_credentials<P: CredentialProvider + 'static>(
+        builder: ClientBuilder,
+        provider: P,
+    ) -> Self {
+        SessionTemplate::new(builder, TemplateAuth::Provider(Arc::new(provider)))
+    }
+
+    /// Creates a template from its parts.
+    pub fn new(builder: ClientBuilder, auth: TemplateAuth) -> Self {
+        SessionTemplate {
+            builder,
+            auth,
+            enable: Vec::new(),
+            quirks: None,
+            retry_policy: None,
+        }
+    }
+
+    /// Enables `capability` after logging in, see [`Session::enable`]. May be called several
+    /// times, and all capabilities are enabled with a single command.
+    pub fn enable<S: Into<String>>(mut self, capability: S) -> Self {
+        self.enable.push(capability.into());
+        self
+    }
+
+    /// Overrides the server quirks of new sessions, see [`Session::set_quirks`].
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// Sets the retry policy of new sessions, see [`Session::set_retry_policy`], and of
+    /// [`SessionTemplate::retry`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// The connection settings.
+    pub fn builder(&self) -> &ClientBuilder {
+        &self.builder
+    }
+
+    /// How new sessions log in.
+    pub fn auth(&self) -> &TemplateAuth {
+        &self.auth
+    }
+
+    /// Connects, logs in and sets up a new session.
+    pub async fn spawn(&self) -> Result<Session<MaybeTlsStream>> {
+        let client = self.builder.connect().await?;
+        let mut session = match self.auth {
+            TemplateAuth::Login {
+                ref username,
+                ref password,
+            } => client.login(username, password.expose()).await,
+            TemplateAuth::Authenticate {
+                ref mechanism,
+                ref authenticator,
+            } => client.authenticate(mechanism, &**authenticator).await,
+            TemplateAuth::OAuth(ref auth) => client.authenticate_oauth(&**auth).await,
+            TemplateAuth::Provider(ref provider) => {
+                let credentials = provider.credentials().await?;
+                let res = log_in(client, &credentials).await;
+                if let Err((Error::Auth(_), _)) = res {
+                    provider.rejected(&credentials);
+                }
+                res
+            }
+        }
+        .map_err(|(err, _client)| err)?;
+
+        if !self.enable.is_empty() {
+            session.enable(&self.enable[..]).await?;
+        }
+        if let Some(ref quirks) = self.quirks {
+            session.set_quirks(quirks.clone());
+        }
+        session.set_retry_policy(self.retry_policy.clone());
+
+        Ok(session)
+    }
+
+    /// Runs `op` on `session`, and runs it again as long as the retry policy of the template, or
+    /// the default [`RetryPolicy`] if none was set, says that the error it failed with is worth
+    /// another attempt. Unlike [`Session::retry`], this also survives a dropped connection: the
+    /// broken `session` is replaced by a newly [spawned](SessionTemplate::spawn) one before `op`
+    /// runs again.
+    ///
+    /// A new session has no mailbox selected, so `op` should select the one it needs itself, and
+    /// it must be safe to run more than once.
+    ///
+    /// ```no_run
+    /// # use futures::prelude::*;
+    /// # fn main() -> async_imap::error::Result<()> {
+    /// # async_std::task::block_on(async {
+    /// use async_imap::{ClientBuilder, SessionTemplate};
+    ///
+    /// let template =
+    ///     SessionTemplate::login(ClientBuilder::new("imap.example.org", 993), "user", "password");
+    /// let mut session = template.spawn().await?;
+    /// let unseen = template
+    ///     .retry(&mut session, |session| {
+    ///         async move {
+    ///             session.examine("INBOX").await?;
+    ///             session.search("UNSEEN").await
+    ///         }
+    ///         .boxed()
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }) }
+    /// ```
+    pub async fn retry<R, F>(&self, session: &mut Session<MaybeTlsStream>, mut op: F) -> Result<R>
+    where
+        F: for<'s> FnMut(&'s mut Session<MaybeTlsStream>) -> BoxFuture<'s, Result<R>>,
+    {
+        let policy = self.retry_policy.clone().unwrap_or_default();
+        let mut attempt = 1;
+        let mut reconnect = false;
+        loop {
+            let res = if reconnect {
+                match self.spawn().await {
+                    Ok(new) => {
+                        *session = new;
+                        reconnect = false;
+                        op(&mut *session).await
+                    }
+                    Err(err) => Err(err),
+                }
+            } else {
+                op(&mut *session).await
+            };
+            match res {
+                Err(ref err) if policy.should_retry(err, attempt) => {
+                    log::debug!("attempt {} failed, retrying: {}", attempt, err);
+                    // until reconnecting succeeds, the session stays broken
+                    reconnect = reconnect || retry::is_connection_error(err);
+                    policy.wait(attempt).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Logs in with `credentials`, using the mechanism they are meant for.
+async fn log_in(
+    client: Client<MaybeTlsStream>,
+    credentials: &Credentials,
+) -> ::std::result::Result<Session<MaybeTlsStream>, (Error, Client<MaybeTlsStream>)> {
+    match credentials {
+        Credentials::Password { username, password } => {
+            client.login(username, password.expose()).await
+        }
+        Credentials::XOAuth2 { user, access_token } => {
+            let auth = XOAuth2::new(user.as_str(), access_token.clone());
+            client.authenticate(XOAuth2::MECHANISM, &auth).await
+        }
+        Credentials::OAuthBearer { user, access_token } => {
+            let auth = OAuthBearer::new(user.as_str(), access_token.clone());
+            client.authenticate(OAuthBearer::MECHANISM, &auth).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use futures::future::FutureExt;
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::endpoint::TlsMode;
+
+    /// Accepts `n` connections, and expects each of them to log in and enable `CONDSTORE`.
+    async fn serve(listener: TcpListener, n: usize) -> std::io::Result<()> {
+        for _ in 0..n {
+            let (mut stream, _) = listener.accept().await?;
+            stream.write_all(b"* OK ready\r\n").await?;
+            let mut buf = [0; 64];
+            let len = stream.read(&mut buf).await?;
+            assert_eq!(&buf[..len], b"A0001 LOGIN \"user\" \"pass\"\r\n");
+            stream.write_all(b"A0001 OK logged in\r\n").await?;
+            let len = stream.read(&mut buf).await?;
+            assert_eq!(&buf[..len], b"A0002 ENABLE CONDSTORE\r\n");
+            stream.write_all(b"A0002 OK enabled\r\n").await?;
+        }
+        Ok(())
+    }
+
+    /// Hands out a new password for each login.
+    #[derive(Default)]
+    struct Counter(std::sync::atomic::AtomicUsize);
+
+    impl CredentialProvider for Counter {
+        fn credentials(&self) -> crate::credentials::CredentialsFuture<'_> {
+            let n = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Box::pin(async move {
+                Ok(Credentials::Password {
+                    username: "user".into(),
+                    password: format!("pass{}", n).into(),
+                })
+            })
+        }
+    }
+
+    #[async_attributes::test]
+    async fn spawn_with_credentials() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = task::spawn(async move {
+            for i in 0..2 {
+                let (mut stream, _) = listener.accept().await?;
+                stream.write_all(b"* OK ready\r\n").await?;
+                let mut buf = [0; 64];
+                let len = stream.read(&mut buf).await?;
+                let expected = format!("A0001 LOGIN \"user\" \"pass{}\"\r\n", i);
+                assert_eq!(&buf[..len], expected.as_bytes());
+                stream.write_all(b"A0001 OK logged in\r\n").await?;
+            }
+            std::io::Result::Ok(())
+        });
+
+        let builder = ClientBuilder::new("127.0.0.1", port).tls_mode(TlsMode::Plaintext);
+        let template = SessionTemplate::with_credentials(builder, Counter::default());
+        for _ in 0..2 {
+            template.spawn().await.unwrap();
+        }
+        server.await.unwrap();
+    }
+
+    #[async_attributes::test]
+    async fn spawn() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = task::spawn(serve(listener, 2));
+
+        let builder = ClientBuilder::new("127.0.0.1", port)
+            .tls_mode(TlsMode::Plaintext)
+            .idle_logout(Duration::from_secs(60));
+        let template = SessionTemplate::login(builder, "user", "pass")
+            .enable("CONDSTORE")
+            .quirks(Quirks::default());
+
+        for _ in 0..2 {
+            let session = template.spawn().await.unwrap();
+            assert_eq!(session.idle_logout, Some(Duration::from_secs(60)));
+        }
+        server.await.unwrap();
+    }
+
+    #[async_attributes::test]
+    async fn retry_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = task::spawn(async move {
+            for i in 0..2 {
+                let (mut stream, _) = listener.accept().await?;
+                stream.write_all(b"* OK ready\r\n").await?;
+                let mut buf = [0; 64];
+                stream.read(&mut buf).await?;
+                stream.write_all(b"A0001 OK logged in\r\n").await?;
+                let len = stream.read(&mut buf).await?;
+                assert_eq!(&buf[..len], b"A0002 NOOP\r\n");
+                if i == 1 {
+                    stream.write_all(b"A0002 OK NOOP completed\r\n").await?;
+                }
+                // the first connection is dropped without an answer
+            }
+            std::io::Result::Ok(())
+        });
+
+        let builder = ClientBuilder::new("127.0.0.1", port).tls_mode(TlsMode::Plaintext);
+        let policy =
+            RetryPolicy::new(3).backoff(Duration::from_millis(0), Duration::from_millis(0));
+        let template = SessionTemplate::login(builder, "user", "pass").retry_policy(policy);
+        let mut session = template.spawn().await.unwrap();
+        assert!(session.retry_policy().is_some());
+        template
+            .retry(&mut session, |session| session.noop().boxed())
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+}
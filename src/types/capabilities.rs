@@ -5,8 +5,21 @@ use std::collections::HashSet;
 const IMAP4REV1_CAPABILITY: &str = "IMAP4rev1";
 const AUTH_CAPABILITY_PREFIX: &str = "AUTH=";
 
+/// The mechanism of an `AUTH=<mechanism>` capability, or `None` if `capability` is some other
+/// one. The prefix is compared without slicing in the middle of a character, since the name may
+/// come from the server.
+fn auth_mechanism(capability: &str) -> Option<&str> {
+    let prefix = capability.get(..AUTH_CAPABILITY_PREFIX.len())?;
+    let mechanism = &capability[prefix.len()..];
+    if prefix.eq_ignore_ascii_case(AUTH_CAPABILITY_PREFIX) && !mechanism.is_empty() {
+        Some(mechanism)
+    } else {
+        None
+    }
+}
+
 /// List of available Capabilities.
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum Capability {
     /// The crucial imap capability.
     Imap4rev1,
@@ -40,9 +53,9 @@ impl From<&CapabilityRef<'_>> for Capability {
 /// authentication mechanism.
 ///
 /// The `LOGINDISABLED` capability indicates that the `LOGIN` command is disabled, and that the
-/// server will respond with a [`super::Error::No`] response to any attempt to use the `LOGIN`
-/// command even if the user name and password are valid.  An IMAP client MUST NOT issue the
-/// `LOGIN` command if the server advertises the `LOGINDISABLED` capability.
+/// server will respond with an [`Error::Auth`](crate::error::Error::Auth) to any attempt to use
+/// the `LOGIN` command even if the user name and password are valid.  An IMAP client MUST NOT
+/// issue the `LOGIN` command if the server advertises the `LOGINDISABLED` capability.
 ///
 /// Other capability names indicate that the server supports an extension, revision, or amendment
 /// to the IMAP4rev1 protocol. Capability names either begin with `X` or they are standard or
@@ -51,9 +64,36 @@ impl From<&CapabilityRef<'_>> for Capability {
 ///
 /// Client implementations SHOULD NOT require any capability name other than `IMAP4rev1`, and MUST
 /// ignore any unknown capability names.
+#[derive(Debug, Clone)]
 pub struct Capabilities(pub(crate) HashSet<Capability>);
 
 impl Capabilities {
+    /// Parses the [`CAPABILITY` response code](https://tools.ietf.org/html/rfc3501#section-7.1)
+    /// that many servers include in their greeting. imap-proto does not know it, so it is left
+    /// at the start of `information`.
+    pub(crate) fn from_response_code(information: Option<&str>) -> Option<Self> {
+        let text = information?.trim_start();
+        if !text.starts_with('[') {
+            return None;
+        }
+        let mut words = text[1..text.find(']')?].split_whitespace();
+        if !words.next()?.eq_ignore_ascii_case("CAPABILITY") {
+            return None;
+        }
+        let capabilities = words
+            .map(|word| {
+                if word.eq_ignore_ascii_case(IMAP4REV1_CAPABILITY) {
+                    return Capability::Imap4rev1;
+                }
+                match auth_mechanism(word) {
+                    Some(mechanism) => Capability::Auth(mechanism.into()),
+                    None => Capability::Atom(word.into()),
+                }
+            })
+            .collect();
+        Some(Capabilities(capabilities))
+    }
+
     /// Check if the server has the given capability.
     pub fn has(&self, cap: &Capability) -> bool {
         self.0.contains(cap)
@@ -65,15 +105,33 @@ impl Capabilities {
         if s.eq_ignore_ascii_case(IMAP4REV1_CAPABILITY) {
             return self.has(&Capability::Imap4rev1);
         }
-        if s.len() > AUTH_CAPABILITY_PREFIX.len() {
-            let (pre, val) = s.split_at(AUTH_CAPABILITY_PREFIX.len());
-            if pre.eq_ignore_ascii_case(AUTH_CAPABILITY_PREFIX) {
-                return self.has(&Capability::Auth(val.into())); // TODO: avoid clone
-            }
+        if let Some(mechanism) = auth_mechanism(s) {
+            return self.has(&Capability::Auth(mechanism.into())); // TODO: avoid clone
         }
         self.has(&Capability::Atom(s.into())) // TODO: avoid clone
     }
 
+    /// Fails with [`Error::MissingCapability`](crate::error::Error::MissingCapability) unless the
+    /// server has `cap`.
+    pub(crate) fn require(&self, cap: &str) -> crate::error::Result<()> {
+        if self.has_str(cap) {
+            return Ok(());
+        }
+        let mut present: Vec<String> = self
+            .iter()
+            .map(|c| match c {
+                Capability::Imap4rev1 => IMAP4REV1_CAPABILITY.to_string(),
+                Capability::Auth(s) => format!("{}{}", AUTH_CAPABILITY_PREFIX, s),
+                Capability::Atom(s) => s.clone(),
+            })
+            .collect();
+        present.sort();
+        Err(crate::error::Error::MissingCapability {
+            required: cap.to_string(),
+            present,
+        })
+    }
+
     /// Iterate over all the server's capabilities
     pub fn iter(&self) -> Iter<'_, Capability> {
         self.0.iter()
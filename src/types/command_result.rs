@@ -0,0 +1,82 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use imap_proto::{RequestId, Response, ResponseCode};
+
+use crate::types::ResponseData;
+
+/// The value produced by a successful command, together with the tagged `OK` response that
+/// completed it.
+///
+/// The completion often carries a [response code](https://tools.ietf.org/html/rfc3501#section-7.1)
+/// with useful information, such as `[READ-WRITE]` after a `SELECT`, `[HIGHESTMODSEQ n]` with
+/// `CONDSTORE`, or `[APPENDUID ...]` and `[COPYUID ...]` with `UIDPLUS`. A `CommandResult`
+/// dereferences to the value, so callers that are not interested in the completion can use it
+/// as if it was the value itself.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandResult<T> {
+    value: T,
+    done: Option<ResponseData>,
+}
+
+impl<T> CommandResult<T> {
+    pub(crate) fn new(value: T, done: Option<ResponseData>) -> Self {
+        CommandResult { value, done }
+    }
+
+    /// The response code of the tagged completion, if any.
+    pub fn code(&self) -> Option<&ResponseCode<'_>> {
+        match self.done.as_ref()?.parsed() {
+            Response::Done { code, .. } => code.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The tag that the command was sent with, e.g. to cancel the updates of a search with
+    /// [`Session::cancel_update`](crate::Session::cancel_update). `None` if the result did not
+    /// come from a command.
+    pub fn tag(&self) -> Option<&RequestId> {
+        self.done.as_ref()?.request_id()
+    }
+
+    /// The human-readable text of the tagged completion, if any.
+    pub fn information(&self) -> Option<&str> {
+        match self.done.as_ref()?.parsed() {
+            Response::Done { information, .. } => *information,
+            _ => None,
+        }
+    }
+
+    /// Discards the completion and returns the value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Applies `f` to the value, keeping the completion.
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> CommandResult<U> {
+        CommandResult {
+            value: f(self.value),
+            done: self.done,
+        }
+    }
+}
+
+impl<T> Deref for CommandResult<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CommandResult<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for CommandResult<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
@@ -0,0 +1,17 @@
+/// How much a connection was used, as returned by [`Connection::stats`](crate::Connection::stats)
+/// and by the `shutdown` methods, e.g. for logging when a connection is closed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ConnectionStats {
+    /// The number of bytes received from the server.
+    pub bytes_read: u64,
+
+    /// The number of bytes sent to the server, including literals.
+    pub bytes_written: u64,
+
+    /// The number of tagged commands that were sent.
+    pub commands: u64,
+
+    /// The largest number of commands that were waiting for their completion at the same time,
+    /// e.g. while a batch queued with [`Session::enqueue`](crate::Session::enqueue) was pipelined.
+    pub max_in_flight: u64,
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Offset, TimeZone, Timelike};
+
+/// Format of Date and Time as defined RFC3501.
+/// See `date-time` element in [Formal Syntax](https://tools.ietf.org/html/rfc3501#section-9)
+/// chapter of this RFC.
+pub(crate) const DATE_TIME_FORMAT: &str = "%d-%b-%Y %H:%M:%S %z";
+
+/// The month names used in IMAP dates, which are always English, regardless of the locale.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `date` as an IMAP [`date`](https://tools.ietf.org/html/rfc3501#section-9), such as
+/// `1-Feb-1994`, for the `SINCE`, `BEFORE` and `ON` criteria of
+/// [`Session::search`](crate::Session::search).
+///
+/// The month is always an English abbreviation, as the protocol requires, so that the result
+/// does not depend on the locale of the system.
+///
+/// ```
+/// use async_imap::types::format_date;
+/// use chrono::NaiveDate;
+///
+/// let date = NaiveDate::from_ymd(1994, 2, 1);
+/// assert_eq!(format!("SINCE {}", format_date(&date)), "SINCE 1-Feb-1994");
+/// ```
+pub fn format_date<D: Datelike>(date: &D) -> String {
+    format!(
+        "{}-{}-{:04}",
+        date.day(),
+        MONTHS[date.month0() as usize],
+        date.year()
+    )
+}
+
+/// Formats `date_time` as a quoted IMAP
+/// [`date-time`](https://tools.ietf.org/html/rfc3501#section-9), such as
+/// `"01-Feb-1994 08:30:00 +0100"`, e.g. for the internal date of
+/// [`Session::append_with_date`](crate::Session::append_with_date). The time is given in the
+/// time zone of `date_time`.
+///
+/// Like [`format_date`], this does not depend on the locale of the system.
+pub fn format_date_time<Tz: TimeZone>(date_time: &DateTime<Tz>) -> String {
+    let offset = date_time.offset().fix().local_minus_utc() / 60;
+    format!(
+        "\"{:02}-{}-{:04} {:02}:{:02}:{:02} {}{:02}{:02}\"",
+        date_time.day(),
+        MONTHS[date_time.month0() as usize],
+        date_time.year(),
+        date_time.hour(),
+        date_time.minute(),
+        date_time.second(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Formats the `YOUNGER` criterion of the [`WITHIN`
+/// extension](https://tools.ietf.org/html/rfc5032), which matches the messages that arrived less
+/// than `interval` ago, for [`Session::search`](crate::Session::search). Unlike `SINCE`, it
+/// does not depend on the time zone of the server, and is not limited to whole days. The server
+/// must advertise the `WITHIN` capability.
+///
+/// The interval is rounded up to whole seconds, and is at least one second.
+///
+/// ```
+/// use std::time::Duration;
+/// use async_imap::types::younger;
+///
+/// assert_eq!(format!("UNSEEN {}", younger(Duration::from_secs(3600))), "UNSEEN YOUNGER 3600");
+/// ```
+pub fn younger(interval: Duration) -> String {
+    format!("YOUNGER {}", interval_seconds(interval))
+}
+
+/// Formats the `OLDER` criterion of the [`WITHIN` extension](https://tools.ietf.org/html/rfc5032),
+/// which matches the messages that arrived at least `interval` ago, see [`younger`].
+pub fn older(interval: Duration) -> String {
+    format!("OLDER {}", interval_seconds(interval))
+}
+
+fn interval_seconds(interval: Duration) -> u32 {
+    let seconds = interval.as_secs() + u64::from(interval.subsec_nanos() > 0);
+    std::cmp::min(std::cmp::max(seconds, 1), u64::from(u32::max_value())) as u32
+}
+
+/// Whether `query` uses the criteria of the `WITHIN` extension. Quoted strings are skipped,
+/// but escaped quotes in them are not recognized, so this may rarely find criteria that are
+/// not there.
+pub(crate) fn uses_within(query: &str) -> bool {
+    query.split('"').step_by(2).any(|unquoted| {
+        unquoted
+            .split(|c| c == ' ' || c == '(' || c == ')')
+            .any(|word| word.eq_ignore_ascii_case("OLDER") || word.eq_ignore_ascii_case("YOUNGER"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{FixedOffset, NaiveDate};
+
+    #[test]
+    fn date_time() {
+        let date_time = FixedOffset::west(3 * 3600 + 30 * 60)
+            .ymd(2020, 12, 3)
+            .and_hms(7, 5, 9);
+        let formatted = format_date_time(&date_time);
+        assert_eq!(formatted, "\"03-Dec-2020 07:05:09 -0330\"");
+        assert_eq!(
+            DateTime::parse_from_str(formatted.trim_matches('"'), DATE_TIME_FORMAT),
+            Ok(date_time)
+        );
+        assert_eq!(format_date(&NaiveDate::from_ymd(2020, 12, 3)), "3-Dec-2020");
+    }
+
+    #[test]
+    fn within() {
+        assert_eq!(older(Duration::from_secs(86400)), "OLDER 86400");
+        assert_eq!(younger(Duration::from_millis(1500)), "YOUNGER 2");
+        assert_eq!(younger(Duration::from_secs(0)), "YOUNGER 1");
+        assert_eq!(older(Duration::from_secs(1 << 40)), "OLDER 4294967295");
+
+        assert!(uses_within("UNSEEN (OR younger 60 FLAGGED)"));
+        assert!(!uses_within("SUBJECT \"older news\" SINCE 1-Feb-1994"));
+        assert!(!uses_within("HEADER X-Age OLDERS"));
+    }
+}
@@ -0,0 +1,235 @@
+use super::parse::{astring, atom, strip_prefix_ignore_case};
+use super::{Rights, UnsolicitedResponse};
+
+/// The usage and limit of a resource under a quota root, as reported in a [`QUOTA`
+/// response](https://tools.ietf.org/html/rfc2087#section-5.1).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuotaResource {
+    /// The name of the resource, e.g. `STORAGE` (in units of 1024 octets) or `MESSAGE`.
+    pub name: String,
+    /// How much of the resource is in use.
+    pub usage: u64,
+    /// How much of the resource may be used.
+    pub limit: u64,
+}
+
+impl QuotaResource {
+    /// How much of the limit is in use, e.g. `0.9` when 90% of it are used up, so that users can
+    /// be warned before they run out. May be above `1.0`, since servers can let the usage exceed
+    /// the limit for a while. A limit of `0` counts as used up.
+    pub fn fraction_used(&self) -> f64 {
+        if self.limit == 0 {
+            return 1.0;
+        }
+        self.usage as f64 / self.limit as f64
+    }
+}
+
+/// The resources under a quota root, from the response to
+/// [`Session::get_quota`](crate::Session::get_quota) or
+/// [`Session::set_quota`](crate::Session::set_quota).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Quota {
+    /// The name of the quota root, which is often empty for the quota of the whole account.
+    pub root: String,
+    /// The usage and limit of each resource that is limited under the root.
+    pub resources: Vec<QuotaResource>,
+}
+
+impl Quota {
+    /// The resource with the given name, e.g. `STORAGE`, if it is limited under this root.
+    pub fn resource(&self, name: &str) -> Option<&QuotaResource> {
+        self.resources
+            .iter()
+            .find(|resource| resource.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The quota roots of a mailbox, from the response to
+/// [`Session::get_quota_root`](crate::Session::get_quota_root).
+///
+/// A mailbox may be subject to several quotas at once, e.g. one for the account and one shared
+/// by a group of users, and new messages can only be added while none of them is exceeded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct QuotaRoot {
+    /// The mailbox that was asked about.
+    pub mailbox: String,
+    /// The names of the quota roots of the mailbox. Empty if it is not subject to any quota.
+    pub roots: Vec<String>,
+    /// The quotas of those roots that the server reported. Servers may leave out the roots that
+    /// the user is not allowed to see.
+    pub quotas: Vec<Quota>,
+}
+
+impl QuotaRoot {
+    /// The quota of the root with the given name, if the server reported it.
+    pub fn quota(&self, root: &str) -> Option<&Quota> {
+        self.quotas.iter().find(|quota| quota.root == root)
+    }
+}
+
+/// Parses a raw untagged `ACL`, `QUOTA`, `QUOTAROOT`, `METADATA` or `ENABLED` response, which
+/// imap-proto does not support, into the matching [`UnsolicitedResponse`].
+pub(crate) fn parse_extension_data(raw: &[u8]) -> Option<UnsolicitedResponse> {
+    let raw = std::str::from_utf8(raw).ok()?.trim_end();
+    let rest = strip_prefix_ignore_case(raw, "* ")?;
+    let (name, rest) = atom(rest)?;
+
+    if name.eq_ignore_ascii_case("ENABLED") {
+        let mut capabilities = Vec::new();
+        let mut rest = rest;
+        while !rest.is_empty() {
+            let (capability, tail) = atom(strip_prefix_ignore_case(rest, " ")?)?;
+            capabilities.push(capability.to_string());
+            rest = tail;
+        }
+        return Some(UnsolicitedResponse::Enabled(capabilities));
+    }
+
+    let (mailbox, mut rest) = astring(strip_prefix_ignore_case(rest, " ")?)?;
+    if name.eq_ignore_ascii_case("ACL") {
+        let mut rights = Vec::new();
+        while !rest.is_empty() {
+            let (identifier, tail) = astring(strip_prefix_ignore_case(rest, " ")?)?;
+            let (granted, tail) = astring(strip_prefix_ignore_case(tail, " ")?)?;
+            rights.push((identifier, Rights::from_response(&granted)));
+            rest = tail;
+        }
+        Some(UnsolicitedResponse::Acl { mailbox, rights })
+    } else if name.eq_ignore_ascii_case("QUOTA") {
+        let list = strip_prefix_ignore_case(rest, " (")?;
+        let list = &list[..list.find(')').filter(|&end| end == list.len() - 1)?];
+        let mut words = list.split_whitespace();
+        let mut resources = Vec::new();
+        while let Some(name) = words.next() {
+            resources.push(QuotaResource {
+                name: name.to_string(),
+                usage: words.next()?.parse().ok()?,
+                limit: words.next()?.parse().ok()?,
+            });
+        }
+        Some(UnsolicitedResponse::Quota {
+            root: mailbox,
+            resources,
+        })
+    } else if name.eq_ignore_ascii_case("QUOTAROOT") {
+        let mut roots = Vec::new();
+        while !rest.is_empty() {
+            let (root, tail) = astring(strip_prefix_ignore_case(rest, " ")?)?;
+            roots.push(root);
+            rest = tail;
+        }
+        Some(UnsolicitedResponse::QuotaRoot { mailbox, roots })
+    } else if name.eq_ignore_ascii_case("METADATA") {
+        // only the unsolicited form, which lists the names of the changed entries
+        let mut entries = Vec::new();
+        while !rest.is_empty() {
+            let (entry, tail) = astring(strip_prefix_ignore_case(rest, " ")?)?;
+            entries.push(entry);
+            rest = tail;
+        }
+        if entries.is_empty() {
+            return None;
+        }
+        Some(UnsolicitedResponse::Metadata { mailbox, entries })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_data() {
+        assert_eq!(
+            parse_extension_data(b"* ACL INBOX Fred rwipslxetad \"Other Guy\" lr\r\n"),
+            Some(UnsolicitedResponse::Acl {
+                mailbox: "INBOX".into(),
+                rights: vec![
+                    ("Fred".into(), "rwipslxetad".parse().unwrap()),
+                    ("Other Guy".into(), Rights::LOOKUP | Rights::READ),
+                ],
+            })
+        );
+        assert_eq!(
+            parse_extension_data(b"* QUOTA \"\" (STORAGE 10 512 MESSAGE 3 1000)\r\n"),
+            Some(UnsolicitedResponse::Quota {
+                root: "".into(),
+                resources: vec![
+                    QuotaResource {
+                        name: "STORAGE".into(),
+                        usage: 10,
+                        limit: 512,
+                    },
+                    QuotaResource {
+                        name: "MESSAGE".into(),
+                        usage: 3,
+                        limit: 1000,
+                    },
+                ],
+            })
+        );
+        assert_eq!(
+            parse_extension_data(b"* QUOTAROOT \"Shared Folders/x\" \"\" group\r\n"),
+            Some(UnsolicitedResponse::QuotaRoot {
+                mailbox: "Shared Folders/x".into(),
+                roots: vec!["".into(), "group".into()],
+            })
+        );
+        assert_eq!(
+            parse_extension_data(b"* QUOTAROOT comp.mail.mime\r\n"),
+            Some(UnsolicitedResponse::QuotaRoot {
+                mailbox: "comp.mail.mime".into(),
+                roots: vec![],
+            })
+        );
+        assert_eq!(
+            parse_extension_data(b"* METADATA \"Lists/rust\" /shared/comment\r\n"),
+            Some(UnsolicitedResponse::Metadata {
+                mailbox: "Lists/rust".into(),
+                entries: vec!["/shared/comment".into()],
+            })
+        );
+        assert_eq!(
+            parse_extension_data(b"* ENABLED CONDSTORE QRESYNC\r\n"),
+            Some(UnsolicitedResponse::Enabled(vec![
+                "CONDSTORE".into(),
+                "QRESYNC".into()
+            ]))
+        );
+
+        // the solicited form of METADATA includes the values
+        assert_eq!(
+            parse_extension_data(b"* METADATA INBOX (/shared/comment \"x\")\r\n"),
+            None
+        );
+        assert_eq!(parse_extension_data(b"* QUOTA \"\" (STORAGE 10)\r\n"), None);
+        assert_eq!(parse_extension_data(b"* QUOTAROOT INBOX \"\r\n"), None);
+        assert_eq!(parse_extension_data(b"* XSTATE 1:3\r\n"), None);
+    }
+
+    #[test]
+    fn fraction_used() {
+        let quota = Quota {
+            root: "".into(),
+            resources: vec![
+                QuotaResource {
+                    name: "STORAGE".into(),
+                    usage: 460,
+                    limit: 512,
+                },
+                QuotaResource {
+                    name: "MESSAGE".into(),
+                    usage: 0,
+                    limit: 0,
+                },
+            ],
+        };
+        let storage = quota.resource("storage").unwrap();
+        assert!(storage.fraction_used() > 0.89 && storage.fraction_used() < 0.9);
+        assert_eq!(quota.resource("MESSAGE").unwrap().fraction_used(), 1.0);
+        assert_eq!(quota.resource("MAILBOX"), None);
+    }
+}
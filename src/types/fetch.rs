@@ -3,14 +3,10 @@ use imap_proto::types::{
     AttributeValue, BodyStructure, Envelope, MessageSection, Response, SectionPath,
 };
 
-use super::{Flag, Seq, Uid};
+use super::date::DATE_TIME_FORMAT;
+use super::{Flag, FlagSet, Seq, Uid};
 use crate::types::ResponseData;
 
-/// Format of Date and Time as defined RFC3501.
-/// See `date-time` element in [Formal Syntax](https://tools.ietf.org/html/rfc3501#section-9)
-/// chapter of this RFC.
-const DATE_TIME_FORMAT: &str = "%d-%b-%Y %H:%M:%S %z";
-
 /// An IMAP [`FETCH` response](https://tools.ietf.org/html/rfc3501#section-7.4.2) that contains
 /// data about a particular message. This response occurs as the result of a `FETCH` or `STORE`
 /// command, as well as by unilateral server decision (e.g., flag updates).
@@ -28,92 +24,87 @@ pub struct Fetch {
     /// A number expressing the [RFC-2822](https://tools.ietf.org/html/rfc2822) size of the message.
     /// Only present if `RFC822.SIZE` was specified in the query argument to `FETCH`.
     pub size: Option<u32>,
+
+    /// The mod-sequence of the last change to the message's metadata, such as its flags.
+    /// Only present if `MODSEQ` was specified in the query argument to `FETCH`, or the server
+    /// includes it on its own because [`CONDSTORE`](https://tools.ietf.org/html/rfc7162) is
+    /// enabled.
+    pub modseq: Option<u64>,
 }
 
 impl Fetch {
     pub(crate) fn new(response: ResponseData) -> Self {
-        let (message, uid, size) = if let Response::Fetch(message, attrs) = response.parsed() {
-            let mut uid = None;
-            let mut size = None;
-
-            for attr in attrs {
-                match attr {
-                    AttributeValue::Uid(id) => uid = Some(*id),
-                    AttributeValue::Rfc822Size(sz) => size = Some(*sz),
-                    _ => {}
-                }
-            }
-            (*message, uid, size)
-        } else {
-            unreachable!()
+        // only ever constructed from `FETCH` responses, so the fallback is never used
+        let message = match response.parsed() {
+            Response::Fetch(message, _) => *message,
+            _ => 0,
         };
+        let mut uid = None;
+        let mut size = None;
+        let mut modseq = None;
+        for attr in attrs(&response) {
+            match attr {
+                AttributeValue::Uid(id) => uid = Some(*id),
+                AttributeValue::Rfc822Size(sz) => size = Some(*sz),
+                AttributeValue::ModSeq(m) => modseq = Some(*m),
+                _ => {}
+            }
+        }
 
         Fetch {
             response,
             message,
             uid,
             size,
+            modseq,
         }
     }
 
-    /// A list of flags that are set for this message.
-    pub fn flags(&self) -> impl Iterator<Item = Flag<'_>> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|attr| match attr {
-                    AttributeValue::Flags(raw_flags) => {
-                        Some(raw_flags.iter().map(|s| Flag::from(*s)))
-                    }
-                    _ => None,
-                })
-                .flatten()
-        } else {
-            unreachable!()
-        }
+    /// The flags that are set for this message.
+    pub fn flags(&self) -> FlagSet {
+        attrs(&self.response)
+            .iter()
+            .filter_map(|attr| match attr {
+                AttributeValue::Flags(raw_flags) => Some(raw_flags.iter().map(|s| Flag::from(*s))),
+                _ => None,
+            })
+            .flatten()
+            .collect()
     }
 
     /// The bytes that make up the header of this message, if `BODY[HEADER]`, `BODY.PEEK[HEADER]`,
     /// or `RFC822.HEADER` was included in the `query` argument to `FETCH`.
     pub fn header(&self) -> Option<&[u8]> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::BodySection {
-                        section: Some(SectionPath::Full(MessageSection::Header)),
-                        data: Some(hdr),
-                        ..
-                    }
-                    | AttributeValue::Rfc822Header(Some(hdr)) => Some(*hdr),
-                    _ => None,
-                })
-                .next()
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::BodySection {
+                    section: Some(SectionPath::Full(MessageSection::Header)),
+                    data: Some(hdr),
+                    ..
+                }
+                | AttributeValue::Rfc822Header(Some(hdr)) => Some(*hdr),
+                _ => None,
+            })
+            .next()
     }
 
     /// The bytes that make up this message, included if `BODY[]` or `RFC822` was included in the
     /// `query` argument to `FETCH`. The bytes SHOULD be interpreted by the client according to the
     /// content transfer encoding, body type, and subtype.
     pub fn body(&self) -> Option<&[u8]> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::BodySection {
-                        section: None,
-                        data: Some(body),
-                        ..
-                    }
-                    | AttributeValue::Rfc822(Some(body)) => Some(*body),
-                    _ => None,
-                })
-                .next()
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::BodySection {
+                    section: None,
+                    data: Some(body),
+                    ..
+                }
+                | AttributeValue::Rfc822(Some(body)) => Some(*body),
+                _ => None,
+            })
+            .next()
     }
 
     /// The bytes that make up the text of this message, included if `BODY[TEXT]`, `RFC822.TEXT`,
@@ -121,22 +112,18 @@ impl Fetch {
     /// interpreted by the client according to the content transfer encoding, body type, and
     /// subtype.
     pub fn text(&self) -> Option<&[u8]> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::BodySection {
-                        section: Some(SectionPath::Full(MessageSection::Text)),
-                        data: Some(body),
-                        ..
-                    }
-                    | AttributeValue::Rfc822Text(Some(body)) => Some(*body),
-                    _ => None,
-                })
-                .next()
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::BodySection {
+                    section: Some(SectionPath::Full(MessageSection::Text)),
+                    data: Some(body),
+                    ..
+                }
+                | AttributeValue::Rfc822Text(Some(body)) => Some(*body),
+                _ => None,
+            })
+            .next()
     }
 
     /// The envelope of this message, if `ENVELOPE` was included in the `query` argument to
@@ -147,17 +134,13 @@ impl Fetch {
     /// The full description of the format of the envelope is given in [RFC 3501 section
     /// 7.4.2](https://tools.ietf.org/html/rfc3501#section-7.4.2).
     pub fn envelope(&self) -> Option<&Envelope<'_>> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::Envelope(env) => Some(&**env),
-                    _ => None,
-                })
-                .next()
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::Envelope(env) => Some(&**env),
+                _ => None,
+            })
+            .next()
     }
 
     /// Extract the bytes that makes up the given `BOD[<section>]` of a `FETCH` response.
@@ -165,21 +148,17 @@ impl Fetch {
     /// See [section 7.4.2 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-7.4.2) for
     /// details.
     pub fn section(&self, path: &SectionPath) -> Option<&[u8]> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::BodySection {
-                        section: Some(sp),
-                        data: Some(data),
-                        ..
-                    } if sp == path => Some(*data),
-                    _ => None,
-                })
-                .next()
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::BodySection {
+                    section: Some(sp),
+                    data: Some(data),
+                    ..
+                } if sp == path => Some(*data),
+                _ => None,
+            })
+            .next()
     }
 
     /// Extract the `INTERNALDATE` of a `FETCH` response
@@ -187,23 +166,19 @@ impl Fetch {
     /// See [section 2.3.3 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.3) for
     /// details.
     pub fn internal_date(&self) -> Option<DateTime<FixedOffset>> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::InternalDate(date_time) => Some(*date_time),
-                    _ => None,
-                })
-                .next()
-                .and_then(
-                    |date_time| match DateTime::parse_from_str(date_time, DATE_TIME_FORMAT) {
-                        Ok(date_time) => Some(date_time),
-                        Err(_) => None,
-                    },
-                )
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::InternalDate(date_time) => Some(*date_time),
+                _ => None,
+            })
+            .next()
+            .and_then(
+                |date_time| match DateTime::parse_from_str(date_time, DATE_TIME_FORMAT) {
+                    Ok(date_time) => Some(date_time),
+                    Err(_) => None,
+                },
+            )
     }
 
     /// Extract the `BODYSTRUCTURE` of a `FETCH` response
@@ -211,16 +186,48 @@ impl Fetch {
     /// See [section 2.3.6 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.6) for
     /// details.
     pub fn bodystructure(&self) -> Option<&BodyStructure<'_>> {
-        if let Response::Fetch(_, attrs) = self.response.parsed() {
-            attrs
-                .iter()
-                .filter_map(|av| match av {
-                    AttributeValue::BodyStructure(bs) => Some(bs),
-                    _ => None,
-                })
-                .next()
-        } else {
-            unreachable!()
-        }
+        attrs(&self.response)
+            .iter()
+            .filter_map(|av| match av {
+                AttributeValue::BodyStructure(bs) => Some(bs),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Parses the [`body`](Fetch::body) of the message with
+    /// [mailparse](https://docs.rs/mailparse). The result borrows from this `Fetch`.
+    #[cfg(feature = "mailparse")]
+    pub fn parsed_body(
+        &self,
+    ) -> Option<Result<mailparse::ParsedMail<'_>, mailparse::MailParseError>> {
+        self.body().map(mailparse::parse_mail)
+    }
+
+    /// Parses the [`header`](Fetch::header) of the message with
+    /// [mailparse](https://docs.rs/mailparse). The result borrows from this `Fetch`.
+    #[cfg(feature = "mailparse")]
+    pub fn parsed_header(
+        &self,
+    ) -> Option<Result<Vec<mailparse::MailHeader<'_>>, mailparse::MailParseError>> {
+        self.header()
+            .map(|header| mailparse::parse_headers(header).map(|(headers, _)| headers))
+    }
+
+    /// Parses the [`body`](Fetch::body) of the message with
+    /// [mail-parser](https://docs.rs/mail-parser), or the [`header`](Fetch::header) if only that
+    /// was fetched. The result borrows from this `Fetch` where possible. Returns `None` if
+    /// neither was fetched, or the data is not a message.
+    #[cfg(feature = "mail-parser")]
+    pub fn message(&self) -> Option<mail_parser::Message<'_>> {
+        mail_parser::Message::parse(self.body().or_else(|| self.header())?)
+    }
+}
+
+/// The attributes of a `FETCH` response, or none for any other response.
+fn attrs(response: &ResponseData) -> &[AttributeValue<'_>] {
+    match response.parsed() {
+        Response::Fetch(_, attrs) => attrs,
+        _ => &[],
     }
 }
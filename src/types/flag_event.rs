@@ -0,0 +1,50 @@
+use imap_proto::types::{AttributeValue, Response};
+
+use super::{Flag, FlagSet, Seq, Uid};
+use crate::types::ResponseData;
+
+/// The flags of a message as reported by the server, as delivered by
+/// [`Session::flag_events`](crate::Session::flag_events). This is derived from every `FETCH`
+/// response that includes `FLAGS`, whether it answers a `FETCH` or `STORE` command or was sent
+/// by the server on its own, e.g. because another client changed the flags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlagEvent {
+    /// The sequence number of the message.
+    pub message: Seq,
+
+    /// The unique identifier of the message, if the response included it. Servers include it
+    /// in the responses to `UID STORE` and `UID FETCH`, and in unsolicited responses if
+    /// `CONDSTORE` or `QRESYNC` is enabled.
+    pub uid: Option<Uid>,
+
+    /// The flags that are now set for the message.
+    pub flags: FlagSet,
+}
+
+impl FlagEvent {
+    /// Returns the flag update in `response`, if it is a `FETCH` response that includes `FLAGS`.
+    pub(crate) fn from_response(response: &ResponseData) -> Option<Self> {
+        let (message, attrs) = match response.parsed() {
+            Response::Fetch(message, attrs) => (*message, attrs),
+            _ => return None,
+        };
+
+        let mut uid = None;
+        let mut flags = None;
+        for attr in attrs {
+            match attr {
+                AttributeValue::Uid(id) => uid = Some(*id),
+                AttributeValue::Flags(raw) => {
+                    flags = Some(raw.iter().map(|s| Flag::from(*s)).collect())
+                }
+                _ => {}
+            }
+        }
+
+        Some(FlagEvent {
+            message,
+            uid,
+            flags: flags?,
+        })
+    }
+}
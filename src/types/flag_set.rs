@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::iter::FromIterator;
+
+use super::Flag;
+
+const SYSTEM_FLAGS: [Flag<'static>; 7] = [
+    Flag::Seen,
+    Flag::Answered,
+    Flag::Flagged,
+    Flag::Deleted,
+    Flag::Draft,
+    Flag::Recent,
+    Flag::MayCreate,
+];
+
+fn bit(flag: &Flag<'_>) -> Option<u8> {
+    SYSTEM_FLAGS
+        .iter()
+        .position(|f| f == flag)
+        .map(|i| 1 << i as u8)
+}
+
+/// A set of [`Flag`]s, e.g. the flags of a message as returned by [`Fetch::flags`].
+///
+/// System flags are stored as a bitmask, so that sets that contain only system flags, which is
+/// the common case, never allocate. Keywords ([`Flag::Custom`]) are kept in a sorted list. The
+/// iteration order is therefore not necessarily the order in which the server sent the flags.
+///
+/// [`Fetch::flags`]: crate::types::Fetch::flags
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct FlagSet {
+    system: u8,
+    keywords: Vec<String>,
+}
+
+impl FlagSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        FlagSet::default()
+    }
+
+    /// Whether `flag` is in the set.
+    pub fn contains(&self, flag: &Flag<'_>) -> bool {
+        match (bit(flag), flag) {
+            (Some(bit), _) => self.system & bit != 0,
+            (None, Flag::Custom(keyword)) => self.find(keyword).is_ok(),
+            (None, _) => false,
+        }
+    }
+
+    /// Adds `flag` to the set. Returns whether it was not present before.
+    pub fn insert(&mut self, flag: Flag<'_>) -> bool {
+        match (bit(&flag), flag) {
+            (Some(bit), _) => {
+                let added = self.system & bit == 0;
+                self.system |= bit;
+                added
+            }
+            (None, Flag::Custom(keyword)) => match self.find(&keyword) {
+                Ok(_) => false,
+                Err(i) => {
+                    self.keywords.insert(i, keyword.into_owned());
+                    true
+                }
+            },
+            (None, _) => false,
+        }
+    }
+
+    /// Removes `flag` from the set. Returns whether it was present.
+    pub fn remove(&mut self, flag: &Flag<'_>) -> bool {
+        match (bit(flag), flag) {
+            (Some(bit), _) => {
+                let removed = self.system & bit != 0;
+                self.system &= !bit;
+                removed
+            }
+            (None, Flag::Custom(keyword)) => match self.find(keyword) {
+                Ok(i) => {
+                    self.keywords.remove(i);
+                    true
+                }
+                Err(_) => false,
+            },
+            (None, _) => false,
+        }
+    }
+
+    /// The number of flags in the set.
+    pub fn len(&self) -> usize {
+        self.system.count_ones() as usize + self.keywords.len()
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.system == 0 && self.keywords.is_empty()
+    }
+
+    /// Iterates over the flags in the set, system flags first.
+    pub fn iter(&self) -> impl Iterator<Item = Flag<'_>> {
+        let system = self.system;
+        SYSTEM_FLAGS
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| system & (1 << *i as u8) != 0)
+            .map(|(_, f)| f.clone())
+            .chain(
+                self.keywords
+                    .iter()
+                    .map(|k| Flag::Custom(Cow::Borrowed(k.as_str()))),
+            )
+    }
+
+    /// The flags that are in `self` or in `other`.
+    pub fn union(&self, other: &FlagSet) -> FlagSet {
+        let mut set = self.clone();
+        set.extend(other.iter());
+        set
+    }
+
+    /// The flags that are in both `self` and `other`.
+    pub fn intersection(&self, other: &FlagSet) -> FlagSet {
+        FlagSet {
+            system: self.system & other.system,
+            keywords: self
+                .keywords
+                .iter()
+                .filter(|k| other.find(k).is_ok())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The flags that are in `self`, but not in `other`. Useful to compute the `STORE` commands
+    /// that turn one set of flags into another.
+    pub fn difference(&self, other: &FlagSet) -> FlagSet {
+        FlagSet {
+            system: self.system & !other.system,
+            keywords: self
+                .keywords
+                .iter()
+                .filter(|k| other.find(k).is_err())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Whether all flags in `self` are also in `other`.
+    pub fn is_subset(&self, other: &FlagSet) -> bool {
+        self.difference(other).is_empty()
+    }
+
+    fn find(&self, keyword: &str) -> std::result::Result<usize, usize> {
+        self.keywords.binary_search_by(|k| k.as_str().cmp(keyword))
+    }
+}
+
+impl<'a> Extend<Flag<'a>> for FlagSet {
+    fn extend<I: IntoIterator<Item = Flag<'a>>>(&mut self, iter: I) {
+        for flag in iter {
+            self.insert(flag);
+        }
+    }
+}
+
+impl<'a> FromIterator<Flag<'a>> for FlagSet {
+    fn from_iter<I: IntoIterator<Item = Flag<'a>>>(iter: I) -> Self {
+        let mut set = FlagSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl fmt::Debug for FlagSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        let a: FlagSet = vec![Flag::Seen, Flag::from("$Junk"), Flag::Flagged]
+            .into_iter()
+            .collect();
+        let b: FlagSet = vec![Flag::Seen, Flag::Deleted, Flag::from("$Forwarded")]
+            .into_iter()
+            .collect();
+
+        assert_eq!(a.len(), 3);
+        assert!(a.contains(&Flag::from("$Junk")));
+        assert!(!a.contains(&Flag::Deleted));
+        assert_eq!(
+            a.iter().collect::<Vec<_>>(),
+            vec![Flag::Seen, Flag::Flagged, Flag::from("$Junk")]
+        );
+
+        assert_eq!(a.union(&b).len(), 5);
+        assert_eq!(
+            a.intersection(&b).iter().collect::<Vec<_>>(),
+            vec![Flag::Seen]
+        );
+        assert_eq!(
+            a.difference(&b).iter().collect::<Vec<_>>(),
+            vec![Flag::Flagged, Flag::from("$Junk")]
+        );
+        assert!(a.intersection(&b).is_subset(&b));
+
+        let mut c = a.clone();
+        assert!(!c.insert(Flag::from("$Junk")));
+        assert!(c.remove(&Flag::from("$Junk")));
+        assert!(c.remove(&Flag::Seen));
+        assert_eq!(c.iter().collect::<Vec<_>>(), vec![Flag::Flagged]);
+    }
+}
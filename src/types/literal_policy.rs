@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// Receives the contents of a literal that exceeded the size limit of
+/// [`Session::fetch_limited`](crate::Session::fetch_limited), e.g. to write a large message
+/// to a file instead of keeping it in memory.
+pub trait LiteralSink: Send {
+    /// A literal of `size` bytes starts. `prefix` is the response up to the literal, such as
+    /// `* 3 FETCH (UID 17 BODY[] `, which identifies the message it belongs to.
+    fn begin(&mut self, _prefix: &[u8], _size: usize) {}
+
+    /// The next chunk of the literal.
+    fn data(&mut self, data: &[u8]);
+
+    /// The literal is complete.
+    fn end(&mut self) {}
+}
+
+/// What to do with a literal that is larger than the limit passed to
+/// [`Session::fetch_limited`](crate::Session::fetch_limited).
+pub enum LiteralPolicy {
+    /// Discard the literal while it is received. It is replaced with `NIL` in the response, so
+    /// e.g. [`Fetch::body`](crate::types::Fetch::body) returns `None`.
+    Skip,
+    /// Pass the literal to a [`LiteralSink`] while it is received. It is replaced with `NIL` in
+    /// the response, as with [`Skip`](LiteralPolicy::Skip).
+    Sink(Box<dyn LiteralSink>),
+    /// Fail with an error as soon as the literal is announced, without receiving it. The rest of
+    /// the literal is still on its way, so the connection can not be used afterwards and should
+    /// be dropped.
+    Abort,
+}
+
+impl fmt::Debug for LiteralPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiteralPolicy::Skip => write!(f, "LiteralPolicy::Skip"),
+            LiteralPolicy::Sink(_) => write!(f, "LiteralPolicy::Sink(..)"),
+            LiteralPolicy::Abort => write!(f, "LiteralPolicy::Abort"),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Flag, Uid};
+use super::{Flag, FlagSet, Uid, UnsolicitedResponse};
 use std::fmt;
 
 /// Meta-information about an IMAP mailbox, as returned by
@@ -6,8 +6,9 @@ use std::fmt;
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Mailbox {
     /// Defined flags in the mailbox.  See the description of the [FLAGS
-    /// response](https://tools.ietf.org/html/rfc3501#section-7.2.6) for more detail.
-    pub flags: Vec<Flag<'static>>,
+    /// response](https://tools.ietf.org/html/rfc3501#section-7.2.6) for more detail. If the
+    /// server sent several `FLAGS` responses, this is the last one.
+    pub flags: FlagSet,
 
     /// The number of messages in the mailbox.  See the description of the [EXISTS
     /// response](https://tools.ietf.org/html/rfc3501#section-7.3.1) for more detail.
@@ -25,8 +26,10 @@ pub struct Mailbox {
     /// A list of message flags that the client can change permanently.  If this is missing, the
     /// client should assume that all flags can be changed permanently. If the client attempts to
     /// STORE a flag that is not in this list list, the server will either ignore the change or
-    /// store the state change for the remainder of the current session only.
-    pub permanent_flags: Vec<Flag<'static>>,
+    /// store the state change for the remainder of the current session only. If the server sent
+    /// several `PERMANENTFLAGS` response codes, this is the last one. Use
+    /// [`is_permanent`](Mailbox::is_permanent) to check a particular flag.
+    pub permanent_flags: Option<FlagSet>,
 
     /// The next unique identifier value.  If this is missing, the client can not make any
     /// assumptions about the next unique identifier value.
@@ -35,19 +38,63 @@ pub struct Mailbox {
     /// The unique identifier validity value.  See [`Uid`] for more details.  If this is missing,
     /// the server does not support unique identifiers.
     pub uid_validity: Option<u32>,
+
+    /// The highest mod-sequence value of all messages in the mailbox, as reported by servers
+    /// that support [`CONDSTORE`](https://tools.ietf.org/html/rfc7162#section-3.1.1). If this is
+    /// missing, the server does not keep mod-sequences for the mailbox.
+    pub highest_modseq: Option<u64>,
 }
 
 impl Default for Mailbox {
     fn default() -> Mailbox {
         Mailbox {
-            flags: Vec::new(),
+            flags: FlagSet::new(),
             exists: 0,
             recent: 0,
             unseen: None,
-            permanent_flags: Vec::new(),
+            permanent_flags: None,
             uid_next: None,
             uid_validity: None,
+            highest_modseq: None,
+        }
+    }
+}
+
+impl Mailbox {
+    /// Whether changes to `flag` are stored permanently, rather than only for the remainder of
+    /// the current session.
+    ///
+    /// Keywords that are not listed in [`permanent_flags`](Mailbox::permanent_flags) are
+    /// permanent if the server allows creating new keywords ([`Flag::MayCreate`]). `\Recent`
+    /// can not be changed by the client at all.
+    pub fn is_permanent(&self, flag: &Flag<'_>) -> bool {
+        match (flag, &self.permanent_flags) {
+            (Flag::Recent, _) | (Flag::MayCreate, _) => false,
+            (_, None) => true,
+            (Flag::Custom(_), Some(permanent)) => {
+                permanent.contains(flag) || permanent.contains(&Flag::MayCreate)
+            }
+            (_, Some(permanent)) => permanent.contains(flag),
+        }
+    }
+
+    /// Updates the mailbox with an unsolicited response received while it is selected, such as a
+    /// `FLAGS` response after a new keyword was created. Returns whether the response applied
+    /// to the mailbox.
+    ///
+    /// Flag updates replace the known flags, since each response lists all of them, so flags
+    /// that the server no longer allows are dropped.
+    pub fn update(&mut self, response: &UnsolicitedResponse) -> bool {
+        match response {
+            UnsolicitedResponse::Flags(flags) => self.flags = flags.clone(),
+            UnsolicitedResponse::PermanentFlags(flags) => {
+                self.permanent_flags = Some(flags.clone());
+            }
+            UnsolicitedResponse::Exists(n) => self.exists = *n,
+            UnsolicitedResponse::Recent(n) => self.recent = *n,
+            _ => return false,
         }
+        true
     }
 }
 
@@ -56,14 +103,15 @@ impl fmt::Display for Mailbox {
         write!(
             f,
             "flags: {:?}, exists: {}, recent: {}, unseen: {:?}, permanent_flags: {:?},\
-             uid_next: {:?}, uid_validity: {:?}",
+             uid_next: {:?}, uid_validity: {:?}, highest_modseq: {:?}",
             self.flags,
             self.exists,
             self.recent,
             self.unseen,
             self.permanent_flags,
             self.uid_next,
-            self.uid_validity
+            self.uid_validity,
+            self.highest_modseq
         )
     }
 }
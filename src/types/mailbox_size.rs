@@ -0,0 +1,104 @@
+/// An estimate of the total size of the messages in a mailbox, as returned by
+/// [`Session::estimate_mailbox_size`](crate::Session::estimate_mailbox_size).
+///
+/// The true size lies in `bytes - margin ..= bytes + margin` with high probability (the margin
+/// covers two standard errors of the sample). If the server reported the size, or all messages
+/// were measured, the estimate is [exact](MailboxSize::is_exact) and `margin` is zero. A sample
+/// of messages that all have the same size has no margin either, but is still an estimate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct MailboxSize {
+    /// The number of messages in the mailbox.
+    pub messages: u32,
+
+    /// The estimated total [RFC 2822](https://tools.ietf.org/html/rfc2822) size of all messages,
+    /// in octets.
+    pub bytes: u64,
+
+    /// The error bound of `bytes`, in octets.
+    pub margin: u64,
+
+    /// The number of messages whose size was fetched to compute the estimate. This is zero if
+    /// the size was reported by the server using `STATUS=SIZE`.
+    pub sampled: u32,
+
+    /// Whether every message was measured, or the server reported the size.
+    exact: bool,
+}
+
+impl MailboxSize {
+    /// Creates an exact size, as reported by the server.
+    pub(crate) fn exact(messages: u32, bytes: u64) -> Self {
+        MailboxSize {
+            messages,
+            bytes,
+            margin: 0,
+            sampled: 0,
+            exact: true,
+        }
+    }
+
+    /// Extrapolates the size of a mailbox with `messages` messages from the sizes of a sample of
+    /// them.
+    pub(crate) fn from_sample(messages: u32, sizes: &[u32]) -> Self {
+        let sampled = sizes.len() as u32;
+        let sum: u64 = sizes.iter().map(|s| u64::from(*s)).sum();
+        if sampled >= messages {
+            return MailboxSize {
+                messages,
+                bytes: sum,
+                margin: 0,
+                sampled,
+                exact: true,
+            };
+        }
+        if sampled == 0 {
+            // nothing is known about the size
+            return MailboxSize {
+                messages,
+                bytes: 0,
+                margin: u64::MAX,
+                sampled,
+                exact: false,
+            };
+        }
+
+        let n = f64::from(messages);
+        let k = f64::from(sampled);
+        let mean = sum as f64 / k;
+        let variance = if sampled > 1 {
+            sizes
+                .iter()
+                .map(|s| (f64::from(*s) - mean).powi(2))
+                .sum::<f64>()
+                / (k - 1.0)
+        } else {
+            // a single message says nothing about the spread, assume it is as large as the mean
+            mean * mean
+        };
+        // standard error of the total, with the finite population correction
+        let std_error = n * (variance / k).sqrt() * ((n - k) / (n - 1.0)).sqrt();
+
+        MailboxSize {
+            messages,
+            bytes: (mean * n).round() as u64,
+            margin: (2.0 * std_error).ceil() as u64,
+            sampled,
+            exact: false,
+        }
+    }
+
+    /// Whether `bytes` is the exact size of the mailbox.
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// The lower bound of the estimate.
+    pub fn min(&self) -> u64 {
+        self.bytes.saturating_sub(self.margin)
+    }
+
+    /// The upper bound of the estimate.
+    pub fn max(&self) -> u64 {
+        self.bytes.saturating_add(self.margin)
+    }
+}
@@ -1,6 +1,7 @@
 //! This module contains types used throughout the IMAP protocol.
 
 use std::borrow::Cow;
+use std::fmt;
 
 /// From section [2.3.1.1 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.1.1).
 ///
@@ -154,7 +155,28 @@ pub enum Flag<'a> {
     Custom(Cow<'a, str>),
 }
 
+/// Keywords registered with [IANA](https://www.iana.org/assignments/imap-jmap-keywords), which
+/// clients should use instead of spelling them out: a misspelled keyword is silently created as
+/// a new one by most servers.
 impl Flag<'static> {
+    /// `$Forwarded`: the message has been forwarded, see
+    /// [RFC 5550](https://tools.ietf.org/html/rfc5550#section-5.9).
+    pub const FORWARDED: Flag<'static> = Flag::Custom(Cow::Borrowed("$Forwarded"));
+
+    /// `$MDNSent`: a message disposition notification has been sent for the message, see
+    /// [RFC 3503](https://tools.ietf.org/html/rfc3503#section-3.1).
+    pub const MDN_SENT: Flag<'static> = Flag::Custom(Cow::Borrowed("$MDNSent"));
+
+    /// `$Junk`: the user or a filter classified the message as spam.
+    pub const JUNK: Flag<'static> = Flag::Custom(Cow::Borrowed("$Junk"));
+
+    /// `$NotJunk`: the user or a filter classified the message as legitimate.
+    pub const NOT_JUNK: Flag<'static> = Flag::Custom(Cow::Borrowed("$NotJunk"));
+
+    /// `$Phishing`: the message is likely a phishing attempt, and links in it should not be
+    /// followed.
+    pub const PHISHING: Flag<'static> = Flag::Custom(Cow::Borrowed("$Phishing"));
+
     fn system(s: &str) -> Option<Self> {
         match s {
             "\\Seen" => Some(Flag::Seen),
@@ -169,6 +191,21 @@ impl Flag<'static> {
     }
 }
 
+impl fmt::Display for Flag<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Flag::Seen => "\\Seen",
+            Flag::Answered => "\\Answered",
+            Flag::Flagged => "\\Flagged",
+            Flag::Deleted => "\\Deleted",
+            Flag::Draft => "\\Draft",
+            Flag::Recent => "\\Recent",
+            Flag::MayCreate => "\\*",
+            Flag::Custom(keyword) => &**keyword,
+        })
+    }
+}
+
 impl<'a> From<String> for Flag<'a> {
     fn from(s: String) -> Self {
         if let Some(f) = Flag::system(&s) {
@@ -192,6 +229,18 @@ impl<'a> From<&'a str> for Flag<'a> {
 mod mailbox;
 pub use self::mailbox::Mailbox;
 
+mod mailbox_size;
+pub use self::mailbox_size::MailboxSize;
+
+mod unread_count;
+pub use self::unread_count::UnreadCount;
+
+mod connection_stats;
+pub use self::connection_stats::ConnectionStats;
+
+mod flag_event;
+pub use self::flag_event::FlagEvent;
+
 mod fetch;
 pub use self::fetch::Fetch;
 
@@ -201,9 +250,62 @@ pub use self::name::{Name, NameAttribute};
 mod capabilities;
 pub use self::capabilities::{Capabilities, Capability};
 
+mod command_result;
+pub use self::command_result::CommandResult;
+
+mod flag_set;
+pub use self::flag_set::FlagSet;
+
+mod server_id;
+pub use self::server_id::ServerId;
+
+mod vanished;
+pub use self::vanished::Vanished;
+
+mod uidplus;
+pub use self::uidplus::{AppendUid, CopyUid};
+
+mod moved;
+pub use self::moved::Moved;
+
+mod extension_data;
+pub(crate) use self::extension_data::parse_extension_data;
+pub use self::extension_data::{Quota, QuotaResource, QuotaRoot};
+
+mod rights;
+pub use self::rights::Rights;
+
+mod utf7;
+pub use self::utf7::{decode_utf7, encode_utf7};
+
+mod sequence_set;
+pub use self::sequence_set::{to_sequence_set, SAVED_RESULT};
+
+mod date;
+pub(crate) use self::date::uses_within;
+pub use self::date::{format_date, format_date_time, older, younger};
+
+mod thread;
+pub(crate) use self::thread::parse_thread;
+pub use self::thread::Thread;
+
+mod namespace;
+pub(crate) use self::namespace::parse_namespace;
+pub use self::namespace::{Namespace, Namespaces};
+
+mod search_result;
+pub(crate) use self::search_result::{parse_esearch, parse_sort, Esearch, MAX_SEARCH_RESULTS};
+pub use self::search_result::{ContextUpdate, EsearchResult, SearchResult, SearchReturn};
+
+mod literal_policy;
+pub use self::literal_policy::{LiteralPolicy, LiteralSink};
+pub(crate) use self::sequence_set::{compress_query, compress_set};
+
 /// re-exported from imap_proto;
 pub use imap_proto::StatusAttribute;
 
+mod parse;
+
 mod id_generator;
 pub(crate) use self::id_generator::IdGenerator;
 
@@ -213,6 +315,43 @@ pub(crate) use self::response_data::ResponseData;
 mod request;
 pub(crate) use self::request::Request;
 
+/// The raw bytes of a response, such as an untagged response that could not be parsed, e.g. a
+/// vendor-specific `* XSTATE ...` response (see
+/// [`Session::unrecognized_responses`](crate::Session)), or any response read through a
+/// [`Passthrough`](crate::Passthrough).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawResponse(pub Vec<u8>);
+
+impl RawResponse {
+    /// The complete response, including the leading `* ` and the trailing CRLF.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The tag of the command that this response completes, or `None` for untagged responses
+    /// and continuation requests.
+    pub fn tag(&self) -> Option<&str> {
+        if self.0.starts_with(b"* ") || self.0.starts_with(b"+") {
+            return None;
+        }
+        let tag = self.0.split(|&b| b == b' ').next()?;
+        std::str::from_utf8(tag).ok()
+    }
+
+    /// Parses a copy of the response, or returns `None` if imap-proto does not understand it.
+    pub fn to_response_data(&self) -> Option<ResponseData> {
+        let mut block = crate::protocol::POOL.alloc(self.0.len());
+        block[..self.0.len()].copy_from_slice(&self.0);
+        let len = self.0.len();
+        ResponseData::try_new(block, |buf| {
+            imap_proto::parse_response(&buf[..len])
+                .map(|(_, response)| response)
+                .map_err(|_| ())
+        })
+        .ok()
+    }
+}
+
 /// Responses that the server sends that are not related to the current command.
 /// [RFC 3501](https://tools.ietf.org/html/rfc3501#section-7) states that clients need to be able
 /// to accept any response at any time. These are the ones we've encountered in the wild.
@@ -271,6 +410,177 @@ pub enum UnsolicitedResponse {
     /// sequence numbers 9, 8, 7, 6, and 5.
     // TODO: the spec doesn't seem to say anything about when these may be received as unsolicited?
     Expunge(u32),
+
+    /// An unsolicited [`VANISHED` response](https://tools.ietf.org/html/rfc7162#section-3.2.10),
+    /// which replaces `EXPUNGE` responses once `QRESYNC` is enabled, and reports the removed
+    /// messages by their UIDs. See [`Vanished`].
+    Vanished(Vanished),
+
+    /// The server reported fewer messages in an `EXISTS` response than there were before,
+    /// without announcing the removed messages with `EXPUNGE` or `VANISHED` first. This violates
+    /// [RFC 3501](https://tools.ietf.org/html/rfc3501#section-7.3.1), and means that message
+    /// sequence numbers can no longer be mapped to previously known messages, so clients that
+    /// keep a local copy of the mailbox should resynchronize it completely.
+    ///
+    /// This follows the [`Exists`](UnsolicitedResponse::Exists) response with the new count.
+    ExistsInconsistent {
+        /// The number of messages before the `EXISTS` response.
+        old: u32,
+        /// The number of messages according to the `EXISTS` response.
+        new: u32,
+    },
+
+    /// The server sent a second tagged response for the command tagged `tag`, which had already
+    /// been completed. This violates [RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.2.2),
+    /// and is reported instead of the response, so that it can not be mistaken for the
+    /// completion of another command.
+    ///
+    /// If the second response is `conflicting`, i.e. its status differs from the first one, the
+    /// result of the command can not be trusted, and the connection fails from now on.
+    DuplicateCompletion {
+        /// The tag of the command.
+        tag: imap_proto::RequestId,
+        /// Whether the status of the second response differs from the first one.
+        conflicting: bool,
+    },
+
+    /// An unsolicited [`FLAGS` response](https://tools.ietf.org/html/rfc3501#section-7.2.6)
+    /// with the updated flags defined in the selected mailbox, e.g. after a new keyword was
+    /// used. See [`Mailbox::update`].
+    Flags(FlagSet),
+
+    /// An unsolicited `OK` response with a [`PERMANENTFLAGS` response
+    /// code](https://tools.ietf.org/html/rfc3501#section-7.1), listing the flags that can be
+    /// changed permanently in the selected mailbox. See [`Mailbox::update`].
+    PermanentFlags(FlagSet),
+
+    /// An [`ACL` response](https://tools.ietf.org/html/rfc4314#section-3.6) listing the rights
+    /// granted on `mailbox`.
+    Acl {
+        /// The mailbox that the rights apply to.
+        mailbox: String,
+        /// The identifiers that rights are granted to, and the rights granted to each of them,
+        /// such as `lrswi`.
+        rights: Vec<(String, Rights)>,
+    },
+
+    /// A [`QUOTA` response](https://tools.ietf.org/html/rfc2087#section-5.1) with the usage and
+    /// limits of a quota root, e.g. after a message was appended. The answers to
+    /// [`Session::get_quota`](crate::Session::get_quota) and the other quota commands are
+    /// returned by the command instead.
+    Quota {
+        /// The name of the quota root.
+        root: String,
+        /// The resources limited by the quota root.
+        resources: Vec<QuotaResource>,
+    },
+
+    /// A [`QUOTAROOT` response](https://tools.ietf.org/html/rfc2087#section-5.2) that was not
+    /// requested with [`Session::get_quota_root`](crate::Session::get_quota_root).
+    QuotaRoot {
+        /// The mailbox whose quota roots are listed.
+        mailbox: String,
+        /// The names of the quota roots of the mailbox.
+        roots: Vec<String>,
+    },
+
+    /// An unsolicited [`METADATA` response](https://tools.ietf.org/html/rfc5464#section-4.4.2),
+    /// reporting that annotations of `mailbox` (or of the server, if `mailbox` is empty) have
+    /// changed.
+    Metadata {
+        /// The mailbox whose annotations changed.
+        mailbox: String,
+        /// The names of the changed entries, such as `/shared/comment`.
+        entries: Vec<String>,
+    },
+
+    /// An [`ENABLED` response](https://tools.ietf.org/html/rfc5161#section-3.2) listing the
+    /// extensions that an `ENABLE` command (see [`Session::enable`](crate::Session::enable))
+    /// turned on.
+    Enabled(Vec<String>),
+
+    /// An [`ID` response](https://tools.ietf.org/html/rfc2971) that was not requested with
+    /// [`Session::id`](crate::Session::id). Answers to `ID` commands are returned by the
+    /// command instead.
+    Id(ServerId),
+
+    /// A change to the result of a search or sort that was started with
+    /// [`SearchReturn::Update`]. See [`ContextUpdate`].
+    ContextUpdate(ContextUpdate),
+
     /// Any other kind of unsolicted response.
     Other(ResponseData),
 }
+
+impl UnsolicitedResponse {
+    /// Copies the response, except for [`Other`](UnsolicitedResponse::Other), whose raw
+    /// response data cannot be copied.
+    pub(crate) fn try_clone(&self) -> Option<UnsolicitedResponse> {
+        Some(match self {
+            UnsolicitedResponse::Status {
+                mailbox,
+                attributes,
+            } => UnsolicitedResponse::Status {
+                mailbox: mailbox.clone(),
+                attributes: attributes
+                    .iter()
+                    .map(|s| match s {
+                        // Fake clone
+                        StatusAttribute::HighestModSeq(a) => StatusAttribute::HighestModSeq(*a),
+                        StatusAttribute::Messages(a) => StatusAttribute::Messages(*a),
+                        StatusAttribute::Recent(a) => StatusAttribute::Recent(*a),
+                        StatusAttribute::UidNext(a) => StatusAttribute::UidNext(*a),
+                        StatusAttribute::UidValidity(a) => StatusAttribute::UidValidity(*a),
+                        StatusAttribute::Unseen(a) => StatusAttribute::Unseen(*a),
+                    })
+                    .collect(),
+            },
+            UnsolicitedResponse::Recent(n) => UnsolicitedResponse::Recent(*n),
+            UnsolicitedResponse::Exists(n) => UnsolicitedResponse::Exists(*n),
+            UnsolicitedResponse::Expunge(n) => UnsolicitedResponse::Expunge(*n),
+            UnsolicitedResponse::Vanished(vanished) => {
+                UnsolicitedResponse::Vanished(vanished.clone())
+            }
+            UnsolicitedResponse::ExistsInconsistent { old, new } => {
+                UnsolicitedResponse::ExistsInconsistent {
+                    old: *old,
+                    new: *new,
+                }
+            }
+            UnsolicitedResponse::DuplicateCompletion { tag, conflicting } => {
+                UnsolicitedResponse::DuplicateCompletion {
+                    tag: tag.clone(),
+                    conflicting: *conflicting,
+                }
+            }
+            UnsolicitedResponse::Flags(flags) => UnsolicitedResponse::Flags(flags.clone()),
+            UnsolicitedResponse::PermanentFlags(flags) => {
+                UnsolicitedResponse::PermanentFlags(flags.clone())
+            }
+            UnsolicitedResponse::Acl { mailbox, rights } => UnsolicitedResponse::Acl {
+                mailbox: mailbox.clone(),
+                rights: rights.clone(),
+            },
+            UnsolicitedResponse::Quota { root, resources } => UnsolicitedResponse::Quota {
+                root: root.clone(),
+                resources: resources.clone(),
+            },
+            UnsolicitedResponse::QuotaRoot { mailbox, roots } => UnsolicitedResponse::QuotaRoot {
+                mailbox: mailbox.clone(),
+                roots: roots.clone(),
+            },
+            UnsolicitedResponse::Metadata { mailbox, entries } => UnsolicitedResponse::Metadata {
+                mailbox: mailbox.clone(),
+                entries: entries.clone(),
+            },
+            UnsolicitedResponse::Enabled(extensions) => {
+                UnsolicitedResponse::Enabled(extensions.clone())
+            }
+            UnsolicitedResponse::Id(id) => UnsolicitedResponse::Id(id.clone()),
+            UnsolicitedResponse::ContextUpdate(update) => {
+                UnsolicitedResponse::ContextUpdate(update.clone())
+            }
+            UnsolicitedResponse::Other(_) => return None,
+        })
+    }
+}
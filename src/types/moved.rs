@@ -0,0 +1,21 @@
+use super::{CopyUid, Seq};
+
+/// The outcome of [`Session::mv`](crate::Session::mv) or
+/// [`Session::uid_mv`](crate::Session::uid_mv).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Moved {
+    /// The new UIDs of the messages in the destination mailbox, if the server supports
+    /// `UIDPLUS`.
+    pub copy_uid: Option<CopyUid>,
+
+    /// The sequence numbers from the `EXPUNGE` responses that removed the messages from the
+    /// selected mailbox, in the order they were received. Like any `EXPUNGE`, each one renumbers
+    /// the messages after it, so these can not be compared to the sequence numbers that were
+    /// moved.
+    ///
+    /// The responses are also delivered as
+    /// [`UnsolicitedResponse::Expunge`](crate::types::UnsolicitedResponse::Expunge), so that
+    /// code that follows the state of the mailbox sees them. Once `QRESYNC` is enabled, the
+    /// server sends a [`Vanished`](crate::types::Vanished) response instead, and this is empty.
+    pub expunged: Vec<Seq>,
+}
@@ -2,7 +2,8 @@ use std::borrow::Cow;
 
 use imap_proto::{MailboxDatum, Response};
 
-use crate::types::ResponseData;
+use crate::error::{Error, Result};
+use crate::types::{decode_utf7, ResponseData};
 
 rental! {
     pub mod rents {
@@ -20,8 +21,8 @@ rental! {
 #[derive(PartialEq, Eq, Debug)]
 pub struct InnerName<'a> {
     attributes: Vec<NameAttribute<'a>>,
-    delimiter: Option<&'a str>,
-    name: &'a str,
+    delimiter: Option<Cow<'a, str>>,
+    name: Cow<'a, str>,
 }
 
 pub use rents::Name;
@@ -46,17 +47,24 @@ pub enum NameAttribute<'a> {
     /// last time the mailbox was selected.
     Unmarked,
 
+    /// The mailbox name refers to a mailbox that does not exist, see [RFC
+    /// 5258](https://tools.ietf.org/html/rfc5258#section-3). This implies
+    /// [`NameAttribute::NoSelect`].
+    NonExistent,
+
     /// A non-standard user- or server-defined name attribute.
     Custom(Cow<'a, str>),
 }
 
 impl NameAttribute<'static> {
     fn system(s: &str) -> Option<Self> {
-        match s {
-            "\\Noinferiors" => Some(NameAttribute::NoInferiors),
-            "\\Noselect" => Some(NameAttribute::NoSelect),
-            "\\Marked" => Some(NameAttribute::Marked),
-            "\\Unmarked" => Some(NameAttribute::Unmarked),
+        // attribute names are case-insensitive, e.g. some servers send `\NoSelect`
+        match s.to_ascii_lowercase().as_str() {
+            "\\noinferiors" => Some(NameAttribute::NoInferiors),
+            "\\noselect" => Some(NameAttribute::NoSelect),
+            "\\marked" => Some(NameAttribute::Marked),
+            "\\unmarked" => Some(NameAttribute::Unmarked),
+            "\\nonexistent" => Some(NameAttribute::NonExistent),
             _ => None,
         }
     }
@@ -83,19 +91,20 @@ impl<'a> From<&'a str> for NameAttribute<'a> {
 }
 
 impl Name {
-    pub(crate) fn from_mailbox_data(resp: ResponseData) -> Self {
-        Name::new(Box::new(resp), |response| match response.parsed() {
+    pub(crate) fn from_mailbox_data(resp: ResponseData) -> Result<Self> {
+        Name::try_new(Box::new(resp), |response| match response.parsed() {
             Response::MailboxData(MailboxDatum::List {
                 flags,
                 delimiter,
                 name,
-            }) => InnerName {
+            }) => Ok(InnerName {
                 attributes: flags.iter().map(|s| NameAttribute::from(*s)).collect(),
-                delimiter: *delimiter,
-                name,
-            },
-            _ => panic!("cannot construct from non mailbox data"),
+                delimiter: delimiter.map(|delimiter| response.unquoted(delimiter)),
+                name: response.unquoted(name),
+            }),
+            response => Err(Error::from(response)),
         })
+        .map_err(|err| err.0)
     }
 
     /// Attributes of this name.
@@ -108,14 +117,70 @@ impl Name {
     /// of naming hierarchy.  All children of a top-level hierarchy node use the same
     /// separator character.  `None` means that no hierarchy exists; the name is a "flat" name.
     pub fn delimiter(&self) -> Option<&str> {
-        self.suffix().delimiter
+        self.suffix().delimiter.as_deref()
     }
 
     /// The name represents an unambiguous left-to-right hierarchy, and are valid for use as a
     /// reference in `LIST` and `LSUB` commands. Unless [`NameAttribute::NoSelect`] is indicated,
     /// the name is also valid as an argument for commands, such as `SELECT`, that accept mailbox
     /// names.
+    ///
+    /// The name is returned the way the server sent it, i.e. in [modified
+    /// UTF-7](https://tools.ietf.org/html/rfc3501#section-5.1.3), but without the escapes of a
+    /// quoted string. It can be passed on to commands such as
+    /// [`Session::select`](crate::Session::select) as is, see [`Name::decoded_name`] for the
+    /// name to show to users.
     pub fn name(&self) -> &str {
-        self.suffix().name
+        &self.suffix().name
+    }
+
+    /// The name decoded from [modified UTF-7](https://tools.ietf.org/html/rfc3501#section-5.1.3),
+    /// e.g. `Entwürfe` for `Entw&APw-rfe`, for showing it to users. If the server sent a name
+    /// that is not valid modified UTF-7, it is returned as is, see [`decode_utf7`].
+    ///
+    /// ```
+    /// # use futures::prelude::*;
+    /// # #[cfg(feature = "doctest-utils")]
+    /// # async_imap::doctest_utils::run("* LIST () \"/\" \"Entw&APw-rfe\"\r\n\
+    /// #     A0001 OK LIST completed\r\n", |mut session| async move {
+    /// let names: Vec<_> = session.list(None, Some("*")).await?.try_collect().await?;
+    /// assert_eq!(names[0].name(), "Entw&APw-rfe");
+    /// assert_eq!(names[0].decoded_name(), "Entwürfe");
+    /// # Ok(()) });
+    /// ```
+    ///
+    /// [`decode_utf7`]: crate::types::decode_utf7
+    pub fn decoded_name(&self) -> Cow<'_, str> {
+        let name = self.name();
+        if !name.contains('&') {
+            return Cow::Borrowed(name);
+        }
+        match decode_utf7(name) {
+            Some(decoded) => Cow::Owned(decoded),
+            None => Cow::Borrowed(name),
+        }
+    }
+
+    /// Builds a mailbox pattern for [`Session::list`](crate::Session::list) and
+    /// [`Session::lsub`](crate::Session::lsub) that matches the names exactly `depth` levels
+    /// below `parent`, or below the top level if `parent` is `None`. The pattern is quoted, so
+    /// that it can be passed on as is.
+    ///
+    /// Each level is matched with the `%` wildcard, which does not match `delimiter`, so the
+    /// server never has to enumerate the deeper levels. On accounts with many nested mailboxes,
+    /// this keeps a listing small; listing every level up to some depth takes one command per
+    /// level. `parent` must not contain wildcards itself.
+    ///
+    /// ```
+    /// use async_imap::types::Name;
+    ///
+    /// assert_eq!(Name::depth_pattern(None, "/", 1), r#""%""#);
+    /// assert_eq!(Name::depth_pattern(Some("Lists"), ".", 2), r#""Lists.%.%""#);
+    /// ```
+    pub fn depth_pattern(parent: Option<&str>, delimiter: &str, depth: usize) -> String {
+        let mut levels: Vec<&str> = parent.into_iter().filter(|p| !p.is_empty()).collect();
+        levels.extend(std::iter::repeat("%").take(depth));
+        let pattern = levels.join(delimiter);
+        format!("\"{}\"", pattern.replace('\\', "\\\\").replace('"', "\\\""))
     }
 }
@@ -0,0 +1,181 @@
+use super::parse::{nstring, strip_prefix_ignore_case};
+
+/// The namespaces of the server, from the response to
+/// [`Session::namespace`](crate::Session::namespace).
+///
+/// Each kind of namespace may be missing, or there may be several of it, e.g. a personal
+/// namespace for the mailboxes of the user and one for mailboxes that are kept on another
+/// server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Namespaces {
+    /// The namespaces of the user's own mailboxes. Most servers have one, whose prefix is empty,
+    /// or `INBOX.` on servers such as Courier that keep all mailboxes below the `INBOX`.
+    pub personal: Vec<Namespace>,
+    /// The namespaces in which the mailboxes of other users are found, e.g. `Other Users/`.
+    pub other_users: Vec<Namespace>,
+    /// The namespaces of mailboxes that are shared by the users, e.g. `Public Folders/`.
+    pub shared: Vec<Namespace>,
+}
+
+impl Namespaces {
+    /// The first personal namespace, in which new mailboxes of the user are usually created.
+    pub fn personal(&self) -> Option<&Namespace> {
+        self.personal.first()
+    }
+}
+
+/// A [namespace](https://tools.ietf.org/html/rfc2342#section-5): a part of the mailbox
+/// hierarchy whose mailbox names start with a common prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Namespace {
+    /// The prefix of the names of the mailboxes in the namespace, usually ending with the
+    /// delimiter, e.g. `INBOX.`. May be empty.
+    pub prefix: String,
+    /// The hierarchy delimiter in the namespace, or `None` if its mailboxes are not organized
+    /// hierarchically.
+    pub delimiter: Option<String>,
+    /// Extensions of the namespace, e.g. the translations of
+    /// [RFC 5255](https://tools.ietf.org/html/rfc5255#section-3.4), as their names and values.
+    pub extensions: Vec<(String, Vec<String>)>,
+}
+
+impl Namespace {
+    /// The full name of the mailbox `name` in this namespace, e.g. `INBOX.Archive` for
+    /// `Archive` on a server with the `INBOX.` prefix. The delimiter is added after the prefix
+    /// if the prefix does not end with it already. `name` is used as is, so it has to be
+    /// encoded like any other mailbox name.
+    pub fn mailbox(&self, name: &str) -> String {
+        let mut mailbox = self.prefix.clone();
+        if let Some(ref delimiter) = self.delimiter {
+            if !mailbox.is_empty() && !mailbox.ends_with(delimiter.as_str()) {
+                mailbox.push_str(delimiter);
+            }
+        }
+        mailbox.push_str(name);
+        mailbox
+    }
+}
+
+/// Parses a raw [`NAMESPACE` response](https://tools.ietf.org/html/rfc2342#section-5), which
+/// imap-proto does not support. Returns `None` if `raw` is some other response, or is
+/// malformed.
+pub(crate) fn parse_namespace(raw: &[u8]) -> Option<Namespaces> {
+    let raw = std::str::from_utf8(raw).ok()?.trim_end();
+    let rest = strip_prefix_ignore_case(raw, "* NAMESPACE ")?;
+    let (personal, rest) = namespaces(rest)?;
+    let (other_users, rest) = namespaces(strip_prefix_ignore_case(rest, " ")?)?;
+    let (shared, rest) = namespaces(strip_prefix_ignore_case(rest, " ")?)?;
+    if !rest.is_empty() {
+        return None;
+    }
+    Some(Namespaces {
+        personal,
+        other_users,
+        shared,
+    })
+}
+
+/// Parses a parenthesized list of namespace descriptions, or `NIL`, at the start of `s`, and
+/// returns it and the remaining input.
+fn namespaces(s: &str) -> Option<(Vec<Namespace>, &str)> {
+    if let Some(rest) = strip_prefix_ignore_case(s, "NIL") {
+        return Some((Vec::new(), rest));
+    }
+
+    let mut rest = strip_prefix_ignore_case(s, "(")?;
+    let mut namespaces = Vec::new();
+    // some servers separate the descriptions with spaces
+    while let Some(description) = strip_prefix_ignore_case(rest.trim_start(), "(") {
+        let (prefix, tail) = nstring(description)?;
+        let (delimiter, mut tail) = nstring(strip_prefix_ignore_case(tail, " ")?)?;
+
+        let mut extensions = Vec::new();
+        while let Some(extension) = strip_prefix_ignore_case(tail, " ") {
+            let (name, values) = nstring(extension)?;
+            let mut values = strip_prefix_ignore_case(values, " (")?;
+            let mut list = Vec::new();
+            loop {
+                let (value, end) = nstring(values)?;
+                list.push(value?);
+                match strip_prefix_ignore_case(end, " ") {
+                    Some(next) => values = next,
+                    None => {
+                        tail = strip_prefix_ignore_case(end, ")")?;
+                        break;
+                    }
+                }
+            }
+            extensions.push((name?, list));
+        }
+
+        rest = strip_prefix_ignore_case(tail, ")")?;
+        namespaces.push(Namespace {
+            prefix: prefix?,
+            delimiter,
+            extensions,
+        });
+    }
+    if namespaces.is_empty() {
+        return None;
+    }
+    Some((namespaces, strip_prefix_ignore_case(rest, ")")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace(prefix: &str, delimiter: Option<&str>) -> Namespace {
+        Namespace {
+            prefix: prefix.into(),
+            delimiter: delimiter.map(Into::into),
+            extensions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        // Courier
+        let namespaces =
+            parse_namespace(b"* NAMESPACE ((\"INBOX.\" \".\")) NIL ((\"#shared.\" \".\"))\r\n")
+                .unwrap();
+        assert_eq!(namespaces.personal, vec![namespace("INBOX.", Some("."))]);
+        assert!(namespaces.other_users.is_empty());
+        assert_eq!(namespaces.shared, vec![namespace("#shared.", Some("."))]);
+        assert_eq!(
+            namespaces.personal().unwrap().mailbox("Archive"),
+            "INBOX.Archive"
+        );
+
+        // examples from RFC 2342 and RFC 5255
+        let namespaces = parse_namespace(
+            b"* NAMESPACE ((\"\" \"/\")(\"#mh/\" \"/\" \"X-PARAM\" (\"FLAG1\" \"FLAG2\"))) \
+            ((\"~\" \"/\")) ((\"#shared/\" \"/\")(\"#public/\" \"/\")(\"#ftp/\" \"/\")(\"#news.\" \".\"))\r\n",
+        )
+        .unwrap();
+        assert_eq!(namespaces.personal.len(), 2);
+        assert_eq!(
+            namespaces.personal[1].extensions,
+            vec![("X-PARAM".into(), vec!["FLAG1".into(), "FLAG2".into()])]
+        );
+        assert_eq!(namespaces.other_users, vec![namespace("~", Some("/"))]);
+        assert_eq!(namespaces.shared.len(), 4);
+        assert_eq!(namespaces.personal().unwrap().mailbox("Drafts"), "Drafts");
+
+        let flat = parse_namespace(b"* NAMESPACE ((\"Mail\" NIL)) NIL NIL\r\n").unwrap();
+        assert_eq!(flat.personal, vec![namespace("Mail", None)]);
+        assert_eq!(namespace("Mail", Some("/")).mailbox("Sent"), "Mail/Sent");
+
+        assert_eq!(
+            parse_namespace(b"* NAMESPACE NIL NIL NIL\r\n"),
+            Some(Namespaces::default())
+        );
+        assert_eq!(
+            parse_namespace(b"* NAMESPACE (\"\" \"/\") NIL NIL\r\n"),
+            None
+        );
+        assert_eq!(parse_namespace(b"* NAMESPACE () NIL NIL\r\n"), None);
+        assert_eq!(parse_namespace(b"* NAMESPACE NIL NIL\r\n"), None);
+        assert_eq!(parse_namespace(b"* NAMESPACES NIL NIL NIL\r\n"), None);
+    }
+}
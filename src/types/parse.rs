@@ -0,0 +1,75 @@
+/// Strips `prefix` from the start of `s`, ignoring ASCII case.
+pub(crate) fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len()
+        && s.is_char_boundary(prefix.len())
+        && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// Parses an atom at the start of `s`, returning it and the remaining input.
+pub(crate) fn atom(s: &str) -> Option<(&str, &str)> {
+    let end = s
+        .find(|c: char| c == ' ' || c == '(' || c == ')' || c == '"' || c == '{')
+        .unwrap_or_else(|| s.len());
+    if end == 0 {
+        return None;
+    }
+    Some((&s[..end], &s[end..]))
+}
+
+/// Parses a quoted string at the start of `s`, returning it and the remaining input.
+pub(crate) fn quoted(s: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = strip_prefix_ignore_case(s, "\"")?.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            '"' => return Some((value, &s[i + 2..])),
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
+/// Parses an atom or a quoted string at the start of `s`, returning it and the remaining input.
+pub(crate) fn astring(s: &str) -> Option<(String, &str)> {
+    if s.starts_with('"') {
+        quoted(s)
+    } else {
+        atom(s).map(|(value, rest)| (value.to_string(), rest))
+    }
+}
+
+/// Parses a quoted string or `NIL` at the start of `s`, returning it and the remaining input.
+pub(crate) fn nstring(s: &str) -> Option<(Option<String>, &str)> {
+    if let Some(rest) = strip_prefix_ignore_case(s, "NIL") {
+        return Some((None, rest));
+    }
+    quoted(s).map(|(value, rest)| (Some(value), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings() {
+        assert_eq!(strip_prefix_ignore_case("* id (", "* ID "), Some("("));
+        assert_eq!(strip_prefix_ignore_case("é", "E"), None);
+        assert_eq!(atom("Fred rw"), Some(("Fred", " rw")));
+        assert_eq!(atom(" rw"), None);
+        assert_eq!(
+            astring("\"Other \\\"Guy\\\"\" lr"),
+            Some(("Other \"Guy\"".into(), " lr"))
+        );
+        assert_eq!(astring("lr)"), Some(("lr".into(), ")")));
+        assert_eq!(nstring("nil)"), Some((None, ")")));
+        assert_eq!(nstring("\"a\" b"), Some((Some("a".into()), " b")));
+        assert_eq!(nstring("\"unterminated"), None);
+        assert_eq!(nstring("atom"), None);
+    }
+}
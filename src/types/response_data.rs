@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::fmt;
 
 use byte_pool::Block;
@@ -45,4 +46,27 @@ impl ResponseData {
     pub fn parsed(&self) -> &Response<'_> {
         self.suffix()
     }
+
+    /// Unescapes `value`, a string from the parsed response, if the server sent it as a quoted
+    /// string. imap-proto leaves the `\\` and `\"` escapes of quoted strings in place, while
+    /// literals and atoms are taken as they are, so a backslash in a mailbox name means different
+    /// things depending on how the name was sent.
+    pub(crate) fn unquoted<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let raw: &[u8] = self.head();
+        let start = (value.as_ptr() as usize).wrapping_sub(raw.as_ptr() as usize);
+        let quoted = start > 0 && start <= raw.len() && raw[start - 1] == b'"';
+        if !quoted || !value.contains('\\') {
+            return Cow::Borrowed(value);
+        }
+
+        let mut unescaped = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                _ => unescaped.push(c),
+            }
+        }
+        Cow::Owned(unescaped)
+    }
 }
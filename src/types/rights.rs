@@ -0,0 +1,189 @@
+use std::fmt;
+use std::ops::{BitAnd, BitOr, Sub};
+use std::str::FromStr;
+
+use crate::error::ValidateError;
+
+/// The letters of the rights, in the order of their bits and in which they are displayed: the
+/// standard rights of RFC 4314, the obsolete `c` and `d` of RFC 2086, and the digits, which are
+/// left to the implementation.
+const LETTERS: &str = "lrswipkxteacd0123456789";
+
+/// A set of [access rights](https://tools.ietf.org/html/rfc4314#section-2.1) on a mailbox, e.g.
+/// `lrswi`, as granted to an identifier in an [`ACL`
+/// response](crate::types::UnsolicitedResponse::Acl).
+///
+/// Rights are stored as a bitmask, and combined like flags:
+///
+/// ```
+/// use async_imap::types::Rights;
+///
+/// let rights: Rights = "lrs".parse().unwrap();
+/// assert!(rights.contains(Rights::LOOKUP | Rights::READ));
+/// assert!(!rights.contains(Rights::WRITE));
+/// assert_eq!((rights | Rights::WRITE).to_string(), "lrsw");
+/// assert!("lrq".parse::<Rights>().is_err());
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Rights(u32);
+
+impl Rights {
+    /// `l`: the mailbox is visible to `LIST` and `LSUB`.
+    pub const LOOKUP: Rights = Rights(1 << 0);
+    /// `r`: the mailbox can be selected, and its messages fetched and searched.
+    pub const READ: Rights = Rights(1 << 1);
+    /// `s`: the `\Seen` flag is kept across sessions.
+    pub const KEEP_SEEN: Rights = Rights(1 << 2);
+    /// `w`: flags and keywords other than `\Seen` and `\Deleted` can be written.
+    pub const WRITE: Rights = Rights(1 << 3);
+    /// `i`: messages can be appended and copied into the mailbox.
+    pub const INSERT: Rights = Rights(1 << 4);
+    /// `p`: messages can be sent to the submission address of the mailbox.
+    pub const POST: Rights = Rights(1 << 5);
+    /// `k`: mailboxes can be created below the mailbox, or with its name.
+    pub const CREATE_MAILBOX: Rights = Rights(1 << 6);
+    /// `x`: the mailbox can be deleted and renamed.
+    pub const DELETE_MAILBOX: Rights = Rights(1 << 7);
+    /// `t`: the `\Deleted` flag can be written.
+    pub const DELETE_MESSAGES: Rights = Rights(1 << 8);
+    /// `e`: the mailbox can be expunged.
+    pub const EXPUNGE: Rights = Rights(1 << 9);
+    /// `a`: the access control list of the mailbox can be changed.
+    pub const ADMINISTER: Rights = Rights(1 << 10);
+    /// `c`: the obsolete create right of [RFC 2086](https://tools.ietf.org/html/rfc2086), which
+    /// servers map to `k`, `x` or both.
+    pub const OBSOLETE_CREATE: Rights = Rights(1 << 11);
+    /// `d`: the obsolete delete right of [RFC 2086](https://tools.ietf.org/html/rfc2086), which
+    /// servers map to `t`, `e`, `x` or some of them.
+    pub const OBSOLETE_DELETE: Rights = Rights(1 << 12);
+
+    /// The empty set.
+    pub fn empty() -> Self {
+        Rights(0)
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all rights in `other` are also in `self`.
+    pub fn contains(self, other: Rights) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The rights that are in `self` or in `other`.
+    pub fn union(self, other: Rights) -> Rights {
+        Rights(self.0 | other.0)
+    }
+
+    /// The rights that are in both `self` and `other`.
+    pub fn intersection(self, other: Rights) -> Rights {
+        Rights(self.0 & other.0)
+    }
+
+    /// The rights that are in `self`, but not in `other`.
+    pub fn difference(self, other: Rights) -> Rights {
+        Rights(self.0 & !other.0)
+    }
+
+    /// Parses the rights in a response from the server. Unlike `FromStr`, rights that
+    /// are not known are skipped, so that a server with rights of its own does not make the whole
+    /// response unreadable.
+    pub(crate) fn from_response(s: &str) -> Rights {
+        s.chars()
+            .filter_map(bit)
+            .fold(Rights::empty(), Rights::union)
+    }
+}
+
+fn bit(letter: char) -> Option<Rights> {
+    LETTERS.find(letter).map(|i| Rights(1 << i))
+}
+
+impl FromStr for Rights {
+    type Err = ValidateError;
+
+    /// Parses rights such as `lrswi`, in any order. Fails on letters that are not rights,
+    /// including upper-case ones.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars().try_fold(Rights::empty(), |rights, letter| {
+            bit(letter)
+                .map(|bit| rights | bit)
+                .ok_or(ValidateError(letter))
+        })
+    }
+}
+
+impl fmt::Display for Rights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, letter) in LETTERS.chars().enumerate() {
+            if self.0 & (1 << i) != 0 {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Rights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rights({:?})", self.to_string())
+    }
+}
+
+impl BitOr for Rights {
+    type Output = Rights;
+
+    fn bitor(self, other: Rights) -> Rights {
+        self.union(other)
+    }
+}
+
+impl BitAnd for Rights {
+    type Output = Rights;
+
+    fn bitand(self, other: Rights) -> Rights {
+        self.intersection(other)
+    }
+}
+
+impl Sub for Rights {
+    type Output = Rights;
+
+    fn sub(self, other: Rights) -> Rights {
+        self.difference(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        let rights: Rights = "rwipslxetad".parse().unwrap();
+        assert_eq!(rights.to_string(), "lrswipxtead");
+        assert!(rights.contains(Rights::ADMINISTER | Rights::OBSOLETE_DELETE));
+        assert!(!rights.contains(Rights::CREATE_MAILBOX));
+        assert_eq!("".parse::<Rights>().unwrap(), Rights::empty());
+        assert_eq!("7l".parse::<Rights>().unwrap().to_string(), "l7");
+        assert_eq!("lR".parse::<Rights>().unwrap_err().0, 'R');
+        assert_eq!(format!("{:?}", Rights::READ), "Rights(\"r\")");
+
+        assert_eq!(Rights::from_response("lrQz"), Rights::LOOKUP | Rights::READ);
+    }
+
+    #[test]
+    fn set_operations() {
+        let a: Rights = "lrsw".parse().unwrap();
+        let b: Rights = "lrik".parse().unwrap();
+        assert_eq!(a.union(b).to_string(), "lrswik");
+        assert_eq!((a & b).to_string(), "lr");
+        assert_eq!((a - b).to_string(), "sw");
+        assert!(a.contains(a & b));
+        assert!(!a.contains(b));
+        assert!(a.contains(Rights::empty()));
+        assert!((a - a).is_empty());
+    }
+}
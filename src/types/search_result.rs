@@ -0,0 +1,463 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::sequence_set::parse_ranges;
+
+/// The most identifiers that the `ESEARCH` responses to a single command may list when they are
+/// expanded into a [`SearchResult`]. No mailbox holds that many messages, and since a short range
+/// like `1:4294967295` expands to billions of them, larger results are rejected rather than
+/// allocated. An [`EsearchResult`] keeps the ranges, and is not limited.
+pub(crate) const MAX_SEARCH_RESULTS: usize = 1 << 24;
+
+/// The result of [`Session::search_detailed`](crate::Session::search_detailed), merged from all
+/// `SEARCH` and `ESEARCH` responses that the server sent for the command.
+///
+/// Servers may split the result over several responses, e.g. several `* SEARCH` lines, or
+/// several `PARTIAL` [`ESEARCH` responses](https://tools.ietf.org/html/rfc4731). The identifiers
+/// are deduplicated and kept in ascending order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchResult {
+    ids: BTreeSet<u32>,
+    lines: usize,
+}
+
+impl SearchResult {
+    /// The matching message sequence numbers or [`Uid`](crate::types::Uid)s, in ascending
+    /// order.
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = u32> + '_ {
+        self.ids.iter().cloned()
+    }
+
+    /// Whether `id` matched the search.
+    pub fn contains(&self, id: u32) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// The number of matching messages.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether no message matched.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The number of response lines that were merged into this result, for diagnostics.
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// The matching identifiers, in ascending order.
+    pub fn into_set(self) -> BTreeSet<u32> {
+        self.ids
+    }
+
+    /// Adds the identifiers from one response line.
+    pub(crate) fn merge<I: IntoIterator<Item = u32>>(&mut self, ids: I) {
+        self.ids.extend(ids);
+        self.lines += 1;
+    }
+}
+
+/// A [return option](https://tools.ietf.org/html/rfc4731#section-3.1) for
+/// [`Session::search_return`](crate::Session::search_return) and
+/// [`Session::sort_return`](crate::Session::sort_return), which selects what the server
+/// reports about the matching messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchReturn {
+    /// The lowest matching identifier.
+    Min,
+    /// The highest matching identifier.
+    Max,
+    /// The number of matching messages.
+    Count,
+    /// All matching identifiers, as ranges.
+    All,
+    /// The matching identifiers at the given positions of the result, counted from 1 and
+    /// inclusive, e.g. `Partial(1, 50)` for the first page of a sorted message list. Needs the
+    /// [`CONTEXT=SEARCH` or `CONTEXT=SORT`](https://tools.ietf.org/html/rfc5267#section-4.4)
+    /// capability.
+    Partial(u32, u32),
+    /// Keeps the result up to date after the command completed: the server reports messages
+    /// that start or stop matching as [`ContextUpdate`]s, until the update is cancelled with
+    /// [`Session::cancel_update`](crate::Session::cancel_update). Needs the `CONTEXT=SEARCH`
+    /// or `CONTEXT=SORT` capability.
+    Update,
+    /// Saves the matching messages on the server, so that later commands can refer to them as
+    /// [`SAVED_RESULT`](crate::types::SAVED_RESULT) instead of listing them. Needs the
+    /// [`SEARCHRES`](https://tools.ietf.org/html/rfc5182) capability. With no other option,
+    /// the server reports nothing about the matches.
+    Save,
+}
+
+impl SearchReturn {
+    /// Whether the option needs the `CONTEXT=SEARCH` or `CONTEXT=SORT` capability.
+    pub(crate) fn needs_context(self) -> bool {
+        match self {
+            SearchReturn::Partial(..) | SearchReturn::Update => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for SearchReturn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SearchReturn::Min => f.write_str("MIN"),
+            SearchReturn::Max => f.write_str("MAX"),
+            SearchReturn::Count => f.write_str("COUNT"),
+            SearchReturn::All => f.write_str("ALL"),
+            SearchReturn::Partial(first, last) => write!(f, "PARTIAL {}:{}", first, last),
+            SearchReturn::Update => f.write_str("UPDATE"),
+            SearchReturn::Save => f.write_str("SAVE"),
+        }
+    }
+}
+
+/// The result of [`Session::search_return`](crate::Session::search_return) or
+/// [`Session::sort_return`](crate::Session::sort_return), from an [`ESEARCH`
+/// response](https://tools.ietf.org/html/rfc4731#section-3.1).
+///
+/// Only the data that was asked for with [`SearchReturn`] options is set. Unlike a
+/// [`SearchResult`], the matching identifiers are kept as ranges, so that the result stays small
+/// when most of a large mailbox matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct EsearchResult {
+    /// Whether the identifiers are [`Uid`](crate::types::Uid)s rather than sequence numbers.
+    pub uid: bool,
+
+    /// The lowest matching identifier, for [`SearchReturn::Min`], unless nothing matched.
+    pub min: Option<u32>,
+
+    /// The highest matching identifier, for [`SearchReturn::Max`], unless nothing matched.
+    pub max: Option<u32>,
+
+    /// The number of matching messages, for [`SearchReturn::Count`].
+    pub count: Option<u32>,
+
+    /// The matching identifiers as inclusive ranges, for [`SearchReturn::All`], in the order
+    /// the server listed them, which is the sort order for `SORT`.
+    pub all: Vec<RangeInclusive<u32>>,
+
+    /// The matching identifiers at the positions asked for with [`SearchReturn::Partial`], as
+    /// inclusive ranges in the order of the result.
+    pub partial: Vec<RangeInclusive<u32>>,
+}
+
+impl EsearchResult {
+    /// Computes the result that the server would have returned for `options` from the matching
+    /// `ids`, in the order of the result, for servers without `ESEARCH` or `ESORT`.
+    /// [`SearchReturn::Update`] can not be emulated, and is ignored.
+    pub(crate) fn from_ids(ids: &[u32], options: &[SearchReturn], uid: bool) -> Self {
+        let mut result = EsearchResult {
+            uid,
+            ..EsearchResult::default()
+        };
+        for option in options {
+            match *option {
+                SearchReturn::Min => result.min = ids.iter().cloned().min(),
+                SearchReturn::Max => result.max = ids.iter().cloned().max(),
+                SearchReturn::Count => result.count = Some(ids.len() as u32),
+                SearchReturn::All => result.all = to_ranges(ids.iter().cloned()),
+                SearchReturn::Partial(first, last) => {
+                    let first = std::cmp::max(first, 1) as usize;
+                    let positions = (last as usize + 1).saturating_sub(first);
+                    result.partial = to_ranges(ids.iter().cloned().skip(first - 1).take(positions));
+                }
+                SearchReturn::Update | SearchReturn::Save => {}
+            }
+        }
+        // `RETURN ()` means `RETURN (ALL)`
+        if options.is_empty() {
+            result.all = to_ranges(ids.iter().cloned());
+        }
+        result
+    }
+
+    /// The matching identifiers listed in [`EsearchResult::all`].
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.all.iter().cloned().flatten()
+    }
+
+    /// Whether `id` is listed in [`EsearchResult::all`].
+    pub fn contains(&self, id: u32) -> bool {
+        self.all.iter().any(|range| range.contains(&id))
+    }
+
+    /// The number of identifiers listed in `ALL` and `PARTIAL` results.
+    pub(crate) fn listed(&self) -> u64 {
+        self.all
+            .iter()
+            .chain(&self.partial)
+            .map(|range| u64::from(range.end() - range.start()) + 1)
+            .sum()
+    }
+
+    /// Adds the data of another response to the same command.
+    pub(crate) fn merge(&mut self, other: EsearchResult) {
+        self.uid |= other.uid;
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+        self.max = std::cmp::max(self.max, other.max);
+        self.count = match (self.count, other.count) {
+            (Some(a), Some(b)) => Some(a.saturating_add(b)),
+            (a, b) => a.or(b),
+        };
+        self.all.extend(other.all);
+        self.partial.extend(other.partial);
+    }
+}
+
+/// A change to the result of a search or sort that was started with [`SearchReturn::Update`],
+/// reported on the `unsolicited_responses` channel of the [`Session`](crate::Session) as
+/// [`UnsolicitedResponse::ContextUpdate`](crate::types::UnsolicitedResponse::ContextUpdate).
+/// See [RFC 5267](https://tools.ietf.org/html/rfc5267#section-4.3).
+///
+/// Each change is the position in the result at which the listed messages start, counted from
+/// 1, and the messages as inclusive ranges. The position is 0 if the server does not say, e.g.
+/// for results of `SEARCH`, which have no order of their own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ContextUpdate {
+    /// The tag of the command whose result changed.
+    pub tag: String,
+
+    /// Whether the identifiers are [`Uid`](crate::types::Uid)s rather than sequence numbers.
+    pub uid: bool,
+
+    /// The messages that now match.
+    pub added: Vec<(u32, Vec<RangeInclusive<u32>>)>,
+
+    /// The messages that no longer match.
+    pub removed: Vec<(u32, Vec<RangeInclusive<u32>>)>,
+}
+
+/// A parsed `ESEARCH` response, see [`parse_esearch`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Esearch {
+    /// The results of the command with the tag, if the response names it.
+    Result(Option<String>, EsearchResult),
+    /// A change to an earlier result.
+    Update(ContextUpdate),
+}
+
+/// Parses a raw [`ESEARCH` response](https://tools.ietf.org/html/rfc4731#section-3.1), which
+/// imap-proto does not support, into the tag of the command it belongs to and its results,
+/// including `PARTIAL` ones, or into an update with `ADDTO` and `REMOVEFROM`
+/// ([RFC 5267](https://tools.ietf.org/html/rfc5267)). Other results, such as `MODSEQ`, are
+/// ignored.
+pub(crate) fn parse_esearch(raw: &[u8]) -> Option<Esearch> {
+    let raw = std::str::from_utf8(raw).ok()?.trim_end();
+    let mut rest = strip_prefix(raw, "* ESEARCH")?;
+
+    let mut tag = None;
+    if let Some(tail) = strip_prefix(rest.trim_start(), "(TAG \"") {
+        let end = tail.find('"')?;
+        tag = Some(tail[..end].to_string());
+        rest = strip_prefix(&tail[end + 1..], ")")?;
+    }
+
+    let mut result = EsearchResult::default();
+    let mut update = ContextUpdate::default();
+    let mut words = rest.split_whitespace();
+    while let Some(word) = words.next() {
+        if word.eq_ignore_ascii_case("UID") {
+            result.uid = true;
+            continue;
+        }
+        let mut value = words.next()?;
+        if word.eq_ignore_ascii_case("MIN") {
+            result.min = Some(value.parse().ok()?);
+        } else if word.eq_ignore_ascii_case("MAX") {
+            result.max = Some(value.parse().ok()?);
+        } else if word.eq_ignore_ascii_case("COUNT") {
+            result.count = Some(value.parse().ok()?);
+        } else if word.eq_ignore_ascii_case("ALL") {
+            result.all.extend(parse_ranges(value)?);
+        } else if word.eq_ignore_ascii_case("PARTIAL") {
+            // `(<range> <set>)`, where the set is `NIL` if the range is beyond the results
+            let set = words.next()?.trim_end_matches(')');
+            if !value.starts_with('(') {
+                return None;
+            }
+            if !set.eq_ignore_ascii_case("NIL") {
+                result.partial.extend(parse_ranges(set)?);
+            }
+        } else if word.eq_ignore_ascii_case("ADDTO") || word.eq_ignore_ascii_case("REMOVEFROM") {
+            // `(<position> <set> <position> <set> ...)`
+            value = strip_char(value, '(')?;
+            let changes = if word.eq_ignore_ascii_case("ADDTO") {
+                &mut update.added
+            } else {
+                &mut update.removed
+            };
+            loop {
+                let set = words.next()?;
+                let end = set.trim_end_matches(')');
+                changes.push((value.parse().ok()?, parse_ranges(end)?));
+                if end.len() < set.len() {
+                    break;
+                }
+                value = words.next()?;
+            }
+        }
+    }
+
+    if update.added.is_empty() && update.removed.is_empty() {
+        return Some(Esearch::Result(tag, result));
+    }
+    update.tag = tag?;
+    update.uid = result.uid;
+    Some(Esearch::Update(update))
+}
+
+/// Parses a raw [`SORT` response](https://tools.ietf.org/html/rfc5256#section-4), which
+/// imap-proto does not support, into the identifiers it lists, in sort order. Returns `None` if
+/// `raw` is some other response, or is malformed.
+pub(crate) fn parse_sort(raw: &[u8]) -> Option<Vec<u32>> {
+    let raw = std::str::from_utf8(raw).ok()?.trim_end();
+    let rest = strip_prefix(raw, "* SORT")?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    rest.split_whitespace().map(|id| id.parse().ok()).collect()
+}
+
+/// Turns identifiers into inclusive ranges, merging runs of consecutive ascending ones and
+/// keeping their order otherwise.
+fn to_ranges<I: IntoIterator<Item = u32>>(ids: I) -> Vec<RangeInclusive<u32>> {
+    let mut ranges: Vec<RangeInclusive<u32>> = Vec::new();
+    for id in ids {
+        match ranges.last_mut() {
+            Some(last) if u64::from(*last.end()) + 1 == u64::from(id) => *last = *last.start()..=id,
+            _ => ranges.push(id..=id),
+        }
+    }
+    ranges
+}
+
+fn strip_char(s: &str, c: char) -> Option<&str> {
+    if s.starts_with(c) {
+        Some(&s[c.len_utf8()..])
+    } else {
+        None
+    }
+}
+
+fn strip_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len()
+        && s.is_char_boundary(prefix.len())
+        && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+    {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(raw: &[u8]) -> (Option<String>, EsearchResult) {
+        match parse_esearch(raw) {
+            Some(Esearch::Result(tag, result)) => (tag, result),
+            other => panic!("not a result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn esearch() {
+        let (tag, result) =
+            parsed(b"* ESEARCH (TAG \"A0001\") UID MIN 2 MAX 9 COUNT 5 ALL 4:6,2,9\r\n");
+        assert_eq!(tag.as_deref(), Some("A0001"));
+        assert_eq!(
+            result,
+            EsearchResult {
+                uid: true,
+                min: Some(2),
+                max: Some(9),
+                count: Some(5),
+                all: vec![4..=6, 2..=2, 9..=9],
+                partial: vec![],
+            }
+        );
+        assert_eq!(result.ids().collect::<Vec<_>>(), vec![4, 5, 6, 2, 9]);
+        assert!(result.contains(5));
+        assert!(!result.contains(7));
+
+        let (_, result) = parsed(b"* ESEARCH (TAG \"A0002\") UID PARTIAL (1:3 200:201,7)\r\n");
+        assert_eq!(result.partial, vec![200..=201, 7..=7]);
+        assert_eq!(result.listed(), 3);
+        let (_, result) = parsed(b"* ESEARCH (TAG \"A0003\") PARTIAL (5:10 NIL)\r\n");
+        assert_eq!(result.listed(), 0);
+
+        assert_eq!(
+            parse_esearch(b"* ESEARCH\r\n"),
+            Some(Esearch::Result(None, EsearchResult::default()))
+        );
+        assert_eq!(parse_esearch(b"* SEARCH 1 2\r\n"), None);
+        assert_eq!(parse_esearch(b"* ESEARCH COUNT lots\r\n"), None);
+
+        // ranges are not expanded, however large they are
+        let (_, result) = parsed(b"* ESEARCH ALL 1:4294967295\r\n");
+        assert_eq!(result.all, vec![1..=4294967295]);
+        assert_eq!(result.listed(), 4294967295);
+    }
+
+    #[test]
+    fn context_update() {
+        assert_eq!(
+            parse_esearch(
+                b"* ESEARCH (TAG \"A0004\") UID ADDTO (1 2733 3 2740:2741) REMOVEFROM (0 2731)\r\n"
+            ),
+            Some(Esearch::Update(ContextUpdate {
+                tag: "A0004".into(),
+                uid: true,
+                added: vec![(1, vec![2733..=2733]), (3, vec![2740..=2741])],
+                removed: vec![(0, vec![2731..=2731])],
+            }))
+        );
+        // updates always name the command they belong to
+        assert_eq!(parse_esearch(b"* ESEARCH ADDTO (1 2733)\r\n"), None);
+        assert_eq!(
+            parse_esearch(b"* ESEARCH (TAG \"A\") ADDTO (1 2733\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn sort() {
+        assert_eq!(parse_sort(b"* SORT 2 84 882\r\n"), Some(vec![2, 84, 882]));
+        assert_eq!(parse_sort(b"* SORT\r\n"), Some(vec![]));
+        assert_eq!(parse_sort(b"* SORT 2 x\r\n"), None);
+        assert_eq!(parse_sort(b"* SORTED 2\r\n"), None);
+    }
+
+    #[test]
+    fn esearch_from_ids() {
+        let ids = [7, 1, 2, 3, 9, 10];
+        let options = [
+            SearchReturn::Min,
+            SearchReturn::Count,
+            SearchReturn::Partial(2, 4),
+        ];
+        let result = EsearchResult::from_ids(&ids, &options, true);
+        assert_eq!(result.min, Some(1));
+        assert_eq!(result.max, None);
+        assert_eq!(result.count, Some(6));
+        assert_eq!(result.partial, vec![1..=3]);
+        assert!(result.all.is_empty());
+
+        let result = EsearchResult::from_ids(&ids, &[], false);
+        assert_eq!(result.all, vec![7..=7, 1..=3, 9..=10]);
+        assert_eq!(result.count, None);
+
+        let result = EsearchResult::from_ids(&ids, &[SearchReturn::Partial(6, 100)], false);
+        assert_eq!(result.partial, vec![10..=10]);
+        assert_eq!(SearchReturn::Partial(1, 50).to_string(), "PARTIAL 1:50");
+    }
+}
@@ -0,0 +1,245 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+/// The sequence set that refers to the messages saved by the last search with
+/// [`SearchReturn::Save`](crate::types::SearchReturn::Save), as defined by [RFC
+/// 5182](https://tools.ietf.org/html/rfc5182). It can be passed to the commands that take a
+/// sequence set, such as [`Session::uid_fetch`](crate::Session::uid_fetch),
+/// [`Session::uid_store`](crate::Session::uid_store),
+/// [`Session::uid_copy`](crate::Session::uid_copy) or
+/// [`Session::uid_mv`](crate::Session::uid_mv), so that large results are not sent back and
+/// forth.
+///
+/// The saved result is empty after a search that failed, and is forgotten when another mailbox
+/// is selected. Saved sequence numbers are not updated when messages are expunged, so it is
+/// best used with the `UID` commands.
+///
+/// ```no_run
+/// use async_imap::types::{SearchReturn, SAVED_RESULT};
+/// # fn main() -> async_imap::error::Result<()> {
+/// # async_std::task::block_on(async {
+/// # let client = async_imap::connect(("imap.example.org", 993), "imap.example.org",
+/// #     async_native_tls::TlsConnector::new()).await?;
+/// # let mut session = client.login("user", "pass").await.map_err(|(e, _)| e)?;
+/// session.select("INBOX").await?;
+/// session
+///     .uid_search_return(&[SearchReturn::Save], "BEFORE 1-Jan-2020")
+///     .await?;
+/// session.uid_copy(SAVED_RESULT, "Archive").await?;
+/// # Ok(())
+/// # }) }
+/// ```
+pub const SAVED_RESULT: &str = "$";
+
+/// Formats `ids` as a [sequence set](https://tools.ietf.org/html/rfc3501#section-9) in minimal
+/// form, e.g. `1:3,7,9:10` for the identifiers 1, 2, 3, 7, 9 and 10.
+///
+/// This is useful to pass the result of a [`Session::search`](crate::Session::search) on to
+/// another command without producing overly long command lines. The identifiers may come in any
+/// order and contain duplicates; formatting them takes `O(n log n)` time.
+///
+/// ```
+/// use async_imap::types::to_sequence_set;
+///
+/// assert_eq!(to_sequence_set(vec![9, 2, 1, 7, 3, 10]), "1:3,7,9:10");
+/// ```
+pub fn to_sequence_set<I: IntoIterator<Item = u32>>(ids: I) -> String {
+    format_ranges(ids.into_iter().map(|id| (id, id)).collect())
+}
+
+/// Rewrites a sequence set given as a string into minimal form. Sets that use `*` or anything
+/// else that can not be interpreted as a list of numbers and ranges are returned unchanged.
+pub(crate) fn compress_set(set: &str) -> Cow<'_, str> {
+    let mut ranges = Vec::new();
+    for part in set.split(',') {
+        let range = match part.find(':') {
+            Some(i) => (part[..i].parse::<u32>(), part[i + 1..].parse::<u32>()),
+            None => (part.parse::<u32>(), part.parse::<u32>()),
+        };
+        match range {
+            (Ok(a), Ok(b)) => ranges.push((std::cmp::min(a, b), std::cmp::max(a, b))),
+            _ => return Cow::Borrowed(set),
+        }
+    }
+
+    let compressed = format_ranges(ranges);
+    if compressed.len() < set.len() {
+        Cow::Owned(compressed)
+    } else {
+        Cow::Borrowed(set)
+    }
+}
+
+/// Parses a sequence set that consists of numbers and ranges only, such as the UID sets in
+/// responses, into inclusive ranges in the order they are listed.
+pub(crate) fn parse_ranges(set: &str) -> Option<Vec<RangeInclusive<u32>>> {
+    let mut ranges = Vec::new();
+    for part in set.split(',') {
+        let (a, b): (u32, u32) = match part.find(':') {
+            Some(i) => (part[..i].parse().ok()?, part[i + 1..].parse().ok()?),
+            None => (part.parse().ok()?, part.parse().ok()?),
+        };
+        ranges.push(std::cmp::min(a, b)..=std::cmp::max(a, b));
+    }
+    Some(ranges)
+}
+
+/// Compresses all sequence sets in a `SEARCH` query. Only words that consist entirely of a
+/// sequence set are considered, and quoted strings are left alone.
+pub(crate) fn compress_query(query: &str) -> Cow<'_, str> {
+    let mut out = String::with_capacity(query.len());
+    let mut changed = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut token_start = None;
+
+    for (i, c) in query.char_indices() {
+        if in_quotes {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            continue;
+        }
+        if c.is_ascii_digit() || c == ',' || c == ':' {
+            let at_word_start = query[..i].ends_with(|p| p == ' ' || p == '(') || i == 0;
+            if token_start.is_none() && at_word_start {
+                token_start = Some(i);
+            } else if token_start.is_none() {
+                out.push(c);
+            }
+            continue;
+        }
+        if let Some(start) = token_start.take() {
+            let token = &query[start..i];
+            if c == ' ' || c == ')' {
+                let compressed = compress_set(token);
+                changed |= compressed.len() != token.len();
+                out.push_str(&compressed);
+            } else {
+                out.push_str(token);
+            }
+        }
+        if c == '"' {
+            in_quotes = true;
+        }
+        out.push(c);
+    }
+    if let Some(start) = token_start {
+        let token = compress_set(&query[start..]);
+        changed |= token.len() != query.len() - start;
+        out.push_str(&token);
+    }
+
+    if changed {
+        Cow::Owned(out)
+    } else {
+        Cow::Borrowed(query)
+    }
+}
+
+/// Sorts and merges inclusive ranges, and formats them as a sequence set. The set is written
+/// into a single string, so that formatting large sets does not allocate for every range.
+fn format_ranges(mut ranges: Vec<(u32, u32)>) -> String {
+    ranges.sort_unstable();
+
+    let mut set = String::new();
+    let mut ranges = ranges.into_iter();
+    let mut current = match ranges.next() {
+        Some(range) => range,
+        None => return set,
+    };
+    for (start, end) in ranges {
+        if u64::from(start) <= u64::from(current.1) + 1 {
+            current.1 = std::cmp::max(current.1, end);
+        } else {
+            push_range(&mut set, current);
+            current = (start, end);
+        }
+    }
+    push_range(&mut set, current);
+    set
+}
+
+/// Appends a range to a sequence set, separated from the ranges before it by a comma.
+fn push_range(set: &mut String, (start, end): (u32, u32)) {
+    if !set.is_empty() {
+        set.push(',');
+    }
+    // writing to a `String` can not fail
+    let _ = if start == end {
+        write!(set, "{}", start)
+    } else {
+        write!(set, "{}:{}", start, end)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_sets() {
+        assert_eq!(compress_set("1,2,3,5,4,9,8"), "1:5,8:9");
+        assert_eq!(compress_set("3:1,2,10"), "1:3,10");
+        assert_eq!(compress_set("1:*"), "1:*");
+        assert_eq!(compress_set("7"), "7");
+        assert_eq!(compress_set("$"), "$");
+    }
+
+    /// Expands a sequence set into its identifiers.
+    fn expand(set: &str) -> Vec<u32> {
+        parse_ranges(set).unwrap().into_iter().flatten().collect()
+    }
+
+    #[test]
+    fn large_sets() {
+        let all: Vec<u32> = (1..=100_000).rev().collect();
+        assert_eq!(to_sequence_set(all), "1:100000");
+
+        let odd: Vec<u32> = (1..=200_000).step_by(2).collect();
+        let set = to_sequence_set(odd.iter().rev().cloned());
+        assert_eq!(set.len(), 644_444);
+        assert_eq!(expand(&set), odd);
+
+        // scattered identifiers with duplicates, from a linear congruential generator
+        let mut state = 12345u32;
+        let mut scattered = Vec::new();
+        for _ in 0..100_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            scattered.push(state % 150_000 + 1);
+        }
+        let set = to_sequence_set(scattered.iter().cloned());
+        let mut expected = scattered;
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(expand(&set), expected);
+        // minimal: ranges neither touch nor overlap
+        let ranges = parse_ranges(&set).unwrap();
+        assert!(ranges
+            .windows(2)
+            .all(|w| u64::from(*w[0].end()) + 1 < u64::from(*w[1].start())));
+
+        assert_eq!(
+            to_sequence_set(vec![u32::max_value(), 1, u32::max_value() - 1]),
+            "1,4294967294:4294967295"
+        );
+        assert_eq!(to_sequence_set(Vec::new()), "");
+    }
+
+    #[test]
+    fn compress_queries() {
+        assert_eq!(
+            compress_query("UID 1,2,3 SUBJECT \"1,2,3\" NOT 5,6"),
+            "UID 1:3 SUBJECT \"1,2,3\" NOT 5:6"
+        );
+        assert_eq!(compress_query("SINCE 1-Feb-1994"), "SINCE 1-Feb-1994");
+        assert_eq!(compress_query("LARGER 1000"), "LARGER 1000");
+        assert_eq!(compress_query("(1,2 OR 3,4:5)"), "(1:2 OR 3:5)");
+        assert_eq!(compress_query("HEADER X-Ids 1,2,3x"), "HEADER X-Ids 1,2,3x");
+    }
+}
@@ -0,0 +1,100 @@
+use super::parse::{nstring, strip_prefix_ignore_case};
+
+/// The implementation details a server reported in response to the [`ID`
+/// command](https://tools.ietf.org/html/rfc2971), such as its name and version.
+///
+/// Field names are case-insensitive. Servers may send arbitrary fields besides the standard ones
+/// (`name`, `version`, `os`, `os-version`, `vendor`, `support-url`, `address`, `date`,
+/// `command`, `arguments` and `environment`), and any of them may be missing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerId {
+    fields: Vec<(String, Option<String>)>,
+}
+
+impl ServerId {
+    /// The value of the field called `key`, if the server sent it and it is not `NIL`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.as_ref().map(String::as_str))
+    }
+
+    /// The name of the server software, e.g. `Dovecot`.
+    pub fn name(&self) -> Option<&str> {
+        self.get("name")
+    }
+
+    /// The version of the server software.
+    pub fn version(&self) -> Option<&str> {
+        self.get("version")
+    }
+
+    /// The vendor of the server software.
+    pub fn vendor(&self) -> Option<&str> {
+        self.get("vendor")
+    }
+
+    /// All fields in the order in which the server sent them.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_ref().map(String::as_str)))
+    }
+
+    /// Parses a raw `* ID (...)` or `* ID NIL` response, which imap-proto does not support.
+    pub(crate) fn parse(raw: &[u8]) -> Option<ServerId> {
+        let raw = std::str::from_utf8(raw).ok()?.trim_end();
+        let rest = strip_prefix_ignore_case(raw, "* ID ")?.trim_start();
+        if rest.eq_ignore_ascii_case("NIL") {
+            return Some(ServerId::default());
+        }
+
+        let mut rest = strip_prefix_ignore_case(rest, "(")?;
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(tail) = strip_prefix_ignore_case(rest, ")") {
+                if !tail.is_empty() {
+                    return None;
+                }
+                break;
+            }
+            let (value, tail) = nstring(rest)?;
+            values.push(value);
+            rest = tail;
+        }
+        if values.len() % 2 != 0 {
+            return None;
+        }
+
+        let mut fields = Vec::with_capacity(values.len() / 2);
+        let mut values = values.into_iter();
+        while let (Some(key), Some(value)) = (values.next(), values.next()) {
+            fields.push((key?, value));
+        }
+        Some(ServerId { fields })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_id() {
+        let id = ServerId::parse(
+            b"* ID (\"name\" \"Dovecot\" \"version\" \"2.3.4\" \"x-quote\" \"a \\\"b\\\"\" \"vendor\" NIL)\r\n",
+        )
+        .unwrap();
+        assert_eq!(id.name(), Some("Dovecot"));
+        assert_eq!(id.get("VERSION"), Some("2.3.4"));
+        assert_eq!(id.get("x-quote"), Some("a \"b\""));
+        assert_eq!(id.vendor(), None);
+        assert_eq!(id.fields().count(), 4);
+
+        assert_eq!(ServerId::parse(b"* ID NIL\r\n"), Some(ServerId::default()));
+        assert_eq!(ServerId::parse(b"* ID (\"name\")\r\n"), None);
+        assert_eq!(ServerId::parse(b"* IDLE\r\n"), None);
+    }
+}
@@ -0,0 +1,174 @@
+/// How deep a thread may be nested. Deeper threads are rejected, since the tree is built and
+/// dropped recursively, and a server could otherwise exhaust the stack with a long enough reply
+/// chain.
+const MAX_DEPTH: usize = 1000;
+
+/// A conversation from the response to [`Session::thread`](crate::Session::thread), as a tree
+/// of messages in which every message is a reply to its parent.
+///
+/// A thread whose first message is missing from the results, e.g. because it was deleted or did
+/// not match the query, has a root without an `id`, whose children are the remaining
+/// sub-threads.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Thread {
+    /// The sequence number or [`Uid`](crate::types::Uid) of the message, depending on the
+    /// command, or `None` for the placeholder of a missing message.
+    pub id: Option<u32>,
+    /// The replies to the message, in the order chosen by the threading algorithm.
+    pub children: Vec<Thread>,
+}
+
+impl Thread {
+    /// The identifiers of all messages in the thread, each message before its replies.
+    pub fn ids(&self) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let mut pending = vec![self];
+        while let Some(thread) = pending.pop() {
+            ids.extend(thread.id);
+            pending.extend(thread.children.iter().rev());
+        }
+        ids
+    }
+
+    /// The number of messages in the thread.
+    pub fn len(&self) -> usize {
+        self.ids().len()
+    }
+
+    /// Whether the thread contains no messages at all.
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none() && self.children.iter().all(Thread::is_empty)
+    }
+}
+
+/// Parses a raw [`THREAD` response](https://tools.ietf.org/html/rfc5256#section-4), which
+/// imap-proto does not support, into its threads. Returns `None` if `raw` is some other response,
+/// or is malformed.
+pub(crate) fn parse_thread(raw: &[u8]) -> Option<Vec<Thread>> {
+    let raw = std::str::from_utf8(raw).ok()?.trim_end();
+    let prefix = raw.get(.."* THREAD".len())?;
+    if !prefix.eq_ignore_ascii_case("* THREAD") {
+        return None;
+    }
+    let mut rest = &raw[prefix.len()..];
+    if !rest.is_empty() {
+        rest = strip_char(rest, ' ')?;
+    }
+
+    let mut threads = Vec::new();
+    while !rest.is_empty() {
+        let (thread, tail) = thread_list(rest, 0)?;
+        threads.push(thread);
+        rest = tail.trim_start_matches(' ');
+    }
+    Some(threads)
+}
+
+/// Parses a parenthesized `thread-list` at the start of `s`, nested `depth` levels deep, and
+/// returns it and the remaining input.
+fn thread_list(s: &str, depth: usize) -> Option<(Thread, &str)> {
+    let mut rest = strip_char(s, '(')?;
+
+    // a chain of messages, each a reply to the one before
+    let mut chain = Vec::new();
+    loop {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or_else(|| rest.len());
+        if end == 0 {
+            break;
+        }
+        chain.push(rest[..end].parse::<u32>().ok()?);
+        rest = rest[end..].trim_start_matches(' ');
+    }
+    let depth = depth + std::cmp::max(chain.len(), 1);
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    // followed by the sub-threads that reply to the last of them, or that share a missing
+    // parent if there is no chain
+    let mut children = Vec::new();
+    while !rest.starts_with(')') {
+        let (child, tail) = thread_list(rest, depth)?;
+        children.push(child);
+        rest = tail.trim_start_matches(' ');
+    }
+    if chain.is_empty() && children.is_empty() {
+        return None;
+    }
+
+    let mut ids = chain.into_iter().rev();
+    let mut thread = Thread {
+        id: ids.next(),
+        children,
+    };
+    for id in ids {
+        thread = Thread {
+            id: Some(id),
+            children: vec![thread],
+        };
+    }
+    Some((thread, &rest[1..]))
+}
+
+fn strip_char(s: &str, c: char) -> Option<&str> {
+    if s.starts_with(c) {
+        Some(&s[c.len_utf8()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(id: u32) -> Thread {
+        Thread {
+            id: Some(id),
+            children: Vec::new(),
+        }
+    }
+
+    fn node(id: Option<u32>, children: Vec<Thread>) -> Thread {
+        Thread { id, children }
+    }
+
+    #[test]
+    fn parse() {
+        // the example from RFC 5256
+        let threads = parse_thread(b"* THREAD (2)(3 6 (4 23)(44 7 96))\r\n").unwrap();
+        assert_eq!(
+            threads,
+            vec![
+                leaf(2),
+                node(
+                    Some(3),
+                    vec![node(
+                        Some(6),
+                        vec![
+                            node(Some(4), vec![leaf(23)]),
+                            node(Some(44), vec![node(Some(7), vec![leaf(96)])]),
+                        ]
+                    )]
+                ),
+            ]
+        );
+        assert_eq!(threads[1].ids(), vec![3, 6, 4, 23, 44, 7, 96]);
+        assert_eq!(threads[1].len(), 7);
+
+        let threads = parse_thread(b"* THREAD ((3)(5))\r\n").unwrap();
+        assert_eq!(threads, vec![node(None, vec![leaf(3), leaf(5)])]);
+        assert!(!threads[0].is_empty());
+
+        assert_eq!(parse_thread(b"* THREAD\r\n"), Some(vec![]));
+        assert_eq!(parse_thread(b"* THREAD (2)(3\r\n"), None);
+        assert_eq!(parse_thread(b"* THREAD ()\r\n"), None);
+        assert_eq!(parse_thread(b"* THREADS (2)\r\n"), None);
+        assert_eq!(parse_thread(b"* SEARCH 2\r\n"), None);
+
+        let deep = format!("* THREAD {}1{}\r\n", "(".repeat(2000), ")".repeat(2000));
+        assert_eq!(parse_thread(deep.as_bytes()), None);
+    }
+}
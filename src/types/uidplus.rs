@@ -0,0 +1,124 @@
+use std::ops::RangeInclusive;
+
+use imap_proto::ResponseCode;
+
+use super::sequence_set::parse_ranges;
+use super::Uid;
+
+/// The [`APPENDUID` response code](https://tools.ietf.org/html/rfc4315#section-3) that servers
+/// with `UIDPLUS` send after [`Session::append`](crate::Session::append), which tells the
+/// [`Uid`] that the new message was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AppendUid {
+    /// The `UIDVALIDITY` of the destination mailbox.
+    pub uid_validity: u32,
+    /// The UID of the appended message.
+    pub uid: Uid,
+}
+
+impl AppendUid {
+    /// Parses an `[APPENDUID ...]` response code.
+    pub(crate) fn parse(
+        code: Option<&ResponseCode<'_>>,
+        information: Option<&str>,
+    ) -> Option<Self> {
+        let words = unknown_code(code, information, "APPENDUID")?;
+        match words[..] {
+            [uid_validity, uid] => Some(AppendUid {
+                uid_validity: uid_validity.parse().ok()?,
+                uid: uid.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The [`COPYUID` response code](https://tools.ietf.org/html/rfc4315#section-3) that servers
+/// with `UIDPLUS` send after copying or moving messages, which tells the [`Uid`]s that the
+/// messages were given in the destination mailbox.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CopyUid {
+    /// The `UIDVALIDITY` of the destination mailbox.
+    pub uid_validity: u32,
+    /// The UIDs of the messages in the source mailbox, as inclusive ranges.
+    pub source: Vec<RangeInclusive<Uid>>,
+    /// The UIDs of the messages in the destination mailbox, in the same order as `source`.
+    pub destination: Vec<RangeInclusive<Uid>>,
+}
+
+impl CopyUid {
+    /// Pairs of the UID of each message in the source mailbox and its UID in the destination
+    /// mailbox.
+    pub fn pairs(&self) -> impl Iterator<Item = (Uid, Uid)> + '_ {
+        let source = self.source.iter().cloned().flatten();
+        source.zip(self.destination.iter().cloned().flatten())
+    }
+
+    /// Parses a `[COPYUID ...]` response code.
+    pub(crate) fn parse(
+        code: Option<&ResponseCode<'_>>,
+        information: Option<&str>,
+    ) -> Option<Self> {
+        let words = unknown_code(code, information, "COPYUID")?;
+        match words[..] {
+            [uid_validity, source, destination] => Some(CopyUid {
+                uid_validity: uid_validity.parse().ok()?,
+                source: parse_ranges(source)?,
+                destination: parse_ranges(destination)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The arguments of the response code `name`. imap-proto does not know the `UIDPLUS` codes, so
+/// they are left at the start of `information`.
+fn unknown_code<'a>(
+    code: Option<&ResponseCode<'_>>,
+    information: Option<&'a str>,
+    name: &str,
+) -> Option<Vec<&'a str>> {
+    if code.is_some() {
+        return None;
+    }
+    let text = information?.trim_start();
+    if !text.starts_with('[') {
+        return None;
+    }
+    let mut words = text[1..text.find(']')?].split(' ');
+    if !words.next()?.eq_ignore_ascii_case(name) {
+        return None;
+    }
+    Some(words.collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let copy_uid =
+            CopyUid::parse(None, Some("[COPYUID 38505 304,319:320 3956:3958] Done")).unwrap();
+        assert_eq!(copy_uid.uid_validity, 38505);
+        assert_eq!(
+            copy_uid.pairs().collect::<Vec<_>>(),
+            vec![(304, 3956), (319, 3957), (320, 3958)]
+        );
+        assert_eq!(CopyUid::parse(None, Some("[COPYUID 38505 304] Done")), None);
+        assert_eq!(CopyUid::parse(None, Some("Done")), None);
+        assert_eq!(CopyUid::parse(None, None), None);
+
+        assert_eq!(
+            AppendUid::parse(None, Some("[APPENDUID 38505 3955] APPEND completed")),
+            Some(AppendUid {
+                uid_validity: 38505,
+                uid: 3955,
+            })
+        );
+        assert_eq!(
+            AppendUid::parse(None, Some("[COPYUID 38505 3955 3956] Done")),
+            None
+        );
+    }
+}
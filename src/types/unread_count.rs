@@ -0,0 +1,10 @@
+/// The number of messages and unseen messages in a mailbox, as returned by
+/// [`Session::unread_counts`](crate::Session::unread_counts).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct UnreadCount {
+    /// The number of messages in the mailbox.
+    pub messages: u32,
+
+    /// The number of messages which do not have [`Flag::Seen`](super::Flag::Seen) set.
+    pub unseen: u32,
+}
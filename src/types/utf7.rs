@@ -0,0 +1,129 @@
+/// Encodes `name` in the [modified UTF-7](https://tools.ietf.org/html/rfc3501#section-5.1.3)
+/// that mailbox names are sent in, e.g. `Entw&APw-rfe` for `Entwürfe`, or `R&-D` for `R&D`.
+///
+/// The result is what [`Session::create`](crate::Session::create), [`Session::select`] and the
+/// other mailbox commands expect, which quote and escape it themselves.
+///
+/// ```
+/// use async_imap::types::{decode_utf7, encode_utf7};
+///
+/// assert_eq!(encode_utf7("Entwürfe"), "Entw&APw-rfe");
+/// assert_eq!(encode_utf7("R&D \"2020\""), "R&-D \"2020\"");
+/// assert_eq!(decode_utf7("&ZeVnLIqe-").unwrap(), "日本語");
+/// ```
+///
+/// [`Session::select`]: crate::Session::select
+pub fn encode_utf7(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    let mut pending: Vec<u16> = Vec::new();
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut encoded, &mut pending);
+            encoded.push(c);
+            if c == '&' {
+                encoded.push('-');
+            }
+        } else {
+            let mut units = [0; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut units));
+        }
+    }
+    flush(&mut encoded, &mut pending);
+    encoded
+}
+
+/// Appends the UTF-16 code units in `pending` to `encoded` as a base64 run, and clears them.
+fn flush(encoded: &mut String, pending: &mut Vec<u16>) {
+    if pending.is_empty() {
+        return;
+    }
+    let bytes: Vec<u8> = pending
+        .iter()
+        .flat_map(|unit| unit.to_be_bytes().to_vec())
+        .collect();
+    encoded.push('&');
+    encoded.push_str(&base64::encode_config(&bytes, base64::STANDARD_NO_PAD).replace('/', ","));
+    encoded.push('-');
+    pending.clear();
+}
+
+/// Decodes a mailbox name in [modified UTF-7](https://tools.ietf.org/html/rfc3501#section-5.1.3),
+/// as listed by the server, see [`Name::decoded_name`](crate::types::Name::decoded_name).
+///
+/// Returns `None` if a `&` is not followed by a valid base64 run ending in `-`, or if the run
+/// encodes printable ASCII characters, which have to be sent as they are. Other characters
+/// that are not ASCII are kept, since some servers send names in UTF-8 instead.
+pub fn decode_utf7(name: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let run = &rest[start + 1..];
+        let end = run.find('-')?;
+        if end == 0 {
+            decoded.push('&');
+        } else {
+            let encoded = &run[..end];
+            if encoded.contains('/') {
+                return None;
+            }
+            let bytes =
+                base64::decode_config(&encoded.replace(',', "/"), base64::STANDARD_NO_PAD).ok()?;
+            if bytes.len() % 2 != 0 {
+                return None;
+            }
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            let chars = String::from_utf16(&units).ok()?;
+            if chars.chars().any(|c| (' '..='~').contains(&c)) {
+                return None;
+            }
+            decoded.push_str(&chars);
+        }
+        rest = &run[end + 1..];
+    }
+    decoded.push_str(rest);
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        // examples from RFC 3501
+        assert_eq!(
+            encode_utf7("~peter/mail/台北/日本語"),
+            "~peter/mail/&U,BTFw-/&ZeVnLIqe-"
+        );
+        assert_eq!(
+            decode_utf7("~peter/mail/&U,BTFw-/&ZeVnLIqe-").unwrap(),
+            "~peter/mail/台北/日本語"
+        );
+
+        for name in &[
+            "INBOX",
+            "",
+            "&",
+            "A&B&",
+            "a\\b\"c",
+            "Entwürfe",
+            "\u{1f4e7} Mail",
+            "tab\there",
+            "ä&ö",
+        ] {
+            assert_eq!(decode_utf7(&encode_utf7(name)).as_deref(), Some(*name));
+        }
+
+        // not valid modified UTF-7
+        assert_eq!(decode_utf7("&U,BTFw"), None);
+        assert_eq!(decode_utf7("&U/BTFw-"), None);
+        assert_eq!(decode_utf7("&AGE-"), None);
+        assert_eq!(decode_utf7("&2D0-"), None);
+        // but raw UTF-8 is kept
+        assert_eq!(decode_utf7("Entwürfe").unwrap(), "Entwürfe");
+    }
+}
@@ -0,0 +1,89 @@
+use std::ops::RangeInclusive;
+
+use super::sequence_set::parse_ranges;
+use super::Uid;
+
+/// A [`VANISHED` response](https://tools.ietf.org/html/rfc7162#section-3.2.10), which reports
+/// expunged messages by their [`Uid`]s instead of `EXPUNGE` responses once `QRESYNC` is
+/// enabled.
+///
+/// Responses without `(EARLIER)` are sent while a mailbox is selected, and are delivered on the
+/// `unsolicited_responses` channel of the [`Session`](crate::Session) as
+/// [`UnsolicitedResponse::Vanished`](crate::types::UnsolicitedResponse::Vanished). They
+/// decrement the number of messages in the mailbox like the equivalent `EXPUNGE` responses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Vanished {
+    /// Whether this is a `VANISHED (EARLIER)` response, which reports messages that were
+    /// already gone before the command, e.g. those expunged since the client last synchronized
+    /// the mailbox with [`Session::select_qresync`](crate::Session::select_qresync). These
+    /// messages have no sequence numbers, and do not change the number of messages.
+    pub earlier: bool,
+
+    /// The UIDs of the expunged messages, as inclusive ranges. In `VANISHED (EARLIER)`
+    /// responses, the ranges may include UIDs that were never assigned to a message.
+    pub uids: Vec<RangeInclusive<Uid>>,
+}
+
+impl Vanished {
+    /// Whether the message with `uid` is reported as expunged.
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.uids.iter().any(|range| range.contains(&uid))
+    }
+
+    /// The UIDs of the expunged messages, in the order the server listed them.
+    pub fn iter(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.uids.iter().cloned().flatten()
+    }
+
+    /// The number of UIDs listed.
+    pub(crate) fn count(&self) -> u32 {
+        self.uids.iter().fold(0u32, |count, range| {
+            count.saturating_add((range.end() - range.start()).saturating_add(1))
+        })
+    }
+
+    /// Parses a raw `VANISHED` response, which imap-proto does not support.
+    pub(crate) fn parse(raw: &[u8]) -> Option<Self> {
+        let raw = std::str::from_utf8(raw).ok()?;
+        let mut words = raw.trim_end().split(' ');
+        if words.next()? != "*" || !words.next()?.eq_ignore_ascii_case("VANISHED") {
+            return None;
+        }
+        let mut set = words.next()?;
+        let earlier = set.eq_ignore_ascii_case("(EARLIER)");
+        if earlier {
+            set = words.next()?;
+        }
+        if words.next().is_some() {
+            return None;
+        }
+
+        Some(Vanished {
+            earlier,
+            uids: parse_ranges(set)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let vanished = Vanished::parse(b"* VANISHED (EARLIER) 41,43:116,118\r\n").unwrap();
+        assert!(vanished.earlier);
+        assert_eq!(vanished.uids, vec![41..=41, 43..=116, 118..=118]);
+        assert_eq!(vanished.count(), 76);
+        assert!(vanished.contains(100));
+        assert!(!vanished.contains(42));
+
+        let vanished = Vanished::parse(b"* vanished 5:3\r\n").unwrap();
+        assert!(!vanished.earlier);
+        assert_eq!(vanished.iter().collect::<Vec<_>>(), vec![3, 4, 5]);
+
+        assert_eq!(Vanished::parse(b"* VANISHED (EARLIER)\r\n"), None);
+        assert_eq!(Vanished::parse(b"* VANISHED 1:*\r\n"), None);
+        assert_eq!(Vanished::parse(b"* XSTATE 1:3\r\n"), None);
+    }
+}
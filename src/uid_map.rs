@@ -0,0 +1,153 @@
+use imap_proto::{AttributeValue, MailboxDatum, Response};
+
+use crate::types::{ResponseData, Seq, Uid, Vanished};
+
+/// The number of messages whose UIDs are tracked at most. Messages at higher positions are not
+/// tracked, so that a server that announces billions of messages can not make the map allocate
+/// memory for all of them.
+const MAX_TRACKED: usize = 1 << 22;
+
+/// The [`Uid`]s of the messages in the selected mailbox by sequence number, as far as they are
+/// known from the responses seen so far, see `Session::track_uids`.
+#[derive(Debug, Default)]
+pub(crate) struct UidMap {
+    /// The UID of the message with sequence number `i + 1` at index `i`.
+    uids: Vec<Option<Uid>>,
+}
+
+impl UidMap {
+    /// A map for a mailbox whose messages have exactly the given UIDs, in ascending order.
+    pub(crate) fn from_uids<I: IntoIterator<Item = Uid>>(uids: I) -> Self {
+        UidMap {
+            uids: uids.into_iter().map(Some).collect(),
+        }
+    }
+
+    pub(crate) fn uid(&self, seq: Seq) -> Option<Uid> {
+        *self.uids.get((seq as usize).checked_sub(1)?)?
+    }
+
+    pub(crate) fn seq(&self, uid: Uid) -> Option<Seq> {
+        let i = self.uids.iter().position(|u| *u == Some(uid))?;
+        Some(i as Seq + 1)
+    }
+
+    /// Updates the map from a `FETCH` that includes the UID, or an `EXISTS` or `EXPUNGE`.
+    pub(crate) fn track(&mut self, response: &ResponseData) {
+        match response.parsed() {
+            Response::Fetch(seq, attrs) => {
+                let uid = attrs.iter().find_map(|attr| match attr {
+                    AttributeValue::Uid(uid) => Some(*uid),
+                    _ => None,
+                });
+                let i = (*seq as usize).saturating_sub(1);
+                if let (Some(uid), true) = (uid, *seq > 0 && i < MAX_TRACKED) {
+                    if i >= self.uids.len() {
+                        self.uids.resize(i + 1, None);
+                    }
+                    self.uids[i] = Some(uid);
+                }
+            }
+            Response::MailboxData(MailboxDatum::Exists(n)) => {
+                let n = std::cmp::min(*n as usize, MAX_TRACKED);
+                if n < self.uids.len() {
+                    // messages disappeared without EXPUNGE, so no position can be trusted
+                    self.forget(n);
+                } else {
+                    self.uids.resize(n, None);
+                }
+            }
+            Response::Expunge(seq) => {
+                let i = (*seq as usize).saturating_sub(1);
+                if *seq > 0 && i < self.uids.len() {
+                    self.uids.remove(i);
+                } else {
+                    let len = self.uids.len();
+                    self.forget(len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes the messages of a `VANISHED` response, which lists UIDs rather than sequence
+    /// numbers.
+    pub(crate) fn vanished(&mut self, vanished: &Vanished) {
+        if vanished.earlier {
+            return;
+        }
+        let len = self.uids.len();
+        self.uids
+            .retain(|uid| !uid.map_or(false, |uid| vanished.contains(uid)));
+        let removed = len - self.uids.len();
+        let count = vanished.count() as usize;
+        if removed < count {
+            // some of the messages were not known, so it is unclear which positions they had
+            let len = len.saturating_sub(count);
+            self.forget(len);
+        }
+    }
+
+    /// Forgets all UIDs, and sets the number of messages to `len`.
+    fn forget(&mut self, len: usize) {
+        self.uids.clear();
+        self.uids.resize(len, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::Protocol;
+
+    fn response(raw: &[u8]) -> ResponseData {
+        let mut protocol = Protocol::new();
+        protocol.feed(raw).unwrap();
+        protocol.decode().unwrap().unwrap()
+    }
+
+    #[test]
+    fn track() {
+        let mut map = UidMap::default();
+        map.track(&response(b"* 4 EXISTS\r\n"));
+        map.track(&response(b"* 2 FETCH (UID 12 FLAGS ())\r\n"));
+        map.track(&response(b"* 3 FETCH (FLAGS () UID 13)\r\n"));
+        assert_eq!(map.uid(2), Some(12));
+        assert_eq!(map.uid(1), None);
+        assert_eq!(map.seq(13), Some(3));
+
+        map.track(&response(b"* 1 EXPUNGE\r\n"));
+        assert_eq!(map.uid(1), Some(12));
+        assert_eq!(map.seq(13), Some(2));
+
+        map.track(&response(b"* 4 EXISTS\r\n"));
+        assert_eq!(map.uid(4), None);
+        map.vanished(&Vanished {
+            earlier: false,
+            uids: vec![12..=12],
+        });
+        assert_eq!(map.seq(13), Some(1));
+
+        // the server lost track of a message, so the positions are unknown
+        map.track(&response(b"* 2 EXISTS\r\n"));
+        assert_eq!(map.seq(13), None);
+        assert_eq!(map.uid(0), None);
+
+        // positions beyond the limit are not tracked
+        map.track(&response(b"* 4294967295 FETCH (UID 7)\r\n"));
+        assert_eq!(map.uids.len(), 2);
+        assert_eq!(map.seq(7), None);
+    }
+
+    #[test]
+    fn vanished_unknown() {
+        let mut map = UidMap::from_uids(vec![3, 5, 8]);
+        map.vanished(&Vanished {
+            earlier: false,
+            uids: vec![4..=5],
+        });
+        assert_eq!(map.seq(8), None);
+        assert_eq!(map.uids.len(), 1);
+    }
+}
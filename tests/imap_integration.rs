@@ -165,8 +165,8 @@ fn inbox() {
         c.noop().await.unwrap();
         println!("noop done");
         let mut unsolicited = Vec::new();
-        while !c.unsolicited_responses.is_empty() {
-            unsolicited.push(c.unsolicited_responses.recv().await.unwrap());
+        while let Ok(Some(response)) = c.unsolicited_responses.try_next() {
+            unsolicited.push(response);
         }
 
         assert_eq!(unsolicited.len(), 2);
@@ -246,13 +246,13 @@ fn inbox_uid() {
         let inbox = c.uid_search("ALL").await.unwrap();
         // and the one message should have the first message sequence number
         assert_eq!(inbox.len(), 1);
-        let uid = inbox.into_iter().next().unwrap();
+        let uid = inbox.into_inner().into_iter().next().unwrap();
 
         // we should also get two unsolicited responses: Exists and Recent
         c.noop().await.unwrap();
         let mut unsolicited = Vec::new();
-        while !c.unsolicited_responses.is_empty() {
-            unsolicited.push(c.unsolicited_responses.recv().await.unwrap());
+        while let Ok(Some(response)) = c.unsolicited_responses.try_next() {
+            unsolicited.push(response);
         }
 
         assert_eq!(unsolicited.len(), 2);